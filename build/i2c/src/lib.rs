@@ -7,7 +7,7 @@ use convert_case::{Case, Casing};
 use indexmap::IndexMap;
 use multimap::MultiMap;
 use serde::Deserialize;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt::Write;
 use std::fs::File;
 
@@ -104,9 +104,22 @@ struct I2cDevice {
     /// sensor information, if any
     sensors: Option<I2cSensors>,
 
+    /// alarm thresholds for each kind of sensor on this device, if any
+    #[serde(default)]
+    thresholds: BTreeMap<Sensor, SensorThresholds>,
+
+    /// kinds of sensor on this device whose statistics are also kept over
+    /// windows (see the `sensor` task's `stats-window-ms`)
+    #[serde(default)]
+    windowed: BTreeSet<Sensor>,
+
     /// device is removable
     #[serde(default)]
     removable: bool,
+
+    /// device uses SMBus Packet Error Checking
+    #[serde(default)]
+    pec: bool,
 }
 
 impl I2cDevice {
@@ -249,6 +262,52 @@ impl I2cSensors {
     }
 }
 
+/// Alarm thresholds for a sensor, which apply to every sensor of a given
+/// kind on a device.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct SensorThresholds {
+    pub lower_critical: Option<f32>,
+    pub lower_warning: Option<f32>,
+    pub upper_warning: Option<f32>,
+    pub upper_critical: Option<f32>,
+
+    /// Distance that a reading must move back past a threshold to clear it
+    #[serde(default)]
+    pub hysteresis: f32,
+}
+
+impl SensorThresholds {
+    /// Checks that the thresholds are in order and the hysteresis is sane.
+    pub fn validate(&self) -> Result<()> {
+        let levels = [
+            self.lower_critical,
+            self.lower_warning,
+            self.upper_warning,
+            self.upper_critical,
+        ];
+        let mut prev: Option<f32> = None;
+        for t in levels.into_iter().flatten() {
+            if t.is_nan() {
+                bail!("thresholds must not be NaN");
+            }
+            if let Some(p) = prev {
+                if t < p {
+                    bail!(
+                        "thresholds must be ordered lower-critical <= \
+                         lower-warning <= upper-warning <= upper-critical"
+                    );
+                }
+            }
+            prev = Some(t);
+        }
+        if self.hysteresis.is_nan() || self.hysteresis < 0.0 {
+            bail!("hysteresis must not be negative");
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Hash)]
 struct DeviceKey {
     device: String,
@@ -939,12 +998,13 @@ impl ConfigGenerator {
 {indent}    PortIndex({port}),
 {indent}    {segment},
 {indent}    {address:#x}
-{indent})"##,
+{indent}){pec}"##,
             description = d.description,
             controller = controller,
             port = port,
             segment = segment,
             address = d.address,
+            pec = if d.pec { ".with_pec()" } else { "" },
             indent = indent,
         )
     }
@@ -1620,6 +1680,39 @@ impl ConfigGenerator {
         Ok(())
     }
 
+    ///
+    /// Generates the sizes of the tables in which the I2C server keeps its
+    /// per-bus, per-segment and per-device error counters.
+    ///
+    pub fn generate_stats(&mut self) -> Result<()> {
+        let mut segments = HashSet::new();
+
+        for d in &self.devices {
+            if let (Some(mux), Some(segment)) = (d.mux, d.segment) {
+                let (controller, port) = self.lookup_controller_port(d);
+                segments.insert((controller, port, mux, segment));
+            }
+        }
+
+        writeln!(
+            &mut self.output,
+            r##"
+    #[allow(dead_code)]
+    pub const NPORTS: usize = {nports};
+
+    #[allow(dead_code)]
+    pub const NSEGMENTS: usize = {nsegments};
+
+    #[allow(dead_code)]
+    pub const NDEVICES: usize = {ndevices};"##,
+            nports = self.ports.len(),
+            nsegments = segments.len(),
+            ndevices = self.devices.len(),
+        )?;
+
+        Ok(())
+    }
+
     pub fn generate_ports(&mut self) -> Result<()> {
         writeln!(
             &mut self.output,
@@ -1680,6 +1773,7 @@ pub fn codegen(disposition: Disposition) -> Result<()> {
             g.generate_pins()?;
             g.generate_ports()?;
             g.generate_muxes()?;
+            g.generate_stats()?;
         }
 
         Disposition::Devices => {
@@ -1708,7 +1802,12 @@ pub fn codegen(disposition: Disposition) -> Result<()> {
 pub struct I2cDeviceDescription {
     pub device: String,
     pub description: String,
+    pub name: Option<String>,
+    pub refdes: Option<String>,
+    pub removable: bool,
     pub sensors: Vec<DeviceSensor>,
+    pub thresholds: BTreeMap<Sensor, SensorThresholds>,
+    pub windowed: BTreeSet<Sensor>,
 }
 
 ///
@@ -1729,7 +1828,12 @@ pub fn device_descriptions() -> impl Iterator<Item = I2cDeviceDescription> {
         |(device, sensors)| I2cDeviceDescription {
             device: device.device,
             description: device.description,
+            name: device.name,
+            refdes: device.refdes,
+            removable: device.removable,
             sensors,
+            thresholds: device.thresholds,
+            windowed: device.windowed,
         },
     )
 }
@@ -26,6 +26,8 @@ pub enum SeqError {
 
     #[idol(server_death)]
     ServerRestarted,
+    #[idol(timeout)]
+    ServerTimedOut,
 }
 
 // On Gimlet, we have two banks of up to 8 DIMMs apiece. Export the "two banks"
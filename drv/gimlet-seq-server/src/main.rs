@@ -22,6 +22,7 @@ use idol_runtime::{NotificationHandler, RequestError};
 use seq_spi::{Addr, Reg};
 use static_assertions::const_assert;
 use task_jefe_api::Jefe;
+use task_packrat_api::HostAlertKind;
 
 task_slot!(SYS, sys);
 task_slot!(SPI, spi_driver);
@@ -381,6 +382,7 @@ fn main() -> ! {
         seq,
         jefe,
         hf,
+        packrat,
         deadline: 0,
     };
 
@@ -527,6 +529,7 @@ struct ServerImpl<S: SpiServer> {
     seq: seq_spi::SequencerFpga<S>,
     jefe: Jefe,
     hf: hf_api::HostFlash,
+    packrat: Packrat,
     deadline: u64,
 }
 
@@ -903,6 +906,15 @@ impl<S: SpiServer> ServerImpl<S> {
         if ifr & thermtrip != 0 {
             self.seq.clear_bytes(Addr::IFR, &[thermtrip]).unwrap_lite();
             self.update_state_internal(PowerState::A0Thermtrip);
+
+            //
+            // The host is already down, but host-sp-comms exempts this alert
+            // from its age limit, so the host can find out why once it's
+            // powered back on -- unless newer alerts have pushed it out of
+            // packrat's queue by then.
+            //
+            self.packrat
+                .raise_host_alert(HostAlertKind::ThermalEmergency as u8, 0xff);
         }
     }
 
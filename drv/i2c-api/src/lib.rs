@@ -19,6 +19,11 @@
 //! - The segment on the multiplexer, if a multiplexer is specified
 //! - The address of the device itself
 //!
+//! A device may additionally use SMBus Packet Error Checking (PEC), in which
+//! case the I2C server appends a PEC byte to writes and checks the PEC byte
+//! that the device appends to reads, failing the operation with
+//! [`ResponseCode::PecMismatch`] on a mismatch.
+//!
 
 #![no_std]
 
@@ -38,6 +43,7 @@ pub struct I2cDevice {
     pub port: PortIndex,
     pub segment: Option<(Mux, Segment)>,
     pub address: u8,
+    pub pec: bool,
 }
 
 type I2cMessage = (u8, Controller, PortIndex, Option<(Mux, Segment)>);
@@ -120,11 +126,30 @@ impl I2cDevice {
             port,
             segment,
             address,
+            pec: false,
         }
     }
+
+    ///
+    /// Returns this [`I2cDevice`], but using SMBus Packet Error Checking for
+    /// all operations.  The device must support PEC (and, for devices where
+    /// it is optional, have it enabled).
+    ///
+    pub fn with_pec(self) -> Self {
+        Self { pec: true, ..self }
+    }
 }
 
 impl I2cDevice {
+    fn op(&self, op: Op) -> u16 {
+        let op = match (op, self.pec) {
+            (Op::WriteRead, true) => Op::WriteReadPec,
+            (Op::WriteReadBlock, true) => Op::WriteReadBlockPec,
+            (op, _) => op,
+        };
+        op as u16
+    }
+
     fn response_code<V>(&self, code: u32, val: V) -> Result<V, ResponseCode> {
         if code != 0 {
             if let Some(_g) = userlib::extract_new_generation(code) {
@@ -164,7 +189,7 @@ impl I2cDevice {
 
         let (code, _) = sys_send(
             self.task,
-            Op::WriteRead as u16,
+            self.op(Op::WriteRead),
             &Marshal::marshal(&(
                 self.address,
                 self.controller,
@@ -192,7 +217,7 @@ impl I2cDevice {
 
         let (code, _) = sys_send(
             self.task,
-            Op::WriteRead as u16,
+            self.op(Op::WriteRead),
             &Marshal::marshal(&(
                 self.address,
                 self.controller,
@@ -222,7 +247,7 @@ impl I2cDevice {
 
         let (code, _) = sys_send(
             self.task,
-            Op::WriteReadBlock as u16,
+            self.op(Op::WriteReadBlock),
             &Marshal::marshal(&(
                 self.address,
                 self.controller,
@@ -249,7 +274,7 @@ impl I2cDevice {
 
         let (code, _) = sys_send(
             self.task,
-            Op::WriteRead as u16,
+            self.op(Op::WriteRead),
             &Marshal::marshal(&(
                 self.address,
                 self.controller,
@@ -273,7 +298,7 @@ impl I2cDevice {
 
         let (code, _) = sys_send(
             self.task,
-            Op::WriteRead as u16,
+            self.op(Op::WriteRead),
             &Marshal::marshal(&(
                 self.address,
                 self.controller,
@@ -296,7 +321,7 @@ impl I2cDevice {
 
         let (code, _) = sys_send(
             self.task,
-            Op::WriteRead as u16,
+            self.op(Op::WriteRead),
             &Marshal::marshal(&(
                 self.address,
                 self.controller,
@@ -328,7 +353,7 @@ impl I2cDevice {
 
         let (code, _) = sys_send(
             self.task,
-            Op::WriteRead as u16,
+            self.op(Op::WriteRead),
             &Marshal::marshal(&(
                 self.address,
                 self.controller,
@@ -370,7 +395,7 @@ impl I2cDevice {
 
         let (code, _) = sys_send(
             self.task,
-            Op::WriteReadBlock as u16,
+            self.op(Op::WriteReadBlock),
             &Marshal::marshal(&(
                 self.address,
                 self.controller,
@@ -405,7 +430,7 @@ impl I2cDevice {
 
         let (code, _) = sys_send(
             self.task,
-            Op::WriteRead as u16,
+            self.op(Op::WriteRead),
             &Marshal::marshal(&(
                 self.address,
                 self.controller,
@@ -443,7 +468,7 @@ impl I2cDevice {
 
         let (code, _) = sys_send(
             self.task,
-            Op::WriteRead as u16,
+            self.op(Op::WriteRead),
             &Marshal::marshal(&(
                 self.address,
                 self.controller,
@@ -463,4 +488,28 @@ impl I2cDevice {
 
         self.response_code(code, val)
     }
+
+    ///
+    /// Returns the error and recovery counters that the I2C server has kept
+    /// for this device, its bus and its mux segment (if any).  This does not
+    /// communicate with the device itself.
+    ///
+    pub fn stats(&self) -> Result<I2cStatsReport, ResponseCode> {
+        let mut report = I2cStatsReport::default();
+
+        let (code, _) = sys_send(
+            self.task,
+            Op::Stats as u16,
+            &Marshal::marshal(&(
+                self.address,
+                self.controller,
+                self.port,
+                self.segment,
+            )),
+            report.as_bytes_mut(),
+            &[],
+        );
+
+        self.response_code(code, report)
+    }
 }
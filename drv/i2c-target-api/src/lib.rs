@@ -0,0 +1,34 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Client API for I2C target-mode register map handlers
+//!
+//! A task that wants to emulate an SMBus device implements the
+//! `I2cTargetHandler` interface, and is listed as a handler in the
+//! configuration of the I2C target server.  Handlers claim addresses at
+//! runtime: when a host addresses the target, the server asks each handler
+//! in turn whether it `responds` to that address, then calls into the one
+//! that does for each register read or written.  This all happens with the
+//! bus clock stretched, so handlers must be of higher priority than the
+//! target server, and should answer promptly.
+
+#![no_std]
+
+use derive_idol_err::IdolError;
+use userlib::*;
+
+#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
+pub enum I2cTargetError {
+    /// The emulated device has no such register
+    NoRegister = 1,
+    /// The register cannot be written
+    ReadOnly,
+    /// The emulated device is not currently present
+    Unavailable,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
@@ -16,6 +16,7 @@ use serde::{Deserialize, Serialize};
 
 use derive_idol_err::IdolError;
 use enum_kinds::EnumKind;
+use zerocopy::{AsBytes, FromBytes};
 
 #[derive(FromPrimitive, Eq, PartialEq)]
 pub enum Op {
@@ -36,6 +37,32 @@ pub enum Op {
     /// without interruption, this logic would not work, but that would be a
     /// very strange device indeed.
     WriteReadBlock = 2,
+
+    /// Like `WriteRead`, but with SMBus Packet Error Checking: each write
+    /// that is not followed by a read has a PEC byte appended, and each read
+    /// is followed by a PEC byte from the device that is checked by the
+    /// server (and not returned to the caller).
+    WriteReadPec = 3,
+
+    /// Like `WriteReadBlock`, but with SMBus Packet Error Checking (as with
+    /// `WriteReadPec`).
+    WriteReadBlockPec = 4,
+
+    /// Returns the [`I2cStatsReport`] for a device, its bus, and its mux
+    /// segment (if any).  No leases are used.
+    Stats = 5,
+}
+
+impl Op {
+    /// Returns `true` if the final read is an SMBus block read
+    pub fn is_block(&self) -> bool {
+        matches!(self, Op::WriteReadBlock | Op::WriteReadBlockPec)
+    }
+
+    /// Returns `true` if SMBus Packet Error Checking is used
+    pub fn has_pec(&self) -> bool {
+        matches!(self, Op::WriteReadPec | Op::WriteReadBlockPec)
+    }
 }
 
 /// The response code returned from the I2C server.  These response codes pretty
@@ -106,6 +133,8 @@ pub enum ResponseCode {
     OperationNotSupported,
     /// Illegal number of leases
     IllegalLeaseCount,
+    /// SMBus Packet Error Check byte from the device did not match its data
+    PecMismatch,
 }
 
 ///
@@ -221,3 +250,70 @@ pub enum Segment {
     S7 = 7,
     S8 = 8,
 }
+
+///
+/// Error and recovery counters, kept by the I2C server for each bus, for each
+/// mux segment, and for each device.  Counters saturate rather than wrap.
+///
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct I2cStats {
+    /// Operations NACK'd by the device (on its address or its register)
+    pub nacks: u32,
+    /// Operations where the controller stayed busy and had to be reset
+    pub timeouts: u32,
+    /// Operations where the bus locked up
+    pub lockups: u32,
+    /// Operations that saw a bus error or a spontaneous bus reset
+    pub bus_errors: u32,
+    /// Reads whose SMBus Packet Error Check failed
+    pub pec_errors: u32,
+    /// Failures to configure a mux for an operation
+    pub mux_errors: u32,
+    /// Operations that failed for any other reason
+    pub other_errors: u32,
+    /// Controller resets (and resets of any muxes on the bus)
+    pub resets: u32,
+    /// Times that a device holding SDA low was freed by wiggling SCL
+    pub wiggles: u32,
+}
+
+impl I2cStats {
+    ///
+    /// Counts an operation that failed with the given response code.
+    ///
+    pub fn record(&mut self, code: ResponseCode) {
+        let counter = match code {
+            ResponseCode::NoDevice | ResponseCode::NoRegister => {
+                &mut self.nacks
+            }
+            ResponseCode::ControllerBusy => &mut self.timeouts,
+            ResponseCode::BusLocked | ResponseCode::BusLockedMux => {
+                &mut self.lockups
+            }
+            ResponseCode::BusError
+            | ResponseCode::BusReset
+            | ResponseCode::BusResetMux => &mut self.bus_errors,
+            ResponseCode::PecMismatch => &mut self.pec_errors,
+            ResponseCode::MuxMissing
+            | ResponseCode::MuxDisconnected
+            | ResponseCode::SegmentDisconnected
+            | ResponseCode::BadMuxRegister => &mut self.mux_errors,
+            _ => &mut self.other_errors,
+        };
+
+        *counter = counter.saturating_add(1);
+    }
+}
+
+///
+/// The counters for a device, along with those of its bus and of its mux
+/// segment.  If the device is not behind a mux, `segment` is all zeroes.
+///
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct I2cStatsReport {
+    pub bus: I2cStats,
+    pub segment: I2cStats,
+    pub device: I2cStats,
+}
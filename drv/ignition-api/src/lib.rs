@@ -24,6 +24,10 @@ use zerocopy::{AsBytes, FromBytes, Unaligned};
 // be learned through the `port_count()` function below.
 pub const PORT_MAX: u8 = 40;
 
+/// The number of events kept in the history of each port. Once full, the
+/// oldest event is overwritten.
+pub const PORT_HISTORY_DEPTH: usize = 8;
+
 #[derive(
     Copy,
     Clone,
@@ -177,6 +181,31 @@ impl Ignition {
         self.controller.link_events(port).map(LinkEvents::from)
     }
 
+    /// Return the recorded events for the given port, oldest first. See
+    /// `PortEvent` for details.
+    pub fn port_history(
+        &self,
+        port: u8,
+    ) -> Result<PortHistoryIter, IgnitionError> {
+        let history = self.controller.port_history(port)?;
+        Ok(PortHistoryIter {
+            iter: history.into_iter(),
+        })
+    }
+
+    /// Discard the recorded events for the given port.
+    #[inline]
+    pub fn clear_port_history(&self, port: u8) -> Result<(), IgnitionError> {
+        self.controller.clear_port_history(port)
+    }
+
+    /// Return a u64 with each bit indicating whether or not the link of this
+    /// port is currently considered to be flapping.
+    #[inline]
+    pub fn flapping_ports(&self) -> Result<u64, IgnitionError> {
+        self.controller.flapping_ports()
+    }
+
     /// Fetch the state of all ports in a single operation and return an
     /// iterator over the individual ports. Be aware that this reply is fairly
     /// large and may require enlarging the stack of the caller.
@@ -225,6 +254,21 @@ impl Iterator for AllLinkEventsIter {
     }
 }
 
+#[derive(Debug)]
+pub struct PortHistoryIter {
+    iter: array::IntoIter<PortEvent, PORT_HISTORY_DEPTH>,
+}
+
+impl Iterator for PortHistoryIter {
+    type Item = PortEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Unused slots in the history are left zeroed, and as such have no
+        // valid kind.
+        self.iter.by_ref().find(|e| e.kind().is_some())
+    }
+}
+
 /// `PortState` is an opague type representing (most of) the state of an
 /// Ignition Controller port. It is highly dependent on the RTL implementation
 /// of the system and the use of the `Port` and `Target` types is encouraged
@@ -655,6 +699,96 @@ impl From<[u8; 3]> for LinkEvents {
     }
 }
 
+/// The kinds of event recorded in the history of a port.
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, FromPrimitive, ToPrimitive, Serialize,
+)]
+#[repr(u8)]
+pub enum PortEventKind {
+    /// A Target was found to be present on the port.
+    TargetArrive = 1,
+    /// The Target present on the port went away.
+    TargetDepart = 2,
+    /// The receiver of the Controller port became aligned and locked.
+    LinkUp = 3,
+    /// The receiver of the Controller port lost alignment or lock.
+    LinkDown = 4,
+    /// New `TransceiverEvents` were observed by one of the transceivers of
+    /// the link. See `PortEvent::txr` and `PortEvent::events`.
+    TransceiverError = 5,
+    /// The port saw more presence and link transitions in the configured
+    /// window than the configured threshold allows.
+    FlapDetected = 6,
+    /// A flapping port has been stable for the configured window.
+    FlapCleared = 7,
+}
+
+/// A timestamped event in the history of a port, as recorded by
+/// `drv-ignition-server`.
+#[derive(
+    Copy, Clone, Debug, Default, PartialEq, Eq, AsBytes, FromBytes, Serialize,
+)]
+#[repr(C)]
+pub struct PortEvent {
+    /// The system time at which the event was observed, in ms.
+    pub timestamp: u64,
+    /// The kind of event, see `PortEventKind`.
+    pub kind: u8,
+    /// For `TransceiverError` events, the `TransceiverSelect` value of the
+    /// transceiver which observed the events. Zero otherwise.
+    pub txr: u8,
+    /// For `TransceiverError` events, the `TransceiverEvents` which were
+    /// newly observed. Zero otherwise.
+    pub events: u8,
+    _reserved: [u8; 5],
+}
+
+impl PortEvent {
+    /// An unused history slot.
+    pub const NONE: Self = Self {
+        timestamp: 0,
+        kind: 0,
+        txr: 0,
+        events: 0,
+        _reserved: [0; 5],
+    };
+
+    pub fn new(timestamp: u64, kind: PortEventKind) -> Self {
+        Self {
+            timestamp,
+            kind: kind as u8,
+            ..Self::NONE
+        }
+    }
+
+    pub fn transceiver_error(
+        timestamp: u64,
+        txr: TransceiverSelect,
+        events: u8,
+    ) -> Self {
+        Self {
+            txr: txr as u8,
+            events,
+            ..Self::new(timestamp, PortEventKind::TransceiverError)
+        }
+    }
+
+    /// Return the kind of this event, or `None` if this is an unused slot.
+    pub fn kind(&self) -> Option<PortEventKind> {
+        PortEventKind::from_u8(self.kind)
+    }
+
+    /// Return the transceiver for `TransceiverError` events.
+    pub fn transceiver(&self) -> Option<TransceiverSelect> {
+        TransceiverSelect::from_u8(self.txr)
+    }
+
+    /// Return the newly observed events for `TransceiverError` events.
+    pub fn transceiver_events(&self) -> TransceiverEvents {
+        TransceiverEvents::from(self.events)
+    }
+}
+
 /// A flattened struct representing the state of a port which can be
 /// reconstructed by Humility from a ssmarshal encoded buffer using DWARF
 /// information.
@@ -2,6 +2,46 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Config {
+    /// Number of presence and link transitions within `flap_window_ms`
+    /// which mark a port as flapping
+    #[serde(default = "default_flap_threshold")]
+    flap_threshold: usize,
+
+    /// Length of the window over which transitions are counted
+    #[serde(default = "default_flap_window_ms")]
+    flap_window_ms: u64,
+
+    /// Tasks (and the notification that they want) to notify when a port is
+    /// found to be flapping
+    #[serde(default)]
+    on_flap: BTreeMap<String, String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            flap_threshold: default_flap_threshold(),
+            flap_window_ms: default_flap_window_ms(),
+            on_flap: BTreeMap::new(),
+        }
+    }
+}
+
+fn default_flap_threshold() -> usize {
+    4
+}
+
+fn default_flap_window_ms() -> u64 {
+    60_000
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     build_util::expose_target_board();
     build_util::build_notifications()?;
@@ -11,5 +51,45 @@ fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         idol::server::ServerStyle::InOrder,
     )?;
 
+    let cfg = build_util::task_maybe_config::<Config>()?.unwrap_or_default();
+
+    // Presence and link state are polled once a second, so a window shorter
+    // than a few polls can't meaningfully hold more than one transition.
+    if cfg.flap_threshold < 2 {
+        return Err("flap-threshold must be at least 2".into());
+    }
+    if cfg.flap_window_ms < 5_000 {
+        return Err("flap-window-ms must be at least 5000".into());
+    }
+
+    let out_dir = build_util::out_dir();
+    let mut out = std::fs::File::create(out_dir.join("ignition_config.rs"))?;
+
+    writeln!(
+        out,
+        "pub(crate) const FLAP_THRESHOLD: usize = {};",
+        cfg.flap_threshold
+    )?;
+    writeln!(
+        out,
+        "pub(crate) const FLAP_WINDOW_MS: u64 = {};",
+        cfg.flap_window_ms
+    )?;
+
+    let task = "hubris_num_tasks::Task";
+    let count = cfg.on_flap.len();
+    writeln!(
+        out,
+        "pub(crate) const FLAP_SUBSCRIBERS: [({task}, u32); {count}] = [",
+    )?;
+    for (name, rec) in cfg.on_flap {
+        writeln!(
+            out,
+            "    ({task}::{name}, crate::notifications::{name}::{}_MASK),",
+            rec.to_ascii_uppercase().replace('-', "_"),
+        )?;
+    }
+    writeln!(out, "];")?;
+
     Ok(())
 }
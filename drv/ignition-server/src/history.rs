@@ -0,0 +1,107 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Per-port event history and flap detection
+//!
+//! The Controller only latches events, which leaves a consumer polling it to
+//! reconstruct what happened and when. Instead we keep a small ring of
+//! timestamped events for each port, so a Target which has gone away can be
+//! diagnosed after the fact. Presence and link transitions are additionally
+//! timestamped in a ring of `FLAP_THRESHOLD` entries; if the oldest of those
+//! is within `FLAP_WINDOW_MS` of the newest, the port is flapping.
+
+use crate::config::{FLAP_THRESHOLD, FLAP_WINDOW_MS};
+use drv_ignition_api::{PortEvent, PortEventKind, PORT_HISTORY_DEPTH};
+
+pub struct PortTracker {
+    /// Ring of recorded events, with `next` the slot to be written next
+    history: [PortEvent; PORT_HISTORY_DEPTH],
+    next: usize,
+
+    /// Ring of the timestamps of the most recent transitions, with
+    /// `transitions_next` the slot to be written next
+    transitions: [u64; FLAP_THRESHOLD],
+    transitions_next: usize,
+    transitions_seen: usize,
+
+    /// Whether the receiver of the Controller port was last seen locked
+    pub link_up: bool,
+
+    /// The transceiver events last seen for this port, used to find newly
+    /// observed events
+    pub link_events: [u8; 3],
+
+    pub flapping: bool,
+}
+
+impl PortTracker {
+    pub const EMPTY: Self = Self {
+        history: [PortEvent::NONE; PORT_HISTORY_DEPTH],
+        next: 0,
+        transitions: [0; FLAP_THRESHOLD],
+        transitions_next: 0,
+        transitions_seen: 0,
+        link_up: false,
+        link_events: [0; 3],
+        flapping: false,
+    };
+
+    pub fn record(&mut self, event: PortEvent) {
+        self.history[self.next] = event;
+        self.next = (self.next + 1) % PORT_HISTORY_DEPTH;
+    }
+
+    /// Return the recorded events, oldest first. Unused slots are zeroed and
+    /// as such come first.
+    pub fn history(&self) -> [PortEvent; PORT_HISTORY_DEPTH] {
+        core::array::from_fn(|i| {
+            self.history[(self.next + i) % PORT_HISTORY_DEPTH]
+        })
+    }
+
+    pub fn clear_history(&mut self) {
+        self.history = [PortEvent::NONE; PORT_HISTORY_DEPTH];
+        self.next = 0;
+    }
+
+    /// Record a presence or link transition, returning true if this caused
+    /// the port to be considered flapping.
+    pub fn transition(&mut self, now: u64, kind: PortEventKind) -> bool {
+        self.record(PortEvent::new(now, kind));
+
+        self.transitions[self.transitions_next] = now;
+        self.transitions_next = (self.transitions_next + 1) % FLAP_THRESHOLD;
+        self.transitions_seen = (self.transitions_seen + 1).min(FLAP_THRESHOLD);
+
+        // With the ring full, the slot to be written next holds the oldest of
+        // the last `FLAP_THRESHOLD` transitions.
+        let oldest = self.transitions[self.transitions_next];
+
+        if !self.flapping
+            && self.transitions_seen == FLAP_THRESHOLD
+            && now - oldest <= FLAP_WINDOW_MS
+        {
+            self.flapping = true;
+            self.record(PortEvent::new(now, PortEventKind::FlapDetected));
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Clear the flapping state if there have been no transitions for a full
+    /// window, returning true if it was cleared.
+    pub fn check_stable(&mut self, now: u64) -> bool {
+        let newest = self.transitions
+            [(self.transitions_next + FLAP_THRESHOLD - 1) % FLAP_THRESHOLD];
+
+        if self.flapping && now - newest > FLAP_WINDOW_MS {
+            self.flapping = false;
+            self.record(PortEvent::new(now, PortEventKind::FlapCleared));
+            true
+        } else {
+            false
+        }
+    }
+}
@@ -9,9 +9,12 @@
 
 use drv_ignition_api::*;
 use drv_sidecar_mainboard_controller::ignition::*;
+use history::PortTracker;
 use ringbuf::*;
 use userlib::*;
 
+mod history;
+
 task_slot!(FPGA, fpga);
 #[cfg(feature = "sequencer")]
 task_slot!(SEQUENCER, sequencer);
@@ -29,8 +32,13 @@ enum Trace {
     TargetDepart(u8),
     SystemPowerRequest(u8, Request),
     SystemPowerRequestError(u8, IgnitionError),
+    LinkUp(u8),
+    LinkDown(u8),
+    TransceiverError(u8, TransceiverSelect, u8),
+    FlapDetected(u8),
+    FlapCleared(u8),
 }
-ringbuf!(Trace, 16, Trace::None);
+ringbuf!(Trace, 32, Trace::None);
 
 const TIMER_INTERVAL: u64 = 1000;
 
@@ -41,6 +49,7 @@ fn main() -> ! {
         controller: IgnitionController::new(FPGA.get_task_id()),
         port_count: 0,
         last_presence_summary: 0,
+        ports: claim_ports(),
     };
 
     // This task is expected to run in an environment where a sequencer is
@@ -92,6 +101,7 @@ struct ServerImpl {
     controller: IgnitionController,
     port_count: u8,
     last_presence_summary: u64,
+    ports: &'static mut [PortTracker; PORT_MAX as usize],
 }
 
 impl ServerImpl {
@@ -107,7 +117,9 @@ impl ServerImpl {
     }
 
     /// Poll the presence summary and track Targets arriving and departing.
-    fn poll_presence(&mut self) -> Result<(), IgnitionError> {
+    /// Returns a bit vector of ports which started flapping.
+    fn poll_presence(&mut self, now: u64) -> Result<u64, IgnitionError> {
+        let mut flapping = 0;
         let current_presence_summary = self.controller.presence_summary()?;
 
         if current_presence_summary != self.last_presence_summary {
@@ -128,9 +140,109 @@ impl ServerImpl {
                 | (self.last_presence_summary & !departed_targets);
 
             ringbuf_entry!(Trace::PresenceUpdate(self.last_presence_summary));
+
+            for port in 0..self.port_count.min(PORT_MAX) {
+                let mask = 1 << port;
+                let tracker = &mut self.ports[port as usize];
+
+                let flapped = if arrived_targets & mask != 0 {
+                    tracker.transition(now, PortEventKind::TargetArrive)
+                } else if departed_targets & mask != 0 {
+                    tracker.transition(now, PortEventKind::TargetDepart)
+                } else {
+                    false
+                };
+
+                if flapped {
+                    flapping |= mask;
+                }
+            }
         }
 
-        Ok(())
+        Ok(flapping)
+    }
+
+    /// Poll the link state and transceiver events of each port, recording
+    /// any changes in the history of the port. Returns a bit vector of ports
+    /// which started flapping.
+    fn poll_links(&mut self, now: u64) -> u64 {
+        let mut flapping = 0;
+
+        for port in 0..self.port_count.min(PORT_MAX) {
+            let mask = 1 << port;
+
+            match self.poll_link(port, now) {
+                Ok(true) => flapping |= mask,
+                Ok(false) => (),
+                Err(e) => ringbuf_entry!(Trace::TargetError(port, e)),
+            }
+
+            if self.ports[port as usize].check_stable(now) {
+                ringbuf_entry!(Trace::FlapCleared(port));
+            }
+        }
+
+        flapping
+    }
+
+    fn poll_link(&mut self, port: u8, now: u64) -> Result<bool, IgnitionError> {
+        let state = Port::from(self.controller.port_state(port)?);
+        let link_up =
+            state.receiver_status.aligned && state.receiver_status.locked;
+        let present = self.last_presence_summary & (1 << port) != 0;
+
+        // Transceiver events are only meaningful while a Target is present.
+        // They are read but never cleared here, leaving that to whoever
+        // consumes them; only events not seen on the previous poll are
+        // recorded.
+        let mut link_events = [0u8; 3];
+        if present {
+            for (i, txr) in TransceiverSelect::ALL.into_iter().enumerate() {
+                link_events[i] =
+                    self.controller.transceiver_events(port, txr)?;
+            }
+        }
+
+        let tracker = &mut self.ports[port as usize];
+
+        for (i, txr) in TransceiverSelect::ALL.into_iter().enumerate() {
+            let new = link_events[i] & !tracker.link_events[i];
+
+            if new != 0 {
+                ringbuf_entry!(Trace::TransceiverError(port, txr, new));
+                tracker.record(PortEvent::transceiver_error(now, txr, new));
+            }
+        }
+        tracker.link_events = link_events;
+
+        if link_up == tracker.link_up {
+            return Ok(false);
+        }
+        tracker.link_up = link_up;
+
+        if link_up {
+            ringbuf_entry!(Trace::LinkUp(port));
+            Ok(tracker.transition(now, PortEventKind::LinkUp))
+        } else {
+            ringbuf_entry!(Trace::LinkDown(port));
+            Ok(tracker.transition(now, PortEventKind::LinkDown))
+        }
+    }
+
+    /// Let subscribers know that the ports in `flapping` started flapping.
+    fn notify_flapping(&self, flapping: u64) {
+        for port in 0..self.port_count.min(PORT_MAX) {
+            if flapping & (1 << port) != 0 {
+                ringbuf_entry!(Trace::FlapDetected(port));
+            }
+        }
+
+        for (task, mask) in config::FLAP_SUBSCRIBERS {
+            let taskid =
+                TaskId::for_index_and_gen(task as usize, Generation::ZERO);
+            let taskid = sys_refresh_task_id(taskid);
+            sys_post(taskid, mask);
+        }
     }
 
     /// Apply the given function to each port for which a bit in the `ports`
@@ -413,6 +525,46 @@ impl idl::InOrderIgnitionImpl for ServerImpl {
 
         Ok(all_link_events)
     }
+
+    fn port_history(
+        &mut self,
+        _: &userlib::RecvMessage,
+        port: u8,
+    ) -> Result<[PortEvent; PORT_HISTORY_DEPTH], RequestError> {
+        if port >= self.port_count.min(PORT_MAX) {
+            return Err(RequestError::from(IgnitionError::InvalidPort));
+        }
+
+        Ok(self.ports[port as usize].history())
+    }
+
+    fn clear_port_history(
+        &mut self,
+        _: &userlib::RecvMessage,
+        port: u8,
+    ) -> Result<(), RequestError> {
+        if port >= self.port_count.min(PORT_MAX) {
+            return Err(RequestError::from(IgnitionError::InvalidPort));
+        }
+
+        self.ports[port as usize].clear_history();
+        Ok(())
+    }
+
+    fn flapping_ports(
+        &mut self,
+        _: &userlib::RecvMessage,
+    ) -> Result<u64, RequestError> {
+        let mut flapping = 0;
+
+        for port in 0..self.port_count.min(PORT_MAX) {
+            if self.ports[port as usize].flapping {
+                flapping |= 1 << port;
+            }
+        }
+
+        Ok(flapping)
+    }
 }
 
 impl idol_runtime::NotificationHandler for ServerImpl {
@@ -427,8 +579,16 @@ impl idol_runtime::NotificationHandler for ServerImpl {
         // count of 0xff may occur if the FPGA is running an incorrect
         // bitstream.
         if self.port_count > 0 && self.port_count != 0xff {
-            if let Err(e) = self.poll_presence() {
-                ringbuf_entry!(Trace::PresencePollError(e));
+            let flapping = match self.poll_presence(start) {
+                Ok(flapping) => flapping,
+                Err(e) => {
+                    ringbuf_entry!(Trace::PresencePollError(e));
+                    0
+                }
+            } | self.poll_links(start);
+
+            if flapping != 0 {
+                self.notify_flapping(flapping);
             }
         }
 
@@ -448,6 +608,19 @@ impl idol_runtime::NotificationHandler for ServerImpl {
     }
 }
 
+/// Grabs a reference to the static per-port history and flap detection
+/// state. Can only be called once.
+fn claim_ports() -> &'static mut [PortTracker; PORT_MAX as usize] {
+    const N: usize = PORT_MAX as usize;
+    mutable_statics::mutable_statics! {
+        static mut PORTS: [PortTracker; N] = [|| PortTracker::EMPTY; _];
+    }
+}
+
+mod config {
+    include!(concat!(env!("OUT_DIR"), "/ignition_config.rs"));
+}
+
 mod idl {
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
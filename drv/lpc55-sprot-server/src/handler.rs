@@ -7,10 +7,11 @@ use attest_api::Attest;
 use crc::{Crc, CRC_32_CKSUM};
 use drv_lpc55_update_api::{RotPage, SlotId, Update};
 use drv_sprot_api::{
-    AttestReq, AttestRsp, CabooseReq, CabooseRsp, DumpReq, DumpRsp, ReqBody,
-    Request, Response, RotIoStats, RotPageRsp, RotState, RotStatus, RspBody,
-    SprocketsError, SprotError, SprotProtocolError, UpdateReq, UpdateRsp,
-    CURRENT_VERSION, MIN_VERSION, REQUEST_BUF_SIZE, RESPONSE_BUF_SIZE,
+    AttestReq, AttestRsp, CabooseReq, CabooseRsp, DumpReq, DumpRsp,
+    RecordEvent, ReqBody, Request, Response, RotIoStats, RotPageRsp, RotState,
+    RotStatus, RspBody, SprocketsError, SprotError, SprotProtocolError,
+    UpdateReq, UpdateRsp, CURRENT_VERSION, MIN_VERSION, REQUEST_BUF_SIZE,
+    RESPONSE_BUF_SIZE,
 };
 use dumper_api::Dumper;
 use lpc55_romapi::bootrom;
@@ -47,6 +48,7 @@ pub enum TrailingData {
     Caboose { slot: SlotId, start: u32, size: u32 },
     AttestCert { index: u32, offset: u32, size: u32 },
     AttestLog { offset: u32, size: u32 },
+    Attest { nonce: [u8; attest_api::NONCE_SIZE] },
     RotPage { page: RotPage },
 }
 
@@ -198,6 +200,18 @@ impl Handler {
                     }
                 }
             }
+            Some(TrailingData::Attest { nonce }) => {
+                match Response::pack_with_cb(&rsp_body, tx_buf, |buf| {
+                    let size = attest_api::ATTESTATION_SIZE;
+                    self.attest
+                        .attest(&nonce, &mut buf[..size])
+                        .map_err(|e| RspBody::Attest(Err(e)))?;
+                    Ok(size)
+                }) {
+                    Ok(size) => size,
+                    Err(e) => Response::pack(&Ok(e), tx_buf),
+                }
+            }
             _ => Response::pack(&rsp_body, tx_buf),
         }
     }
@@ -346,6 +360,24 @@ impl Handler {
                 };
                 Ok((RspBody::Attest(rsp), None))
             }
+            ReqBody::Attest(AttestReq::RecordEvent(RecordEvent {
+                algorithm,
+                event_type,
+                tag_len,
+                tag,
+            })) => {
+                let tag = tag
+                    .get(..tag_len as usize)
+                    .ok_or(SprotProtocolError::BadMessageLength)?;
+                let rsp = match self
+                    .attest
+                    .record_event(algorithm, event_type, req.blob, tag)
+                {
+                    Ok(()) => Ok(AttestRsp::Record),
+                    Err(e) => Err(e),
+                };
+                Ok((RspBody::Attest(rsp), None))
+            }
             ReqBody::RotPage { page } => {
                 // This command returns a variable amount of data that belongs
                 // in the trailing data region of the response. We return a
@@ -367,6 +399,10 @@ impl Handler {
                 };
                 Ok((RspBody::Attest(rsp), None))
             }
+            ReqBody::Attest(AttestReq::Attest { nonce }) => Ok((
+                RspBody::Attest(Ok(AttestRsp::Attest)),
+                Some(TrailingData::Attest { nonce }),
+            )),
         }
     }
 }
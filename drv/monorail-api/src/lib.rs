@@ -61,6 +61,77 @@ pub struct PortCounters {
     pub phy_link_down_sticky: bool,
 }
 
+/// Window over which [PortRates] are computed
+#[derive(
+    Copy, Clone, Debug, Serialize, SerializedSize, Deserialize, Eq, PartialEq,
+)]
+pub enum RateWindow {
+    /// The last 10 seconds, in 1 second samples
+    Short,
+    /// The last 60 seconds, in 10 second samples
+    Long,
+}
+
+/// Per-second rates computed from the periodically sampled counters of a
+/// port's MAC.
+///
+/// There is no drop rate: frames dropped by the switch core are counted in
+/// the VSC7448's `SYS:STAT` block, and reading it has not been verified
+/// against the PAC or the datasheet.
+#[derive(
+    Copy, Clone, Debug, Default, Serialize, SerializedSize, Deserialize,
+)]
+pub struct PortRates {
+    /// Length of time that the rates were actually computed over, which may be
+    /// shorter than the requested window if the port was only recently
+    /// sampled for the first time.  If this is zero, the rates are meaningless.
+    pub window_ms: u32,
+
+    /// Received packets (unicast, multicast, and broadcast) per second
+    pub rx_packets: f32,
+    /// Transmitted packets (unicast, multicast, and broadcast) per second
+    pub tx_packets: f32,
+    /// Received frames with a bad FCS per second
+    pub rx_crc_errors: f32,
+    /// Received frames discarded by the MAC as malformed (undersize,
+    /// fragments, jabbers, and oversize) per second
+    pub rx_malformed: f32,
+
+    /// Alarms currently raised for this port
+    pub alarms: PortAlarms,
+}
+
+/// Alarms raised when a port's error rates exceed [AlarmThresholds]
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    Serialize,
+    SerializedSize,
+    Deserialize,
+    Eq,
+    PartialEq,
+)]
+pub struct PortAlarms {
+    pub rx_crc_errors: bool,
+    pub rx_malformed: bool,
+}
+
+impl PortAlarms {
+    pub fn any(&self) -> bool {
+        self.rx_crc_errors || self.rx_malformed
+    }
+}
+
+/// Per-second error rates above which a port's alarms are raised.  These are
+/// evaluated over [RateWindow::Short] each time the counters are sampled.
+#[derive(Copy, Clone, Debug, Serialize, SerializedSize, Deserialize)]
+pub struct AlarmThresholds {
+    pub rx_crc_errors: f32,
+    pub rx_malformed: f32,
+}
+
 /// Error-code-only version of [VscError], for use in RPC calls
 #[derive(
     Copy, Clone, Debug, Eq, PartialEq, FromPrimitive, ToPrimitive, IdolError,
@@ -5,14 +5,28 @@
 //! An I2C device emulator for Sidecar Mainboard, intended to convince the
 //! sequencer task it is running on a Sidecar Mainboard rather than say a
 //! Gimletlet.
+//!
+//! The emulated devices are served to a real bus by the I2C target server,
+//! for which this task is a handler; on a Gimletlet, the sequencer reaches
+//! them by wiring the `i2c_driver` controller it uses to the target
+//! server's controller.  Each device is a map of 256 registers that read
+//! back whatever was last written to them, and are initially zero.
 
 #![no_std]
 #![no_main]
 
-use drv_i2c_api::*;
+use drv_i2c_target_api::I2cTargetError;
+use idol_runtime::RequestError;
 use ringbuf::*;
 use userlib::*;
 
+/// Addresses of the emulated devices, which match `config.i2c.devices` in
+/// `app-sidecar-emulator.toml`
+const DEVICES: [u8; 2] = [
+    0b1100_011, // TF2 VDD rail (RAA229618)
+    0b1011_000, // Clock generator (IDT8A34001)
+];
+
 #[derive(Copy, Clone, PartialEq)]
 enum Trace {
     None,
@@ -22,51 +36,70 @@ ringbuf!(Trace, 16, Trace::None);
 
 #[export_name = "main"]
 fn main() -> ! {
-    let mut buffer = [0; 4];
+    let regs = mutable_statics::mutable_statics! {
+        static mut REGS: [[u8; 256]; DEVICES.len()] = [|| [0; 256]; _];
+    };
+    let mut server = ServerImpl { regs };
+    let mut buffer = [0; idl::INCOMING_SIZE];
 
     loop {
-        hl::recv_without_notification(&mut buffer, |op, msg| match op {
-            Op::WriteRead | Op::WriteReadBlock => {
-                let (payload, caller) = msg
-                    .fixed_with_leases::<[u8; 4], usize>(2)
-                    .ok_or(ResponseCode::BadArg)?;
-
-                let (addr, _, _, _) = Marshal::unmarshal(payload)?;
-
-                if let Some(_) = ReservedAddress::from_u8(addr) {
-                    return Err(ResponseCode::ReservedAddress);
-                }
+        idol_runtime::dispatch(&mut buffer, &mut server);
+    }
+}
 
-                ringbuf_entry!(Trace::Addr(addr));
+struct ServerImpl {
+    regs: &'static mut [[u8; 256]; DEVICES.len()],
+}
 
-                let wbuf = caller.borrow(0);
-                let winfo = wbuf.info().ok_or(ResponseCode::BadArg)?;
+impl ServerImpl {
+    fn device(
+        &mut self,
+        address: u8,
+    ) -> Result<&mut [u8; 256], RequestError<I2cTargetError>> {
+        let i = DEVICES
+            .iter()
+            .position(|&a| a == address)
+            .ok_or(I2cTargetError::Unavailable)?;
+        Ok(&mut self.regs[i])
+    }
+}
 
-                if !winfo.attributes.contains(LeaseAttributes::READ) {
-                    return Err(ResponseCode::BadArg);
-                }
+impl idl::InOrderI2cTargetHandlerImpl for ServerImpl {
+    fn responds(
+        &mut self,
+        _: &RecvMessage,
+        address: u8,
+    ) -> Result<bool, RequestError<I2cTargetError>> {
+        let rval = DEVICES.contains(&address);
+        if rval {
+            ringbuf_entry!(Trace::Addr(address));
+        }
+        Ok(rval)
+    }
 
-                let rbuf = caller.borrow(1);
-                let rinfo = rbuf.info().ok_or(ResponseCode::BadArg)?;
+    fn read_register(
+        &mut self,
+        _: &RecvMessage,
+        address: u8,
+        register: u8,
+    ) -> Result<u8, RequestError<I2cTargetError>> {
+        Ok(self.device(address)?[register as usize])
+    }
 
-                if winfo.len == 0 && rinfo.len == 0 {
-                    // We must have either a write OR a read -- while perhaps
-                    // valid to support both being zero as a way of testing an
-                    // address for a NACK, it's not a mode that we (currently)
-                    // support.
-                    return Err(ResponseCode::BadArg);
-                }
+    fn write_register(
+        &mut self,
+        _: &RecvMessage,
+        address: u8,
+        register: u8,
+        value: u8,
+    ) -> Result<(), RequestError<I2cTargetError>> {
+        self.device(address)?[register as usize] = value;
+        Ok(())
+    }
+}
 
-                if winfo.len > 255 || rinfo.len > 255 {
-                    // For now, we don't support writing or reading more than
-                    // 255 bytes.
-                    return Err(ResponseCode::BadArg);
-                }
+mod idl {
+    use drv_i2c_target_api::I2cTargetError;
 
-                // Send an empty reply back to the caller.
-                caller.reply(0);
-                Ok(())
-            }
-        });
-    }
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
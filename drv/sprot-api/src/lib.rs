@@ -47,7 +47,7 @@ pub const MIN_VERSION: Version = Version(2);
 /// Code between the `CURRENT_VERSION` and `MIN_VERSION` must remain
 /// compatible. Use the rules described in the comments for [`Msg`] to evolve
 /// the protocol such that this remains true.
-pub const CURRENT_VERSION: Version = Version(4);
+pub const CURRENT_VERSION: Version = Version(5);
 
 /// We allow room in the buffer for message evolution
 pub const REQUEST_BUF_SIZE: usize = 1024;
@@ -387,6 +387,21 @@ pub enum AttestReq {
     Record { algorithm: HashAlgorithm },
     Log { offset: u32, size: u32 },
     LogLen,
+    // Added in sprot protocol version 5
+    Attest { nonce: [u8; attest_api::NONCE_SIZE] },
+    // Added in sprot protocol version 5
+    RecordEvent(RecordEvent),
+}
+
+/// An event for the attestation log, whose digest is the request's blob
+//
+// Added in sprot protocol version 5
+#[derive(Clone, Serialize, Deserialize, SerializedSize)]
+pub struct RecordEvent {
+    pub algorithm: HashAlgorithm,
+    pub event_type: u32,
+    pub tag_len: u8,
+    pub tag: [u8; attest_api::EVENT_TAG_MAX_SIZE],
 }
 
 /// A response used for RoT updates
@@ -414,6 +429,8 @@ pub enum AttestRsp {
     Record,
     Log,
     LogLen(u32),
+    // Added in sprot protocol version 5
+    Attest,
 }
 
 /// The body of a sprot response.
@@ -6,7 +6,10 @@
 #![no_main]
 #![deny(elided_lifetimes_in_paths)]
 
-use attest_api::HashAlgorithm;
+use attest_api::{
+    AttestError, HashAlgorithm, ATTESTATION_SIZE, EVENT_TAG_MAX_SIZE,
+    NONCE_SIZE,
+};
 use core::convert::Into;
 use drv_lpc55_update_api::{
     RotBootInfo, RotPage, SlotId, SwitchDuration, UpdateTarget,
@@ -41,6 +44,9 @@ cfg_if::cfg_if! {
 
 task_slot!(SYS, sys);
 
+#[cfg(feature = "host-alerts")]
+task_slot!(PACKRAT, packrat);
+
 #[derive(Copy, Clone, PartialEq)]
 enum Trace {
     None,
@@ -97,6 +103,12 @@ const MAX_UPDATE_ATTEMPTS: u16 = 3;
 // On the flipside, we have learned via unintended experiment that 5ms is too short!
 const DUMP_TIMEOUT: u32 = 1000;
 
+// Time to wait for an attestation
+//
+// The RoT hashes the whole measurement log and makes an Ed25519 signature
+// before replying, which takes well beyond TIMEOUT_MEDIUM.
+const ATTEST_TIMEOUT: u32 = 500;
+
 // ROT_IRQ comes from app.toml
 // We use spi3 on gimletlet and spi4 on gemini and gimlet.
 // You should be able to move the RoT board between SPI3, SPI4, and SPI6
@@ -695,6 +707,7 @@ impl<S: SpiServer> idl::InOrderSpRotImpl for ServerImpl<S> {
             DEFAULT_ATTEMPTS,
         )?;
         if let RspBody::Ok = rsp.body? {
+            raise_rot_update_pending(slot);
             Ok(())
         } else {
             Err(SprotProtocolError::UnexpectedResponse)?
@@ -903,6 +916,53 @@ impl<S: SpiServer> idl::InOrderSpRotImpl for ServerImpl<S> {
 
         match rsp.body {
             Ok(RspBody::Attest(Ok(AttestRsp::Record))) => Ok(()),
+            Ok(RspBody::Attest(Err(e))) => {
+                Err(AttestOrSprotError::Attest(e).into())
+            }
+            Ok(_) => Err(AttestOrSprotError::Sprot(SprotError::Protocol(
+                SprotProtocolError::UnexpectedResponse,
+            ))
+            .into()),
+            Err(e) => Err(AttestOrSprotError::Sprot(e).into()),
+        }
+    }
+
+    fn record_event(
+        &mut self,
+        _: &userlib::RecvMessage,
+        algorithm: HashAlgorithm,
+        event_type: u32,
+        data: idol_runtime::LenLimit<
+            idol_runtime::Leased<idol_runtime::R, [u8]>,
+            MAX_BLOB_SIZE,
+        >,
+        tag: idol_runtime::LenLimit<
+            idol_runtime::Leased<idol_runtime::R, [u8]>,
+            EVENT_TAG_MAX_SIZE,
+        >,
+    ) -> Result<(), idol_runtime::RequestError<AttestOrSprotError>> {
+        let mut buf = [0u8; EVENT_TAG_MAX_SIZE];
+        tag.read_range(0..tag.len(), &mut buf[..tag.len()])
+            .map_err(|()| {
+                idol_runtime::RequestError::Fail(
+                    idol_runtime::ClientError::WentAway,
+                )
+            })?;
+
+        let body = ReqBody::Attest(AttestReq::RecordEvent(RecordEvent {
+            algorithm,
+            event_type,
+            tag_len: tag.len() as u8,
+            tag: buf,
+        }));
+        let tx_size = Request::pack_with_blob(&body, self.tx_buf, data)?;
+        let rsp = self.do_send_recv_retries(tx_size, TIMEOUT_QUICK, 1)?;
+
+        match rsp.body {
+            Ok(RspBody::Attest(Ok(AttestRsp::Record))) => Ok(()),
+            Ok(RspBody::Attest(Err(e))) => {
+                Err(AttestOrSprotError::Attest(e).into())
+            }
             Ok(_) => Err(AttestOrSprotError::Sprot(SprotError::Protocol(
                 SprotProtocolError::UnexpectedResponse,
             ))
@@ -1009,8 +1069,82 @@ impl<S: SpiServer> idl::InOrderSpRotImpl for ServerImpl<S> {
             Err(e) => Err(AttestOrSprotError::Sprot(e).into()),
         }
     }
+
+    fn attest(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        nonce: idol_runtime::LenLimit<
+            idol_runtime::Leased<idol_runtime::R, [u8]>,
+            NONCE_SIZE,
+        >,
+        dest: idol_runtime::Leased<idol_runtime::W, [u8]>,
+    ) -> Result<(), idol_runtime::RequestError<AttestOrSprotError>> {
+        if nonce.len() != NONCE_SIZE || dest.len() != ATTESTATION_SIZE {
+            return Err(
+                AttestOrSprotError::Attest(AttestError::BadLease).into()
+            );
+        }
+        let mut buf = [0u8; NONCE_SIZE];
+        nonce.read_range(0..NONCE_SIZE, &mut buf).map_err(|()| {
+            idol_runtime::RequestError::Fail(
+                idol_runtime::ClientError::WentAway,
+            )
+        })?;
+
+        let body = ReqBody::Attest(AttestReq::Attest { nonce: buf });
+        let tx_size = Request::pack(&body, self.tx_buf);
+        let rsp = self.do_send_recv_retries(
+            tx_size,
+            ATTEST_TIMEOUT,
+            DEFAULT_ATTEMPTS,
+        )?;
+
+        match rsp.body {
+            Ok(RspBody::Attest(Ok(AttestRsp::Attest))) => {
+                // Copy the signature from the trailing data into the lease
+                if rsp.blob.len() < ATTESTATION_SIZE {
+                    return Err(AttestOrSprotError::Sprot(
+                        SprotError::Protocol(
+                            SprotProtocolError::BadMessageLength,
+                        ),
+                    )
+                    .into());
+                }
+                dest.write_range(
+                    0..ATTESTATION_SIZE,
+                    &rsp.blob[..ATTESTATION_SIZE],
+                )
+                .map_err(|()| {
+                    idol_runtime::RequestError::Fail(
+                        idol_runtime::ClientError::WentAway,
+                    )
+                })?;
+                Ok(())
+            }
+            Ok(RspBody::Attest(Err(e))) => {
+                Err(AttestOrSprotError::Attest(e).into())
+            }
+            Ok(RspBody::Attest(_)) | Ok(_) => Err(AttestOrSprotError::Sprot(
+                SprotError::Protocol(SprotProtocolError::UnexpectedResponse),
+            )
+            .into()),
+            Err(e) => Err(AttestOrSprotError::Sprot(e).into()),
+        }
+    }
+}
+
+/// Tells the host that the RoT will boot a different image (in `slot`) when
+/// it is next reset.
+#[cfg(feature = "host-alerts")]
+fn raise_rot_update_pending(slot: SlotId) {
+    use task_packrat_api::{HostAlertKind, Packrat};
+    let packrat = Packrat::from(PACKRAT.get_task_id());
+    packrat.raise_host_alert(HostAlertKind::RotUpdatePending as u8, slot as u8);
 }
 
+#[cfg(not(feature = "host-alerts"))]
+fn raise_rot_update_pending(_slot: SlotId) {}
+
 mod idl {
     use super::{
         AttestOrSprotError, DumpOrSprotError, HashAlgorithm, PulseStatus,
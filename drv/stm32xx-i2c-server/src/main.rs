@@ -247,9 +247,11 @@ fn reset(
     port: PortIndex,
     muxes: &[I2cMux<'_>],
     muxmap: &mut MuxMap,
+    stats: &mut Stats,
 ) {
     let bus = (controller.controller, port);
     ringbuf_entry!(Trace::Reset(bus));
+    stats.update(bus, None, |s| s.resets = s.resets.saturating_add(1));
 
     let sys = SYS.get_task_id();
     let sys = Sys::from(sys);
@@ -290,9 +292,10 @@ fn reset_if_needed(
     port: PortIndex,
     muxes: &[I2cMux<'_>],
     muxmap: &mut MuxMap,
+    stats: &mut Stats,
 ) {
     if reset_needed(code) {
-        reset(controller, port, muxes, muxmap)
+        reset(controller, port, muxes, muxmap, stats)
     }
 }
 
@@ -318,6 +321,154 @@ enum MuxState {
 type MuxMap =
     FixedMap<(Controller, PortIndex), MuxState, { i2c_config::NMUXEDBUSES }>;
 
+///
+/// Most devices never see an error, so rather than keeping counters for
+/// every device, we keep them for (at most) this many devices, in the order
+/// in which they first fail.  Errors on other devices are still counted
+/// against their bus and segment.  Probing for a device that isn't there
+/// fails with `NoDevice`, which doesn't claim one of these (see
+/// `Stats::record_error`).
+///
+const MAX_DEVICE_STATS: usize = 8;
+
+const NDEVICESTATS: usize = if i2c_config::NDEVICES < MAX_DEVICE_STATS {
+    i2c_config::NDEVICES
+} else {
+    MAX_DEVICE_STATS
+};
+
+///
+/// A table of [`I2cStats`] with room for `N` keys, with entries created on
+/// first use.
+///
+struct StatsTable<K, const N: usize> {
+    entries: [Option<(K, I2cStats)>; N],
+}
+
+impl<K: Copy + PartialEq, const N: usize> StatsTable<K, N> {
+    fn new() -> Self {
+        Self { entries: [None; N] }
+    }
+
+    fn get_mut(&mut self, key: K) -> Option<&mut I2cStats> {
+        self.entries
+            .iter_mut()
+            .flatten()
+            .find(|(k, _)| *k == key)
+            .map(|(_, stats)| stats)
+    }
+
+    fn get(&self, key: K) -> I2cStats {
+        self.entries
+            .iter()
+            .flatten()
+            .find(|(k, _)| *k == key)
+            .map(|(_, stats)| *stats)
+            .unwrap_or_default()
+    }
+
+    ///
+    /// Returns the counters for `key`, creating them if needed -- or `None`
+    /// if the table is full.
+    ///
+    fn entry(&mut self, key: K) -> Option<&mut I2cStats> {
+        let index = self
+            .entries
+            .iter()
+            .position(|e| matches!(e, Some((k, _)) if *k == key))
+            .or_else(|| self.entries.iter().position(|e| e.is_none()))?;
+
+        let (_, stats) =
+            self.entries[index].get_or_insert((key, I2cStats::default()));
+        Some(stats)
+    }
+}
+
+type Bus = (Controller, PortIndex);
+
+///
+/// Error and recovery counters for every bus, every mux segment that has a
+/// device on it, and some devices.
+///
+struct Stats {
+    buses: StatsTable<Bus, { i2c_config::NPORTS }>,
+    segments: StatsTable<(Bus, Mux, Segment), { i2c_config::NSEGMENTS }>,
+    devices: StatsTable<(Bus, Option<(Mux, Segment)>, u8), NDEVICESTATS>,
+}
+
+impl Stats {
+    fn new() -> Self {
+        Self {
+            buses: StatsTable::new(),
+            segments: StatsTable::new(),
+            devices: StatsTable::new(),
+        }
+    }
+
+    ///
+    /// Calls `func` on the counters for the bus and segment (if any).
+    ///
+    fn update(
+        &mut self,
+        bus: Bus,
+        mux: Option<(Mux, Segment)>,
+        func: impl Fn(&mut I2cStats),
+    ) {
+        if let Some(stats) = self.buses.entry(bus) {
+            func(stats);
+        }
+
+        if let Some((mux, segment)) = mux {
+            if let Some(stats) = self.segments.entry((bus, mux, segment)) {
+                func(stats);
+            }
+        }
+    }
+
+    ///
+    /// Records `code` against the bus, segment (if any) and device.  A
+    /// `NoDevice` error is only counted against a device that already has
+    /// counters: otherwise, scanning a bus would fill the table with
+    /// addresses that have nothing on them.
+    ///
+    fn record_error(
+        &mut self,
+        bus: Bus,
+        mux: Option<(Mux, Segment)>,
+        addr: u8,
+        code: ResponseCode,
+    ) {
+        self.update(bus, mux, |s| s.record(code));
+
+        let key = (bus, mux, addr);
+        let stats = if code == ResponseCode::NoDevice {
+            self.devices.get_mut(key)
+        } else {
+            self.devices.entry(key)
+        };
+
+        if let Some(stats) = stats {
+            stats.record(code);
+        }
+    }
+
+    fn report(
+        &self,
+        bus: Bus,
+        mux: Option<(Mux, Segment)>,
+        addr: u8,
+    ) -> I2cStatsReport {
+        I2cStatsReport {
+            bus: self.buses.get(bus),
+            segment: match mux {
+                Some((mux, segment)) => self.segments.get((bus, mux, segment)),
+                None => I2cStats::default(),
+            },
+            device: self.devices.get((bus, mux, addr)),
+        }
+    }
+}
+
 #[export_name = "main"]
 fn main() -> ! {
     let controllers = i2c_config::controllers();
@@ -327,10 +478,11 @@ fn main() -> ! {
     // This is our actual mutable state
     let mut portmap = PortMap::default();
     let mut muxmap = MuxMap::default();
+    let mut stats = Stats::new();
 
     // Turn the actual peripheral on so that we can interact with it.
     turn_on_i2c(&controllers);
-    configure_pins(&controllers, &pins, &mut portmap);
+    configure_pins(&controllers, &pins, &mut portmap, &mut stats);
     configure_controllers(&controllers);
 
     // Field messages.
@@ -352,11 +504,15 @@ fn main() -> ! {
         &mut portmap,
         &mut muxmap,
         &ctrl,
+        &mut stats,
     );
 
     loop {
         hl::recv_without_notification(&mut buffer, |op, msg| match op {
-            Op::WriteRead | Op::WriteReadBlock => {
+            Op::WriteRead
+            | Op::WriteReadBlock
+            | Op::WriteReadPec
+            | Op::WriteReadBlockPec => {
                 let lease_count = msg.lease_count();
 
                 let (payload, caller) = msg
@@ -390,12 +546,16 @@ fn main() -> ! {
                     Ok(_) => {}
                     Err(code) => {
                         ringbuf_entry!(Trace::MuxError(code.into()));
+                        stats.update((controller.controller, port), mux, |s| {
+                            s.mux_errors = s.mux_errors.saturating_add(1)
+                        });
                         reset_if_needed(
                             code,
                             controller,
                             port,
                             &muxes,
                             &mut muxmap,
+                            &mut stats,
                         );
                         return Err(code);
                     }
@@ -426,21 +586,23 @@ fn main() -> ! {
                         return Err(ResponseCode::BadArg);
                     }
 
-                    if winfo.len > 255 || rinfo.len > 255 {
-                        // For now, we don't support writing or reading more
-                        // than 255 bytes.
+                    // For now, we don't support writing or reading more
+                    // than 255 bytes -- including any PEC byte.
+                    let max = if op.has_pec() { 254 } else { 255 };
+
+                    if winfo.len > max || rinfo.len > max {
                         return Err(ResponseCode::BadArg);
                     }
 
                     let mut nread = 0;
 
-                    match controller.write_read(
+                    match controller.write_read_pec(
                         addr,
                         winfo.len,
                         |pos| wbuf.read_at(pos),
                         // Only the final read operation in a WriteReadBlock is
                         // a block read; everything else is a normal read.
-                        if op == Op::WriteReadBlock && i == lease_count - 2 {
+                        if op.is_block() && i == lease_count - 2 {
                             ReadLength::Variable
                         } else {
                             ReadLength::Fixed(rinfo.len)
@@ -452,6 +614,7 @@ fn main() -> ! {
 
                             rbuf.write_at(pos, byte)
                         },
+                        op.has_pec(),
                         &ctrl,
                     ) {
                         Err(code) => {
@@ -469,12 +632,20 @@ fn main() -> ! {
                                 }
                             }
 
+                            stats.record_error(
+                                (controller.controller, port),
+                                mux,
+                                addr,
+                                code,
+                            );
+
                             reset_if_needed(
                                 code,
                                 controller,
                                 port,
                                 &muxes,
                                 &mut muxmap,
+                                &mut stats,
                             );
                             return Err(code);
                         }
@@ -487,6 +658,25 @@ fn main() -> ! {
                 caller.reply(total);
                 Ok(())
             }
+
+            Op::Stats => {
+                let (payload, caller) = msg
+                    .fixed_with_leases::<[u8; 4], I2cStatsReport>(0)
+                    .ok_or(ResponseCode::BadArg)?;
+
+                let (addr, controller, port, mux) =
+                    Marshal::unmarshal(payload)?;
+
+                let controller = lookup_controller(&controllers, controller)?;
+                validate_port(&pins, controller.controller, port)?;
+
+                caller.reply(stats.report(
+                    (controller.controller, port),
+                    mux,
+                    addr,
+                ));
+                Ok(())
+            }
         });
     }
 }
@@ -584,7 +774,7 @@ fn configure_port(
 ///
 /// [0] Analog Devices. AN-686: Implementing an I2C Reset. 2003.
 ///
-fn wiggle_scl(sys: &Sys, scl: PinSet, sda: PinSet) {
+fn wiggle_scl(sys: &Sys, scl: PinSet, sda: PinSet) -> u8 {
     let mut wiggles = 0_u8;
     sys.gpio_set(scl);
 
@@ -642,12 +832,14 @@ fn wiggle_scl(sys: &Sys, scl: PinSet, sda: PinSet) {
     }
 
     ringbuf_entry!(Trace::Wiggles(wiggles));
+    wiggles
 }
 
 fn configure_pins(
     controllers: &[I2cController<'_>],
     pins: &[I2cPins],
     map: &mut PortMap,
+    stats: &mut Stats,
 ) {
     let sys = SYS.get_task_id();
     let sys = Sys::from(sys);
@@ -657,7 +849,11 @@ fn configure_pins(
     // transaction.
     //
     for pin in pins {
-        wiggle_scl(&sys, pin.scl, pin.sda);
+        if wiggle_scl(&sys, pin.scl, pin.sda) > 0 {
+            stats.update((pin.controller, pin.port), None, |s| {
+                s.wiggles = s.wiggles.saturating_add(1)
+            });
+        }
     }
 
     for pin in pins {
@@ -709,6 +905,7 @@ fn configure_muxes(
     map: &mut PortMap,
     muxmap: &mut MuxMap,
     ctrl: &I2cControl,
+    stats: &mut Stats,
 ) {
     let sys = SYS.get_task_id();
     let sys = Sys::from(sys);
@@ -753,7 +950,7 @@ fn configure_muxes(
                         ringbuf_entry!(Trace::SegmentFailed(code.into()));
 
                         if reset_needed(code) && !reset_attempted {
-                            reset(controller, mux.port, muxes, muxmap);
+                            reset(controller, mux.port, muxes, muxmap, stats);
                             reset_attempted = true;
                             continue;
                         }
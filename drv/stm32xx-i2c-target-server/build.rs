@@ -0,0 +1,52 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::io::Write;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Config {
+    /// Tasks implementing the `I2cTargetHandler` interface, in the order in
+    /// which they are asked whether they respond to an address
+    handlers: Vec<String>,
+}
+
+fn main() -> Result<()> {
+    build_util::expose_target_board();
+    build_util::build_notifications()?;
+
+    let disposition = build_i2c::Disposition::Target;
+
+    if let Err(e) = build_i2c::codegen(disposition) {
+        println!("code generation failed: {}", e);
+        std::process::exit(1);
+    }
+
+    let cfg = build_util::task_config::<Config>()?;
+
+    let mut tasks = BTreeSet::new();
+    for h in &cfg.handlers {
+        if !tasks.insert(h) {
+            bail!("handler {h} is listed more than once");
+        }
+    }
+
+    let out_dir = build_util::out_dir();
+    let dest_path = out_dir.join("target_config.rs");
+    let mut out = std::fs::File::create(dest_path)
+        .context("creating target_config.rs")?;
+
+    let task = "hubris_num_tasks::Task";
+    let count = cfg.handlers.len();
+    writeln!(out, "pub(crate) const HANDLERS: [{task}; {count}] = [")?;
+    for h in &cfg.handlers {
+        writeln!(out, "    {task}::{h},")?;
+    }
+    writeln!(out, "];")?;
+
+    Ok(())
+}
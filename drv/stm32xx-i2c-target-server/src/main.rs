@@ -0,0 +1,148 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! I2C target-mode server
+//!
+//! This server operates an I2C controller as a target, emulating SMBus
+//! devices on behalf of other tasks.  Each emulated device is a map of
+//! byte-wide registers at a 7-bit address; the register pointer semantics
+//! are handled here (see [`drv_stm32xx_i2c::target`]), while the contents
+//! of the registers are provided by a handler task implementing the
+//! `I2cTargetHandler` interface.  The handler tasks are listed in this
+//! task's configuration:
+//!
+//! ```toml
+//! [tasks.i2c_target.config]
+//! handlers = ["fru"]
+//! ```
+//!
+//! Handlers claim addresses at runtime rather than in the configuration:
+//! whenever a transaction begins, we ask each handler in turn whether it
+//! `responds` to the address, so a handler can start or stop emulating a
+//! device as it sees fit.
+//!
+//! This task spends its life waiting on the bus (and clock stretching
+//! while a handler is consulted), so it never receives messages itself:
+//! handlers must be of higher priority than this task.  A handler that
+//! returns an error (or dies) causes reads to return `0xff` and writes to
+//! be dropped.
+
+#![no_std]
+#![no_main]
+
+use drv_i2c_target_api::{I2cTargetError, I2cTargetHandler};
+use drv_stm32xx_i2c::target::RegisterMap;
+use drv_stm32xx_i2c::{I2cControl, I2cPins};
+use drv_stm32xx_sys_api::{OutputType, Pull, Speed, Sys};
+use ringbuf::{ringbuf, ringbuf_entry};
+use userlib::{
+    sys_irq_control, sys_recv_closed, sys_refresh_task_id, task_slot,
+    Generation, TaskId,
+};
+
+task_slot!(SYS, sys);
+
+fn configure_pins(pins: &[I2cPins]) {
+    let sys = SYS.get_task_id();
+    let sys = Sys::from(sys);
+
+    for pin in pins {
+        for gpio_pin in &[pin.scl, pin.sda] {
+            sys.gpio_configure_alternate(
+                *gpio_pin,
+                OutputType::OpenDrain,
+                Speed::High,
+                Pull::None,
+                pin.function,
+            );
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    Ready,
+    ReadFailed(u8, u8, I2cTargetError),
+    WriteFailed(u8, u8, I2cTargetError),
+    None,
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
+include!(concat!(env!("OUT_DIR"), "/i2c_config.rs"));
+
+mod config {
+    include!(concat!(env!("OUT_DIR"), "/target_config.rs"));
+}
+
+/// Register map that forwards each access to the handler task that claimed
+/// the address being accessed
+struct Handlers {
+    /// Handler for the transaction in progress, if any
+    current: Option<I2cTargetHandler>,
+}
+
+fn handler(task: hubris_num_tasks::Task) -> I2cTargetHandler {
+    let taskid = TaskId::for_index_and_gen(task as usize, Generation::ZERO);
+    I2cTargetHandler::from(sys_refresh_task_id(taskid))
+}
+
+impl RegisterMap for Handlers {
+    fn responds(&mut self, addr: u8) -> bool {
+        // A handler that fails to answer (or has died) doesn't claim the
+        // address.
+        self.current = config::HANDLERS
+            .iter()
+            .map(|&task| handler(task))
+            .find(|h| h.responds(addr) == Ok(true));
+        self.current.is_some()
+    }
+
+    fn read(&mut self, addr: u8, reg: u8) -> Option<u8> {
+        match self.current.as_ref()?.read_register(addr, reg) {
+            Ok(val) => Some(val),
+            Err(e) => {
+                ringbuf_entry!(Trace::ReadFailed(addr, reg, e));
+                None
+            }
+        }
+    }
+
+    fn write(&mut self, addr: u8, reg: u8, val: u8) {
+        if let Some(handler) = &self.current {
+            if let Err(e) = handler.write_register(addr, reg, val) {
+                ringbuf_entry!(Trace::WriteFailed(addr, reg, e));
+            }
+        }
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    let controller = &i2c_config::controllers()[0];
+    let pins = i2c_config::pins();
+
+    // Enable the controller
+    let sys = Sys::from(SYS.get_task_id());
+    controller.enable(&sys);
+
+    // Configure our pins
+    configure_pins(&pins);
+
+    ringbuf_entry!(Trace::Ready);
+
+    let ctrl = I2cControl {
+        enable: |notification| {
+            sys_irq_control(notification, true);
+        },
+        wfi: |notification| {
+            let _ = sys_recv_closed(&mut [], notification, TaskId::KERNEL);
+        },
+    };
+
+    let mut handlers = Handlers { current: None };
+    controller.operate_as_register_target(&ctrl, &mut handlers);
+}
+
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
@@ -47,6 +47,7 @@ pub type Isr = device::i2c1::isr::R;
 pub mod ltc4306;
 pub mod max7358;
 pub mod pca9548;
+pub mod target;
 
 use ringbuf::*;
 use userlib::*;
@@ -174,6 +175,7 @@ enum Trace {
     BusySleep,
     Stop,
     RepeatedStart(bool),
+    PecMismatch(u8),
     None,
 }
 
@@ -529,31 +531,62 @@ impl I2cController<'_> {
     /// the device can support longer buffers, and the implementation could
     /// be extended in the future to allow them.
     pub fn write_read(
+        &self,
+        addr: u8,
+        wlen: usize,
+        getbyte: impl Fn(usize) -> Option<u8>,
+        rlen: ReadLength,
+        putbyte: impl FnMut(usize, u8) -> Option<()>,
+        ctrl: &I2cControl,
+    ) -> Result<(), drv_i2c_api::ResponseCode> {
+        self.write_read_pec(addr, wlen, getbyte, rlen, putbyte, false, ctrl)
+    }
+
+    /// Like [`write_read`], but if `pec` is set, uses SMBus Packet Error
+    /// Checking: a write that is not followed by a read has a PEC byte
+    /// appended to it, and a read expects a PEC byte from the device after
+    /// the data (which is checked here, rather than passed to `putbyte`).
+    /// With `pec` set, the lengths must be less than 255 bytes, to leave room
+    /// for the PEC byte.
+    ///
+    /// On a PEC mismatch, the transfer is still completed, but the data that
+    /// has been passed to `putbyte` must be discarded.
+    pub fn write_read_pec(
         &self,
         addr: u8,
         wlen: usize,
         getbyte: impl Fn(usize) -> Option<u8>,
         mut rlen: ReadLength,
         mut putbyte: impl FnMut(usize, u8) -> Option<()>,
+        pec: bool,
         ctrl: &I2cControl,
     ) -> Result<(), drv_i2c_api::ResponseCode> {
         // Assert our preconditions as described above
         assert!(wlen > 0 || rlen != ReadLength::Fixed(0));
-        assert!(wlen <= 255);
+        assert!(wlen + (pec as usize) <= 255);
 
         if let ReadLength::Fixed(rlen) = rlen {
-            assert!(rlen <= 255);
+            assert!(rlen + (pec as usize) <= 255);
         }
 
         let i2c = self.registers;
         let notification = self.notification;
 
+        // The PEC covers every byte of the transaction, including addresses,
+        // from the first START to the STOP.
+        let mut digest = smbus_pec::Pec::new();
+
+        // Only a write that isn't followed by a read carries our PEC byte
+        let wpec = pec && rlen == ReadLength::Fixed(0);
+
         self.wait_until_notbusy()?;
 
         if wlen > 0 {
+            digest.write_address(addr);
+
             #[rustfmt::skip]
             i2c.cr2.modify(|_, w| { w
-                .nbytes().bits(wlen as u8)
+                .nbytes().bits((wlen + wpec as usize) as u8)
                 .autoend().clear_bit()
                 .add10().clear_bit()
                 .sadd().bits((addr << 1).into())
@@ -563,7 +596,7 @@ impl I2cController<'_> {
 
             let mut pos = 0;
 
-            while pos < wlen {
+            while pos < wlen + wpec as usize {
                 loop {
                     let isr = i2c.isr.read();
                     ringbuf_entry!(Trace::WriteISR(isr.bits()));
@@ -583,9 +616,15 @@ impl I2cController<'_> {
                     (ctrl.enable)(notification);
                 }
 
-                // Get a single byte.
-                let byte =
-                    getbyte(pos).ok_or(drv_i2c_api::ResponseCode::BadArg)?;
+                // Get a single byte -- or, after the data, our PEC.
+                let byte = if pos < wlen {
+                    let byte = getbyte(pos)
+                        .ok_or(drv_i2c_api::ResponseCode::BadArg)?;
+                    digest.update(&[byte]);
+                    byte
+                } else {
+                    digest.value()
+                };
 
                 // And send it!
                 i2c.txdr.write(|w| w.txdata().bits(byte));
@@ -621,10 +660,12 @@ impl I2cController<'_> {
             // permit a STOP between a register address write and a subsequent
             // read).
             //
+            digest.read_address(addr);
+
             if let ReadLength::Fixed(rlen) = rlen {
                 #[rustfmt::skip]
                 i2c.cr2.modify(|_, w| { w
-                    .nbytes().bits(rlen as u8)
+                    .nbytes().bits((rlen + pec as usize) as u8)
                     .autoend().clear_bit()
                     .add10().clear_bit()
                     .sadd().bits((addr << 1).into())
@@ -645,10 +686,11 @@ impl I2cController<'_> {
             }
 
             let mut pos = 0;
+            let mut pec_ok = true;
 
             loop {
                 if let ReadLength::Fixed(rlen) = rlen {
-                    if pos >= rlen {
+                    if pos >= rlen + pec as usize {
                         break;
                     }
                 }
@@ -676,16 +718,31 @@ impl I2cController<'_> {
                 let byte: u8 = i2c.rxdr.read().rxdata().bits();
 
                 if rlen == ReadLength::Variable {
+                    if pec && byte == u8::MAX {
+                        // There's no room for the PEC byte; this can't be a
+                        // legitimate SMBus block.
+                        return Err(drv_i2c_api::ResponseCode::BadDeviceState);
+                    }
+
                     #[rustfmt::skip]
                     i2c.cr2.modify(|_, w| { w
-                        .nbytes().bits(byte)
+                        .nbytes().bits(byte + pec as u8)
                         .reload().clear_bit()
                     });
 
+                    digest.update(&[byte]);
                     rlen = ReadLength::Fixed(byte.into());
                     continue;
                 }
 
+                if rlen == ReadLength::Fixed(pos) {
+                    // This can only be our PEC byte.
+                    pec_ok = digest.value() == byte;
+                    pos += 1;
+                    continue;
+                }
+
+                digest.update(&[byte]);
                 putbyte(pos, byte).ok_or(drv_i2c_api::ResponseCode::BadArg)?;
                 pos += 1;
             }
@@ -704,6 +761,12 @@ impl I2cController<'_> {
                 (ctrl.wfi)(notification);
                 (ctrl.enable)(notification);
             }
+
+            if !pec_ok {
+                ringbuf_entry!(Trace::PecMismatch(addr));
+                i2c.cr2.modify(|_, w| w.stop().set_bit());
+                return Err(drv_i2c_api::ResponseCode::PecMismatch);
+            }
         }
 
         //
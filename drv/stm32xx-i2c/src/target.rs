@@ -0,0 +1,107 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Register-map emulation for I2C target mode
+//!
+//! Most SMBus devices -- EEPROMs, sensors, power controllers -- present
+//! themselves as a flat map of byte-wide registers: the first byte of a
+//! write selects a register (the SMBus command code, or the word address of
+//! an EEPROM), any further bytes in the write are written to consecutive
+//! registers, and a read returns consecutive registers starting from
+//! wherever the pointer was left.  This module implements that state
+//! machine on top of [`I2cController::operate_as_target`], leaving only the
+//! contents of the registers to the implementor of [`RegisterMap`].
+
+use crate::{I2cControl, I2cController};
+use core::cell::RefCell;
+
+/// Value clocked out when the register map has nothing to say
+pub const FILLER: u8 = 0xff;
+
+///
+/// A trait to express a set of emulated devices, each of which is a map of
+/// byte-wide registers.
+///
+pub trait RegisterMap {
+    /// Returns true if the map will respond to the given 7-bit address
+    fn responds(&mut self, addr: u8) -> bool;
+
+    /// Read the register at `reg` from the device at `addr`, returning
+    /// `None` if there is no such register
+    fn read(&mut self, addr: u8, reg: u8) -> Option<u8>;
+
+    /// Write `val` to the register at `reg` on the device at `addr`
+    fn write(&mut self, addr: u8, reg: u8, val: u8);
+}
+
+#[derive(Copy, Clone, Default)]
+struct Pointer {
+    /// Address of the device that the pointer belongs to
+    addr: u8,
+
+    /// Current register
+    reg: u8,
+
+    /// True if the next byte written is a register rather than data
+    expect_reg: bool,
+}
+
+impl Pointer {
+    fn initiate(&mut self, addr: u8) {
+        if self.addr != addr {
+            *self = Pointer {
+                addr,
+                ..Default::default()
+            };
+        }
+
+        self.expect_reg = true;
+    }
+
+    fn rx(&mut self, map: &mut impl RegisterMap, byte: u8) {
+        if self.expect_reg {
+            self.reg = byte;
+            self.expect_reg = false;
+        } else {
+            map.write(self.addr, self.reg, byte);
+            self.reg = self.reg.wrapping_add(1);
+        }
+    }
+
+    fn tx(&mut self, map: &mut impl RegisterMap) -> u8 {
+        let val = map.read(self.addr, self.reg).unwrap_or(FILLER);
+        self.reg = self.reg.wrapping_add(1);
+        val
+    }
+}
+
+impl<'a> I2cController<'a> {
+    ///
+    /// Operate as an I2C target, emulating the devices expressed by `map`.
+    /// Like [`I2cController::operate_as_target`], this never returns.
+    ///
+    pub fn operate_as_register_target(
+        &self,
+        ctrl: &I2cControl,
+        map: &mut impl RegisterMap,
+    ) -> ! {
+        let map = RefCell::new(map);
+        let ptr = RefCell::new(Pointer::default());
+
+        self.operate_as_target(
+            ctrl,
+            |addr| {
+                let rval = map.borrow_mut().responds(addr);
+
+                if rval {
+                    ptr.borrow_mut().initiate(addr);
+                }
+
+                rval
+            },
+            |_, byte| ptr.borrow_mut().rx(&mut **map.borrow_mut(), byte),
+            |_| Some(ptr.borrow_mut().tx(&mut **map.borrow_mut())),
+        )
+    }
+}
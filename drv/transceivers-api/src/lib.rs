@@ -12,6 +12,8 @@ use task_sensor_api::{config::other_sensors, SensorId};
 use userlib::{sys_send, FromPrimitive};
 use zerocopy::{AsBytes, FromBytes};
 
+pub use transceiver_ddm::{DdmFlags, LevelFlags, MAX_LANES};
+
 #[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
 pub enum TransceiversError {
     FpgaError = 1,
@@ -20,6 +22,14 @@ pub enum TransceiversError {
     InvalidPowerState,
     InvalidModuleResult,
     LedI2cError,
+    /// The module is still executing a previous CDB command
+    CdbBusy,
+    /// The module is not a paged CMIS module, so has no CDB
+    CdbNotSupported,
+    /// A firmware block was written with no download in progress
+    CdbNotDownloading,
+    /// The header or block is too long for a CDB message
+    InvalidCdbLength,
 
     #[idol(server_death)]
     ServerRestarted,
@@ -59,6 +69,44 @@ pub const PAGE_SIZE_BYTES: usize = 128;
 /// ports.
 pub const NUM_PORTS: u8 = 32;
 
+/// Longest vendor header that can accompany a CDB Start Firmware Download
+pub const CDB_MAX_HEADER: usize = 112;
+
+/// Longest block that can be written by a CDB Write Firmware Block
+pub const CDB_MAX_BLOCK: usize = 116;
+
+/// State of a module's Command Data Block (CDB) engine, as far as the
+/// transceivers server knows it.
+#[derive(Copy, Clone, Default, PartialEq, Eq, FromBytes, AsBytes)]
+#[repr(C)]
+pub struct CdbStatus {
+    /// Size of the image being (or last) downloaded
+    pub image_size: u32,
+    /// Bytes of the image written so far
+    pub bytes_written: u32,
+    /// Most recently issued CDB command
+    pub command: u16,
+    /// Raw `CdbStatus1` register: busy (bit 7), failed (bit 6) and a
+    /// status code (bits 5-0)
+    pub status: u8,
+    /// Non-zero if a firmware download is in progress
+    pub downloading: u8,
+}
+
+impl CdbStatus {
+    pub fn is_busy(&self) -> bool {
+        self.status & 0x80 != 0
+    }
+
+    pub fn has_failed(&self) -> bool {
+        self.status & 0x40 != 0
+    }
+
+    pub fn code(&self) -> u8 {
+        self.status & 0x3f
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 pub const TRANSCEIVER_TEMPERATURE_SENSORS: [SensorId; NUM_PORTS as usize] = [
@@ -0,0 +1,237 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! CMIS Command Data Block (CDB) engine, for module firmware upgrades
+//!
+//! CDB is how CMIS modules take commands too large for a register write:
+//! the host writes a command message into page 9Fh, and writing the command
+//! ID last triggers the module to execute it, reporting its progress in
+//! `CdbStatus1`.  Firmware upgrades are a sequence of these commands -- start
+//! a download, write the image a block at a time, complete the download,
+//! run the new image, and commit it once it's known to be good.
+//!
+//! Commands are issued without waiting for the module to finish: some take
+//! seconds, and we have a thermal loop to run.  Instead, the caller polls
+//! `cdb_status` until the module is no longer busy before issuing the next.
+//!
+//! See CMIS rev 5.0, Sections 8.4.11 and 9.
+use crate::ServerImpl;
+use drv_fpga_api::FpgaError;
+use drv_sidecar_front_io::transceivers::LogicalPort;
+use drv_transceivers_api::{
+    CdbStatus, TransceiversError, CDB_MAX_BLOCK, CDB_MAX_HEADER, NUM_PORTS,
+};
+use ringbuf::*;
+use transceiver_messages::mgmt::ManagementInterface;
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    Command(u8, u16),
+    Status(u8, u8),
+    RestorePageError(u8, FpgaError),
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// `CdbStatus1`, in the lower page
+const CDB_STATUS: u8 = 37;
+
+/// Status reported in place of `CdbStatus1` when the module is too busy to
+/// answer at all; modules without background CDB support may NACK while
+/// executing a command.
+const CDB_STATUS_BUSY: u8 = 0x80;
+
+/// Page holding the CDB command message, and its layout
+const CDB_PAGE: u8 = 0x9F;
+const CDB_CMD_ID: u8 = 128;
+const CDB_EPL_LENGTH: u8 = 130;
+const CDB_HEADER_SIZE: usize = 8;
+const CDB_MAX_LPL: usize = 120;
+
+const CMD_START_DOWNLOAD: u16 = 0x0101;
+const CMD_ABORT_DOWNLOAD: u16 = 0x0102;
+const CMD_WRITE_BLOCK_LPL: u16 = 0x0103;
+const CMD_COMPLETE_DOWNLOAD: u16 = 0x0107;
+const CMD_RUN_IMAGE: u16 = 0x0109;
+const CMD_COMMIT_IMAGE: u16 = 0x010A;
+
+impl ServerImpl {
+    pub fn cdb_start_download(
+        &mut self,
+        port: u8,
+        image_size: u32,
+        header: &[u8],
+    ) -> Result<(), TransceiversError> {
+        if header.len() > CDB_MAX_HEADER {
+            return Err(TransceiversError::InvalidCdbLength);
+        }
+        let mut lpl = [0u8; CDB_MAX_LPL];
+        lpl[..4].copy_from_slice(&image_size.to_be_bytes());
+        lpl[8..][..header.len()].copy_from_slice(header);
+
+        self.cdb_issue(port, CMD_START_DOWNLOAD, &lpl[..8 + header.len()])?;
+
+        let cdb = &mut self.cdb[port as usize];
+        cdb.image_size = image_size;
+        cdb.bytes_written = 0;
+        cdb.downloading = 1;
+        Ok(())
+    }
+
+    pub fn cdb_write_firmware_block(
+        &mut self,
+        port: u8,
+        offset: u32,
+        data: &[u8],
+    ) -> Result<(), TransceiversError> {
+        if data.is_empty() || data.len() > CDB_MAX_BLOCK {
+            return Err(TransceiversError::InvalidCdbLength);
+        }
+        if self.cdb.get(port as usize).map(|c| c.downloading) != Some(1) {
+            return Err(TransceiversError::CdbNotDownloading);
+        }
+        let mut lpl = [0u8; CDB_MAX_LPL];
+        lpl[..4].copy_from_slice(&offset.to_be_bytes());
+        lpl[4..][..data.len()].copy_from_slice(data);
+
+        self.cdb_issue(port, CMD_WRITE_BLOCK_LPL, &lpl[..4 + data.len()])?;
+
+        // Blocks may be retried, so progress is the furthest we've written
+        let cdb = &mut self.cdb[port as usize];
+        let end = offset.saturating_add(data.len() as u32);
+        cdb.bytes_written = cdb.bytes_written.max(end);
+        Ok(())
+    }
+
+    pub fn cdb_complete_download(
+        &mut self,
+        port: u8,
+    ) -> Result<(), TransceiversError> {
+        self.cdb_issue(port, CMD_COMPLETE_DOWNLOAD, &[])?;
+        self.cdb[port as usize].downloading = 0;
+        Ok(())
+    }
+
+    pub fn cdb_abort_download(
+        &mut self,
+        port: u8,
+    ) -> Result<(), TransceiversError> {
+        self.cdb_issue(port, CMD_ABORT_DOWNLOAD, &[])?;
+        self.cdb[port as usize].downloading = 0;
+        Ok(())
+    }
+
+    pub fn cdb_run_image(
+        &mut self,
+        port: u8,
+        mode: u8,
+        delay_ms: u16,
+    ) -> Result<(), TransceiversError> {
+        let [hi, lo] = delay_ms.to_be_bytes();
+        self.cdb_issue(port, CMD_RUN_IMAGE, &[0, mode, hi, lo])
+    }
+
+    pub fn cdb_commit_image(
+        &mut self,
+        port: u8,
+    ) -> Result<(), TransceiversError> {
+        self.cdb_issue(port, CMD_COMMIT_IMAGE, &[])
+    }
+
+    pub fn cdb_get_status(
+        &mut self,
+        port: u8,
+    ) -> Result<CdbStatus, TransceiversError> {
+        let p = self.cdb_port(port)?;
+        let status = self.cdb_read_status(p)?;
+        let cdb = &mut self.cdb[port as usize];
+        cdb.status = status;
+        Ok(*cdb)
+    }
+
+    /// Checks that `port` holds a module that we can talk CDB to.
+    fn cdb_port(&self, port: u8) -> Result<LogicalPort, TransceiversError> {
+        if port >= NUM_PORTS {
+            return Err(TransceiversError::InvalidPortNumber);
+        }
+        let p = LogicalPort(port);
+        if !(self.disabled & p).is_empty() {
+            return Err(TransceiversError::CdbNotSupported);
+        }
+        match self.thermal_models[port as usize] {
+            Some(m) if matches!(m.interface, ManagementInterface::Cmis) => {
+                Ok(p)
+            }
+            _ => Err(TransceiversError::CdbNotSupported),
+        }
+    }
+
+    fn cdb_read_status(
+        &mut self,
+        port: LogicalPort,
+    ) -> Result<u8, TransceiversError> {
+        let mut status = [0u8; 1];
+        let status = match self.read_module(port, None, CDB_STATUS, &mut status)
+        {
+            Ok(()) => status[0],
+            Err(FpgaError::ImplError(_)) => CDB_STATUS_BUSY,
+            Err(e) => return Err(e.into()),
+        };
+        ringbuf_entry!(Trace::Status(port.0, status));
+        Ok(status)
+    }
+
+    /// Issues a CDB command with the given local payload (LPL).
+    fn cdb_issue(
+        &mut self,
+        port: u8,
+        command: u16,
+        lpl: &[u8],
+    ) -> Result<(), TransceiversError> {
+        let p = self.cdb_port(port)?;
+        if lpl.len() > CDB_MAX_LPL {
+            return Err(TransceiversError::InvalidCdbLength);
+        }
+        if self.cdb_read_status(p)? & CDB_STATUS_BUSY != 0 {
+            return Err(TransceiversError::CdbBusy);
+        }
+
+        // The header is the command ID, EPL length (we never use the EPL),
+        // LPL length, a checksum, and the reply's length and checksum
+        // (which the module fills in).
+        let mut msg = [0u8; CDB_HEADER_SIZE + CDB_MAX_LPL];
+        msg[..2].copy_from_slice(&command.to_be_bytes());
+        msg[4] = lpl.len() as u8;
+        msg[CDB_HEADER_SIZE..][..lpl.len()].copy_from_slice(lpl);
+        let msg = &mut msg[..CDB_HEADER_SIZE + lpl.len()];
+        msg[5] = !msg.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+
+        ringbuf_entry!(Trace::Command(port, command));
+
+        // Everything but the command ID goes first; writing the command ID
+        // is what starts the module executing.
+        self.select_module_page(p, CDB_PAGE)?;
+        let r = self
+            .write_module_current_page(p, CDB_EPL_LENGTH, &msg[2..])
+            .and_then(|_| {
+                self.write_module_current_page(p, CDB_CMD_ID, &msg[..2])
+            });
+
+        // The module may ignore us while it executes the command, so a
+        // failure to restore the page isn't an error; the next access to
+        // an upper page will select its own page anyway.
+        if let Err(e) = self.select_module_page(p, 0) {
+            ringbuf_entry!(Trace::RestorePageError(port, e));
+        }
+        r?;
+
+        self.cdb[port as usize].command = command;
+        Ok(())
+    }
+}
@@ -0,0 +1,330 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Digital diagnostic monitoring (DDM) of transceivers
+//!
+//! Module temperature is read in `main.rs`, because it feeds the thermal
+//! loop.  Everything else that modules monitor -- supply voltage, and TX
+//! bias, TX power and RX power on each lane -- is read here and posted to
+//! the `sensor` task.  The number of lanes depends on what is plugged in, so
+//! these sensors are allocated from the `sensor` task's pool of dynamic
+//! sensors when a module appears, and released when it goes away.
+//!
+//! Modules also latch alarm and warning flags for each measurement, which
+//! clear when read; we accumulate those in a `DdmFlags` per port.
+//!
+//! The registers themselves are decoded by the `transceiver-ddm` crate.
+use crate::ServerImpl;
+use drv_fpga_api::FpgaError;
+use drv_sidecar_front_io::transceivers::LogicalPort;
+use drv_transceivers_api::{DdmFlags, LevelFlags, MAX_LANES};
+use ringbuf::*;
+use task_sensor_api::{DynamicSensorError, NoData, SensorApiError, SensorId};
+use transceiver_ddm::{
+    cmis_media_lanes, cmis_tx_bias_lsb, decode_cmis_lanes, decode_cmis_module,
+    decode_sff8636, mask_lanes, Measurements, CMIS_ADVERTISING_PAGE,
+    CMIS_FIRST_APPLICATION_LANES, CMIS_FLAT_MEM, CMIS_FLAT_MEM_REG,
+    CMIS_LANE_FLAGS, CMIS_LANE_LEN, CMIS_LANE_PAGE, CMIS_MODULE_FLAGS,
+    CMIS_MODULE_LEN, CMIS_TX_BIAS_MULTIPLIER, DDM_SENSORS,
+    SFF8636_DATA_NOT_READY, SFF8636_DDM_LEN, SFF8636_FLAGS, SFF8636_STATUS,
+    SUPPLY, TX_BIAS,
+};
+use transceiver_messages::mgmt::ManagementInterface;
+use userlib::sys_get_timer;
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    Attached(u8, u8),
+    AttachRetry(u8, u64),
+    Detached(u8),
+    AllocateError(u8, DynamicSensorError),
+    ReleaseError(u8, DynamicSensorError),
+    ReadError(u8, FpgaError),
+    SensorError(u8, SensorApiError),
+    Flags(u8, DdmFlags),
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Time to wait before retrying a module that we failed to attach; doubled
+/// after each failure, up to `DDM_RETRY_MAX_MS`.
+const DDM_RETRY_INITIAL_MS: u64 = 1000;
+const DDM_RETRY_MAX_MS: u64 = 60_000;
+
+/// DDM state for one port
+#[derive(Copy, Clone)]
+pub struct DdmPort {
+    /// True if there is a module that we're monitoring
+    attached: bool,
+
+    /// Number of lanes that we're monitoring
+    lanes: u8,
+
+    /// Units of TX bias, in mA; CMIS modules can advertise a multiplier
+    tx_bias_lsb: f32,
+
+    /// True if this is a CMIS module with only a lower page and page 00h
+    flat_mem: bool,
+
+    /// Our dynamically allocated sensors, indexed like `Measurements`
+    sensors: [Option<SensorId>; DDM_SENSORS],
+
+    /// Flags accumulated since the module was attached
+    flags: DdmFlags,
+
+    /// If we failed to attach the module, when to try again
+    retry_at: Option<u64>,
+
+    /// How long we waited before that retry, or 0 if there hasn't been a
+    /// failure since the module appeared
+    retry_backoff_ms: u64,
+}
+
+impl DdmPort {
+    pub const EMPTY: Self = Self {
+        attached: false,
+        lanes: 0,
+        tx_bias_lsb: 0.002,
+        flat_mem: false,
+        sensors: [None; DDM_SENSORS],
+        flags: DdmFlags {
+            temperature: LEVEL_FLAGS_EMPTY,
+            supply: LEVEL_FLAGS_EMPTY,
+            tx_bias: LEVEL_FLAGS_EMPTY,
+            tx_power: LEVEL_FLAGS_EMPTY,
+            rx_power: LEVEL_FLAGS_EMPTY,
+        },
+        retry_at: None,
+        retry_backoff_ms: 0,
+    };
+
+    pub fn flags(&self) -> DdmFlags {
+        self.flags
+    }
+}
+
+const LEVEL_FLAGS_EMPTY: LevelFlags = LevelFlags {
+    high_alarm: 0,
+    low_alarm: 0,
+    high_warning: 0,
+    low_warning: 0,
+};
+
+impl ServerImpl {
+    /// Starts monitoring a newly-arrived module, allocating its sensors.
+    ///
+    /// If the module can't be read, `ddm_update` tries again later, backing
+    /// off while it keeps failing.
+    pub fn ddm_attach(
+        &mut self,
+        port: LogicalPort,
+        interface: ManagementInterface,
+    ) {
+        let mut ddm = DdmPort::EMPTY;
+
+        match interface {
+            ManagementInterface::Sff8636 => ddm.lanes = 4,
+            ManagementInterface::Cmis => {
+                if let Err(e) = self.ddm_cmis_discover(port, &mut ddm) {
+                    ringbuf_entry!(Trace::ReadError(port.0, e));
+                    self.ddm_schedule_retry(port);
+                    return;
+                }
+            }
+            ManagementInterface::Unknown(..) => return,
+        }
+
+        for (i, sensor) in ddm.sensors.iter_mut().enumerate() {
+            let monitored = match i {
+                SUPPLY => true,
+                _ => (i - TX_BIAS) % MAX_LANES < ddm.lanes as usize,
+            };
+            if !monitored {
+                continue;
+            }
+            let tag = (port.0 as u32) << 8 | i as u32;
+            match self.sensor_api.allocate(tag) {
+                Ok(id) => *sensor = Some(id),
+                Err(e) => ringbuf_entry!(Trace::AllocateError(port.0, e)),
+            }
+        }
+
+        ringbuf_entry!(Trace::Attached(port.0, ddm.lanes));
+        ddm.attached = true;
+        self.ddm[port.0 as usize] = ddm;
+    }
+
+    /// Stops monitoring a module that has gone away, releasing its sensors.
+    pub fn ddm_detach(&mut self, port: LogicalPort) {
+        let ddm =
+            core::mem::replace(&mut self.ddm[port.0 as usize], DdmPort::EMPTY);
+        for id in ddm.sensors.iter().flatten() {
+            if let Err(e) = self.sensor_api.release(*id) {
+                ringbuf_entry!(Trace::ReleaseError(port.0, e));
+            }
+        }
+        ringbuf_entry!(Trace::Detached(port.0));
+    }
+
+    /// Reads a module's measurements and flags, posting the former to the
+    /// `sensor` task and accumulating the latter.
+    pub fn ddm_update(
+        &mut self,
+        port: LogicalPort,
+        interface: ManagementInterface,
+    ) {
+        let mut ddm = self.ddm[port.0 as usize];
+        if !ddm.attached {
+            match ddm.retry_at {
+                Some(t) if sys_get_timer().now >= t => {
+                    self.ddm_attach(port, interface);
+                    ddm = self.ddm[port.0 as usize];
+                    if !ddm.attached {
+                        return;
+                    }
+                }
+                _ => return,
+            }
+        }
+
+        let result = match interface {
+            ManagementInterface::Sff8636 => self.ddm_read_sff8636(port),
+            ManagementInterface::Cmis => self.ddm_read_cmis(port, &ddm),
+            ManagementInterface::Unknown(..) => return,
+        };
+
+        let (values, mut flags) = match result {
+            Ok(Some(r)) => r,
+            // The module has no valid data yet; try again next time
+            Ok(None) => return,
+            Err(e) => {
+                ringbuf_entry!(Trace::ReadError(port.0, e));
+                for id in ddm.sensors.iter().flatten() {
+                    if let Err(e) =
+                        self.sensor_api.nodata_now(*id, NoData::DeviceError)
+                    {
+                        ringbuf_entry!(Trace::SensorError(port.0, e));
+                    }
+                }
+                return;
+            }
+        };
+
+        for (id, value) in ddm.sensors.iter().zip(values) {
+            if let Some(id) = id {
+                if let Err(e) = self.sensor_api.post_now(*id, value) {
+                    ringbuf_entry!(Trace::SensorError(port.0, e));
+                }
+            }
+        }
+
+        mask_lanes(&mut flags, ddm.lanes);
+        let mut all = ddm.flags;
+        all |= flags;
+        if all != ddm.flags {
+            ringbuf_entry!(Trace::Flags(port.0, all));
+            self.ddm[port.0 as usize].flags = all;
+        }
+    }
+
+    /// Records a failure to attach a module, scheduling the next attempt.
+    fn ddm_schedule_retry(&mut self, port: LogicalPort) {
+        let ddm = &mut self.ddm[port.0 as usize];
+        let backoff = match ddm.retry_backoff_ms {
+            0 => DDM_RETRY_INITIAL_MS,
+            b => (b * 2).min(DDM_RETRY_MAX_MS),
+        };
+        *ddm = DdmPort {
+            retry_at: Some(sys_get_timer().now + backoff),
+            retry_backoff_ms: backoff,
+            ..DdmPort::EMPTY
+        };
+        ringbuf_entry!(Trace::AttachRetry(port.0, backoff));
+    }
+
+    /// Works out how many lanes a CMIS module has, and how to scale its TX
+    /// bias monitors.
+    fn ddm_cmis_discover(
+        &mut self,
+        port: LogicalPort,
+        ddm: &mut DdmPort,
+    ) -> Result<(), FpgaError> {
+        let mut b = [0u8; 1];
+        self.read_module(port, None, CMIS_FLAT_MEM_REG, &mut b)?;
+        ddm.flat_mem = b[0] & CMIS_FLAT_MEM != 0;
+
+        // Lane monitors are per media lane
+        self.read_module(port, None, CMIS_FIRST_APPLICATION_LANES, &mut b)?;
+        ddm.lanes = cmis_media_lanes(b[0]);
+
+        if ddm.flat_mem {
+            // Without page 11h there are no lane monitors, but we still
+            // want the supply voltage.
+            ddm.lanes = 0;
+            return Ok(());
+        }
+
+        self.read_module(
+            port,
+            Some(CMIS_ADVERTISING_PAGE),
+            CMIS_TX_BIAS_MULTIPLIER,
+            &mut b,
+        )?;
+        ddm.tx_bias_lsb = cmis_tx_bias_lsb(b[0]);
+        Ok(())
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn ddm_read_sff8636(
+        &mut self,
+        port: LogicalPort,
+    ) -> Result<Option<(Measurements, DdmFlags)>, FpgaError> {
+        let mut status = [0u8; 1];
+        self.read_module(port, None, SFF8636_STATUS, &mut status)?;
+        if status[0] & SFF8636_DATA_NOT_READY != 0 {
+            return Ok(None);
+        }
+
+        let mut buf = [0u8; SFF8636_DDM_LEN];
+        self.read_module(port, None, SFF8636_FLAGS, &mut buf)?;
+        Ok(Some(decode_sff8636(&buf)))
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn ddm_read_cmis(
+        &mut self,
+        port: LogicalPort,
+        ddm: &DdmPort,
+    ) -> Result<Option<(Measurements, DdmFlags)>, FpgaError> {
+        let mut m = [f32::NAN; DDM_SENSORS];
+        let mut flags = DdmFlags::default();
+
+        let mut module = [0u8; CMIS_MODULE_LEN];
+        self.read_module(port, None, CMIS_MODULE_FLAGS, &mut module)?;
+        decode_cmis_module(&module, &mut m, &mut flags);
+
+        if !ddm.flat_mem {
+            let mut lanes = [0u8; CMIS_LANE_LEN];
+            self.read_module(
+                port,
+                Some(CMIS_LANE_PAGE),
+                CMIS_LANE_FLAGS,
+                &mut lanes,
+            )?;
+            decode_cmis_lanes(
+                &lanes,
+                ddm.lanes as usize,
+                ddm.tx_bias_lsb,
+                &mut m,
+                &mut flags,
+            );
+        }
+        Ok(Some((m, flags)))
+    }
+}
@@ -15,10 +15,13 @@ use drv_sidecar_front_io::{
 };
 use drv_sidecar_seq_api::{SeqError, Sequencer};
 use drv_transceivers_api::{
-    ModuleStatus, TransceiversError, NUM_PORTS, TRANSCEIVER_TEMPERATURE_SENSORS,
+    CdbStatus, DdmFlags, ModuleStatus, TransceiversError, CDB_MAX_BLOCK,
+    CDB_MAX_HEADER, NUM_PORTS, TRANSCEIVER_TEMPERATURE_SENSORS,
 };
 use enum_map::Enum;
-use idol_runtime::{NotificationHandler, RequestError};
+use idol_runtime::{
+    ClientError, Leased, LenLimit, NotificationHandler, RequestError, R,
+};
 use multitimer::{Multitimer, Repeat};
 use ringbuf::*;
 use task_sensor_api::{NoData, Sensor, SensorApiError};
@@ -29,8 +32,12 @@ use transceiver_messages::{
 use userlib::{units::Celsius, *};
 use zerocopy::{AsBytes, FromBytes};
 
+mod cdb; // CMIS CDB firmware upgrades are implemented in a separate file
+mod ddm; // Digital diagnostic monitoring is implemented in a separate file
 mod udp; // UDP API is implemented in a separate file
 
+use ddm::DdmPort;
+
 task_slot!(I2C, i2c_driver);
 task_slot!(FRONT_IO, front_io);
 task_slot!(SEQ, seq);
@@ -118,6 +125,12 @@ struct ServerImpl {
 
     /// Thermal models are populated by the host
     thermal_models: [Option<ThermalModel>; NUM_PORTS as usize],
+
+    /// Digital diagnostic monitoring state, including dynamic sensors
+    ddm: &'static mut [DdmPort; NUM_PORTS as usize],
+
+    /// State of CDB firmware upgrades
+    cdb: [CdbStatus; NUM_PORTS as usize],
 }
 
 #[derive(Copy, Clone)]
@@ -257,13 +270,110 @@ impl ServerImpl {
                     // increments of 1/256 degrees Celsius"
                     //
                     // - SFF-8636 rev 2.10a, Section 6.2.4
-                    return Ok(Celsius(out.temperature.get() as f32 / 256.0));
+                    return Ok(Celsius(transceiver_ddm::celsius(
+                        out.temperature.get(),
+                    )));
                 }
             }
             userlib::hl::sleep_for(1);
         }
     }
 
+    /// Reads `out.len()` bytes from `reg` of a module, selecting `page` (in
+    /// bank 0) first if given, and returning to page 00h afterwards so that
+    /// we leave the module as we found it.
+    fn read_module(
+        &mut self,
+        port: LogicalPort,
+        page: Option<u8>,
+        reg: u8,
+        out: &mut [u8],
+    ) -> Result<(), FpgaError> {
+        if let Some(page) = page {
+            self.select_module_page(port, page)?;
+        }
+
+        let r = self.read_module_current_page(port, reg, out);
+
+        if page.is_some() {
+            self.select_module_page(port, 0)?;
+        }
+        r
+    }
+
+    fn select_module_page(
+        &mut self,
+        port: LogicalPort,
+        page: u8,
+    ) -> Result<(), FpgaError> {
+        // Common to both CMIS and SFF-8636.  The bank and page are written
+        // in a single transaction, as CMIS asks of hosts.
+        const BANK_SELECT: u8 = 0x7E;
+        self.write_module_current_page(port, BANK_SELECT, &[0, page])
+    }
+
+    fn write_module_current_page(
+        &mut self,
+        port: LogicalPort,
+        reg: u8,
+        data: &[u8],
+    ) -> Result<(), FpgaError> {
+        let result = self.transceivers.set_i2c_write_buffer(data);
+        if result.error().is_set(port) {
+            return Err(FpgaError::CommsError);
+        }
+        let result = self.transceivers.setup_i2c_write(
+            reg,
+            data.len() as u8,
+            port.as_mask(),
+        );
+        if !result.error().is_empty() {
+            return Err(FpgaError::CommsError);
+        }
+
+        let result = self.transceivers.wait_and_check_i2c(port.as_mask());
+        if !result.error().is_empty() {
+            Err(FpgaError::CommsError)
+        } else if !result.failure().is_empty() {
+            Err(FpgaError::ImplError(Reg::QSFP::PORT0_STATUS::ERROR))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn read_module_current_page(
+        &self,
+        port: LogicalPort,
+        reg: u8,
+        out: &mut [u8],
+    ) -> Result<(), FpgaError> {
+        let result = self.transceivers.setup_i2c_read(
+            reg,
+            out.len() as u8,
+            port.as_mask(),
+        );
+        if !result.error().is_empty() {
+            return Err(FpgaError::CommsError);
+        }
+
+        // The status register is contiguous with the output buffer
+        let mut buf = [0u8; 129];
+        let buf = &mut buf[..out.len() + 1];
+        loop {
+            self.transceivers
+                .get_i2c_status_and_read_buffer(port, buf)?;
+            let status = buf[0];
+            if status & Reg::QSFP::PORT0_STATUS::BUSY == 0 {
+                if status & Reg::QSFP::PORT0_STATUS::ERROR != 0 {
+                    return Err(FpgaError::ImplError(status));
+                }
+                out.copy_from_slice(&buf[1..]);
+                return Ok(());
+            }
+            userlib::hl::sleep_for(1);
+        }
+    }
+
     fn get_transceiver_interface(
         &mut self,
         port: LogicalPort,
@@ -347,7 +457,10 @@ impl ServerImpl {
                 match self.get_transceiver_interface(port) {
                     Ok(interface) => {
                         self.thermal_models[i] =
-                            self.decode_interface(port, interface)
+                            self.decode_interface(port, interface);
+                        if self.thermal_models[i].is_some() {
+                            self.ddm_attach(port, interface);
+                        }
                     }
                     Err(FpgaError::ImplError(e)) => {
                         match Reg::QSFP::PORT0_STATUS::Encoded::from_u8(e) {
@@ -383,6 +496,8 @@ impl ServerImpl {
                     ringbuf_entry!(Trace::SensorError(i, e));
                 }
 
+                self.ddm_detach(port);
+
                 if (self.disabled & port).is_empty() {
                     ringbuf_entry!(Trace::UnpluggedModule(i));
                 } else {
@@ -472,6 +587,17 @@ impl ServerImpl {
         if !to_disable.is_empty() {
             self.disable_ports(to_disable);
         }
+
+        // Read everything else that the modules monitor
+        for i in 0..self.thermal_models.len() {
+            let port = LogicalPort(i as u8);
+            if !(self.disabled & port).is_empty() {
+                continue;
+            }
+            if let Some(m) = self.thermal_models[i] {
+                self.ddm_update(port, m.interface);
+            }
+        }
     }
 
     fn disable_ports(&mut self, mask: LogicalPortMask) {
@@ -579,6 +705,91 @@ impl idl::InOrderTransceiversImpl for ServerImpl {
         self.set_system_led_state(LedState::Blink);
         Ok(())
     }
+
+    fn get_ddm_flags(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        port: u8,
+    ) -> Result<DdmFlags, idol_runtime::RequestError<TransceiversError>> {
+        self.ddm
+            .get(port as usize)
+            .map(DdmPort::flags)
+            .ok_or_else(|| TransceiversError::InvalidPortNumber.into())
+    }
+
+    fn cdb_start(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        port: u8,
+        image_size: u32,
+        header: LenLimit<Leased<R, [u8]>, CDB_MAX_HEADER>,
+    ) -> Result<(), idol_runtime::RequestError<TransceiversError>> {
+        let mut buf = [0u8; CDB_MAX_HEADER];
+        let buf = &mut buf[..header.len()];
+        header
+            .read_range(0..buf.len(), buf)
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        self.cdb_start_download(port, image_size, buf)
+            .map_err(RequestError::from)
+    }
+
+    fn cdb_write_block(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        port: u8,
+        offset: u32,
+        data: LenLimit<Leased<R, [u8]>, CDB_MAX_BLOCK>,
+    ) -> Result<(), idol_runtime::RequestError<TransceiversError>> {
+        let mut buf = [0u8; CDB_MAX_BLOCK];
+        let buf = &mut buf[..data.len()];
+        data.read_range(0..buf.len(), buf)
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        self.cdb_write_firmware_block(port, offset, buf)
+            .map_err(RequestError::from)
+    }
+
+    fn cdb_complete(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        port: u8,
+    ) -> Result<(), idol_runtime::RequestError<TransceiversError>> {
+        self.cdb_complete_download(port).map_err(RequestError::from)
+    }
+
+    fn cdb_abort(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        port: u8,
+    ) -> Result<(), idol_runtime::RequestError<TransceiversError>> {
+        self.cdb_abort_download(port).map_err(RequestError::from)
+    }
+
+    fn cdb_run(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        port: u8,
+        mode: u8,
+        delay_ms: u16,
+    ) -> Result<(), idol_runtime::RequestError<TransceiversError>> {
+        self.cdb_run_image(port, mode, delay_ms)
+            .map_err(RequestError::from)
+    }
+
+    fn cdb_commit(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        port: u8,
+    ) -> Result<(), idol_runtime::RequestError<TransceiversError>> {
+        self.cdb_commit_image(port).map_err(RequestError::from)
+    }
+
+    fn cdb_status(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        port: u8,
+    ) -> Result<CdbStatus, idol_runtime::RequestError<TransceiversError>> {
+        self.cdb_get_status(port).map_err(RequestError::from)
+    }
 }
 
 impl NotificationHandler for ServerImpl {
@@ -629,7 +840,7 @@ fn main() -> ! {
         let net = task_net_api::Net::from(NET.get_task_id());
         let thermal_api = Thermal::from(THERMAL.get_task_id());
         let sensor_api = Sensor::from(SENSOR.get_task_id());
-        let (tx_data_buf, rx_data_buf) = claim_statics();
+        let (tx_data_buf, rx_data_buf, ddm) = claim_statics();
         let mut server = ServerImpl {
             transceivers,
             leds,
@@ -645,6 +856,8 @@ fn main() -> ! {
             thermal_api,
             sensor_api,
             thermal_models: [None; NUM_PORTS as usize],
+            ddm,
+            cdb: [CdbStatus::default(); NUM_PORTS as usize],
         };
 
         ringbuf_entry!(Trace::LEDInit);
@@ -702,28 +915,35 @@ fn main() -> ! {
                 tx_data_buf.as_mut_slice(),
                 rx_data_buf.as_mut_slice(),
             );
+            server.check_cdb_net(
+                tx_data_buf.as_mut_slice(),
+                rx_data_buf.as_mut_slice(),
+            );
             idol_runtime::dispatch_n(&mut buffer, &mut server);
         }
     }
 }
 ////////////////////////////////////////////////////////////////////////////////
 
-/// Grabs references to the static descriptor/buffer receive rings. Can only be
-/// called once.
+/// Grabs references to the static descriptor/buffer receive rings and DDM
+/// state. Can only be called once.
 pub fn claim_statics() -> (
     &'static mut [u8; MAX_PACKET_SIZE],
     &'static mut [u8; MAX_PACKET_SIZE],
+    &'static mut [DdmPort; NUM_PORTS as usize],
 ) {
     const S: usize = MAX_PACKET_SIZE;
+    const N: usize = NUM_PORTS as usize;
     mutable_statics::mutable_statics! {
         static mut TX_BUF: [u8; S] = [|| 0u8; _];
         static mut RX_BUF: [u8; S] = [|| 0u8; _];
+        static mut DDM: [DdmPort; N] = [|| DdmPort::EMPTY; _];
     }
 }
 ////////////////////////////////////////////////////////////////////////////////
 
 mod idl {
-    use super::{ModuleStatus, TransceiversError};
+    use super::{CdbStatus, DdmFlags, ModuleStatus, TransceiversError};
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
@@ -10,6 +10,10 @@
 //!
 //! All of the API types in `transceiver_messages` operate on **physical**
 //! ports, i.e. an FPGA paired by a physical port index (or mask).
+//!
+//! CMIS CDB firmware upgrades (see `cdb.rs`) use the messages in
+//! `transceiver_cdb_messages` instead, on their own socket, and address
+//! modules by logical port.
 use crate::ServerImpl;
 use drv_sidecar_front_io::{
     transceivers::{
@@ -17,9 +21,13 @@ use drv_sidecar_front_io::{
     },
     Reg,
 };
+use drv_transceivers_api::TransceiversError;
 use hubpack::SerializedSize;
 use ringbuf::*;
 use task_net_api::*;
+use transceiver_cdb_messages::{
+    self as cdb_messages, CdbError, CdbProgress, CdbRequest, CdbResponse,
+};
 use transceiver_messages::{
     mac::MacAddrs,
     message::*,
@@ -59,6 +67,8 @@ enum Trace {
     LedState(ModuleId),
     SetLedState(ModuleId, LedState),
     ClearDisableLatch(ModuleId),
+    CdbRequest(CdbRequest),
+    CdbError(TransceiversError),
 }
 
 ringbuf!(Trace, 16, Trace::None);
@@ -127,32 +137,175 @@ impl ServerImpl {
         if let Some(out_len) = out_len {
             // Modify meta.size based on the output packet size
             meta.size = out_len;
-            if let Err(e) = self.net.send_packet(
-                SocketName::transceivers,
-                meta,
-                &tx_data_buf[..meta.size as usize],
-            ) {
-                // We'll drop packets if the outgoing queue is full;
-                // the host is responsible for retrying.
-                //
-                // Other errors are unexpected and panic.
-                //
-                // This includes ServerRestarted, because the server should only
-                // restart if the watchdog times out, and the watchdog should
-                // not be timing out, because we're literally replying to a
-                // packet here.
-                ringbuf_entry!(Trace::SendError(e));
-                match e {
-                    SendError::QueueFull => (),
-                    SendError::Other
-                    | SendError::ServerRestarted
-                    | SendError::NotYours
-                    | SendError::InvalidVLan => panic!(),
+            self.send_reply(SocketName::transceivers, meta, tx_data_buf);
+        }
+    }
+
+    /// Sends a reply, whose length is given by `meta.size`
+    fn send_reply(
+        &mut self,
+        socket: SocketName,
+        meta: UdpMetadata,
+        tx_data_buf: &[u8],
+    ) {
+        if let Err(e) = self.net.send_packet(
+            socket,
+            meta,
+            &tx_data_buf[..meta.size as usize],
+        ) {
+            // We'll drop packets if the outgoing queue is full;
+            // the host is responsible for retrying.
+            //
+            // Other errors are unexpected and panic.
+            //
+            // This includes ServerRestarted, because the server should only
+            // restart if the watchdog times out, and the watchdog should
+            // not be timing out, because we're literally replying to a
+            // packet here.
+            ringbuf_entry!(Trace::SendError(e));
+            match e {
+                SendError::QueueFull => (),
+                SendError::Other
+                | SendError::ServerRestarted
+                | SendError::NotYours
+                | SendError::InvalidVLan => panic!(),
+            }
+        }
+    }
+
+    /// Attempt to read and handle a CDB request from its `net` socket
+    pub fn check_cdb_net(
+        &mut self,
+        rx_data_buf: &mut [u8],
+        tx_data_buf: &mut [u8],
+    ) {
+        match self.net.recv_packet(
+            SocketName::transceivers_cdb,
+            LargePayloadBehavior::Discard,
+            rx_data_buf,
+        ) {
+            Ok(mut meta) => {
+                let request = &rx_data_buf[..meta.size as usize];
+                if let Some(out_len) =
+                    self.handle_cdb_packet(request, tx_data_buf)
+                {
+                    meta.size = out_len;
+                    self.send_reply(
+                        SocketName::transceivers_cdb,
+                        meta,
+                        tx_data_buf,
+                    );
                 }
             }
+            Err(RecvError::QueueEmpty | RecvError::ServerRestarted) => (),
+            Err(RecvError::NotYours | RecvError::Other) => panic!(),
         }
     }
 
+    /// Handles a single CDB packet, returning the length of the reply written
+    /// into `tx_data_buf` (if any).
+    fn handle_cdb_packet(
+        &mut self,
+        request: &[u8],
+        tx_data_buf: &mut [u8],
+    ) -> Option<u32> {
+        // Without a header, we don't have a message ID to reply to
+        let (header, request) =
+            match hubpack::deserialize::<cdb_messages::Header>(request) {
+                Ok(h) => h,
+                Err(e) => {
+                    ringbuf_entry!(Trace::DeserializeHeaderError(e));
+                    return None;
+                }
+            };
+
+        let response = if header.version != cdb_messages::version::CURRENT {
+            ringbuf_entry!(Trace::WrongVersion(header.version));
+            CdbResponse::Error(CdbError::VersionMismatch {
+                expected: cdb_messages::version::CURRENT,
+                actual: header.version,
+            })
+        } else {
+            match hubpack::deserialize::<CdbRequest>(request) {
+                Ok((r, data)) => self.handle_cdb_request(r, data),
+                Err(e) => {
+                    ringbuf_entry!(Trace::DeserializeError(e));
+                    CdbResponse::Error(CdbError::BadRequest)
+                }
+            }
+        };
+
+        let header = cdb_messages::Header {
+            version: cdb_messages::version::CURRENT,
+            message_id: header.message_id,
+        };
+        let hdr_len = hubpack::serialize(tx_data_buf, &header).unwrap();
+        let msg_len =
+            hubpack::serialize(&mut tx_data_buf[hdr_len..], &response).unwrap();
+        Some((hdr_len + msg_len) as u32)
+    }
+
+    /// Handles a CDB request from the host, with its trailing data in `data`
+    ///
+    /// Commands are only issued here; the host polls with
+    /// `CdbRequest::Status` to find out when each one finishes.
+    fn handle_cdb_request(
+        &mut self,
+        r: CdbRequest,
+        data: &[u8],
+    ) -> CdbResponse {
+        ringbuf_entry!(Trace::CdbRequest(r));
+        let result = match r {
+            CdbRequest::Start { port, image_size } => self
+                .cdb_start_download(port, image_size, data)
+                .map(|()| CdbResponse::Ack),
+            CdbRequest::WriteBlock { port, offset } => self
+                .cdb_write_firmware_block(port, offset, data)
+                .map(|()| CdbResponse::Ack),
+            // The remaining requests carry no data
+            _ if !data.is_empty() => Err(TransceiversError::InvalidCdbLength),
+            CdbRequest::Complete { port } => {
+                self.cdb_complete_download(port).map(|()| CdbResponse::Ack)
+            }
+            CdbRequest::Abort { port } => {
+                self.cdb_abort_download(port).map(|()| CdbResponse::Ack)
+            }
+            CdbRequest::Run {
+                port,
+                mode,
+                delay_ms,
+            } => self
+                .cdb_run_image(port, mode, delay_ms)
+                .map(|()| CdbResponse::Ack),
+            CdbRequest::Commit { port } => {
+                self.cdb_commit_image(port).map(|()| CdbResponse::Ack)
+            }
+            CdbRequest::Status { port } => self.cdb_get_status(port).map(|s| {
+                CdbResponse::Status(CdbProgress {
+                    image_size: s.image_size,
+                    bytes_written: s.bytes_written,
+                    command: s.command,
+                    status: s.status,
+                    downloading: s.downloading != 0,
+                })
+            }),
+        };
+
+        result.unwrap_or_else(|e| {
+            ringbuf_entry!(Trace::CdbError(e));
+            CdbResponse::Error(match e {
+                TransceiversError::InvalidPortNumber => CdbError::InvalidPort,
+                TransceiversError::CdbBusy => CdbError::Busy,
+                TransceiversError::CdbNotSupported => CdbError::NotSupported,
+                TransceiversError::CdbNotDownloading => {
+                    CdbError::NotDownloading
+                }
+                TransceiversError::InvalidCdbLength => CdbError::InvalidLength,
+                _ => CdbError::ModuleError,
+            })
+        })
+    }
+
     /// At this point, Message deserialization has failed, so we can't handle
     /// the packet. We'll look at *just the header* (which should never change),
     /// in the hopes of logging a more detailed error message about a version
@@ -295,6 +448,9 @@ impl ServerImpl {
     /// `HwError` per module), even if there is no additional payload data
     /// expected. We must ensure that we do not overflow the `out` buffer by
     /// keeping the data written under `transceiver_messages::MAX_PAYLOAD_SIZE`.
+    ///
+    /// CMIS CDB firmware upgrades arrive on their own socket instead, and are
+    /// handled by `handle_cdb_request`.
     fn handle_host_request(
         &mut self,
         h: HostRequest,
@@ -22,6 +22,11 @@ use syn::{parse_macro_input, spanned::Spanned, DeriveInput};
 /// will be returned when performing an RPC call against a task that has died /
 /// was restarted.  If no such annotation is present, such an RPC call will
 /// crash the caller (when `unwrap` is called on the return code).
+///
+/// Similarly, if one of the variants is annotated with `#[idol(timeout)]`,
+/// that variant will be returned when an RPC call made under
+/// `userlib::with_send_deadline` (with its `send-deadline` feature) times
+/// out.
 #[proc_macro_derive(IdolError, attributes(idol))]
 pub fn derive(input: TokenStream) -> TokenStream {
     let DeriveInput { ident, data, .. } = parse_macro_input!(input);
@@ -40,6 +45,7 @@ pub fn derive(input: TokenStream) -> TokenStream {
     let mut variant_errors = vec![];
     let mut discriminant = None;
     let mut dead_code = None;
+    let mut timeout = None;
     for v in &data.variants {
         if v.fields != syn::Fields::Unit {
             variant_errors.push(compile_error(
@@ -83,8 +89,7 @@ pub fn derive(input: TokenStream) -> TokenStream {
 
         // Look at attributes that are of the form #[idol...]
         //
-        // Right now, the only one we accept is #[idol(server_death)], but that
-        // could change in the future.
+        // Right now, we accept #[idol(server_death)] and #[idol(timeout)].
         for s in v
             .attrs
             .iter()
@@ -139,6 +144,16 @@ pub fn derive(input: TokenStream) -> TokenStream {
                         }
                         dead_code = Some(v.ident.clone());
                     }
+                    "timeout" => {
+                        if timeout.is_some() {
+                            variant_errors.push(compile_error(
+                                s.span(),
+                                "multiple variants annotated with \
+                                 #[idol(timeout)]",
+                            ));
+                        }
+                        timeout = Some(v.ident.clone());
+                    }
                     i => {
                         variant_errors.push(compile_error(
                             s.span(),
@@ -164,6 +179,15 @@ pub fn derive(input: TokenStream) -> TokenStream {
         }
     });
 
+    let timed_out = abi::TIMED_OUT;
+    let timeout_handler = timeout.map(|timeout| {
+        quote! {
+            if v == #timed_out {
+                return Ok(Self::#timeout);
+            }
+        }
+    });
+
     let output = quote! {
         #( #variant_errors )*
 
@@ -181,6 +205,7 @@ pub fn derive(input: TokenStream) -> TokenStream {
             type Error = ();
             fn try_from(v: u32) -> Result<Self, Self::Error> {
                 #dead_code_handler
+                #timeout_handler
 
                 Self::from_u32(v).ok_or(())
             }
@@ -0,0 +1,231 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Choosing which task dumps to evict.
+//!
+//! Jefe keeps a record of every dump in the dump areas, in a fixed table of
+//! slots.  When a new task dump has no room, it asks its configured
+//! [`DumpPolicy`] which dump (if any) to evict.  That choice is kept apart
+//! from Jefe so that it can be tested on the host, as is [`in_area`], which
+//! confines the release of the evicted dump's area.
+
+#![cfg_attr(not(test), no_std)]
+
+/// What a policy needs to know about a dump.
+pub trait Dump {
+    /// Order in which the dump was taken; older dumps have lower numbers.
+    fn sequence(&self) -> u32;
+    /// Index of the dumped task, or `None` for a whole-system dump.
+    fn task(&self) -> Option<u16>;
+}
+
+/// What to do with a new task dump when there's no room for it, as configured
+/// by `dump-policy` in the app.toml.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DumpPolicy {
+    /// Keep the dumps we have, dropping the new one.
+    KeepFirst,
+    /// Evict the oldest task dump.
+    KeepRecent,
+    /// Evict the oldest dump of the faulting task if it already has this many
+    /// dumps; otherwise, evict the oldest dump of whichever task has the most.
+    TaskQuota(u8),
+}
+
+impl DumpPolicy {
+    /// Returns `true` if a new dump of `task` must evict one of its own
+    /// first, whether or not there's room for it.
+    pub fn over_quota<D: Dump>(&self, dumps: &[Option<D>], task: u16) -> bool {
+        match *self {
+            DumpPolicy::TaskQuota(quota) => {
+                count(dumps, task) >= usize::from(quota)
+            }
+            DumpPolicy::KeepFirst | DumpPolicy::KeepRecent => false,
+        }
+    }
+
+    /// Picks the slot of a dump to evict to make room for a dump of `task`.
+    /// Whole-system dumps are never picked.
+    pub fn victim<D: Dump>(
+        &self,
+        dumps: &[Option<D>],
+        task: u16,
+    ) -> Option<usize> {
+        match *self {
+            DumpPolicy::KeepFirst => None,
+            DumpPolicy::KeepRecent => oldest(dumps, |d| d.task().is_some()),
+            DumpPolicy::TaskQuota(_) => {
+                if self.over_quota(dumps, task) {
+                    return oldest(dumps, |d| d.task() == Some(task));
+                }
+
+                let busiest = dumps
+                    .iter()
+                    .flatten()
+                    .filter_map(Dump::task)
+                    .max_by_key(|&t| count(dumps, t))?;
+                oldest(dumps, |d| d.task() == Some(busiest))
+            }
+        }
+    }
+}
+
+/// Returns the slot of the oldest dump that matches `pred`.
+pub fn oldest<D: Dump>(
+    dumps: &[Option<D>],
+    pred: impl Fn(&D) -> bool,
+) -> Option<usize> {
+    dumps
+        .iter()
+        .enumerate()
+        .filter_map(|(i, d)| d.as_ref().filter(|d| pred(d)).map(|d| (i, d)))
+        .min_by_key(|(_, d)| d.sequence())
+        .map(|(i, _)| i)
+}
+
+fn count<D: Dump>(dumps: &[Option<D>], task: u16) -> usize {
+    dumps
+        .iter()
+        .flatten()
+        .filter(|d| d.task() == Some(task))
+        .count()
+}
+
+/// Returns `true` if a write of `len` bytes at `addr` lies wholly within the
+/// dump area of `area_len` bytes at `start`.
+///
+/// Humpty can only release every dump area from a given one onwards, so to
+/// release only the evicted dump's area, Jefe lets through just the writes
+/// to area headers that this allows.
+pub fn in_area(start: u32, area_len: u32, addr: u32, len: usize) -> bool {
+    // Widened so that areas and writes at the top of memory can't overflow
+    let end = u64::from(start) + u64::from(area_len);
+    addr >= start && u64::from(addr).saturating_add(len as u64) <= end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Copy, Clone, Debug)]
+    struct TestDump(u32, Option<u16>);
+
+    impl Dump for TestDump {
+        fn sequence(&self) -> u32 {
+            self.0
+        }
+        fn task(&self) -> Option<u16> {
+            self.1
+        }
+    }
+
+    /// Evicts dumps for new dumps of `task` until there's nothing left that
+    /// the policy will evict, returning the sequence numbers evicted in
+    /// order.
+    fn evictions(
+        policy: DumpPolicy,
+        mut dumps: Vec<Option<TestDump>>,
+        task: u16,
+    ) -> Vec<u32> {
+        let mut evicted = vec![];
+        while let Some(slot) = policy.victim(&dumps, task) {
+            evicted.push(dumps[slot].take().unwrap().0);
+        }
+        evicted
+    }
+
+    /// Slots deliberately out of sequence order, with a whole-system dump
+    fn dumps() -> Vec<Option<TestDump>> {
+        vec![
+            Some(TestDump(4, Some(1))),
+            Some(TestDump(0, Some(2))),
+            None,
+            Some(TestDump(2, Some(1))),
+            Some(TestDump(1, None)),
+            Some(TestDump(3, Some(1))),
+            Some(TestDump(5, Some(3))),
+        ]
+    }
+
+    #[test]
+    fn keep_first_never_evicts() {
+        assert_eq!(evictions(DumpPolicy::KeepFirst, dumps(), 1), vec![]);
+        assert!(!DumpPolicy::KeepFirst.over_quota(&dumps(), 1));
+    }
+
+    #[test]
+    fn keep_recent_evicts_oldest_task_dumps() {
+        assert_eq!(
+            evictions(DumpPolicy::KeepRecent, dumps(), 1),
+            vec![0, 2, 3, 4, 5]
+        );
+        assert!(!DumpPolicy::KeepRecent.over_quota(&dumps(), 1));
+    }
+
+    #[test]
+    fn task_quota_evicts_own_dumps_over_quota() {
+        let policy = DumpPolicy::TaskQuota(2);
+        assert!(policy.over_quota(&dumps(), 1));
+        assert!(!policy.over_quota(&dumps(), 2));
+
+        // Task 1 has three dumps, so its oldest goes first; then it's at
+        // quota, and still the busiest task, until it's down to one dump.
+        let mut d = dumps();
+        let slot = policy.victim(&d, 1).unwrap();
+        assert_eq!(d[slot].take().unwrap().0, 2);
+        assert!(policy.over_quota(&d, 1));
+        let slot = policy.victim(&d, 1).unwrap();
+        assert_eq!(d[slot].take().unwrap().0, 3);
+        assert!(!policy.over_quota(&d, 1));
+    }
+
+    #[test]
+    fn task_quota_evicts_busiest_task_under_quota() {
+        // Task 2 is under quota, so task 1 (the busiest) gives up its dumps,
+        // oldest first, until every task has one; ties then go to the task
+        // whose dump sits in the last slot.
+        assert_eq!(
+            evictions(DumpPolicy::TaskQuota(4), dumps(), 2),
+            vec![2, 3, 5, 0, 4]
+        );
+    }
+
+    #[test]
+    fn whole_system_dumps_are_never_evicted() {
+        let d = vec![Some(TestDump(0, None)), Some(TestDump(1, None))];
+        for policy in [
+            DumpPolicy::KeepFirst,
+            DumpPolicy::KeepRecent,
+            DumpPolicy::TaskQuota(1),
+        ] {
+            assert_eq!(evictions(policy, d.clone(), 0), vec![]);
+        }
+    }
+
+    #[test]
+    fn writes_confined_to_area() {
+        let (start, len) = (0x1000, 0x100);
+
+        // The area's own header, and the whole area
+        assert!(in_area(start, len, 0x1000, 16));
+        assert!(in_area(start, len, 0x1000, 0x100));
+        assert!(in_area(start, len, 0x10f0, 16));
+
+        // The next area's header, the previous area's, and writes that
+        // straddle either edge
+        assert!(!in_area(start, len, 0x1100, 16));
+        assert!(!in_area(start, len, 0x0ff0, 16));
+        assert!(!in_area(start, len, 0x0fff, 2));
+        assert!(!in_area(start, len, 0x10ff, 2));
+        assert!(!in_area(start, len, 0x1000, 0x101));
+    }
+
+    #[test]
+    fn area_at_top_of_memory() {
+        let start = 0xffff_ff00;
+        assert!(in_area(start, 0x100, 0xffff_fff0, 16));
+        assert!(!in_area(start, 0x100, 0xffff_fff0, 17));
+        assert!(!in_area(start, 0x100, 0xffff_ffff, usize::MAX));
+    }
+}
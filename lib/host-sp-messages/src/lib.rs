@@ -90,8 +90,8 @@ pub enum HostToSp {
     // Host ack'ing SP task startup.
     AckSpStart,
     GetAlert,
-    RotRequest, // Followed by a binary data blob (the request)
-    RotAddHostMeasurements, // Followed by a binary data blob?
+    RotRequest, // Followed by a binary data blob (a `HostRotRequest`)
+    RotAddHostMeasurements, // Followed by a binary data blob (measurements)
     /// Get as much phase 2 data as we can from the image identified by `hash`
     /// starting at `offset`.
     GetPhase2Data {
@@ -147,13 +147,13 @@ pub enum SpToHost {
         status: Status,
         startup: HostStartupOptions,
     },
-    // Followed by a binary data blob (the alert), or maybe action is another
-    // hubpack-encoded enum?
+    // If `action` is nonzero, it is a `HostAlertKind` and will be followed by
+    // a binary blob of a hubpack-serialized `HostAlert`; if it is zero, there
+    // are no alerts pending and there is no subsequent binary blob.
     Alert {
-        // details TBD
         action: u8,
     },
-    // Followed by a binary data blob (the response)
+    // Followed by a binary data blob (the response; see `HostRotRequest`)
     RotResponse,
     // Followed by a binary data blob (the data)
     Phase2Data,
@@ -211,6 +211,197 @@ pub enum KeySetResult {
     DataTooLong,
 }
 
+/// A request for the RoT, carried as a hubpack-encoded blob after
+/// [`HostToSp::RotRequest`].
+///
+/// The response is a blob after [`SpToHost::RotResponse`], holding a
+/// hubpack-encoded `Result<HostRotResponse, HostRotError>`; for
+/// `HostRotResponse::Cert`, `HostRotResponse::Log` and
+/// `HostRotResponse::Attest`, that is followed by the requested bytes.
+/// [`HostToSp::RotAddHostMeasurements`] is answered the same way.
+///
+/// These **cannot be reordered**; the host and SP must agree on them.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, SerializedSize,
+)]
+pub enum HostRotRequest {
+    /// Number of certs in the attestation cert chain
+    CertChainLen,
+    /// Length of the cert at `index` in the chain
+    CertLen { index: u32 },
+    /// `size` bytes of the cert at `index` in the chain, starting at
+    /// `offset`. At most [`MAX_ROT_RESPONSE_DATA_LEN`] bytes are returned.
+    Cert { index: u32, offset: u32, size: u32 },
+    /// Length of the serialized measurement log
+    LogLen,
+    /// `size` bytes of the serialized measurement log, starting at `offset`.
+    /// At most [`MAX_ROT_RESPONSE_DATA_LEN`] bytes are returned.
+    Log { offset: u32, size: u32 },
+    /// Ed25519 signature over the SHA3-256 digest of the measurement log
+    /// followed by `nonce`, made by the RoT with its alias key.
+    Attest { nonce: [u8; 32] },
+}
+
+/// Maximum number of bytes returned after a `HostRotResponse::Cert` or
+/// `HostRotResponse::Log`; this is what the SP can fetch from the RoT at once.
+pub const MAX_ROT_RESPONSE_DATA_LEN: usize = 512;
+
+/// A successful response to a [`HostRotRequest`] or to
+/// [`HostToSp::RotAddHostMeasurements`].
+///
+/// These **cannot be reordered**; the host and SP must agree on them.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, SerializedSize,
+)]
+pub enum HostRotResponse {
+    CertChainLen(u32),
+    CertLen(u32),
+    /// Followed by this many cert bytes, which may be fewer than requested.
+    Cert(u32),
+    LogLen(u32),
+    /// Followed by this many log bytes, which may be fewer than requested.
+    Log(u32),
+    /// This many measurements were recorded in the attestation log.
+    MeasurementsRecorded(u32),
+    /// Followed by this many signature bytes.
+    Attest(u32),
+}
+
+/// Failures of a [`HostRotRequest`] or of
+/// [`HostToSp::RotAddHostMeasurements`].
+///
+/// These **cannot be reordered**; the host and SP must agree on them.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, SerializedSize,
+)]
+pub enum HostRotError {
+    /// The request blob could not be decoded
+    BadRequest,
+    /// The RoT has no attestation certs
+    NoCerts,
+    InvalidCertIndex,
+    /// The requested range is outside the cert or log
+    OutOfRange,
+    /// The attestation log has no room for more measurements; any
+    /// measurements before the one that didn't fit were recorded.
+    LogFull,
+    /// The RoT reported some other attestation failure
+    AttestFailed,
+    /// We could not communicate with the RoT
+    CommsFailed,
+    /// Every measurement in the attestation log must use the same hash
+    /// algorithm; any measurements before the mismatched one were recorded.
+    AlgorithmMismatch,
+}
+
+/// Hash algorithms of host measurements.
+///
+/// These **cannot be reordered**; the host and SP must agree on them.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, SerializedSize,
+)]
+pub enum HostHashAlgorithm {
+    Sha3_256,
+    Sha256,
+    Sha384,
+}
+
+impl HostHashAlgorithm {
+    pub const fn digest_len(&self) -> usize {
+        match self {
+            HostHashAlgorithm::Sha3_256 => 32,
+            HostHashAlgorithm::Sha256 => 32,
+            HostHashAlgorithm::Sha384 => 48,
+        }
+    }
+}
+
+/// Maximum length of the tag describing a host measurement
+pub const MAX_HOST_MEASUREMENT_TAG_LEN: usize = 16;
+
+/// A host measurement to record in the RoT's attestation log.
+///
+/// The blob after [`HostToSp::RotAddHostMeasurements`] is a sequence of
+/// measurements, each a hubpack-encoded `HostMeasurement` followed by a
+/// digest of `algorithm`'s length and then `tag_len` bytes of tag.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, SerializedSize,
+)]
+pub struct HostMeasurement {
+    /// Must be the log's algorithm, which the RoT's image fixes and lists in
+    /// the Spec ID event at the start of the log (see [`HostRotRequest::Log`]).
+    /// The log holds that one algorithm only; Gimlet's RoT uses SHA-256.
+    pub algorithm: HostHashAlgorithm,
+    /// Event type from the TCG PC Client Platform Firmware Profile, e.g.
+    /// 0xf for `EV_NONHOST_CODE`
+    pub event_type: u32,
+    /// Length of the tag describing what was measured, at most
+    /// [`MAX_HOST_MEASUREMENT_TAG_LEN`]
+    pub tag_len: u8,
+}
+
+/// Conditions the SP raises with the host, so that it can react (e.g., by
+/// shutting down gracefully) before the SP takes action itself.
+///
+/// The host learns that alerts are pending from [`Status::ALERTS_AVAILABLE`]
+/// and fetches them one at a time with [`HostToSp::GetAlert`]; the SP replies
+/// with [`SpToHost::Alert`], whose `action` is the alert's kind (or 0 if there
+/// are no more alerts), followed by a hubpack-encoded [`HostAlert`].
+///
+/// These **cannot be reordered**; the host and SP must agree on them.  New
+/// variants may be added to the end.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Deserialize_repr,
+    Serialize_repr,
+    num_derive::FromPrimitive,
+)]
+#[repr(u8)]
+pub enum HostAlertKind {
+    /// A thermal zone has exceeded its critical temperature; the SP will
+    /// power off the host if it doesn't cool down.  `index` is the zone, or
+    /// 255 if the CPU asserted THERMTRIP and the sequencer has already cut
+    /// its power.
+    ThermalEmergency = 1,
+    /// The SP is about to power off the host.  If it has to do so at once,
+    /// the host only sees this (with its `age_ms`) once it's powered back on.
+    ImpendingPowerOff = 2,
+    /// A power supply has lost its input or failed.
+    PsuLoss = 3,
+    /// A fan has failed.
+    FanFailure = 4,
+    /// An update for the RoT is staged, and will take effect when it is next
+    /// reset; `index` is the slot holding the update.
+    RotUpdatePending = 5,
+    /// A sensor has crossed one of its critical alarm thresholds; `index` is
+    /// its sensor ID, or 255 if that doesn't fit in a byte.
+    SensorCritical = 6,
+}
+
+// We're using serde_repr for `HostAlertKind`, so we have to supply our own
+// `SerializedSize` impl (since hubpack assumes it's serializing enum variants
+// itself as raw u8s).
+impl hubpack::SerializedSize for HostAlertKind {
+    const MAX_SIZE: usize = core::mem::size_of::<HostAlertKind>();
+}
+
+/// Data payload following an [`SpToHost::Alert`]
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, SerializedSize,
+)]
+pub struct HostAlert {
+    pub kind: HostAlertKind,
+    /// Which instance of the affected component (thermal zone, fan, power
+    /// supply) raised the alert, if there's more than one; otherwise 0.
+    pub index: u8,
+    /// How long ago the condition was raised, in milliseconds
+    pub age_ms: u64,
+}
+
 /// Results for an inventory data request
 ///
 /// These **cannot be reordered**; the host and SP must agree on them.
@@ -854,6 +1045,55 @@ mod tests {
         }
     }
 
+    #[test]
+    fn host_measurement_layout() {
+        let mut buf = [0; HostMeasurement::MAX_SIZE];
+        let measurement = HostMeasurement {
+            algorithm: HostHashAlgorithm::Sha384,
+            event_type: 0xf,
+            tag_len: 3,
+        };
+        let n = hubpack::serialize(&mut buf[..], &measurement).unwrap();
+        assert_eq!(&buf[..n], &[0x2, 0xf, 0, 0, 0, 3]);
+    }
+
+    #[test]
+    fn host_hash_algorithm_values() {
+        let mut buf = [0; HostHashAlgorithm::MAX_SIZE];
+
+        for (expected_cmd, variant) in [
+            (0x0, HostHashAlgorithm::Sha3_256),
+            (0x1, HostHashAlgorithm::Sha256),
+            (0x2, HostHashAlgorithm::Sha384),
+        ] {
+            let n = hubpack::serialize(&mut buf[..], &variant).unwrap();
+            assert!(n <= 1);
+            assert_eq!(expected_cmd, buf[0]);
+        }
+    }
+
+    #[test]
+    fn host_alert_kind_values() {
+        let mut buf = [0; HostAlertKind::MAX_SIZE];
+
+        for (expected_cmd, variant) in [
+            (0x1, HostAlertKind::ThermalEmergency),
+            (0x2, HostAlertKind::ImpendingPowerOff),
+            (0x3, HostAlertKind::PsuLoss),
+            (0x4, HostAlertKind::FanFailure),
+            (0x5, HostAlertKind::RotUpdatePending),
+            (0x6, HostAlertKind::SensorCritical),
+        ] {
+            let n = hubpack::serialize(&mut buf[..], &variant).unwrap();
+            assert_eq!(n, 1);
+            assert_eq!(expected_cmd, buf[0]);
+            assert_eq!(
+                Some(variant),
+                num_traits::FromPrimitive::from_u8(expected_cmd)
+            );
+        }
+    }
+
     #[test]
     fn inventory_data() {
         let mut v = [0; 512];
@@ -0,0 +1,182 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Deciding when to restart a faulted task.
+//!
+//! Jefe applies a [`RestartPolicy`] to each fault of a task it would restart,
+//! counting restarts within a window and backing off between them.  That
+//! bookkeeping is kept apart from Jefe so that it can be tested on the host.
+
+#![cfg_attr(not(test), no_std)]
+
+/// Action taken when a task exceeds the restart budget in its
+/// [`RestartPolicy`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Default)]
+pub enum Escalation {
+    /// Stop restarting the task and hold it at its fault.
+    #[default]
+    Hold,
+    /// Reset the entire system.
+    Reset,
+}
+
+/// Policy governing how a faulted task is restarted, as configured in the
+/// `restart-policy` section of Jefe's config.  The default policy restarts a
+/// task immediately, without limit.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RestartPolicy {
+    pub initial_backoff_ms: u32,
+    pub max_backoff_ms: u32,
+    pub max_restarts: Option<u32>,
+    pub window_ms: u32,
+    pub escalation: Escalation,
+}
+
+impl RestartPolicy {
+    /// Returns the delay to apply before restarting a task that has already
+    /// been restarted `n` times within the current window.
+    fn backoff(&self, n: u32) -> u64 {
+        let backoff = u64::from(self.initial_backoff_ms) << n.min(32);
+        backoff.min(u64::from(self.max_backoff_ms))
+    }
+}
+
+/// What to do with a task that has just faulted.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RestartAction {
+    Now,
+    At(u64),
+    Escalate(Escalation),
+}
+
+/// The restarts a task has used up in its current window.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RestartWindow {
+    /// Time of the fault that opened the window.
+    pub start: u64,
+    /// Number of restarts we've granted within the window.
+    pub restarts: u32,
+}
+
+impl RestartWindow {
+    /// Applies `policy` to a fault at time `now` of a task that we would
+    /// otherwise restart, and charges the restart to the window.
+    ///
+    /// Faults of a held task should not come through here: they would use up
+    /// its budget, and releasing it would then escalate at its next fault.
+    pub fn admit(&mut self, policy: &RestartPolicy, now: u64) -> RestartAction {
+        if self.restarts == 0
+            || now.saturating_sub(self.start) >= u64::from(policy.window_ms)
+        {
+            self.start = now;
+            self.restarts = 0;
+        }
+
+        if let Some(max) = policy.max_restarts {
+            if self.restarts >= max {
+                return RestartAction::Escalate(policy.escalation);
+            }
+        }
+
+        let backoff = policy.backoff(self.restarts);
+        self.restarts += 1;
+
+        if backoff == 0 {
+            RestartAction::Now
+        } else {
+            RestartAction::At(now + backoff)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> RestartPolicy {
+        RestartPolicy {
+            initial_backoff_ms: 10,
+            max_backoff_ms: 50,
+            max_restarts: Some(4),
+            window_ms: 1000,
+            escalation: Escalation::Reset,
+        }
+    }
+
+    #[test]
+    fn default_restarts_immediately_forever() {
+        let policy = RestartPolicy::default();
+        let mut window = RestartWindow::default();
+        for now in 0..100 {
+            assert_eq!(window.admit(&policy, now), RestartAction::Now);
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let policy = RestartPolicy {
+            max_restarts: None,
+            ..policy()
+        };
+        let mut window = RestartWindow::default();
+        let delays: Vec<_> = (0..6)
+            .map(|_| match window.admit(&policy, 100) {
+                RestartAction::At(when) => when - 100,
+                a => panic!("unexpected {a:?}"),
+            })
+            .collect();
+        assert_eq!(delays, [10, 20, 40, 50, 50, 50]);
+    }
+
+    #[test]
+    fn escalates_past_max_restarts() {
+        let policy = policy();
+        let mut window = RestartWindow::default();
+        for _ in 0..4 {
+            assert!(matches!(window.admit(&policy, 10), RestartAction::At(_)));
+        }
+        assert_eq!(
+            window.admit(&policy, 20),
+            RestartAction::Escalate(Escalation::Reset)
+        );
+        // Escalation doesn't charge the window.
+        assert_eq!(window.restarts, 4);
+    }
+
+    #[test]
+    fn new_window_resets_budget_and_backoff() {
+        let policy = policy();
+        let mut window = RestartWindow::default();
+        for _ in 0..4 {
+            window.admit(&policy, 10);
+        }
+
+        // The window is measured from the fault that opened it, at 10.
+        assert_eq!(
+            window.admit(&policy, 1009),
+            RestartAction::Escalate(Escalation::Reset)
+        );
+        assert_eq!(window.admit(&policy, 1010), RestartAction::At(1020));
+        assert_eq!(window.start, 1010);
+        assert_eq!(window.restarts, 1);
+    }
+
+    #[test]
+    fn window_opens_at_first_fault() {
+        let policy = policy();
+        let mut window = RestartWindow::default();
+
+        // A task that first faults after boot opens its window then, rather
+        // than inheriting one that opened at time zero.
+        assert_eq!(window.admit(&policy, 500), RestartAction::At(510));
+        assert_eq!(window.start, 500);
+        for _ in 0..3 {
+            window.admit(&policy, 600);
+        }
+        assert_eq!(
+            window.admit(&policy, 1200),
+            RestartAction::Escalate(Escalation::Reset)
+        );
+    }
+}
@@ -0,0 +1,146 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! SMBus Packet Error Checking.
+//!
+//! The PEC is a CRC-8 (polynomial `x^8 + x^2 + x + 1`, zero initial value)
+//! over every byte of a transaction, from the first START to the STOP --
+//! including each address byte, and the count byte of a block read.  The I2C
+//! server feeds [`Pec`] the bytes as they go over the wire; this is kept apart
+//! from the server so that it can be tested on the host.
+
+#![cfg_attr(not(test), no_std)]
+
+static SMBUS_PEC: crc::Crc<u8> = crc::Crc::<u8>::new(&crc::CRC_8_SMBUS);
+
+/// A PEC accumulated over the bytes of a transaction so far.
+#[derive(Clone)]
+pub struct Pec(crc::Digest<'static, u8>);
+
+impl Default for Pec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pec {
+    pub fn new() -> Self {
+        Self(SMBUS_PEC.digest())
+    }
+
+    /// Accounts for the address byte of a START (or repeated START) that
+    /// begins a write to the 7-bit address `addr`.
+    pub fn write_address(&mut self, addr: u8) {
+        self.0.update(&[addr << 1]);
+    }
+
+    /// Accounts for the address byte of a START (or repeated START) that
+    /// begins a read from the 7-bit address `addr`.
+    pub fn read_address(&mut self, addr: u8) {
+        self.0.update(&[(addr << 1) | 1]);
+    }
+
+    /// Accounts for data bytes, written or read.
+    pub fn update(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    /// Returns the PEC byte for the transaction so far: the byte to send
+    /// after a write, or to compare against the one a device sends after a
+    /// read.
+    pub fn value(&self) -> u8 {
+        self.0.clone().finalize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Apart from the CRC catalogue's check value, these vectors were worked
+    // by hand from the polynomial, one bit at a time.
+
+    #[test]
+    fn check_value() {
+        let mut pec = Pec::new();
+        pec.update(b"123456789");
+        assert_eq!(pec.value(), 0xf4);
+    }
+
+    #[test]
+    fn write_byte() {
+        // START 0x5a W | command 0x01 | data 0x55 | PEC
+        let mut pec = Pec::new();
+        pec.write_address(0x5a);
+        pec.update(&[0x01, 0x55]);
+        assert_eq!(pec.value(), 0xf8);
+    }
+
+    #[test]
+    fn receive_byte() {
+        // START 0x40 R | data 0x12 | PEC
+        let mut pec = Pec::new();
+        pec.read_address(0x40);
+        pec.update(&[0x12]);
+        assert_eq!(pec.value(), 0xdd);
+    }
+
+    #[test]
+    fn read_word_with_repeated_start() {
+        // START 0x40 W | command 0x8b | RESTART 0x40 R | 0x12 0x34 | PEC
+        let mut pec = Pec::new();
+        pec.write_address(0x40);
+        pec.update(&[0x8b]);
+        pec.read_address(0x40);
+        pec.update(&[0x12, 0x34]);
+        assert_eq!(pec.value(), 0xbd);
+    }
+
+    #[test]
+    fn block_read() {
+        // START 0x40 W | command 0x9a | RESTART 0x40 R | count 3 | "ABC" | PEC
+        let mut pec = Pec::new();
+        pec.write_address(0x40);
+        pec.update(&[0x9a]);
+        pec.read_address(0x40);
+        pec.update(&[3]);
+        pec.update(b"ABC");
+        assert_eq!(pec.value(), 0x78);
+    }
+
+    #[test]
+    fn value_does_not_consume() {
+        // The server takes the value mid-transaction, then keeps going.
+        let mut pec = Pec::new();
+        pec.write_address(0x40);
+        let before = pec.value();
+        assert_eq!(pec.value(), before);
+        pec.update(&[0x8b]);
+        assert_ne!(pec.value(), before);
+    }
+
+    #[test]
+    fn corruption_is_detected() {
+        // A PEC over the bytes it follows leaves a zero remainder; flipping
+        // any single bit of the transaction changes the PEC.
+        let bytes = [0x80, 0x8b, 0x81, 0x12, 0x34];
+        let mut pec = Pec::new();
+        pec.update(&bytes);
+        let good = pec.value();
+
+        let mut check = pec.clone();
+        check.update(&[good]);
+        assert_eq!(check.value(), 0);
+
+        for i in 0..bytes.len() {
+            for bit in 0..8 {
+                let mut corrupt = bytes;
+                corrupt[i] ^= 1 << bit;
+                let mut pec = Pec::new();
+                pec.update(&corrupt);
+                assert_ne!(pec.value(), good, "byte {i} bit {bit}");
+            }
+        }
+    }
+}
@@ -0,0 +1,241 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Serialization of measurement logs in the "crypto agile" event log format
+//! of the TCG PC Client Platform Firmware Profile (rev 1.05, Section 10).
+//!
+//! A log starts with a `TCG_PCClientPCREvent` holding the
+//! `TCG_EfiSpecIDEvent`, which lists the log's algorithms, followed by a
+//! `TCG_PCR_EVENT2` per measurement. All integers are little-endian.
+//!
+//! This writes logs of a single algorithm, with every event logged against
+//! PCR 0; the attest task has no PCRs, and only the digest that each event
+//! was recorded with.
+
+#![cfg_attr(not(test), no_std)]
+
+/// `TPM_ALG_ID`s from the TCG Algorithm Registry
+pub const TPM_ALG_SHA256: u16 = 0x000b;
+pub const TPM_ALG_SHA384: u16 = 0x000c;
+pub const TPM_ALG_SHA3_256: u16 = 0x0027;
+
+/// `EV_NO_ACTION`, the event type of the spec ID event
+pub const EV_NO_ACTION: u32 = 0x3;
+
+const SPEC_ID_SIGNATURE: &[u8; 16] = b"Spec ID Event03\0";
+
+/// Size of `TCG_EfiSpecIDEvent`: signature, platform class, four version
+/// bytes, algorithm count, a single (algorithm, size) pair and an empty
+/// vendor info.
+const SPEC_ID_EVENT_DATA_SIZE: usize =
+    SPEC_ID_SIGNATURE.len() + 4 + 4 + 4 + 4 + 1;
+
+/// Size of the `TCG_PCClientPCREvent` that holds the spec ID event: PCR
+/// index, event type, SHA-1 sized digest, event size and the event itself.
+pub const SPEC_ID_EVENT_SIZE: usize = 4 + 4 + 20 + 4 + SPEC_ID_EVENT_DATA_SIZE;
+
+/// Returns the size of a `TCG_PCR_EVENT2` with a single digest: PCR index,
+/// event type, digest count, algorithm ID, digest, event size and the event
+/// data.
+pub const fn event_size(digest_size: usize, data_size: usize) -> usize {
+    4 + 4 + 4 + 2 + digest_size + 4 + data_size
+}
+
+/// The buffer given to a [`Writer`] is too small for the log
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BufferTooSmall;
+
+/// Appends events to a buffer
+pub struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+
+    /// Returns the number of bytes written
+    pub fn finish(self) -> usize {
+        self.len
+    }
+
+    fn put(&mut self, bytes: &[u8]) -> Result<(), BufferTooSmall> {
+        let end = self.len + bytes.len();
+        self.buf
+            .get_mut(self.len..end)
+            .ok_or(BufferTooSmall)?
+            .copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+
+    fn put_u8(&mut self, v: u8) -> Result<(), BufferTooSmall> {
+        self.put(&[v])
+    }
+
+    fn put_u16(&mut self, v: u16) -> Result<(), BufferTooSmall> {
+        self.put(&v.to_le_bytes())
+    }
+
+    fn put_u32(&mut self, v: u32) -> Result<(), BufferTooSmall> {
+        self.put(&v.to_le_bytes())
+    }
+
+    /// Writes the `TCG_PCClientPCREvent` that must start a log of events
+    /// hashed with the algorithm `algorithm_id`, whose digests are
+    /// `digest_size` bytes
+    pub fn spec_id_event(
+        &mut self,
+        algorithm_id: u16,
+        digest_size: u16,
+    ) -> Result<(), BufferTooSmall> {
+        self.put_u32(0)?;
+        self.put_u32(EV_NO_ACTION)?;
+        self.put(&[0; 20])?;
+        self.put_u32(SPEC_ID_EVENT_DATA_SIZE as u32)?;
+
+        self.put(SPEC_ID_SIGNATURE)?;
+        // platformClass: client
+        self.put_u32(0)?;
+        // specVersionMinor, specVersionMajor, specErrata
+        self.put(&[0, 2, 0])?;
+        // uintnSize: UINT32
+        self.put_u8(1)?;
+        self.put_u32(1)?;
+        self.put_u16(algorithm_id)?;
+        self.put_u16(digest_size)?;
+        // vendorInfoSize
+        self.put_u8(0)
+    }
+
+    /// Writes a `TCG_PCR_EVENT2` holding a single digest
+    pub fn event(
+        &mut self,
+        event_type: u32,
+        algorithm_id: u16,
+        digest: &[u8],
+        data: &[u8],
+    ) -> Result<(), BufferTooSmall> {
+        self.put_u32(0)?;
+        self.put_u32(event_type)?;
+        self.put_u32(1)?;
+        self.put_u16(algorithm_id)?;
+        self.put(digest)?;
+        self.put_u32(data.len() as u32)?;
+        self.put(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The expected bytes below are laid out field by field from the
+    // definitions in the PC Client Platform Firmware Profile, Section 10.2.
+
+    /// A spec ID event for a SHA-256 log
+    const SHA256_SPEC_ID_EVENT: [u8; 65] = [
+        // TCG_PCClientPCREvent
+        0x00, 0x00, 0x00, 0x00, // pcrIndex
+        0x03, 0x00, 0x00, 0x00, // eventType: EV_NO_ACTION
+        // digest: SHA-1 sized, and zero
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+        0x21, 0x00, 0x00, 0x00, // eventDataSize: 33
+        // TCG_EfiSpecIdEvent
+        b'S', b'p', b'e', b'c', b' ', b'I', b'D', b' ', // signature
+        b'E', b'v', b'e', b'n', b't', b'0', b'3', 0x00, //
+        0x00, 0x00, 0x00, 0x00, // platformClass: client
+        0x00, // specVersionMinor
+        0x02, // specVersionMajor
+        0x00, // specErrata
+        0x01, // uintnSize: UINT32
+        0x01, 0x00, 0x00, 0x00, // numberOfAlgorithms
+        0x0b, 0x00, // digestSizes[0].algorithmId: TPM_ALG_SHA256
+        0x20, 0x00, // digestSizes[0].digestSize: 32
+        0x00, // vendorInfoSize
+    ];
+
+    #[test]
+    fn spec_id_event() {
+        assert_eq!(SPEC_ID_EVENT_SIZE, SHA256_SPEC_ID_EVENT.len());
+
+        let mut buf = [0xff; 128];
+        let mut w = Writer::new(&mut buf);
+        w.spec_id_event(TPM_ALG_SHA256, 32).unwrap();
+        let len = w.finish();
+        assert_eq!(&buf[..len], &SHA256_SPEC_ID_EVENT);
+
+        // Only the algorithm differs between logs
+        let mut buf = [0xff; 128];
+        let mut w = Writer::new(&mut buf);
+        w.spec_id_event(TPM_ALG_SHA384, 48).unwrap();
+        let len = w.finish();
+        assert_eq!(&buf[..len - 5], &SHA256_SPEC_ID_EVENT[..60]);
+        assert_eq!(&buf[len - 5..len], &[0x0c, 0x00, 0x30, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn event() {
+        let digest = [0xa5; 32];
+        let mut expected = vec![
+            0x00, 0x00, 0x00, 0x00, // pcrIndex
+            0x06, 0x00, 0x00, 0x00, // eventType: EV_EVENT_TAG
+            0x01, 0x00, 0x00, 0x00, // digests.count
+            0x27, 0x00, // digests.digests[0].hashAlg: TPM_ALG_SHA3_256
+        ];
+        expected.extend_from_slice(&digest);
+        expected.extend_from_slice(&[
+            0x03, 0x00, 0x00, 0x00, // eventSize
+            b'a', b'b', b'c', // event
+        ]);
+
+        let mut buf = [0xff; 128];
+        let mut w = Writer::new(&mut buf);
+        w.event(0x6, TPM_ALG_SHA3_256, &digest, b"abc").unwrap();
+        let len = w.finish();
+        assert_eq!(&buf[..len], &expected[..]);
+        assert_eq!(len, event_size(digest.len(), 3));
+    }
+
+    #[test]
+    fn log() {
+        let digest = [0x11; 48];
+        let mut buf = [0xff; 256];
+        let mut w = Writer::new(&mut buf);
+        w.spec_id_event(TPM_ALG_SHA384, 48).unwrap();
+        w.event(0x0d, TPM_ALG_SHA384, &digest, &[]).unwrap();
+        let vendor_event = 0x8000_0001;
+        w.event(vendor_event, TPM_ALG_SHA384, &digest, &[0x42])
+            .unwrap();
+        let len = w.finish();
+        assert_eq!(
+            len,
+            SPEC_ID_EVENT_SIZE + event_size(48, 0) + event_size(48, 1)
+        );
+
+        // The events follow each other directly
+        let second = SPEC_ID_EVENT_SIZE + event_size(48, 0);
+        assert_eq!(&buf[second - 4..second], &[0, 0, 0, 0]);
+        assert_eq!(&buf[second + 4..second + 8], &[0x01, 0x00, 0x00, 0x80]);
+        assert_eq!(&buf[len - 5..len], &[0x01, 0x00, 0x00, 0x00, 0x42]);
+    }
+
+    #[test]
+    fn buffer_too_small() {
+        let mut buf = [0; SPEC_ID_EVENT_SIZE - 1];
+        let mut w = Writer::new(&mut buf);
+        assert_eq!(w.spec_id_event(TPM_ALG_SHA256, 32), Err(BufferTooSmall));
+
+        let mut buf = [0; SPEC_ID_EVENT_SIZE + 1];
+        let mut w = Writer::new(&mut buf);
+        w.spec_id_event(TPM_ALG_SHA256, 32).unwrap();
+        assert_eq!(
+            w.event(0x6, TPM_ALG_SHA256, &[0; 32], &[]),
+            Err(BufferTooSmall)
+        );
+    }
+}
@@ -0,0 +1,151 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Messages for driving CMIS CDB firmware upgrades of Sidecar's transceivers
+//! from the host.
+//!
+//! The rest of the transceiver protocol is defined by the
+//! `transceiver-messages` crate; these messages travel on their own UDP port
+//! so that they can be versioned independently of it.  Each packet is a
+//! [`Header`] followed by a [`CdbRequest`] (from the host) or
+//! [`CdbResponse`] (from the SP), then any trailing data the variant calls
+//! for.  The SP replies to every request that has a valid header with the
+//! same `message_id`.
+
+#![cfg_attr(not(test), no_std)]
+
+use hubpack::SerializedSize;
+use serde::{Deserialize, Serialize};
+
+pub use hubpack::error::Error as HubpackError;
+
+pub mod version {
+    pub const V1: u8 = 1;
+    pub const CURRENT: u8 = V1;
+}
+
+/// Longest trailing data accepted with a request: the vendor header sent with
+/// `Start`, or the image block sent with `WriteBlock`.
+pub const MAX_DATA_SIZE: usize = 116;
+
+/// Longest packet in either direction.
+pub const MAX_PACKET_SIZE: usize =
+    Header::MAX_SIZE + CdbRequest::MAX_SIZE + MAX_DATA_SIZE;
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, SerializedSize,
+)]
+pub struct Header {
+    pub version: u8,
+    pub message_id: u64,
+}
+
+/// A request from the host, addressed to a single logical port.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, SerializedSize,
+)]
+pub enum CdbRequest {
+    /// Starts a firmware download; followed by the vendor's image header.
+    Start { port: u8, image_size: u32 },
+    /// Writes part of the image at `offset`; followed by the block itself.
+    WriteBlock { port: u8, offset: u32 },
+    /// Tells the module that the whole image has been written.
+    Complete { port: u8 },
+    /// Abandons a download in progress.
+    Abort { port: u8 },
+    /// Runs the downloaded image, after `delay_ms`, using a CMIS reset mode.
+    Run { port: u8, mode: u8, delay_ms: u16 },
+    /// Commits the running image, so that it's used after a reset.
+    Commit { port: u8 },
+    /// Reports the progress of the most recent command.
+    Status { port: u8 },
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, SerializedSize,
+)]
+pub enum CdbResponse {
+    /// The command was issued; poll with `Status` until it's done.
+    Ack,
+    Status(CdbProgress),
+    Error(CdbError),
+}
+
+/// Progress of a module's CDB engine, as far as the SP knows it.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, SerializedSize,
+)]
+pub struct CdbProgress {
+    /// Size of the image being (or last) downloaded
+    pub image_size: u32,
+    /// Bytes of the image written so far
+    pub bytes_written: u32,
+    /// Most recently issued CDB command
+    pub command: u16,
+    /// Raw `CdbStatus1` register: busy (bit 7), failed (bit 6) and a
+    /// status code (bits 5-0)
+    pub status: u8,
+    pub downloading: bool,
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, SerializedSize,
+)]
+pub enum CdbError {
+    /// The request has a version we don't understand
+    VersionMismatch {
+        expected: u8,
+        actual: u8,
+    },
+    /// The request couldn't be deserialized
+    BadRequest,
+    /// The trailing data is the wrong length for the request
+    InvalidLength,
+    InvalidPort,
+    /// The module is not a paged CMIS module, so has no CDB
+    NotSupported,
+    /// The module is still executing a previous command
+    Busy,
+    /// A block was written with no download in progress
+    NotDownloading,
+    /// We couldn't talk to the module
+    ModuleError,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let mut buf = [0; MAX_PACKET_SIZE];
+        for req in [
+            CdbRequest::Start {
+                port: 3,
+                image_size: 0x1_0000,
+            },
+            CdbRequest::WriteBlock {
+                port: 31,
+                offset: 116,
+            },
+            CdbRequest::Run {
+                port: 0,
+                mode: 1,
+                delay_ms: 100,
+            },
+            CdbRequest::Status { port: 7 },
+        ] {
+            let n = hubpack::serialize(&mut buf, &req).unwrap();
+            let (out, rest) =
+                hubpack::deserialize::<CdbRequest>(&buf[..n]).unwrap();
+            assert_eq!(out, req);
+            assert!(rest.is_empty());
+        }
+    }
+
+    #[test]
+    fn response_fits_in_packet() {
+        assert!(Header::MAX_SIZE + CdbResponse::MAX_SIZE <= MAX_PACKET_SIZE);
+    }
+}
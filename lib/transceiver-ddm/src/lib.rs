@@ -0,0 +1,542 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Decoding of transceivers' digital diagnostic monitoring (DDM) registers.
+//!
+//! The transceivers server reads a module's monitors and flags a block of
+//! registers at a time, and hands the blocks to the decoders here; they are
+//! kept apart from the server so that they can be tested on the host.
+//!
+//! Register locations are from SFF-8636 rev 2.10a, Section 6.2, and from
+//! CMIS rev 5.0, Sections 8.2 and 8.9.
+
+#![cfg_attr(not(test), no_std)]
+
+use zerocopy::{AsBytes, FromBytes};
+
+/// Most lanes on a module that fits our QSFP cages: four, whether it's an
+/// SFF-8636 or a CMIS module.  (Eight-lane CMIS modules are QSFP-DD or OSFP.)
+pub const MAX_LANES: usize = 4;
+
+/// Latched alarm and warning flags for one kind of digital diagnostic
+/// measurement, with one bit per lane (or only bit 0 for measurements of the
+/// whole module).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, FromBytes, AsBytes)]
+#[repr(C)]
+pub struct LevelFlags {
+    pub high_alarm: u8,
+    pub low_alarm: u8,
+    pub high_warning: u8,
+    pub low_warning: u8,
+}
+
+impl LevelFlags {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl core::ops::BitOrAssign for LevelFlags {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.high_alarm |= rhs.high_alarm;
+        self.low_alarm |= rhs.low_alarm;
+        self.high_warning |= rhs.high_warning;
+        self.low_warning |= rhs.low_warning;
+    }
+}
+
+/// Latched digital diagnostic monitoring (DDM) flags of a module.
+///
+/// Modules clear these flags when they are read, so the transceivers server
+/// accumulates them from when the module is first seen until it is removed.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, FromBytes, AsBytes)]
+#[repr(C)]
+pub struct DdmFlags {
+    pub temperature: LevelFlags,
+    pub supply: LevelFlags,
+    pub tx_bias: LevelFlags,
+    pub tx_power: LevelFlags,
+    pub rx_power: LevelFlags,
+}
+
+impl DdmFlags {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl core::ops::BitOrAssign for DdmFlags {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.temperature |= rhs.temperature;
+        self.supply |= rhs.supply;
+        self.tx_bias |= rhs.tx_bias;
+        self.tx_power |= rhs.tx_power;
+        self.rx_power |= rhs.rx_power;
+    }
+}
+
+/// Measurements per module: supply voltage, then TX bias, TX power and RX
+/// power for each lane, in that order.
+pub const DDM_SENSORS: usize = 1 + 3 * MAX_LANES;
+
+pub const SUPPLY: usize = 0;
+pub const TX_BIAS: usize = 1;
+pub const TX_POWER: usize = TX_BIAS + MAX_LANES;
+pub const RX_POWER: usize = TX_POWER + MAX_LANES;
+
+/// A set of measurements, indexed as described at [`DDM_SENSORS`]; those
+/// that a module doesn't have are NaN.
+pub type Measurements = [f32; DDM_SENSORS];
+
+// SFF-8636 lower page registers
+pub const SFF8636_STATUS: u8 = 2;
+pub const SFF8636_DATA_NOT_READY: u8 = 1 << 0;
+pub const SFF8636_FLAGS: u8 = 6;
+
+/// Bytes read from [`SFF8636_FLAGS`] for [`decode_sff8636`]: 6 through 57.
+pub const SFF8636_DDM_LEN: usize = 52;
+
+// CMIS lower page registers
+pub const CMIS_FLAT_MEM_REG: u8 = 2;
+pub const CMIS_FLAT_MEM: u8 = 1 << 7;
+pub const CMIS_MODULE_FLAGS: u8 = 9;
+pub const CMIS_FIRST_APPLICATION_LANES: u8 = 88;
+
+/// Bytes read from [`CMIS_MODULE_FLAGS`] for [`decode_cmis_module`]: 9
+/// through 17.
+pub const CMIS_MODULE_LEN: usize = 9;
+
+// CMIS page 01h registers
+pub const CMIS_ADVERTISING_PAGE: u8 = 0x01;
+pub const CMIS_TX_BIAS_MULTIPLIER: u8 = 160;
+
+// CMIS page 11h registers
+pub const CMIS_LANE_PAGE: u8 = 0x11;
+pub const CMIS_LANE_FLAGS: u8 = 139;
+
+/// Bytes read from [`CMIS_LANE_FLAGS`] for [`decode_cmis_lanes`]: 139
+/// through 201.
+pub const CMIS_LANE_LEN: usize = 63;
+
+/// Converts a temperature monitor, a signed value in units of 1/256 degree,
+/// to degrees Celsius.
+pub fn celsius(raw: i16) -> f32 {
+    raw as f32 / 256.0
+}
+
+/// Converts a monitor in units of 100 µV to volts
+fn volts(raw: u16) -> f32 {
+    raw as f32 * 0.0001
+}
+
+/// Converts a monitor in units of 0.1 µW to milliwatts
+fn milliwatts(raw: u16) -> f32 {
+    raw as f32 * 0.0001
+}
+
+fn word(buf: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([buf[offset], buf[offset + 1]])
+}
+
+/// Decodes a flag nibble -- high alarm, low alarm, high warning and low
+/// warning, from most to least significant bit -- into the bit for `lane`.
+fn decode_nibble(flags: &mut LevelFlags, lane: usize, nibble: u8) {
+    let bit = 1 << lane;
+    if nibble & 0b1000 != 0 {
+        flags.high_alarm |= bit;
+    }
+    if nibble & 0b0100 != 0 {
+        flags.low_alarm |= bit;
+    }
+    if nibble & 0b0010 != 0 {
+        flags.high_warning |= bit;
+    }
+    if nibble & 0b0001 != 0 {
+        flags.low_warning |= bit;
+    }
+}
+
+/// Decodes SFF-8636 lower page bytes 6 through 57.
+pub fn decode_sff8636(buf: &[u8; SFF8636_DDM_LEN]) -> (Measurements, DdmFlags) {
+    // Offsets relative to byte 6
+    let reg_byte = |reg: usize| buf[reg - SFF8636_FLAGS as usize];
+    let reg_word = |reg: usize| word(buf, reg - SFF8636_FLAGS as usize);
+
+    let mut m = [f32::NAN; DDM_SENSORS];
+    m[SUPPLY] = volts(reg_word(26));
+    for lane in 0..4 {
+        m[RX_POWER + lane] = milliwatts(reg_word(34 + 2 * lane));
+        m[TX_BIAS + lane] = reg_word(42 + 2 * lane) as f32 * 0.002;
+        m[TX_POWER + lane] = milliwatts(reg_word(50 + 2 * lane));
+    }
+
+    // Each flag byte holds two lanes (or, for bytes 6 and 7, the whole
+    // module) with the first in the upper nibble.
+    let mut flags = DdmFlags::default();
+    decode_nibble(&mut flags.temperature, 0, reg_byte(6) >> 4);
+    decode_nibble(&mut flags.supply, 0, reg_byte(7) >> 4);
+    for (f, reg) in [
+        (&mut flags.rx_power, 9),
+        (&mut flags.tx_bias, 11),
+        (&mut flags.tx_power, 13),
+    ] {
+        for lane in 0..4 {
+            let b = reg_byte(reg + lane / 2);
+            let nibble = if lane % 2 == 0 { b >> 4 } else { b & 0xf };
+            decode_nibble(f, lane, nibble);
+        }
+    }
+
+    (m, flags)
+}
+
+/// Decodes CMIS lower page bytes 9 through 17 into the module-wide values.
+pub fn decode_cmis_module(
+    buf: &[u8; CMIS_MODULE_LEN],
+    m: &mut Measurements,
+    flags: &mut DdmFlags,
+) {
+    // Offsets relative to byte 9; the temperature and supply flags are in
+    // the opposite order to SFF-8636 (low warning is the most significant).
+    let reverse = |nibble: u8| {
+        (0..4).fold(0, |acc, i| acc | (((nibble >> i) & 1) << (3 - i)))
+    };
+    decode_nibble(&mut flags.temperature, 0, reverse(buf[0] & 0xf));
+    decode_nibble(&mut flags.supply, 0, reverse(buf[0] >> 4));
+    m[SUPPLY] = volts(word(buf, 16 - 9));
+}
+
+/// Decodes CMIS page 11h bytes 139 through 201 into per-lane values.
+pub fn decode_cmis_lanes(
+    buf: &[u8; CMIS_LANE_LEN],
+    lanes: usize,
+    tx_bias_lsb: f32,
+    m: &mut Measurements,
+    flags: &mut DdmFlags,
+) {
+    // Offsets relative to byte 139; each flag is a byte with a bit per lane
+    let reg_byte = |reg: usize| buf[reg - CMIS_LANE_FLAGS as usize];
+    let reg_word = |reg: usize| word(buf, reg - CMIS_LANE_FLAGS as usize);
+
+    for (f, reg) in [
+        (&mut flags.tx_power, 139),
+        (&mut flags.tx_bias, 143),
+        (&mut flags.rx_power, 149),
+    ] {
+        *f = LevelFlags {
+            high_alarm: reg_byte(reg),
+            low_alarm: reg_byte(reg + 1),
+            high_warning: reg_byte(reg + 2),
+            low_warning: reg_byte(reg + 3),
+        };
+    }
+
+    for lane in 0..lanes {
+        m[TX_POWER + lane] = milliwatts(reg_word(154 + 2 * lane));
+        m[TX_BIAS + lane] = reg_word(170 + 2 * lane) as f32 * tx_bias_lsb;
+        m[RX_POWER + lane] = milliwatts(reg_word(186 + 2 * lane));
+    }
+}
+
+/// Returns the number of media lanes to monitor on a CMIS module, given
+/// byte 88 of its lower page.  That's the lane counts of its first (default)
+/// application: host lanes in the high nibble, media lanes in the low.
+/// Modules that don't advertise a count (or claim more than fit a QSFP cage)
+/// get `MAX_LANES`.
+pub fn cmis_media_lanes(first_application_lanes: u8) -> u8 {
+    match first_application_lanes & 0xf {
+        n @ 1..=4 => n,
+        _ => MAX_LANES as u8,
+    }
+}
+
+/// Returns the units of a CMIS module's TX bias monitors, in mA, given byte
+/// 160 of page 01h.
+pub fn cmis_tx_bias_lsb(multiplier: u8) -> f32 {
+    match (multiplier >> 3) & 0b11 {
+        0b01 => 0.004,
+        0b10 => 0.008,
+        _ => 0.002,
+    }
+}
+
+/// Masks off per-lane flags for lanes that we aren't monitoring.
+pub fn mask_lanes(flags: &mut DdmFlags, lanes: u8) {
+    let mask = (1u16 << lanes).wrapping_sub(1) as u8;
+    for f in [&mut flags.tx_bias, &mut flags.tx_power, &mut flags.rx_power] {
+        f.high_alarm &= mask;
+        f.low_alarm &= mask;
+        f.high_warning &= mask;
+        f.low_warning &= mask;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The pages below are laid out by hand at the addresses given in the
+    // specs, rather than at the offsets the decoders use, so that a decoder
+    // reading the wrong register fails.
+
+    /// Returns a page of memory (lower or upper, addressed 0 to 255) with
+    /// `bytes` and big-endian `words` written at their addresses.
+    fn memory(bytes: &[(usize, u8)], words: &[(usize, u16)]) -> [u8; 256] {
+        let mut page = [0u8; 256];
+        for &(addr, b) in bytes {
+            page[addr] = b;
+        }
+        for &(addr, w) in words {
+            page[addr..addr + 2].copy_from_slice(&w.to_be_bytes());
+        }
+        page
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() <= expected.abs() * 1e-6,
+            "{actual} != {expected}"
+        );
+    }
+
+    #[test]
+    fn temperature_is_signed() {
+        assert_close(celsius(i16::from_be_bytes([0x19, 0x80])), 25.5);
+        assert_close(celsius(i16::from_be_bytes([0xf6, 0x00])), -10.0);
+        assert_close(celsius(i16::from_be_bytes([0x80, 0x00])), -128.0);
+    }
+
+    #[test]
+    fn sff8636() {
+        let page = memory(
+            &[
+                (6, 0b1000_0000), // temperature high alarm
+                (7, 0b0001_0000), // supply low warning
+                (9, 0x84),        // RX power: lane 1 high alarm, 2 low alarm
+                (10, 0x21),       // lane 3 high warning, 4 low warning
+                (11, 0x10),       // TX bias: lane 1 low warning
+                (13, 0x08),       // TX power: lane 2 high alarm
+                (22, 0xff),       // temperature monitor, which we skip
+            ],
+            &[
+                (26, 33000),  // 3.3 V
+                (34, 8000),   // RX power, 0.8 mW
+                (38, 0xffff), // unsigned, so 6.5535 mW
+                (40, 1),
+                (42, 6000),   // TX bias, 12 mA
+                (46, 0x8000), // unsigned, so 65.536 mA
+                (50, 10000),  // TX power, 1 mW
+                (56, 5000),
+            ],
+        );
+        let (m, flags) = decode_sff8636(page[6..58].try_into().unwrap());
+
+        assert_close(m[SUPPLY], 3.3);
+        for (lane, rx) in [0.8, 0.0, 6.5535, 0.0001].into_iter().enumerate() {
+            assert_close(m[RX_POWER + lane], rx);
+        }
+        for (lane, bias) in [12.0, 0.0, 65.536, 0.0].into_iter().enumerate() {
+            assert_close(m[TX_BIAS + lane], bias);
+        }
+        for (lane, tx) in [1.0, 0.0, 0.0, 0.5].into_iter().enumerate() {
+            assert_close(m[TX_POWER + lane], tx);
+        }
+
+        assert_eq!(
+            flags,
+            DdmFlags {
+                temperature: LevelFlags {
+                    high_alarm: 1,
+                    ..Default::default()
+                },
+                supply: LevelFlags {
+                    low_warning: 1,
+                    ..Default::default()
+                },
+                rx_power: LevelFlags {
+                    high_alarm: 0b0001,
+                    low_alarm: 0b0010,
+                    high_warning: 0b0100,
+                    low_warning: 0b1000,
+                },
+                tx_bias: LevelFlags {
+                    low_warning: 0b0001,
+                    ..Default::default()
+                },
+                tx_power: LevelFlags {
+                    high_alarm: 0b0010,
+                    ..Default::default()
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn cmis_module() {
+        let page = memory(
+            &[(9, 0b0010_0001)], // supply low alarm, temperature high alarm
+            &[(14, 0x1980), (16, 32000)], // 25.5 C (skipped), 3.2 V
+        );
+        let mut m = [f32::NAN; DDM_SENSORS];
+        let mut flags = DdmFlags::default();
+        decode_cmis_module(page[9..18].try_into().unwrap(), &mut m, &mut flags);
+
+        assert_close(m[SUPPLY], 3.2);
+        assert!(m[TX_BIAS..].iter().all(|v| v.is_nan()));
+        assert_eq!(
+            flags,
+            DdmFlags {
+                temperature: LevelFlags {
+                    high_alarm: 1,
+                    ..Default::default()
+                },
+                supply: LevelFlags {
+                    low_alarm: 1,
+                    ..Default::default()
+                },
+                ..Default::default()
+            }
+        );
+
+        let page = memory(&[(9, 0b1000_0100)], &[]);
+        let mut flags = DdmFlags::default();
+        decode_cmis_module(page[9..18].try_into().unwrap(), &mut m, &mut flags);
+        assert_eq!(
+            flags.temperature,
+            LevelFlags {
+                high_warning: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            flags.supply,
+            LevelFlags {
+                low_warning: 1,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn cmis_lanes() {
+        let page = memory(
+            &[
+                (135, 0xff), // TX fault, which we skip
+                (139, 0x01), // TX power high alarm, lane 1
+                (140, 0x02),
+                (142, 0x08),
+                (143, 0x04), // TX bias high alarm, lane 3
+                (147, 0xff), // RX LOS, which we skip
+                (149, 0x03), // RX power high alarm, lanes 1 and 2
+                (152, 0x80),
+            ],
+            &[
+                (154, 10000), // TX power, lane 1: 1 mW
+                (156, 5000),
+                (158, 9999), // lane 3, which we aren't monitoring
+                (170, 4000), // TX bias, lane 1: 16 mA at 4 µA
+                (172, 0xffff),
+                (186, 8000), // RX power, lane 1: 0.8 mW
+                (188, 2),
+            ],
+        );
+        let mut m = [f32::NAN; DDM_SENSORS];
+        let mut flags = DdmFlags::default();
+        decode_cmis_lanes(
+            page[139..202].try_into().unwrap(),
+            2,
+            0.004,
+            &mut m,
+            &mut flags,
+        );
+
+        assert_close(m[TX_POWER], 1.0);
+        assert_close(m[TX_POWER + 1], 0.5);
+        assert_close(m[TX_BIAS], 16.0);
+        assert_close(m[TX_BIAS + 1], 262.14);
+        assert_close(m[RX_POWER], 0.8);
+        assert_close(m[RX_POWER + 1], 0.0002);
+        for base in [TX_POWER, TX_BIAS, RX_POWER] {
+            assert!(m[base + 2..base + MAX_LANES].iter().all(|v| v.is_nan()));
+        }
+        assert!(m[SUPPLY].is_nan());
+
+        assert_eq!(
+            flags.tx_power,
+            LevelFlags {
+                high_alarm: 0x01,
+                low_alarm: 0x02,
+                high_warning: 0,
+                low_warning: 0x08,
+            }
+        );
+        assert_eq!(
+            flags.tx_bias,
+            LevelFlags {
+                high_alarm: 0x04,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            flags.rx_power,
+            LevelFlags {
+                high_alarm: 0x03,
+                low_warning: 0x80,
+                ..Default::default()
+            }
+        );
+
+        mask_lanes(&mut flags, 2);
+        assert_eq!(flags.tx_power.low_warning, 0);
+        assert!(flags.tx_bias.is_empty());
+        assert_eq!(
+            flags.rx_power,
+            LevelFlags {
+                high_alarm: 0x03,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn cmis_discovery() {
+        assert_eq!(cmis_media_lanes(0x44), 4);
+        assert_eq!(cmis_media_lanes(0x81), 1);
+        assert_eq!(cmis_media_lanes(0x40), MAX_LANES as u8);
+        assert_eq!(cmis_media_lanes(0x88), MAX_LANES as u8);
+
+        assert_close(cmis_tx_bias_lsb(0b0000_0000), 0.002);
+        assert_close(cmis_tx_bias_lsb(0b0000_1000), 0.004);
+        assert_close(cmis_tx_bias_lsb(0b0001_0000), 0.008);
+        assert_close(cmis_tx_bias_lsb(0b1110_0111), 0.002);
+    }
+
+    #[test]
+    fn mask_all_lanes() {
+        let all = LevelFlags {
+            high_alarm: 0xff,
+            low_alarm: 0xff,
+            high_warning: 0xff,
+            low_warning: 0xff,
+        };
+        let mut flags = DdmFlags {
+            temperature: all,
+            supply: all,
+            tx_bias: all,
+            tx_power: all,
+            rx_power: all,
+        };
+        mask_lanes(&mut flags, 0);
+        assert_eq!(flags.temperature, all);
+        assert_eq!(flags.supply, all);
+        assert!(flags.tx_bias.is_empty());
+
+        let mut flags = DdmFlags {
+            rx_power: all,
+            ..Default::default()
+        };
+        mask_lanes(&mut flags, 8);
+        assert_eq!(flags.rx_power, all);
+    }
+}
@@ -6,6 +6,7 @@
 
 #![no_std]
 
+use hubpack::SerializedSize;
 use serde::{Deserialize, Serialize};
 use zerocopy::{AsBytes, FromBytes};
 
@@ -25,7 +26,9 @@ use zerocopy::{AsBytes, FromBytes};
 ///
 /// The task index is in the lower `TaskId::INDEX_BITS` bits, while the
 /// generation is in the remaining top bits.
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
 pub struct TaskId(pub u16);
 
 impl TaskId {
@@ -86,6 +89,12 @@ impl From<u8> for Generation {
     }
 }
 
+impl From<Generation> for u8 {
+    fn from(g: Generation) -> Self {
+        g.0
+    }
+}
+
 /// Newtype wrapper for an interrupt index
 #[derive(
     Copy,
@@ -216,6 +225,13 @@ pub const fn extract_new_generation(code: u32) -> Option<Generation> {
 /// Response code returned by the kernel if a lender has defected.
 pub const DEFECT: u32 = 1;
 
+/// Response code returned by the kernel if a `SEND_TIMEOUT` is abandoned
+/// because the sender's timer deadline passed before it got a reply.
+///
+/// This sits just below the dead codes, well clear of the 16-bit codes that
+/// servers normally use.
+pub const TIMED_OUT: u32 = 0xffff_fe00;
+
 /// State used to make scheduling decisions.
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 pub enum TaskState {
@@ -291,7 +307,9 @@ impl From<SchedState> for TaskState {
 }
 
 /// A record describing a fault taken by a task.
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Deserialize, Serialize, SerializedSize,
+)]
 pub enum FaultInfo {
     /// The task has violated memory access rules. This may have come from a
     /// memory protection fault while executing the task (in the case of
@@ -365,7 +383,9 @@ impl From<UsageError> for FaultInfo {
 }
 
 /// A kernel-defined fault, arising from how a user task behaved.
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Deserialize, Serialize, SerializedSize,
+)]
 pub enum UsageError {
     /// A program used an undefined syscall number.
     BadSyscallNumber,
@@ -390,7 +410,9 @@ pub enum UsageError {
 }
 
 /// Origin of a fault.
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Deserialize, Serialize, SerializedSize,
+)]
 pub enum FaultSource {
     /// User code did something that was intercepted by the processor.
     User,
@@ -399,7 +421,9 @@ pub enum FaultSource {
 }
 
 /// Reasons a server might cite when using the `REPLY_FAULT` syscall.
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Deserialize, Serialize, SerializedSize,
+)]
 pub enum ReplyFaultReason {
     /// The message indicated some operation number that is unknown to the
     /// server -- which almost certainly indicates that the client intended the
@@ -444,6 +468,12 @@ pub enum Sysnum {
     RefreshTaskId = 10,
     Post = 11,
     ReplyFault = 12,
+    SendTimeout = 13,
+}
+
+impl Sysnum {
+    /// Number of defined syscalls; valid syscall numbers are `0..COUNT`.
+    pub const COUNT: usize = 14;
 }
 
 /// We're using an explicit `TryFrom` impl for `Sysnum` instead of
@@ -467,6 +497,7 @@ impl core::convert::TryFrom<u32> for Sysnum {
             10 => Ok(Self::RefreshTaskId),
             11 => Ok(Self::Post),
             12 => Ok(Self::ReplyFault),
+            13 => Ok(Self::SendTimeout),
             _ => Err(()),
         }
     }
@@ -479,6 +510,59 @@ pub struct TaskDumpRegion {
     pub size: u32,
 }
 
+/// Scheduling and accounting counters the kernel maintains for each task.
+///
+/// These are cumulative since boot: they are *not* reset when the task is
+/// restarted.
+#[derive(
+    Copy, Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize,
+)]
+pub struct TaskStats {
+    /// Number of kernel timer ticks that arrived while this task was running.
+    /// This is a sampled measure of CPU time, including time the kernel spent
+    /// working on the task's behalf.
+    pub ticks: u64,
+    /// Number of times the scheduler switched to this task from another.
+    pub scheduled: u32,
+    /// Number of times this task was switched out because an interrupt (the
+    /// kernel timer or a hardware interrupt) made a higher-priority task
+    /// runnable.
+    pub preemptions: u32,
+    /// Number of syscalls made by this task, indexed by `Sysnum`.
+    pub syscalls: [u32; Sysnum::COUNT],
+}
+
+/// Record of a priority inversion: a task blocked sending to a server whose
+/// (configured) priority is lower than its own.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct PriorityInversion {
+    /// Index of the server the task was blocked on.
+    pub server: u16,
+    /// Time at which the task blocked, in kernel ticks.
+    pub start: u64,
+    /// How long the task was blocked, in kernel ticks.
+    pub ticks: u64,
+}
+
+/// Priority inversions the kernel has observed for a task, as a client.
+///
+/// As with `TaskStats`, these are cumulative since boot. An inversion is
+/// counted when the task is unblocked, so one that is still in progress is not
+/// reflected here.
+#[derive(
+    Copy, Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize,
+)]
+pub struct InversionStats {
+    /// Number of inversions.
+    pub count: u32,
+    /// Total time spent blocked in inversions, in kernel ticks.
+    pub total_ticks: u64,
+    /// Longest time spent blocked in a single inversion, in kernel ticks.
+    pub worst_ticks: u64,
+    /// The most recent inversion, if any.
+    pub last: Option<PriorityInversion>,
+}
+
 /// Representation of kipc numbers
 pub enum Kipcnum {
     ReadTaskStatus = 1,
@@ -488,6 +572,10 @@ pub enum Kipcnum {
     Reset = 5,
     GetTaskDumpRegion = 6,
     ReadTaskDumpRegion = 7,
+    FindFaultedTasks = 8,
+    ReadTaskStats = 9,
+    ReadPriorityInversions = 10,
+    ReadPanicMessage = 11,
 }
 
 impl core::convert::TryFrom<u16> for Kipcnum {
@@ -502,6 +590,10 @@ impl core::convert::TryFrom<u16> for Kipcnum {
             5 => Ok(Self::Reset),
             6 => Ok(Self::GetTaskDumpRegion),
             7 => Ok(Self::ReadTaskDumpRegion),
+            8 => Ok(Self::FindFaultedTasks),
+            9 => Ok(Self::ReadTaskStats),
+            10 => Ok(Self::ReadPriorityInversions),
+            11 => Ok(Self::ReadPanicMessage),
             _ => Err(()),
         }
     }
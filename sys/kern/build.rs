@@ -15,7 +15,9 @@ use indexmap::IndexMap;
 use proc_macro2::TokenStream;
 
 fn main() -> Result<()> {
-    build_util::expose_m_profile();
+    if !is_hosted() {
+        build_util::expose_m_profile();
+    }
 
     let g = process_config()?;
     generate_statics(&g)?;
@@ -23,6 +25,12 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+/// Checks whether we're building for a hosted (`std`) target, which is only
+/// done to run the kernel under the simulator in `arch::sim`.
+fn is_hosted() -> bool {
+    build_util::target_os() != "none"
+}
+
 struct Generated {
     tasks: Vec<TokenStream>,
     regions: Vec<TokenStream>,
@@ -37,9 +45,18 @@ enum RegionKey {
 }
 
 fn process_config() -> Result<Generated> {
-    let kconfig: KernelConfig =
+    let kconfig: KernelConfig = if is_hosted() {
+        // The simulator builds its task table at runtime, so a hosted build
+        // gets an empty application: no tasks, regions, or interrupts.
+        KernelConfig {
+            tasks: vec![],
+            shared_regions: Default::default(),
+            irqs: Default::default(),
+        }
+    } else {
         ron::de::from_str(&build_util::env_var("HUBRIS_KCONFIG")?)
-            .context("parsing kconfig from HUBRIS_KCONFIG")?;
+            .context("parsing kconfig from HUBRIS_KCONFIG")?
+    };
 
     // The kconfig data structure keeps things somewhat abstract to give us, the
     // kernel, more freedom about our internal implementation choices. However,
@@ -179,7 +196,7 @@ fn process_config() -> Result<Generated> {
     let task_irq_map = per_task_irqs.into_iter().collect::<Vec<_>>();
 
     let target = build_util::target();
-    let irq_code = if target.starts_with("thumbv6m") {
+    let irq_code = if target.starts_with("thumbv6m") || is_hosted() {
         // On ARMv6-M we have no hardware division, which the perfect hash table
         // relies on (to get efficient integer remainder). Fall back to a good
         // old sorted list with binary search instead.
@@ -187,6 +204,8 @@ fn process_config() -> Result<Generated> {
         // This means our dispatch time for interrupts on ARMv6-M is O(log N)
         // instead of O(1), but these parts also tend to have few interrupts,
         // so, not the end of the world.
+        //
+        // Hosted builds have no interrupts at all, so they take this path too.
 
         let task_irq_map = phash_gen::OwnedSortedList::build(task_irq_map)
             .context("building task-to-IRQ map")?;
@@ -345,9 +364,13 @@ fn fmt_region(region: &RegionConfig) -> TokenStream {
 }
 
 fn generate_statics(gen: &Generated) -> Result<()> {
-    let image_id: u64 = build_util::env_var("HUBRIS_IMAGE_ID")?
-        .parse()
-        .context("parsing HUBRIS_IMAGE_ID")?;
+    let image_id: u64 = if is_hosted() {
+        0
+    } else {
+        build_util::env_var("HUBRIS_IMAGE_ID")?
+            .parse()
+            .context("parsing HUBRIS_IMAGE_ID")?
+    };
 
     let out = build_util::out_dir();
     let kconfig_path = out.join("kconfig.rs");
@@ -364,7 +387,7 @@ fn generate_statics(gen: &Generated) -> Result<()> {
         file,
         "{}",
         quote::quote! {
-            const HUBRIS_TASK_COUNT: usize = #task_count;
+            pub(crate) const HUBRIS_TASK_COUNT: usize = #task_count;
             #[no_mangle]
             pub static HUBRIS_IMAGE_ID: u64 = #image_id;
 
@@ -14,6 +14,9 @@
 //!
 //! For this to work, each architecture support module must define the same set
 //! of names.
+//!
+//! On hosted targets, the `sim` module stands in for a real architecture, so
+//! that the portable parts of the kernel can be tested off-target.
 
 cfg_if::cfg_if! {
     // Note: cfg_if! is slightly touchy about ordering and expression
@@ -25,6 +28,10 @@ cfg_if::cfg_if! {
         #[macro_use]
         pub mod arm_m;
         pub use arm_m::*;
+    } else if #[cfg(not(target_os = "none"))] {
+        #[macro_use]
+        pub mod sim;
+        pub use sim::*;
     } else {
         compile_error!("support for this architecture not implemented");
     }
@@ -641,6 +641,7 @@ pub fn start_first_task(tick_divisor: u32, task: &mut task::Task) -> ! {
         mpu.ctrl.write(ENABLE | PRIVDEFENA);
     }
 
+    task.record_scheduled();
     CURRENT_TASK_PTR.store(task, Ordering::Relaxed);
 
     extern "C" {
@@ -890,6 +891,12 @@ pub unsafe extern "C" fn SVCall() {
 /// pointer while you have access to `task`, and as long as the `task` being
 /// stored is actually in the task table, you'll be okay.
 pub unsafe fn set_current_task(task: &mut task::Task) {
+    // We only compare the previous pointer, never dereference it, so this
+    // doesn't conflict with our access to `task`.
+    let prev = CURRENT_TASK_PTR.load(Ordering::Relaxed);
+    if !core::ptr::eq(prev, task) {
+        task.record_scheduled();
+    }
     CURRENT_TASK_PTR.store(task, Ordering::Relaxed);
     crate::profiling::event_context_switch(task as *mut _ as usize);
 }
@@ -924,7 +931,20 @@ static TICKS: [AtomicU32; 2] = {
 #[no_mangle]
 pub unsafe extern "C" fn SysTick() {
     crate::profiling::event_timer_isr_enter();
+
+    // Figure out which task this tick should be charged to. The pointer can
+    // be null if the timer fires while the kernel is still starting up.
+    let current = CURRENT_TASK_PTR.load(Ordering::Relaxed);
+    // Safety: we're dereferencing the current task pointer, which we're
+    // trusting the rest of this module to maintain correctly.
+    let current =
+        unsafe { current.as_ref() }.map(|t| usize::from(t.descriptor().index));
+
     with_task_table(|tasks| {
+        if let Some(current) = current {
+            tasks[current].record_tick();
+        }
+
         // Load the time before this tick event.
         let t0 = TICKS[0].load(Ordering::Relaxed);
         let t1 = TICKS[1].load(Ordering::Relaxed);
@@ -1070,6 +1090,11 @@ unsafe extern "C" fn pendsv_entry() {
 
     with_task_table(|tasks| {
         let next = task::select(current, tasks);
+        // PendSV is only pended from interrupt handlers, so if we're leaving
+        // the current task, it's been preempted.
+        if next != current {
+            tasks[current].record_preemption();
+        }
         let next = &mut tasks[next];
         apply_memory_protection(next);
         // Safety: next comes from the task table and we don't use it again
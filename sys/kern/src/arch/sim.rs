@@ -0,0 +1,1067 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Architecture support for simulating the kernel on a hosted target.
+//!
+//! This stands in for a real architecture module so that the portable parts of
+//! the kernel -- the scheduler, timers, IPC, and fault handling -- can be
+//! exercised by tests on a development machine or in CI.
+//!
+//! There is no instruction-level emulation here: simulated tasks don't execute
+//! code. Instead, the test plays the part of whichever task is currently
+//! scheduled, loading syscall arguments into its saved registers and entering
+//! the kernel through [`Simulator::syscall`], just as the `SVCall` handler does
+//! on hardware. Time advances only when the test calls [`Simulator::tick`], so
+//! every run is deterministic.
+//!
+//! Task memory is backed by host memory, and the kernel addresses it by its
+//! real host address. Because the kernel's descriptors store addresses as
+//! `u32`, the simulator only works on 32-bit hosts:
+//!
+//! ```text
+//! cargo test -p kern --target i686-unknown-linux-gnu
+//! ```
+//!
+//! State that the ARM-M port keeps in globals (the tick counter and the set of
+//! enabled interrupts) is kept thread-local here, so that tests can run in
+//! parallel without interfering with one another.
+
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use abi::{FaultInfo, TaskId};
+
+use crate::atomic::AtomicExt;
+use crate::descs::{
+    RegionAttributes, RegionDesc, TaskDesc, TaskFlags, REGIONS_PER_TASK,
+};
+use crate::task::{self, NextTask, Task};
+use crate::time::Timestamp;
+use crate::umem::USlice;
+
+macro_rules! uassert {
+    ($cond : expr) => {
+        if !$cond {
+            panic!("Assertion failed!");
+        }
+    };
+}
+
+thread_local! {
+    /// Kernel timestamp, measured in ticks.
+    static TICKS: Cell<u64> = Cell::new(0);
+
+    /// Interrupts that are currently enabled.
+    static ENABLED_IRQS: RefCell<BTreeSet<u32>> = RefCell::new(BTreeSet::new());
+}
+
+/// Largest task table that the simulator accepts.  This only bounds the
+/// per-task bitmaps, which are sized from the application on real targets.
+pub const MAX_TASKS: usize = 64;
+
+/// Simulated registers that must be saved across context switches.
+///
+/// As on ARM-M, syscall arguments and return values share registers, so a
+/// syscall's results overwrite its arguments.
+#[derive(Debug, Default)]
+pub struct SavedState {
+    regs: [u32; 7],
+    descriptor: u32,
+    sp: u32,
+}
+
+impl SavedState {
+    /// Returns the syscall return registers, as the task would see them on
+    /// returning from the kernel.
+    pub fn results(&self) -> [u32; 6] {
+        let mut r = [0; 6];
+        r.copy_from_slice(&self.regs[..6]);
+        r
+    }
+}
+
+impl task::ArchState for SavedState {
+    fn stack_pointer(&self) -> u32 {
+        self.sp
+    }
+
+    /// Reads syscall argument register 0.
+    fn arg0(&self) -> u32 {
+        self.regs[0]
+    }
+    fn arg1(&self) -> u32 {
+        self.regs[1]
+    }
+    fn arg2(&self) -> u32 {
+        self.regs[2]
+    }
+    fn arg3(&self) -> u32 {
+        self.regs[3]
+    }
+    fn arg4(&self) -> u32 {
+        self.regs[4]
+    }
+    fn arg5(&self) -> u32 {
+        self.regs[5]
+    }
+    fn arg6(&self) -> u32 {
+        self.regs[6]
+    }
+
+    fn syscall_descriptor(&self) -> u32 {
+        self.descriptor
+    }
+
+    /// Writes syscall return argument 0.
+    fn ret0(&mut self, x: u32) {
+        self.regs[0] = x
+    }
+    fn ret1(&mut self, x: u32) {
+        self.regs[1] = x
+    }
+    fn ret2(&mut self, x: u32) {
+        self.regs[2] = x
+    }
+    fn ret3(&mut self, x: u32) {
+        self.regs[3] = x
+    }
+    fn ret4(&mut self, x: u32) {
+        self.regs[4] = x
+    }
+    fn ret5(&mut self, x: u32) {
+        self.regs[5] = x
+    }
+}
+
+/// There's no clock to tell the debugger about in simulation.
+pub unsafe fn set_clock_freq(_tick_divisor: u32) {}
+
+pub fn reinitialize(task: &mut task::Task) {
+    *task.save_mut() = SavedState {
+        sp: task.descriptor().initial_stack,
+        ..SavedState::default()
+    };
+}
+
+/// Simulated tasks don't execute, so there's nothing to protect.
+pub fn apply_memory_protection(_task: &task::Task) {}
+
+pub fn start_first_task(_tick_divisor: u32, _task: &mut task::Task) -> ! {
+    panic!("simulated kernels are run with `Simulator`, not `start_kernel`");
+}
+
+/// Records `task` as the current user task. The `Simulator` keeps track of
+/// the current task itself, so this only needs to inform the profiler.
+///
+/// # Safety
+///
+/// This is only unsafe for consistency with other architectures.
+pub unsafe fn set_current_task(task: &mut task::Task) {
+    crate::profiling::event_context_switch(task as *mut _ as usize);
+}
+
+/// Reads the tick counter.
+pub fn now() -> Timestamp {
+    Timestamp::from(TICKS.with(|t| t.get()))
+}
+
+pub fn disable_irq(n: u32) {
+    ENABLED_IRQS.with(|irqs| irqs.borrow_mut().remove(&n));
+}
+
+pub fn enable_irq(n: u32) {
+    ENABLED_IRQS.with(|irqs| irqs.borrow_mut().insert(n));
+}
+
+/// Checks whether interrupt `n` is currently enabled.
+pub fn irq_enabled(n: u32) -> bool {
+    ENABLED_IRQS.with(|irqs| irqs.borrow().contains(&n))
+}
+
+pub fn reset() -> ! {
+    panic!("system reset");
+}
+
+impl AtomicExt for AtomicBool {
+    type Primitive = bool;
+
+    fn swap_polyfill(
+        &self,
+        value: Self::Primitive,
+        ordering: Ordering,
+    ) -> Self::Primitive {
+        self.swap(value, ordering)
+    }
+}
+
+/// Description of a simulated task, for use with [`make_task_descs`].
+#[derive(Copy, Clone, Debug)]
+pub struct SimTask {
+    /// Initial priority of this task.
+    pub priority: u8,
+    /// Should this task be started automatically on boot?
+    pub start_at_boot: bool,
+    /// Size, in bytes, of this task's RAM.
+    pub ram_size: usize,
+}
+
+/// Builds a task descriptor table for `tasks`.
+///
+/// Each task is given its own region of host memory of the requested size, as
+/// its region 1, with read and write access. Its initial stack pointer is the
+/// top of that region. All other region slots are filled with a region that
+/// confers no access.
+///
+/// The descriptors and memory are leaked, since the kernel expects to hold
+/// `'static` references to them.
+pub fn make_task_descs(tasks: &[SimTask]) -> &'static [TaskDesc] {
+    let null_region: &'static RegionDesc = Box::leak(Box::new(RegionDesc {
+        base: 0,
+        size: 32,
+        attributes: RegionAttributes::empty(),
+    }));
+
+    let descs = tasks
+        .iter()
+        .enumerate()
+        .map(|(i, t)| {
+            // Allocate in words to get the alignment that task memory would
+            // have on hardware.
+            let words = (t.ram_size + 7) / 8;
+            let ram = Box::leak(vec![0u64; words].into_boxed_slice());
+            let base = ram.as_mut_ptr() as usize as u32;
+            let size = (words * 8) as u32;
+
+            let ram_region: &'static RegionDesc =
+                Box::leak(Box::new(RegionDesc {
+                    base,
+                    size,
+                    attributes: RegionAttributes::READ
+                        | RegionAttributes::WRITE,
+                }));
+
+            let mut regions = [null_region; REGIONS_PER_TASK];
+            regions[1] = ram_region;
+
+            TaskDesc {
+                regions,
+                entry_point: base,
+                initial_stack: base + size,
+                priority: t.priority,
+                flags: if t.start_at_boot {
+                    TaskFlags::START_AT_BOOT
+                } else {
+                    TaskFlags::empty()
+                },
+                index: u16::try_from(i).expect("over 2**16 tasks??"),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    Box::leak(descs.into_boxed_slice())
+}
+
+/// A simulated kernel, with its own task table.
+///
+/// Creating a `Simulator` resets the (thread-local) simulated clock and
+/// interrupt state, so only one should be used per thread at a time.
+pub struct Simulator {
+    tasks: Vec<Task>,
+    current: usize,
+}
+
+impl Simulator {
+    /// Creates a task table from `descs` and picks the first task to run, as
+    /// `start_kernel` does on hardware.
+    ///
+    /// # Panics
+    ///
+    /// If no tasks are runnable.
+    pub fn new(descs: &'static [TaskDesc]) -> Self {
+        assert!(descs.len() <= MAX_TASKS);
+        TICKS.with(|t| t.set(0));
+        ENABLED_IRQS.with(|irqs| irqs.borrow_mut().clear());
+
+        let mut tasks =
+            descs.iter().map(Task::from_descriptor).collect::<Vec<_>>();
+        for task in tasks.iter_mut() {
+            reinitialize(task);
+        }
+
+        // Act like we're scheduling after the last task, which will cause a
+        // scan from 0 on.
+        let current = task::select(tasks.len() - 1, &tasks);
+        tasks[current].record_scheduled();
+        let mut sim = Self { tasks, current };
+        sim.switch_to(current);
+        sim
+    }
+
+    /// Returns the task table.
+    pub fn tasks(&self) -> &[Task] {
+        &self.tasks
+    }
+
+    /// Returns the index of the currently scheduled task.
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// Returns the current `TaskId` (i.e., with the correct generation) for
+    /// task `index`.
+    pub fn task_id(&self, index: usize) -> TaskId {
+        task::current_id(&self.tasks, index)
+    }
+
+    /// Returns the base address and size of the RAM region of task `index`,
+    /// as allocated by [`make_task_descs`].
+    pub fn ram(&self, index: usize) -> (u32, u32) {
+        let region = self.tasks[index].region_table()[1];
+        (region.base, region.size)
+    }
+
+    /// Returns the syscall return registers of task `index`.
+    pub fn results(&self, index: usize) -> [u32; 6] {
+        self.tasks[index].save().results()
+    }
+
+    /// Has the current task make syscall number `nr` with arguments `args`,
+    /// then performs any context switch that the kernel requests.
+    ///
+    /// Returns the index of the task that is scheduled afterwards.
+    pub fn syscall(&mut self, nr: u32, args: [u32; 7]) -> usize {
+        let current = self.current;
+        {
+            let save = self.tasks[current].save_mut();
+            save.regs = args;
+            save.descriptor = nr;
+        }
+
+        crate::profiling::event_syscall_enter(nr);
+        match crate::syscalls::safe_syscall_entry(nr, current, &mut self.tasks)
+        {
+            NextTask::Same => (),
+            NextTask::Specific(i) => self.switch_to(i),
+            NextTask::Other => {
+                let next = task::select(current, &self.tasks);
+                self.switch_to(next);
+            }
+        }
+        crate::profiling::event_syscall_exit();
+
+        self.current
+    }
+
+    /// Advances time by `ticks`, one tick at a time, processing timers and
+    /// preempting the current task as the `SysTick` handler would.
+    ///
+    /// Returns the index of the task that is scheduled afterwards.
+    pub fn tick(&mut self, ticks: u64) -> usize {
+        for _ in 0..ticks {
+            crate::profiling::event_timer_isr_enter();
+            self.tasks[self.current].record_tick();
+            let now = TICKS.with(|t| {
+                t.set(t.get() + 1);
+                t.get()
+            });
+            let switch = task::process_timers(&mut self.tasks, now.into());
+            crate::profiling::event_timer_isr_exit();
+
+            if switch != NextTask::Same {
+                self.reschedule();
+            }
+        }
+        self.current
+    }
+
+    /// Simulates an interrupt that posts `notification` to task `index`. As
+    /// on hardware, the interrupt is disabled until the task re-enables it.
+    ///
+    /// Returns the index of the task that is scheduled afterwards.
+    pub fn interrupt(
+        &mut self,
+        irq: u32,
+        index: usize,
+        notification: u32,
+    ) -> usize {
+        crate::profiling::event_isr_enter();
+        disable_irq(irq);
+        let switch =
+            self.tasks[index].post(task::NotificationSet(notification));
+        crate::profiling::event_isr_exit();
+
+        if switch {
+            self.reschedule();
+        }
+        self.current
+    }
+
+    /// Copies `data` into the memory of task `index` at `addr`, subject to
+    /// that task's memory access rights.
+    pub fn write_memory(
+        &mut self,
+        index: usize,
+        addr: u32,
+        data: &[u8],
+    ) -> Result<(), FaultInfo> {
+        let mut slice = USlice::from_raw(addr as usize, data.len())
+            .map_err(FaultInfo::SyscallUsage)?;
+        self.tasks[index]
+            .try_write(&mut slice)?
+            .copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Reads `len` bytes from the memory of task `index` at `addr`, subject
+    /// to that task's memory access rights.
+    pub fn read_memory(
+        &self,
+        index: usize,
+        addr: u32,
+        len: usize,
+    ) -> Result<Vec<u8>, FaultInfo> {
+        let slice = USlice::from_raw(addr as usize, len)
+            .map_err(FaultInfo::SyscallUsage)?;
+        Ok(self.tasks[index].try_read(&slice)?.to_vec())
+    }
+
+    /// Picks the next task to run after the current one, as `PendSV` does.
+    fn reschedule(&mut self) {
+        let next = task::select(self.current, &self.tasks);
+        if next != self.current {
+            self.tasks[self.current].record_preemption();
+        }
+        self.switch_to(next);
+    }
+
+    fn switch_to(&mut self, index: usize) {
+        let task = &mut self.tasks[index];
+        if index != self.current {
+            task.record_scheduled();
+        }
+        apply_memory_protection(task);
+        // Safety: set_current_task is safe in simulation.
+        unsafe {
+            set_current_task(task);
+        }
+        self.current = index;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use abi::{SchedState, Sysnum, TaskState, UsageError};
+
+    const FAULT: u32 = crate::startup::HUBRIS_FAULT_NOTIFICATION;
+
+    fn task(priority: u8) -> SimTask {
+        SimTask {
+            priority,
+            start_at_boot: true,
+            ram_size: 1024,
+        }
+    }
+
+    /// Has the current task enter an open RECV, with its buffer at the base
+    /// of its RAM.
+    fn recv(sim: &mut Simulator, len: u32, mask: u32) -> usize {
+        let (base, _) = sim.ram(sim.current());
+        sim.syscall(Sysnum::Recv as u32, [base, len, mask, 0, 0, 0, 0])
+    }
+
+    /// Has the current task send `msg` to `callee`, with its response buffer
+    /// at offset 256 in its RAM.
+    fn send(sim: &mut Simulator, callee: usize, op: u16, msg: &[u8]) -> usize {
+        send_with(sim, Sysnum::Send, callee, op, msg)
+    }
+
+    /// Like `send`, but using syscall `nr`, which takes the same arguments.
+    fn send_with(
+        sim: &mut Simulator,
+        nr: Sysnum,
+        callee: usize,
+        op: u16,
+        msg: &[u8],
+    ) -> usize {
+        let me = sim.current();
+        let (base, _) = sim.ram(me);
+        sim.write_memory(me, base, msg).unwrap();
+        let callee = sim.task_id(callee);
+        sim.syscall(
+            nr as u32,
+            [
+                u32::from(callee.0) << 16 | u32::from(op),
+                base,
+                msg.len() as u32,
+                base + 256,
+                256,
+                0,
+                0,
+            ],
+        )
+    }
+
+    fn state(sim: &Simulator, index: usize) -> TaskState {
+        *sim.tasks()[index].state()
+    }
+
+    #[test]
+    fn boot_picks_most_important() {
+        let sim = Simulator::new(make_task_descs(&[task(0), task(2), task(1)]));
+        assert_eq!(sim.current(), 0);
+    }
+
+    #[test]
+    fn blocking_yields_to_next_most_important() {
+        let mut sim =
+            Simulator::new(make_task_descs(&[task(0), task(2), task(1)]));
+
+        assert_eq!(recv(&mut sim, 0, FAULT), 2);
+        assert_eq!(recv(&mut sim, 0, 0), 1);
+        assert_eq!(
+            state(&sim, 2),
+            TaskState::Healthy(SchedState::InRecv(None))
+        );
+    }
+
+    #[test]
+    fn reply_blocked_task_is_not_scheduled() {
+        let mut sim =
+            Simulator::new(make_task_descs(&[task(0), task(1), task(1)]));
+
+        assert_eq!(recv(&mut sim, 0, FAULT), 1);
+        // Sending to the supervisor unblocks it...
+        assert_eq!(send(&mut sim, 0, 1, &[]), 0);
+        // ...and when it goes back to sleep without replying, the sender is
+        // still waiting, so the other task at its priority runs.
+        assert_eq!(recv(&mut sim, 0, FAULT), 2);
+    }
+
+    #[test]
+    fn send_recv_reply() {
+        let mut sim =
+            Simulator::new(make_task_descs(&[task(0), task(1), task(2)]));
+
+        assert_eq!(recv(&mut sim, 0, FAULT), 1);
+        assert_eq!(recv(&mut sim, 16, 0), 2);
+
+        // The client sends to the server, which is more important, so it runs
+        // immediately.
+        assert_eq!(send(&mut sim, 1, 42, b"hello"), 1);
+        let client = sim.task_id(2);
+        assert_eq!(
+            state(&sim, 2),
+            TaskState::Healthy(SchedState::InReply(sim.task_id(1)))
+        );
+        assert_eq!(sim.results(1), [0, u32::from(client.0), 42, 5, 256, 0]);
+        let (server_base, _) = sim.ram(1);
+        assert_eq!(sim.read_memory(1, server_base, 5).unwrap(), b"hello");
+
+        // The server replies; this doesn't switch tasks.
+        sim.write_memory(1, server_base + 512, b"world!").unwrap();
+        assert_eq!(
+            sim.syscall(
+                Sysnum::Reply as u32,
+                [u32::from(client.0), 7, server_base + 512, 6, 0, 0, 0],
+            ),
+            1
+        );
+        assert_eq!(state(&sim, 2), TaskState::Healthy(SchedState::Runnable));
+        assert_eq!(sim.results(2)[..2], [7, 6]);
+        let (client_base, _) = sim.ram(2);
+        assert_eq!(
+            sim.read_memory(2, client_base + 256, 6).unwrap(),
+            b"world!"
+        );
+
+        // And once the server is waiting again, the client runs.
+        assert_eq!(recv(&mut sim, 16, 0), 2);
+    }
+
+    #[test]
+    fn send_to_blocked_server_waits() {
+        let mut sim =
+            Simulator::new(make_task_descs(&[task(0), task(2), task(1)]));
+
+        // The server (task 1) is less important than the client, so the
+        // client gets to SEND before the server ever RECVs.
+        assert_eq!(recv(&mut sim, 0, FAULT), 2);
+        assert_eq!(send(&mut sim, 1, 1, b"hi"), 1);
+        assert_eq!(
+            state(&sim, 2),
+            TaskState::Healthy(SchedState::InSend(sim.task_id(1)))
+        );
+
+        // Delivery happens when the server gets around to receiving.
+        assert_eq!(recv(&mut sim, 16, 0), 1);
+        assert_eq!(sim.results(1)[1], u32::from(sim.task_id(2).0));
+        assert_eq!(
+            state(&sim, 2),
+            TaskState::Healthy(SchedState::InReply(sim.task_id(1)))
+        );
+    }
+
+    #[test]
+    fn timer_preempts() {
+        let mut sim =
+            Simulator::new(make_task_descs(&[task(0), task(1), task(2)]));
+
+        assert_eq!(recv(&mut sim, 0, FAULT), 1);
+        sim.syscall(Sysnum::SetTimer as u32, [1, 10, 0, 0b100, 0, 0, 0]);
+        assert_eq!(recv(&mut sim, 0, 0b100), 2);
+
+        assert_eq!(sim.tick(9), 2);
+        assert_eq!(sim.tick(1), 1);
+        assert_eq!(
+            sim.results(1)[..3],
+            [0, u32::from(TaskId::KERNEL.0), 0b100]
+        );
+    }
+
+    #[test]
+    fn task_stats_accounting() {
+        let mut sim =
+            Simulator::new(make_task_descs(&[task(0), task(1), task(2)]));
+
+        assert_eq!(recv(&mut sim, 0, FAULT), 1);
+        sim.syscall(Sysnum::SetTimer as u32, [1, 10, 0, 0b100, 0, 0, 0]);
+        assert_eq!(recv(&mut sim, 0, 0b100), 2);
+        assert_eq!(sim.tick(10), 1);
+
+        let stats = |i: usize| *sim.tasks()[i].stats();
+        assert_eq!(stats(0).scheduled, 1);
+        assert_eq!(stats(0).syscalls[Sysnum::Recv as usize], 1);
+
+        // Task 1 is switched to twice: once when the supervisor blocks, and
+        // again when its timer preempts task 2.
+        assert_eq!(stats(1).scheduled, 2);
+        assert_eq!(stats(1).syscalls[Sysnum::SetTimer as usize], 1);
+        assert_eq!(stats(1).syscalls[Sysnum::Recv as usize], 1);
+        assert_eq!(stats(1).preemptions, 0);
+
+        // Task 2 was running for every tick.
+        assert_eq!(stats(2).ticks, 10);
+        assert_eq!(stats(2).scheduled, 1);
+        assert_eq!(stats(2).preemptions, 1);
+        assert_eq!(stats(2).syscalls, [0; Sysnum::COUNT]);
+    }
+
+    #[test]
+    fn send_timeout_gives_up_at_deadline() {
+        let mut sim = Simulator::new(make_task_descs(&[
+            task(0),
+            task(1),
+            task(2),
+            task(3),
+        ]));
+
+        assert_eq!(recv(&mut sim, 0, FAULT), 1);
+        assert_eq!(recv(&mut sim, 16, 0), 2);
+
+        // The client arms its timer without any notification, and sends to
+        // the server, which takes the message but never replies.
+        sim.syscall(Sysnum::SetTimer as u32, [1, 5, 0, 0, 0, 0, 0]);
+        assert_eq!(send_with(&mut sim, Sysnum::SendTimeout, 1, 1, &[]), 1);
+        assert_eq!(recv(&mut sim, 16, 0), 3);
+        assert_eq!(
+            state(&sim, 2),
+            TaskState::Healthy(SchedState::InReply(sim.task_id(1)))
+        );
+
+        assert_eq!(sim.tick(4), 3);
+        assert_eq!(sim.tick(1), 2);
+        assert_eq!(sim.results(2)[0], abi::TIMED_OUT);
+        assert_eq!(state(&sim, 2), TaskState::Healthy(SchedState::Runnable));
+    }
+
+    #[test]
+    fn late_reply_to_abandoned_send_is_dropped() {
+        let mut sim = Simulator::new(make_task_descs(&[
+            task(0),
+            task(1),
+            task(2),
+            task(3),
+        ]));
+
+        assert_eq!(recv(&mut sim, 0, FAULT), 1);
+        assert_eq!(recv(&mut sim, 16, 0), 2);
+
+        // The server takes the client's message, and goes back to receiving
+        // without replying; the client gives up.
+        sim.syscall(Sysnum::SetTimer as u32, [1, 5, 0, 0, 0, 0, 0]);
+        assert_eq!(send_with(&mut sim, Sysnum::SendTimeout, 1, 1, &[]), 1);
+        assert_eq!(recv(&mut sim, 16, 0), 3);
+        assert_eq!(sim.tick(5), 2);
+        assert_eq!(sim.results(2)[0], abi::TIMED_OUT);
+
+        // The client's next message waits until the server has answered the
+        // old one, though the server is receiving.
+        assert_eq!(send(&mut sim, 1, 2, &[]), 3);
+        assert_eq!(
+            state(&sim, 2),
+            TaskState::Healthy(SchedState::InSend(sim.task_id(1)))
+        );
+
+        // Another client wakes the server, which then replies to the old
+        // message. That reply is dropped.
+        assert_eq!(send(&mut sim, 1, 3, &[]), 1);
+        let (base, _) = sim.ram(1);
+        let client = sim.task_id(2);
+        sim.syscall(
+            Sysnum::Reply as u32,
+            [u32::from(client.0), 7, base, 0, 0, 0, 0],
+        );
+        assert_eq!(
+            state(&sim, 2),
+            TaskState::Healthy(SchedState::InSend(sim.task_id(1)))
+        );
+        let other = sim.task_id(3);
+        sim.syscall(
+            Sysnum::Reply as u32,
+            [u32::from(other.0), 0, base, 0, 0, 0, 0],
+        );
+
+        // Now the server gets the new message, and its reply goes through.
+        assert_eq!(recv(&mut sim, 16, 0), 1);
+        assert_eq!(sim.results(1)[1..3], [u32::from(client.0), 2]);
+        sim.syscall(
+            Sysnum::Reply as u32,
+            [u32::from(client.0), 9, base, 0, 0, 0, 0],
+        );
+        assert_eq!(state(&sim, 2), TaskState::Healthy(SchedState::Runnable));
+        assert_eq!(sim.results(2)[0], 9);
+    }
+
+    #[test]
+    fn send_timeout_completes_normally() {
+        let mut sim =
+            Simulator::new(make_task_descs(&[task(0), task(1), task(2)]));
+
+        assert_eq!(recv(&mut sim, 0, FAULT), 1);
+        assert_eq!(recv(&mut sim, 16, 0), 2);
+        sim.syscall(Sysnum::SetTimer as u32, [1, 5, 0, 0, 0, 0, 0]);
+        assert_eq!(send_with(&mut sim, Sysnum::SendTimeout, 1, 1, &[]), 1);
+
+        let client = sim.task_id(2);
+        let (base, _) = sim.ram(1);
+        sim.syscall(
+            Sysnum::Reply as u32,
+            [u32::from(client.0), 7, base, 0, 0, 0, 0],
+        );
+        assert_eq!(recv(&mut sim, 16, 0), 2);
+        assert_eq!(sim.results(2)[0], 7);
+
+        // The timer firing later has no effect on the completed send.
+        assert_eq!(sim.tick(5), 2);
+        assert_eq!(sim.results(2)[0], 7);
+    }
+
+    #[test]
+    fn send_timeout_without_timer_fails() {
+        let mut sim =
+            Simulator::new(make_task_descs(&[task(0), task(1), task(2)]));
+
+        assert_eq!(recv(&mut sim, 0, FAULT), 1);
+        assert_eq!(recv(&mut sim, 16, 0), 2);
+
+        assert_eq!(send_with(&mut sim, Sysnum::SendTimeout, 1, 1, &[]), 2);
+        assert_eq!(sim.results(2)[0], abi::TIMED_OUT);
+        // The message wasn't delivered.
+        assert_eq!(
+            state(&sim, 1),
+            TaskState::Healthy(SchedState::InRecv(None))
+        );
+    }
+
+    #[test]
+    fn interrupt_posts_and_disables() {
+        let mut sim =
+            Simulator::new(make_task_descs(&[task(0), task(1), task(2)]));
+
+        assert_eq!(recv(&mut sim, 0, FAULT), 1);
+        assert_eq!(recv(&mut sim, 0, 0b10), 2);
+
+        enable_irq(5);
+        assert_eq!(sim.interrupt(5, 1, 0b10), 1);
+        assert!(!irq_enabled(5));
+        assert_eq!(sim.results(1)[2], 0b10);
+    }
+
+    /// Has a client at priority 1 send to a server at priority 2, which
+    /// receives the message and works on it for `ticks` before replying.
+    ///
+    /// Returns the index of the task scheduled after the reply.
+    fn inverted_call(sim: &mut Simulator, ticks: u64) -> usize {
+        assert_eq!(recv(sim, 0, FAULT), 1);
+        assert_eq!(send(sim, 2, 1, &[]), 2);
+        assert_eq!(recv(sim, 16, 0), 2);
+        assert_eq!(sim.tick(ticks), 2);
+
+        let client = sim.task_id(1);
+        let (base, _) = sim.ram(2);
+        sim.syscall(
+            Sysnum::Reply as u32,
+            [u32::from(client.0), 0, base, 0, 0, 0, 0],
+        )
+    }
+
+    #[test]
+    fn send_to_less_important_server_records_inversion() {
+        let mut sim =
+            Simulator::new(make_task_descs(&[task(0), task(1), task(2)]));
+
+        inverted_call(&mut sim, 5);
+        assert_eq!(state(&sim, 1), TaskState::Healthy(SchedState::Runnable));
+
+        let inversions = *sim.tasks()[1].inversions();
+        assert_eq!(inversions.count, 1);
+        assert_eq!(inversions.worst_ticks, 5);
+        assert_eq!(inversions.total_ticks, 5);
+        assert_eq!(
+            inversions.last,
+            Some(abi::PriorityInversion {
+                server: 2,
+                start: 0,
+                ticks: 5,
+            })
+        );
+        // The server was never blocked, so has nothing to report.
+        assert_eq!(sim.tasks()[2].inversions().count, 0);
+    }
+
+    #[test]
+    fn send_to_more_important_server_is_not_an_inversion() {
+        let mut sim =
+            Simulator::new(make_task_descs(&[task(0), task(1), task(2)]));
+
+        assert_eq!(recv(&mut sim, 0, FAULT), 1);
+        assert_eq!(recv(&mut sim, 16, 0), 2);
+        assert_eq!(send(&mut sim, 1, 1, &[]), 1);
+        let client = sim.task_id(2);
+        let (base, _) = sim.ram(1);
+        sim.syscall(
+            Sysnum::Reply as u32,
+            [u32::from(client.0), 0, base, 0, 0, 0, 0],
+        );
+        assert_eq!(state(&sim, 2), TaskState::Healthy(SchedState::Runnable));
+        assert_eq!(sim.tasks()[2].inversions().count, 0);
+    }
+
+    #[cfg(not(feature = "priority-donation"))]
+    #[test]
+    fn inverted_server_keeps_running_after_reply() {
+        let mut sim =
+            Simulator::new(make_task_descs(&[task(0), task(1), task(2)]));
+
+        // Without donation, the server runs at its own priority throughout,
+        // and keeps the CPU after replying to its more important client.
+        assert_eq!(inverted_call(&mut sim, 1), 2);
+        assert_eq!(sim.tasks()[2].priority(), crate::descs::Priority(2));
+    }
+
+    #[cfg(feature = "priority-donation")]
+    #[test]
+    fn server_inherits_priority_of_blocked_client() {
+        use crate::descs::Priority;
+
+        let mut sim = Simulator::new(make_task_descs(&[
+            task(0),
+            task(1),
+            task(3),
+            task(2),
+        ]));
+
+        assert_eq!(recv(&mut sim, 0, FAULT), 1);
+        // The client blocks on the server, which isn't receiving yet; the
+        // server inherits the client's priority, and so runs ahead of task 3.
+        assert_eq!(send(&mut sim, 2, 1, &[]), 2);
+        assert_eq!(sim.tasks()[2].priority(), Priority(1));
+        assert_eq!(recv(&mut sim, 16, 0), 2);
+
+        // Replying hands back the priority, and the client runs immediately.
+        let client = sim.task_id(1);
+        let (base, _) = sim.ram(2);
+        assert_eq!(
+            sim.syscall(
+                Sysnum::Reply as u32,
+                [u32::from(client.0), 0, base, 0, 0, 0, 0],
+            ),
+            1
+        );
+        assert_eq!(sim.tasks()[2].priority(), Priority(3));
+    }
+
+    #[cfg(feature = "priority-donation")]
+    #[test]
+    fn timed_out_client_withdraws_donation() {
+        use crate::descs::Priority;
+
+        let mut sim = Simulator::new(make_task_descs(&[
+            task(0),
+            task(1),
+            task(3),
+            task(2),
+        ]));
+
+        assert_eq!(recv(&mut sim, 0, FAULT), 1);
+        sim.syscall(Sysnum::SetTimer as u32, [1, 5, 0, 0, 0, 0, 0]);
+        assert_eq!(send_with(&mut sim, Sysnum::SendTimeout, 2, 1, &[]), 2);
+        assert_eq!(sim.tasks()[2].priority(), Priority(1));
+
+        // The server never gets around to receiving; once the client gives
+        // up, the server drops back to its own priority.
+        assert_eq!(sim.tick(5), 1);
+        assert_eq!(sim.results(1)[0], abi::TIMED_OUT);
+        assert_eq!(sim.tasks()[2].priority(), Priority(3));
+    }
+
+    #[cfg(feature = "priority-donation")]
+    #[test]
+    fn chained_donation_is_unwound() {
+        use crate::descs::Priority;
+
+        let mut sim = Simulator::new(make_task_descs(&[
+            task(0),
+            task(1),
+            task(3),
+            task(4),
+        ]));
+
+        assert_eq!(recv(&mut sim, 0, FAULT), 1);
+        // The client sleeps for a bit, while task 2 blocks sending to task 3.
+        sim.syscall(Sysnum::SetTimer as u32, [1, 2, 0, 1, 0, 0, 0]);
+        assert_eq!(recv(&mut sim, 0, 1), 2);
+        assert_eq!(send(&mut sim, 3, 1, &[]), 3);
+        assert_eq!(sim.tasks()[3].priority(), Priority(3));
+
+        // When the client blocks on task 2, its priority is passed along to
+        // task 3...
+        assert_eq!(sim.tick(2), 1);
+        sim.syscall(Sysnum::SetTimer as u32, [1, 10, 0, 0, 0, 0, 0]);
+        assert_eq!(send_with(&mut sim, Sysnum::SendTimeout, 2, 1, &[]), 3);
+        assert_eq!(sim.tasks()[2].priority(), Priority(1));
+        assert_eq!(sim.tasks()[3].priority(), Priority(1));
+
+        // ...and taken back from both when it gives up; task 3 keeps the
+        // priority lent by task 2.
+        assert_eq!(sim.tick(8), 1);
+        assert_eq!(sim.tasks()[2].priority(), Priority(3));
+        assert_eq!(sim.tasks()[3].priority(), Priority(3));
+    }
+
+    #[test]
+    fn fault_notifies_supervisor() {
+        let mut sim =
+            Simulator::new(make_task_descs(&[task(0), task(1), task(2)]));
+
+        assert_eq!(recv(&mut sim, 0, FAULT), 1);
+        assert_eq!(recv(&mut sim, 16, 0), 2);
+
+        // A bogus syscall number faults the caller and wakes the supervisor.
+        assert_eq!(sim.syscall(0xFFFF, [0; 7]), 0);
+        assert_eq!(
+            state(&sim, 2),
+            TaskState::Faulted {
+                fault: FaultInfo::SyscallUsage(UsageError::BadSyscallNumber),
+                original_state: SchedState::Runnable,
+            }
+        );
+        assert_eq!(sim.results(0)[2], FAULT);
+    }
+
+    #[test]
+    fn supervisor_reads_panic_message() {
+        let mut sim = Simulator::new(make_task_descs(&[task(0), task(1)]));
+
+        assert_eq!(recv(&mut sim, 0, FAULT), 1);
+        let (base, _) = sim.ram(1);
+        sim.write_memory(1, base, b"oh no").unwrap();
+        assert_eq!(
+            sim.syscall(Sysnum::Panic as u32, [base, 5, 0, 0, 0, 0, 0]),
+            0
+        );
+
+        // Ask the kernel for task 1's epitaph.
+        let (base, _) = sim.ram(0);
+        sim.write_memory(0, base, &1u32.to_le_bytes()).unwrap();
+        sim.syscall(
+            Sysnum::Send as u32,
+            [
+                u32::from(TaskId::KERNEL.0) << 16
+                    | abi::Kipcnum::ReadPanicMessage as u32,
+                base,
+                4,
+                base + 256,
+                256,
+                0,
+                0,
+            ],
+        );
+        let [rc, len, ..] = sim.results(0);
+        assert_eq!((rc, len), (0, 5));
+        assert_eq!(sim.read_memory(0, base + 256, 5).unwrap(), b"oh no");
+    }
+
+    #[test]
+    fn bad_recv_buffer_faults_server() {
+        let mut sim =
+            Simulator::new(make_task_descs(&[task(0), task(1), task(2)]));
+
+        assert_eq!(recv(&mut sim, 0, FAULT), 1);
+        // The server waits for a message with a buffer it doesn't own...
+        assert_eq!(
+            sim.syscall(Sysnum::Recv as u32, [0x10, 16, 0, 0, 0, 0, 0]),
+            2
+        );
+
+        // ...so when the client sends, the server is faulted at delivery. The
+        // client is left blocked, and the supervisor (having been notified of
+        // the fault) runs.
+        assert_eq!(send(&mut sim, 1, 1, b"hi"), 0);
+        assert!(matches!(state(&sim, 1), TaskState::Faulted { .. }));
+        assert_eq!(
+            state(&sim, 2),
+            TaskState::Healthy(SchedState::InSend(sim.task_id(1)))
+        );
+    }
+
+    /// Checks `task::select` against a brute-force model over a bunch of
+    /// pseudo-random task tables.
+    #[test]
+    fn select_picks_most_important_runnable() {
+        // A small LCG keeps this deterministic without extra dependencies.
+        let mut seed = 0x1234_5678u32;
+        let mut rand = move |n: u32| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (seed >> 16) % n
+        };
+
+        for _ in 0..200 {
+            let n = 1 + rand(8) as usize;
+            let specs = (0..n)
+                .map(|i| SimTask {
+                    priority: rand(4) as u8,
+                    start_at_boot: i == 0 || rand(2) == 0,
+                    ram_size: 32,
+                })
+                .collect::<Vec<_>>();
+            let sim = Simulator::new(make_task_descs(&specs));
+            let previous = rand(n as u32) as usize;
+
+            let best = (0..n)
+                .filter(|&i| specs[i].start_at_boot)
+                .map(|i| specs[i].priority)
+                .min()
+                .unwrap();
+            let expected = (previous + 1..n)
+                .chain(0..=previous)
+                .find(|&i| specs[i].start_at_boot && specs[i].priority == best)
+                .unwrap();
+
+            assert_eq!(task::select(previous, sim.tasks()), expected);
+        }
+    }
+}
@@ -98,13 +98,13 @@ impl Write for Eulogist {
     }
 }
 
-#[cfg(not(feature = "nano"))]
+#[cfg(all(target_os = "none", not(feature = "nano")))]
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo<'_>) -> ! {
     die(info)
 }
 
-#[cfg(feature = "nano")]
+#[cfg(all(target_os = "none", feature = "nano"))]
 #[panic_handler]
 fn panic(_info: &core::panic::PanicInfo<'_>) -> ! {
     unsafe {
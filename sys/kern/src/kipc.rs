@@ -31,6 +31,21 @@ pub fn handle_kernel_message(
             read_image_id(tasks, caller, args.response?)
         }
         Ok(Kipcnum::Reset) => reset(tasks, caller, args.message?),
+        Ok(Kipcnum::FindFaultedTasks) => {
+            find_faulted_tasks(tasks, caller, args.message?, args.response?)
+        }
+        Ok(Kipcnum::ReadTaskStats) => {
+            read_task_stats(tasks, caller, args.message?, args.response?)
+        }
+        Ok(Kipcnum::ReadPriorityInversions) => read_priority_inversions(
+            tasks,
+            caller,
+            args.message?,
+            args.response?,
+        ),
+        Ok(Kipcnum::ReadPanicMessage) => {
+            read_panic_message(tasks, caller, args.message?, args.response?)
+        }
         #[cfg(feature = "dump")]
         Ok(Kipcnum::GetTaskDumpRegion) => {
             get_task_dump_region(tasks, caller, args.message?, args.response?)
@@ -122,11 +137,29 @@ fn restart_task(
         )));
     }
     let old_id = current_id(tasks, index);
+    let old_state = *tasks[index].state();
     tasks[index].reinitialize();
     if start {
         tasks[index].set_healthy_state(SchedState::Runnable);
     }
 
+    // The new incarnation owes nobody the reply to an abandoned send.
+    for task in tasks.iter_mut() {
+        task.take_stale_reply_from(index);
+    }
+
+    // If the task was blocked on another, it no longer lends that task its
+    // priority.
+    #[cfg(feature = "priority-donation")]
+    if let TaskState::Healthy(
+        SchedState::InSend(server) | SchedState::InReply(server),
+    ) = old_state
+    {
+        crate::task::withdraw_donation(tasks, server);
+    }
+    #[cfg(not(feature = "priority-donation"))]
+    let _ = old_state;
+
     // Restarting a task can have implications for other tasks. We don't want to
     // leave tasks sitting around waiting for a reply that will never come, for
     // example. So, make a pass over the task table and unblock anyone who was
@@ -223,6 +256,148 @@ fn read_image_id(
     Ok(NextTask::Same)
 }
 
+///
+/// Scans a page of (up to) 32 tasks, starting at the given task index, and
+/// returns a bitmap indicating which of them are in the `Faulted` state.  Bit
+/// `n` of the response corresponds to task index `base + n`; bits for task
+/// indices beyond the end of the task table are zero.  This allows the
+/// supervisor to find faulted tasks with one syscall per page of tasks,
+/// rather than one per task.
+///
+fn find_faulted_tasks(
+    tasks: &mut [Task],
+    caller: usize,
+    message: USlice<u8>,
+    response: USlice<u8>,
+) -> Result<NextTask, UserError> {
+    let base: u32 = deserialize_message(&tasks[caller], message)?;
+    let base = base as usize;
+    if base >= tasks.len() {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::TaskOutOfRange,
+        )));
+    }
+
+    let faulted = tasks[base..]
+        .iter()
+        .take(u32::BITS as usize)
+        .enumerate()
+        .filter(|(_, task)| matches!(task.state(), TaskState::Faulted { .. }))
+        .fold(0u32, |bits, (i, _)| bits | (1 << i));
+
+    let response_len =
+        serialize_response(&mut tasks[caller], response, &faulted)?;
+    tasks[caller]
+        .save_mut()
+        .set_send_response_and_length(0, response_len);
+    Ok(NextTask::Same)
+}
+
+fn read_task_stats(
+    tasks: &mut [Task],
+    caller: usize,
+    message: USlice<u8>,
+    response: USlice<u8>,
+) -> Result<NextTask, UserError> {
+    let index: u32 = deserialize_message(&tasks[caller], message)?;
+    if index as usize >= tasks.len() {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::TaskOutOfRange,
+        )));
+    }
+    // copy the stats out before taking a mutable borrow on tasks
+    let stats = *tasks[index as usize].stats();
+
+    let response_len =
+        serialize_response(&mut tasks[caller], response, &stats)?;
+    tasks[caller]
+        .save_mut()
+        .set_send_response_and_length(0, response_len);
+    Ok(NextTask::Same)
+}
+
+fn read_priority_inversions(
+    tasks: &mut [Task],
+    caller: usize,
+    message: USlice<u8>,
+    response: USlice<u8>,
+) -> Result<NextTask, UserError> {
+    let index: u32 = deserialize_message(&tasks[caller], message)?;
+    if index as usize >= tasks.len() {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::TaskOutOfRange,
+        )));
+    }
+    let inversions = *tasks[index as usize].inversions();
+
+    let response_len =
+        serialize_response(&mut tasks[caller], response, &inversions)?;
+    tasks[caller]
+        .save_mut()
+        .set_send_response_and_length(0, response_len);
+    Ok(NextTask::Same)
+}
+
+fn read_panic_message(
+    tasks: &mut [Task],
+    caller: usize,
+    message: USlice<u8>,
+    response: USlice<u8>,
+) -> Result<NextTask, UserError> {
+    use crate::umem::safe_copy;
+
+    if caller != 0 {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::NotSupervisor,
+        )));
+    }
+
+    let index: u32 = deserialize_message(&tasks[caller], message)?;
+    let index = index as usize;
+
+    // As with `read_task_dump_region`, the supervisor can't ask about itself;
+    // this keeps the copy below between two distinct tasks.
+    if index == caller || index >= tasks.len() {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::TaskOutOfRange,
+        )));
+    }
+
+    // The message is only available while the task sits in the fault left by
+    // its PANIC syscall, whose arguments are still in its saved registers.
+    let panicked = matches!(
+        tasks[index].state(),
+        TaskState::Faulted {
+            fault: FaultInfo::Panic,
+            ..
+        }
+    );
+    let from = if panicked {
+        tasks[index].save().as_panic_args().message.ok()
+    } else {
+        None
+    };
+
+    let response_len = match from {
+        None => 0,
+        Some(from) => match safe_copy(tasks, index, from, caller, response) {
+            Ok(len) => len,
+            // A panicking task that handed the kernel a bogus message isn't
+            // worth faulting again; we just report no message. A bad response
+            // buffer, on the other hand, is the supervisor's fault.
+            Err(interact) => match interact.dst {
+                Some(fault) => return Err(UserError::Unrecoverable(fault)),
+                None => 0,
+            },
+        },
+    };
+
+    tasks[caller]
+        .save_mut()
+        .set_send_response_and_length(0, response_len);
+    Ok(NextTask::Same)
+}
+
 #[cfg(feature = "dump")]
 fn get_task_dump_region(
     tasks: &mut [Task],
@@ -37,7 +37,7 @@
 pub mod arch;
 
 pub mod atomic;
-mod descs;
+pub mod descs;
 pub mod err;
 pub mod fail;
 pub mod header;
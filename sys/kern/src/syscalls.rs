@@ -95,7 +95,12 @@ pub unsafe extern "C" fn syscall_entry(nr: u32, task: *mut Task) {
 
 /// Factored out of `syscall_entry` to encapsulate the bits that don't need
 /// unsafe.
-fn safe_syscall_entry(nr: u32, current: usize, tasks: &mut [Task]) -> NextTask {
+pub(crate) fn safe_syscall_entry(
+    nr: u32,
+    current: usize,
+    tasks: &mut [Task],
+) -> NextTask {
+    tasks[current].record_syscall(nr);
     let res = match Sysnum::try_from(nr) {
         Ok(Sysnum::Send) => send(tasks, current),
         Ok(Sysnum::Recv) => recv(tasks, current).map_err(UserError::from),
@@ -112,6 +117,7 @@ fn safe_syscall_entry(nr: u32, current: usize, tasks: &mut [Task]) -> NextTask {
         Ok(Sysnum::ReplyFault) => {
             reply_fault(tasks, current).map_err(UserError::from)
         }
+        Ok(Sysnum::SendTimeout) => send_timeout(tasks, current),
         Err(_) => {
             // Bogus syscall number! That's a fault.
             Err(FaultInfo::SyscallUsage(UsageError::BadSyscallNumber).into())
@@ -152,10 +158,13 @@ fn send(tasks: &mut [Task], caller: usize) -> Result<NextTask, UserError> {
     // Verify the given callee ID, converting it into a table index on success.
     let callee = task::check_task_id_against_table(tasks, callee_id)?;
 
-    // Check for ready peer.
+    // Check for ready peer. If the callee still owes us the reply to a send we
+    // abandoned, it can't take this message until it has sent that.
     let mut next_task = NextTask::Same;
     let caller_id = current_id(tasks, caller);
-    if tasks[callee].state().can_accept_message_from(caller_id) {
+    if tasks[callee].state().can_accept_message_from(caller_id)
+        && !tasks[caller].awaits_stale_reply_from(callee)
+    {
         // Callee is waiting in receive -- either an open receive, or a
         // closed receive from just us. Either way, we can directly deliver the
         // message and switch tasks...unless either task was naughty, in which
@@ -164,6 +173,7 @@ fn send(tasks: &mut [Task], caller: usize) -> Result<NextTask, UserError> {
             Ok(_) => {
                 // Delivery succeeded! The initiating task is now blocked in
                 // reply. Switch directly to the callee.
+                block_on(tasks, caller, callee);
                 return Ok(NextTask::Specific(callee));
             }
             Err(interact) => {
@@ -181,11 +191,76 @@ fn send(tasks: &mut [Task], caller: usize) -> Result<NextTask, UserError> {
     // Caller needs to block sending, callee is either busy or
     // faulted.
     tasks[caller].set_healthy_state(SchedState::InSend(callee_id));
+    block_on(tasks, caller, callee);
     // We may not know what task to run next, but we're pretty sure it isn't the
     // caller.
     Ok(NextTask::Other.combine(next_task))
 }
 
+/// Bookkeeping for a task at index `caller` that has just blocked on the task
+/// at index `callee`, either in SEND or, once its message was delivered, in
+/// REPLY.
+fn block_on(tasks: &mut [Task], caller: usize, callee: usize) {
+    // Sending to a less important task is a priority inversion: while we wait
+    // on the callee, it (and we) can be starved by anything in between.
+    // Keep track of how long we end up blocked.
+    let priority = tasks[caller].priority();
+    if priority.is_more_important_than(tasks[callee].base_priority()) {
+        tasks[caller].begin_inversion(callee, arch::now());
+    }
+    #[cfg(feature = "priority-donation")]
+    task::donate_priority(tasks, callee, priority);
+}
+
+/// Gives back any priority donated to the task at index `server` by tasks no
+/// longer blocked on it, returning a scheduling hint.
+#[cfg(feature = "priority-donation")]
+fn release_donation(tasks: &mut [Task], server: usize) -> NextTask {
+    if task::restore_priority(tasks, server) {
+        NextTask::Other
+    } else {
+        NextTask::Same
+    }
+}
+
+/// Without priority donation, servers always run at their own priority.
+#[cfg(not(feature = "priority-donation"))]
+fn release_donation(_tasks: &mut [Task], _server: usize) -> NextTask {
+    NextTask::Same
+}
+
+/// Implementation of the SEND_TIMEOUT IPC primitive: a SEND that is abandoned
+/// if the caller's timer fires before it completes.
+///
+/// `caller` is a valid task index (i.e. not directly from user code).
+///
+/// # Panics
+///
+/// If `caller` is out of range for `tasks`.
+fn send_timeout(
+    tasks: &mut [Task],
+    caller: usize,
+) -> Result<NextTask, UserError> {
+    // Without an armed timer, there's nothing to stop us blocking forever --
+    // and the likeliest reason for it not to be armed is that it has already
+    // fired.
+    let (deadline, _) = tasks[caller].timer();
+    if deadline.is_none() {
+        return Err(UserError::Recoverable(abi::TIMED_OUT, NextTask::Same));
+    }
+
+    tasks[caller].set_timed_send(true);
+    let result = send(tasks, caller);
+    if !matches!(
+        tasks[caller].state(),
+        TaskState::Healthy(SchedState::InSend(_) | SchedState::InReply(_))
+    ) {
+        // The send finished (or failed) without blocking.
+        tasks[caller].set_timed_send(false);
+    }
+    result
+}
+
 /// Implementation of the RECV IPC primitive.
 ///
 /// `caller` is a valid task index (i.e. not directly from user code).
@@ -226,8 +301,11 @@ fn recv(tasks: &mut [Task], caller: usize) -> Result<NextTask, UserError> {
 
         // First possibility: that task you're asking about is DEAD.
         let sender_idx = task::check_task_id_against_table(tasks, sender_id)?;
-        // Second possibility: task has a message for us.
-        if tasks[sender_idx].state().is_sending_to(caller_id) {
+        // Second possibility: task has a message for us (and isn't waiting on
+        // us to reply to an abandoned one).
+        if tasks[sender_idx].state().is_sending_to(caller_id)
+            && !tasks[sender_idx].awaits_stale_reply_from(caller)
+        {
             // Oh hello sender!
             match deliver(tasks, sender_idx, caller) {
                 Ok(_) => {
@@ -265,6 +343,7 @@ fn recv(tasks: &mut [Task], caller: usize) -> Result<NextTask, UserError> {
         // Is anyone blocked waiting to send to us?
         while let Some(sender) = task::priority_scan(last, tasks, |t| {
             t.state().is_sending_to(caller_id)
+                && !t.awaits_stale_reply_from(caller)
         }) {
             // Oh hello sender!
             match deliver(tasks, sender, caller) {
@@ -312,18 +391,26 @@ fn reply(tasks: &mut [Task], caller: usize) -> Result<NextTask, FaultInfo> {
     // the caller crashed before receiving its reply) but we treat invalid
     // indices that could never have been received as a malfunction.
     let callee = match task::check_task_id_against_table(tasks, callee) {
-        Err(UserError::Recoverable(_, hint)) => return Ok(hint),
+        Err(UserError::Recoverable(_, hint)) => {
+            return Ok(hint.combine(release_donation(tasks, caller)))
+        }
         Err(UserError::Unrecoverable(f)) => return Err(f),
         Ok(x) => x,
     };
 
+    // If the target gave up waiting for this reply, it's not the reply to
+    // whatever it's doing now; drop it.
+    if tasks[callee].take_stale_reply_from(caller) {
+        return Ok(release_donation(tasks, caller));
+    }
+
     if tasks[callee].state()
         != &TaskState::Healthy(SchedState::InReply(caller_id))
     {
         // Huh. The target task is off doing something else. This can happen if
         // application-specific supervisory logic unblocks it before we've had a
         // chance to reply (e.g. to implement timeouts).
-        return Ok(NextTask::Same);
+        return Ok(release_donation(tasks, caller));
     }
 
     // Deliver the reply. Note that we can't use `deliver`, which is
@@ -383,8 +470,10 @@ fn reply(tasks: &mut [Task], caller: usize) -> Result<NextTask, FaultInfo> {
 
     // KEY ASSUMPTION: sends go from less important tasks to more important
     // tasks. As a result, Reply doesn't have scheduling implications unless
-    // the task using it faults.
-    Ok(NextTask::Same)
+    // the task using it faults -- or we were running on a priority donated by
+    // the task we just replied to, which we now give back, and that task (or
+    // another) may now outrank us.
+    Ok(release_donation(tasks, caller))
 }
 
 /// Implementation of the `SET_TIMER` syscall.
@@ -792,22 +881,31 @@ fn reply_fault(
     // if the caller crashed before receiving its reply) but we treat invalid
     // indices that could never have been received as a malfunction.
     let callee = match task::check_task_id_against_table(tasks, args.callee) {
-        Err(UserError::Recoverable(_, hint)) => return Ok(hint),
+        Err(UserError::Recoverable(_, hint)) => {
+            return Ok(hint.combine(release_donation(tasks, caller)))
+        }
         Err(UserError::Unrecoverable(f)) => return Err(f),
         Ok(x) => x,
     };
 
+    // A task that gave up waiting isn't to blame for whatever went wrong with
+    // the message it abandoned.
+    if tasks[callee].take_stale_reply_from(caller) {
+        return Ok(release_donation(tasks, caller));
+    }
+
     if tasks[callee].state()
         != &TaskState::Healthy(SchedState::InReply(caller_id))
     {
         // Huh. The target task is off doing something else. This can happen if
         // application-specific supervisory logic unblocks it before we've had a
         // chance to reply (e.g. to implement timeouts).
-        return Ok(NextTask::Same);
+        return Ok(release_donation(tasks, caller));
     }
 
     // Check and deliver the fault. We explicitly discard its scheduling hint,
     // because the caller is lower priority than we are.
+    let priority = tasks[caller].priority();
     let _hint = task::force_fault(
         tasks,
         callee,
@@ -816,6 +914,11 @@ fn reply_fault(
 
     // KEY ASSUMPTION: sends go from less important tasks to more important
     // tasks. As a result, Reply doesn't have scheduling implications unless
-    // the task using it faults.
-    Ok(NextTask::Same)
+    // the task using it faults -- or faulting the callee withdrew a priority
+    // it had donated to us.
+    if priority.is_more_important_than(tasks[caller].priority()) {
+        Ok(NextTask::Other)
+    } else {
+        Ok(NextTask::Same)
+    }
 }
@@ -8,8 +8,9 @@ use core::convert::TryFrom;
 use core::ops::Range;
 
 use abi::{
-    FaultInfo, FaultSource, Generation, ReplyFaultReason, SchedState, TaskId,
-    TaskState, ULease, UsageError,
+    FaultInfo, FaultSource, Generation, InversionStats, PriorityInversion,
+    ReplyFaultReason, SchedState, TaskId, TaskState, TaskStats, ULease,
+    UsageError,
 };
 use zerocopy::FromBytes;
 
@@ -34,12 +35,22 @@ pub struct Task {
     /// Saved machine state of the user program.
     save: crate::arch::SavedState,
     // NOTE: it is critical that the above field appear first!
-    /// Current priority of the task.
+    /// Current priority of the task. This is normally the priority from the
+    /// task's descriptor, but may be raised temporarily by priority donation.
     priority: Priority,
     /// State used to make status and scheduling decisions.
     state: TaskState,
     /// State for tracking the task's timer.
     timer: TimerState,
+    /// Whether the task is blocked in a `SEND_TIMEOUT`, which should be
+    /// abandoned when its timer fires.
+    timed_send: bool,
+    /// Bitmap (by task index) of servers that received a message from this
+    /// task and still owe it a reply, though the task abandoned the send when
+    /// its timer fired. That reply is discarded when it arrives, and until
+    /// then the server can't receive another message from this task, so
+    /// that it can't be mistaken for the reply to a later send.
+    stale_replies: [u32; STALE_REPLY_WORDS],
     /// Restart count for this task. We increment this whenever we reinitialize
     /// the task. The low bits of this become the task's generation number.
     generation: u32,
@@ -47,6 +58,17 @@ pub struct Task {
     /// Notification status.
     notifications: u32,
 
+    /// Scheduling and accounting counters. Unlike most of the task state,
+    /// these survive reinitialization.
+    stats: TaskStats,
+
+    /// If this task is blocked on a less important server, the index of that
+    /// server and the time at which the task blocked.
+    inversion_start: Option<(u16, Timestamp)>,
+    /// Record of completed priority inversions. Like `stats`, this survives
+    /// reinitialization.
+    inversions: InversionStats,
+
     /// Pointer to the ROM descriptor used to create this task, so it can be
     /// restarted.
     descriptor: &'static TaskDesc,
@@ -67,7 +89,12 @@ impl Task {
             descriptor,
 
             generation: 0,
+            timed_send: false,
+            stale_replies: [0; STALE_REPLY_WORDS],
             notifications: 0,
+            stats: TaskStats::default(),
+            inversion_start: None,
+            inversions: InversionStats::default(),
             save: crate::arch::SavedState::default(),
             timer: crate::task::TimerState::default(),
         }
@@ -308,6 +335,13 @@ impl Task {
         (self.timer.deadline, self.timer.to_post)
     }
 
+    /// Sets whether this task's current send should be abandoned when its
+    /// timer fires. This is cleared automatically when the task is no longer
+    /// blocked in the send.
+    pub fn set_timed_send(&mut self, timed: bool) {
+        self.timed_send = timed;
+    }
+
     /// Rewrites this task's state back to its initial form, to effect a task
     /// reboot.
     ///
@@ -321,8 +355,12 @@ impl Task {
     pub fn reinitialize(&mut self) {
         self.generation = self.generation.wrapping_add(1);
         self.timer = TimerState::default();
+        self.timed_send = false;
+        self.stale_replies = [0; STALE_REPLY_WORDS];
         self.notifications = 0;
         self.state = TaskState::default();
+        self.priority = self.base_priority();
+        self.inversion_start = None;
 
         crate::arch::reinitialize(self);
     }
@@ -349,11 +387,123 @@ impl Task {
         self.priority
     }
 
+    /// Returns this task's configured priority, ignoring any donation.
+    pub fn base_priority(&self) -> Priority {
+        Priority(self.descriptor.priority)
+    }
+
     /// Returns a reference to this task's current state, for inspection.
     pub fn state(&self) -> &TaskState {
         &self.state
     }
 
+    /// If this task is blocked in a `SEND_TIMEOUT`, gives up on it, making the
+    /// task runnable with the `TIMED_OUT` response code. Returns the server
+    /// the task was blocked on if it was woken.
+    fn abandon_timed_send(&mut self) -> Option<TaskId> {
+        if !self.timed_send {
+            return None;
+        }
+        // The flag is only set while the task is blocked in SEND, but it's
+        // cheap to make sure.
+        match self.state {
+            TaskState::Healthy(SchedState::InSend(server)) => {
+                self.save.set_error_response(abi::TIMED_OUT);
+                self.set_healthy_state(SchedState::Runnable);
+                Some(server)
+            }
+            TaskState::Healthy(SchedState::InReply(server)) => {
+                // The server has our message, and will reply eventually.
+                let (word, bit) = stale_reply_bit(server.index());
+                self.stale_replies[word] |= bit;
+                self.save.set_error_response(abi::TIMED_OUT);
+                self.set_healthy_state(SchedState::Runnable);
+                Some(server)
+            }
+            _ => {
+                self.timed_send = false;
+                None
+            }
+        }
+    }
+
+    /// Checks whether the server at index `server` owes this task the reply
+    /// to an abandoned send; see `stale_replies`.
+    pub fn awaits_stale_reply_from(&self, server: usize) -> bool {
+        let (word, bit) = stale_reply_bit(server);
+        self.stale_replies[word] & bit != 0
+    }
+
+    /// Consumes the reply owed by the server at index `server` to an
+    /// abandoned send, if there is one. Returns `true` if there was, in which
+    /// case the reply should be discarded.
+    pub fn take_stale_reply_from(&mut self, server: usize) -> bool {
+        let owed = self.awaits_stale_reply_from(server);
+        let (word, bit) = stale_reply_bit(server);
+        self.stale_replies[word] &= !bit;
+        owed
+    }
+
+    /// Returns this task's scheduling and accounting counters.
+    pub fn stats(&self) -> &TaskStats {
+        &self.stats
+    }
+
+    /// Records a syscall by this task. Out-of-range syscall numbers are not
+    /// counted (they fault the task instead).
+    pub fn record_syscall(&mut self, nr: u32) {
+        if let Some(count) = self.stats.syscalls.get_mut(nr as usize) {
+            *count = count.wrapping_add(1);
+        }
+    }
+
+    /// Charges a timer tick to this task, which was running when it arrived.
+    pub fn record_tick(&mut self) {
+        self.stats.ticks = self.stats.ticks.wrapping_add(1);
+    }
+
+    /// Records that the scheduler has switched to this task from another.
+    pub fn record_scheduled(&mut self) {
+        self.stats.scheduled = self.stats.scheduled.wrapping_add(1);
+    }
+
+    /// Records that this task was switched out as the result of an interrupt.
+    pub fn record_preemption(&mut self) {
+        self.stats.preemptions = self.stats.preemptions.wrapping_add(1);
+    }
+
+    /// Returns the priority inversions observed with this task as the client.
+    pub fn inversions(&self) -> &InversionStats {
+        &self.inversions
+    }
+
+    /// Notes that this task is about to block on the less important task at
+    /// index `server`, starting at `now`. The inversion is recorded when the
+    /// task next becomes runnable.
+    pub fn begin_inversion(&mut self, server: usize, now: Timestamp) {
+        self.inversion_start = Some((server as u16, now));
+    }
+
+    /// Completes any inversion begun by `begin_inversion`, recording its
+    /// duration.
+    fn end_inversion(&mut self) {
+        let Some((server, start)) = self.inversion_start.take() else {
+            return;
+        };
+        let start = u64::from(start);
+        let ticks = u64::from(crate::arch::now()).saturating_sub(start);
+
+        let inv = &mut self.inversions;
+        inv.count = inv.count.wrapping_add(1);
+        inv.total_ticks = inv.total_ticks.wrapping_add(ticks);
+        inv.worst_ticks = inv.worst_ticks.max(ticks);
+        inv.last = Some(PriorityInversion {
+            server,
+            start,
+            ticks,
+        });
+    }
+
     /// Alters this task's state from one healthy state to another.
     ///
     /// To deliver a fault, use `force_fault` instead.
@@ -371,6 +521,13 @@ impl Task {
         if let TaskState::Faulted { .. } = last {
             panic!();
         }
+        // A task that was blocked on a less important server (or with a
+        // timeout) stays blocked as its message is delivered (moving from
+        // InSend to InReply), but is done once it moves on to anything else.
+        if !matches!(s, SchedState::InSend(_) | SchedState::InReply(_)) {
+            self.end_inversion();
+            self.timed_send = false;
+        }
     }
 
     /// Returns a reference to the saved machine state for the task.
@@ -756,20 +913,54 @@ impl NextTask {
     }
 }
 
+/// Number of words in the `Task::stale_replies` bitmap, which has a bit for
+/// each task in the table.
+#[cfg(target_os = "none")]
+const STALE_REPLY_WORDS: usize =
+    (crate::startup::HUBRIS_TASK_COUNT + u32::BITS as usize - 1)
+        / u32::BITS as usize;
+
+/// The simulator builds its task table at runtime, so its bitmap is sized for
+/// the largest table it accepts.
+#[cfg(not(target_os = "none"))]
+const STALE_REPLY_WORDS: usize =
+    (crate::arch::MAX_TASKS + u32::BITS as usize - 1) / u32::BITS as usize;
+
+/// Returns the word and bit for the server at index `server` in
+/// `Task::stale_replies`.
+fn stale_reply_bit(server: usize) -> (usize, u32) {
+    let bits = u32::BITS as usize;
+    (server / bits, 1 << (server % bits))
+}
+
 /// Processes all enabled timers in the task table, posting notifications for
 /// any that have expired by `current_time` (and disabling them atomically).
+// With priority donation, the loop also needs the whole table.
+#[allow(clippy::needless_range_loop)]
 pub fn process_timers(tasks: &mut [Task], current_time: Timestamp) -> NextTask {
     let mut sched_hint = NextTask::Same;
-    for (index, task) in tasks.iter_mut().enumerate() {
+    for index in 0..tasks.len() {
+        let task = &mut tasks[index];
         if let Some(deadline) = task.timer.deadline {
             if deadline <= current_time {
                 task.timer.deadline = None;
-                let task_hint = if task.post(task.timer.to_post) {
-                    NextTask::Specific(index)
-                } else {
-                    NextTask::Same
-                };
-                sched_hint = sched_hint.combine(task_hint)
+                let abandoned = task.abandon_timed_send();
+                let task_hint =
+                    if task.post(task.timer.to_post) || abandoned.is_some() {
+                        NextTask::Specific(index)
+                    } else {
+                        NextTask::Same
+                    };
+                sched_hint = sched_hint.combine(task_hint);
+
+                // The task may have been lending its priority to the server
+                // it gave up on.
+                #[cfg(feature = "priority-donation")]
+                if let Some(server) = abandoned {
+                    if withdraw_donation(tasks, server) {
+                        sched_hint = sched_hint.combine(NextTask::Other);
+                    }
+                }
             }
         }
     }
@@ -871,6 +1062,7 @@ pub fn force_fault(
     fault: FaultInfo,
 ) -> NextTask {
     let task = &mut tasks[index];
+    let prior = task.state;
     task.state = match task.state {
         TaskState::Healthy(sched) => TaskState::Faulted {
             original_state: sched,
@@ -885,6 +1077,18 @@ pub fn force_fault(
             }
         }
     };
+    // A faulted task no longer lends its priority to whoever it was blocked
+    // on.
+    #[cfg(feature = "priority-donation")]
+    if let TaskState::Healthy(
+        SchedState::InSend(server) | SchedState::InReply(server),
+    ) = prior
+    {
+        withdraw_donation(tasks, server);
+    }
+    #[cfg(not(feature = "priority-donation"))]
+    let _ = prior;
+
     let supervisor_awoken =
         tasks[0].post(NotificationSet(HUBRIS_FAULT_NOTIFICATION));
     if supervisor_awoken {
@@ -894,6 +1098,97 @@ pub fn force_fault(
     }
 }
 
+/// Lends `priority` to the server at index `server`, which a task with that
+/// priority is about to block on, if it's more important than the server's
+/// current priority. If the server is itself blocked on another task, the
+/// donation is passed along the chain.
+#[cfg(feature = "priority-donation")]
+pub fn donate_priority(tasks: &mut [Task], server: usize, priority: Priority) {
+    let mut server = server;
+    // Bound the walk by the size of the table, in case tasks are deadlocked
+    // sending to one another.
+    for _ in 0..tasks.len() {
+        let task = &mut tasks[server];
+        if !priority.is_more_important_than(task.priority) {
+            break;
+        }
+        task.priority = priority;
+        match task.state {
+            TaskState::Healthy(
+                SchedState::InSend(next) | SchedState::InReply(next),
+            ) => server = next.index(),
+            _ => break,
+        }
+    }
+}
+
+/// Recomputes the priority of the server at index `server` once a client has
+/// stopped waiting on it: this is the most important of its configured
+/// priority and the priorities of any tasks still blocked on it. If the
+/// server is itself blocked on another task, that task's priority is
+/// recomputed in turn, unwinding any donation passed along the chain.
+///
+/// Returns `true` if this lowered any task's priority, in which case the
+/// current task may no longer be the most important runnable one.
+#[cfg(feature = "priority-donation")]
+pub fn restore_priority(tasks: &mut [Task], server: usize) -> bool {
+    let mut server = server;
+    let mut lowered = false;
+    // Bound the walk by the size of the table, as in `donate_priority`.
+    for _ in 0..tasks.len() {
+        let server_id = current_id(tasks, server);
+        let priority = tasks
+            .iter()
+            .filter(|t| match t.state {
+                TaskState::Healthy(
+                    SchedState::InSend(peer) | SchedState::InReply(peer),
+                ) => peer == server_id,
+                _ => false,
+            })
+            .map(|t| t.priority)
+            .fold(tasks[server].base_priority(), |best, p| {
+                if p.is_more_important_than(best) {
+                    p
+                } else {
+                    best
+                }
+            });
+
+        let task = &mut tasks[server];
+        if !task.priority.is_more_important_than(priority) {
+            // Unchanged (or raised, which `donate_priority` would have passed
+            // along already), so nothing further down the chain can change.
+            task.priority = priority;
+            break;
+        }
+        task.priority = priority;
+        lowered = true;
+        match task.state {
+            TaskState::Healthy(
+                SchedState::InSend(next) | SchedState::InReply(next),
+            ) => server = next.index(),
+            _ => break,
+        }
+    }
+    lowered
+}
+
+/// Withdraws any priority donated to `server` by a task that has stopped
+/// waiting on it, e.g. because it faulted, was restarted, or gave up on a
+/// timed send. `server` may be stale, in which case the task it named has
+/// already been restarted at its configured priority.
+///
+/// Returns `true` if this lowered any task's priority.
+#[cfg(feature = "priority-donation")]
+pub fn withdraw_donation(tasks: &mut [Task], server: TaskId) -> bool {
+    if server.index() >= tasks.len()
+        || current_id(tasks, server.index()) != server
+    {
+        return false;
+    }
+    restore_priority(tasks, server.index())
+}
+
 /// Produces a current `TaskId` (i.e. one with the correct generation) for
 /// `tasks[index]`.
 pub fn current_id(tasks: &[Task], index: usize) -> TaskId {
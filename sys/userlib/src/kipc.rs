@@ -24,6 +24,77 @@ pub fn read_task_status(task: usize) -> abi::TaskState {
     ssmarshal::deserialize(&response[..len]).unwrap_lite().0
 }
 
+/// Returns a bitmap of the faulted tasks among the (up to) 32 tasks starting
+/// at index `base`: bit `n` is set if task `base + n` is faulted.  To scan the
+/// whole task table, call this with `base` stepping by 32.
+pub fn find_faulted_tasks(base: usize) -> u32 {
+    // Coerce `base` to a known size (Rust doesn't assume that usize == u32)
+    let base = base as u32;
+    let mut response = [0; core::mem::size_of::<u32>()];
+    let (rc, len) = sys_send(
+        TaskId::KERNEL,
+        Kipcnum::FindFaultedTasks as u16,
+        base.as_bytes(),
+        &mut response,
+        &[],
+    );
+    assert_eq!(rc, 0);
+    ssmarshal::deserialize(&response[..len]).unwrap_lite().0
+}
+
+/// Returns the kernel's scheduling and accounting counters for `task`.
+pub fn read_task_stats(task: usize) -> abi::TaskStats {
+    // Coerce `task` to a known size (Rust doesn't assume that usize == u32)
+    let task = task as u32;
+    let mut response = [0; core::mem::size_of::<abi::TaskStats>()];
+    let (rc, len) = sys_send(
+        TaskId::KERNEL,
+        Kipcnum::ReadTaskStats as u16,
+        task.as_bytes(),
+        &mut response,
+        &[],
+    );
+    assert_eq!(rc, 0);
+    ssmarshal::deserialize(&response[..len]).unwrap_lite().0
+}
+
+/// Returns the priority inversions the kernel has observed with `task` as the
+/// client, i.e. times it blocked on a less important server.
+pub fn read_priority_inversions(task: usize) -> abi::InversionStats {
+    // Coerce `task` to a known size (Rust doesn't assume that usize == u32)
+    let task = task as u32;
+    let mut response = [0; core::mem::size_of::<abi::InversionStats>()];
+    let (rc, len) = sys_send(
+        TaskId::KERNEL,
+        Kipcnum::ReadPriorityInversions as u16,
+        task.as_bytes(),
+        &mut response,
+        &[],
+    );
+    assert_eq!(rc, 0);
+    ssmarshal::deserialize(&response[..len]).unwrap_lite().0
+}
+
+/// Copies the message that `task` passed to `sys_panic` into `response`,
+/// returning its length (truncated to fit `response`).
+///
+/// This only works while `task` is faulted because of that panic; otherwise
+/// (or if the task's message was bogus) it returns 0. Only the supervisor can
+/// call this.
+pub fn read_panic_message(task: usize, response: &mut [u8]) -> usize {
+    // Coerce `task` to a known size (Rust doesn't assume that usize == u32)
+    let task = task as u32;
+    let (rc, len) = sys_send(
+        TaskId::KERNEL,
+        Kipcnum::ReadPanicMessage as u16,
+        task.as_bytes(),
+        response,
+        &[],
+    );
+    assert_eq!(rc, 0);
+    len
+}
+
 pub fn get_task_dump_region(
     task: usize,
     region: usize,
@@ -114,6 +114,11 @@ impl From<RcLen> for (u32, usize) {
     }
 }
 
+/// Sends a message to `target`, and waits for its reply.
+///
+/// With the `send-deadline` feature, this is bounded by the deadline of any
+/// enclosing `with_send_deadline`: see `sys_send_timeout`. Sends to the kernel
+/// are never bounded, because the kernel always replies immediately.
 #[inline(always)]
 pub fn sys_send(
     target: TaskId,
@@ -122,6 +127,15 @@ pub fn sys_send(
     incoming: &mut [u8],
     leases: &[Lease<'_>],
 ) -> (u32, usize) {
+    #[cfg(feature = "send-deadline")]
+    if let Some(deadline) = SEND_DEADLINE.0.get() {
+        if target != TaskId::KERNEL {
+            return sys_send_timeout(
+                target, operation, outgoing, incoming, leases, deadline,
+            );
+        }
+    }
+
     let mut args = SendArgs {
         packed_target_operation: u32::from(target.0) << 16
             | u32::from(operation),
@@ -219,6 +233,153 @@ unsafe extern "C" fn sys_send_stub(_args: &mut SendArgs<'_>) -> RcLen {
     }
 }
 
+/// Sends a message to `target` like `sys_send`, but gives up if no reply has
+/// arrived by `deadline` (in kernel ticks, as returned by `sys_get_timer`). In
+/// that case, this returns the response code `TIMED_OUT`.
+///
+/// This borrows the task's timer to track the deadline, restoring it before
+/// returning. If the timer's own deadline passes in the meantime, its
+/// notification is posted when it's restored, so it isn't lost, just late.
+///
+/// Giving up doesn't cancel the request: the server may still be working on
+/// it, and will have its eventual reply discarded.
+pub fn sys_send_timeout(
+    target: TaskId,
+    operation: u16,
+    outgoing: &[u8],
+    incoming: &mut [u8],
+    leases: &[Lease<'_>],
+    deadline: u64,
+) -> (u32, usize) {
+    let timer = sys_get_timer();
+    // If the deadline has already passed, this leaves the timer disarmed, and
+    // the kernel fails the send immediately.
+    sys_set_timer(Some(deadline), 0);
+
+    let mut args = SendArgs {
+        packed_target_operation: u32::from(target.0) << 16
+            | u32::from(operation),
+        outgoing_ptr: outgoing.as_ptr(),
+        outgoing_len: outgoing.len(),
+        incoming_ptr: incoming.as_mut_ptr(),
+        incoming_len: incoming.len(),
+        lease_ptr: leases.as_ptr(),
+        lease_len: leases.len(),
+    };
+    let result = unsafe { sys_send_timeout_stub(&mut args).into() };
+
+    sys_set_timer(timer.deadline, timer.on_dl);
+    result
+}
+
+/// Runs `body` with every `sys_send` it makes bounded by `deadline`, as if
+/// each used `sys_send_timeout`.
+///
+/// This is how to put a deadline on calls through Idol-generated client
+/// stubs. A call that times out returns `TIMED_OUT` to the stub, which turns
+/// it into the error variant marked `#[idol(timeout)]`, if the operation's
+/// error type has one -- otherwise the stub will panic, so only use this with
+/// operations that are prepared for it.
+///
+/// Calls may be nested, in which case the earlier deadline applies.
+///
+/// This is only available with the `send-deadline` feature, so that tasks
+/// which don't use it don't pay for checking the deadline in every send.
+#[cfg(feature = "send-deadline")]
+pub fn with_send_deadline<R>(deadline: u64, body: impl FnOnce() -> R) -> R {
+    let prev = SEND_DEADLINE.0.get();
+    let deadline = prev.map_or(deadline, |p| p.min(deadline));
+    SEND_DEADLINE.0.set(Some(deadline));
+    let result = body();
+    SEND_DEADLINE.0.set(prev);
+    result
+}
+
+/// Deadline applied to `sys_send` by `with_send_deadline`, if any.
+#[cfg(feature = "send-deadline")]
+struct SendDeadline(core::cell::Cell<Option<u64>>);
+
+// Safety: tasks are single-threaded and don't have interrupt handlers, so
+// there's no way for this to be accessed concurrently.
+#[cfg(feature = "send-deadline")]
+unsafe impl Sync for SendDeadline {}
+
+#[cfg(feature = "send-deadline")]
+static SEND_DEADLINE: SendDeadline = SendDeadline(core::cell::Cell::new(None));
+
+/// Core implementation of the SEND_TIMEOUT syscall, which takes the same
+/// arguments as SEND.
+///
+/// See the note on syscall stubs at the top of this module for rationale.
+#[naked]
+unsafe extern "C" fn sys_send_timeout_stub(_args: &mut SendArgs<'_>) -> RcLen {
+    cfg_if::cfg_if! {
+        if #[cfg(armv6m)] {
+            arch::asm!("
+                @ Spill the registers we're about to use to pass stuff.
+                push {{r4-r7, lr}}
+                mov r4, r8
+                mov r5, r9
+                mov r6, r10
+                mov r7, r11
+                push {{r4-r7}}
+                @ Load the constant syscall number.
+                eors r4, r4
+                adds r4, #{sysnum}
+                mov r11, r4
+                @ Load in args from the struct.
+                ldm r0!, {{r4-r7}}
+                ldm r0, {{r0-r2}}
+                mov r8, r0
+                mov r9, r1
+                mov r10, r2
+
+                @ To the kernel!
+                svc #0
+
+                @ Move the two results back into their return positions.
+                mov r0, r4
+                mov r1, r5
+                @ Restore the registers we used.
+                pop {{r4-r7}}
+                mov r8, r4
+                mov r9, r5
+                mov r10, r6
+                mov r11, r7
+                pop {{r4-r7, pc}}
+                ",
+                sysnum = const Sysnum::SendTimeout as u32,
+                options(noreturn),
+            )
+        } else if #[cfg(any(armv7m, armv8m))] {
+            arch::asm!("
+                @ Spill the registers we're about to use to pass stuff.
+                push {{r4-r11}}
+                @ Load in args from the struct.
+                ldm r0, {{r4-r10}}
+                @ Load the constant syscall number.
+                mov r11, {sysnum}
+
+                @ To the kernel!
+                svc #0
+
+                @ Move the two results back into their return positions.
+                mov r0, r4
+                mov r1, r5
+                @ Restore the registers we used.
+                pop {{r4-r11}}
+                @ Fin.
+                bx lr
+                ",
+                sysnum = const Sysnum::SendTimeout as u32,
+                options(noreturn),
+            )
+        } else {
+            compile_error!("missing sys_send_timeout_stub for ARM profile");
+        }
+    }
+}
+
 /// Performs an "open" RECV that will accept messages from any task or
 /// notifications from the kernel.
 ///
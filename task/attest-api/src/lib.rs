@@ -10,6 +10,17 @@ use hubpack::SerializedSize;
 use serde::{Deserialize, Serialize};
 use userlib::sys_send;
 
+/// Length of the caller-provided nonce included in an attestation
+pub const NONCE_SIZE: usize = 32;
+
+/// Length of an attestation: an Ed25519 signature, made with the DICE alias
+/// key, over the SHA3-256 digest of the serialized log followed by the nonce.
+/// That digest is SHA3-256 whatever the log's `HashAlgorithm`.
+pub const ATTESTATION_SIZE: usize = 64;
+
+/// Maximum length of the description recorded with an event
+pub const EVENT_TAG_MAX_SIZE: usize = 16;
+
 #[derive(
     Copy, Clone, Debug, Deserialize, Eq, PartialEq, Serialize, SerializedSize,
 )]
@@ -24,6 +35,9 @@ pub enum AttestError {
     BadLease,
     UnsupportedAlgorithm,
     SerializeLog,
+    /// The measurement doesn't use the log's hash algorithm, which is fixed
+    /// when the image is built and listed in the log's Spec ID event
+    AlgorithmMismatch,
 }
 
 impl From<idol_runtime::ServerDeath> for AttestError {
@@ -32,11 +46,36 @@ impl From<idol_runtime::ServerDeath> for AttestError {
     }
 }
 
+/// Hash algorithm of a measurement.
+///
+/// The log doesn't mix algorithms: each image records only the one set by
+/// the attest task's `log-algorithm` config (SHA3-256 unless the app says
+/// otherwise), and rejects the others with `AttestError::AlgorithmMismatch`.
 #[derive(
     Copy, Clone, Debug, Deserialize, Eq, PartialEq, Serialize, SerializedSize,
 )]
 pub enum HashAlgorithm {
     Sha3_256,
+    Sha256,
+    Sha384,
+}
+
+/// Event types from the TCG PC Client Platform Firmware Profile, for use
+/// with `Attest::record_event`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(u32)]
+pub enum EventType {
+    NoAction = 0x3,
+    Separator = 0x4,
+    Action = 0x5,
+    EventTag = 0x6,
+    SCrtmContents = 0x7,
+    SCrtmVersion = 0x8,
+    CpuMicrocode = 0x9,
+    PlatformConfigFlags = 0xa,
+    NonhostCode = 0xf,
+    NonhostConfig = 0x10,
+    NonhostInfo = 0x11,
 }
 
 include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
@@ -4,6 +4,7 @@
 
 use anyhow::{Context, Result};
 use idol::server::{self, ServerStyle};
+use serde::Deserialize;
 use std::{fs::File, io::Write};
 
 mod config {
@@ -14,6 +15,24 @@ use config::DataRegion;
 
 const CFG_SRC: &str = "attest-config.rs";
 
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Config {
+    /// Hash algorithm of every measurement in the log
+    #[serde(default)]
+    log_algorithm: LogAlgorithm,
+}
+
+/// Mirrors `attest_api::HashAlgorithm`
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "kebab-case")]
+enum LogAlgorithm {
+    #[default]
+    Sha3_256,
+    Sha256,
+    Sha384,
+}
+
 fn main() -> Result<()> {
     server::build_server_support(
         "../../idl/attest.idol",
@@ -58,5 +77,14 @@ pub const ALIAS_DATA: DataRegion = DataRegion {{
         region.address, region.size
     )?;
 
+    let cfg = build_util::task_maybe_config::<Config>()?.unwrap_or_default();
+    writeln!(
+        out,
+        "
+pub const LOG_ALGORITHM: attest_api::HashAlgorithm =
+    attest_api::HashAlgorithm::{:?};",
+        cfg.log_algorithm
+    )?;
+
     Ok(())
 }
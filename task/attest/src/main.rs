@@ -10,19 +10,22 @@
 #![no_main]
 
 mod config;
+mod tcg;
 
-use attest_api::{AttestError, HashAlgorithm};
+use attest_api::{
+    AttestError, EventType, HashAlgorithm, ATTESTATION_SIZE,
+    EVENT_TAG_MAX_SIZE, NONCE_SIZE,
+};
 use config::DataRegion;
 use core::slice;
 use crypto_common::{typenum::Unsigned, OutputSizeUser};
 use hubpack::SerializedSize;
-use idol_runtime::{ClientError, Leased, RequestError, W};
-use lib_dice::{AliasData, CertData};
+use idol_runtime::{ClientError, Leased, LenLimit, RequestError, R, W};
+use lib_dice::{AliasData, CertData, SeedBuf};
 use mutable_statics::mutable_statics;
 use ringbuf::{ringbuf, ringbuf_entry};
-use serde::{Deserialize, Serialize};
-use serde_with::serde_as;
-use sha3::Sha3_256Core;
+use serde::Deserialize;
+use sha3::{Digest as _, Sha3_256, Sha3_256Core};
 use stage0_handoff::{HandoffData, HandoffDataLoadError};
 use zerocopy::AsBytes;
 
@@ -33,7 +36,7 @@ mod build {
     include!(concat!(env!("OUT_DIR"), "/attest-config.rs"));
 }
 
-use build::{ALIAS_DATA, CERT_DATA};
+use build::{ALIAS_DATA, CERT_DATA, LOG_ALGORITHM};
 
 #[derive(Copy, Clone, PartialEq)]
 enum Trace {
@@ -47,9 +50,11 @@ enum Trace {
     Offset(u32),
     Startup,
     Record(HashAlgorithm),
+    RecordEvent(HashAlgorithm, u32),
     BadLease(usize),
     LogLen(u32),
     Log,
+    Attest,
     None,
 }
 
@@ -83,14 +88,25 @@ fn load_data_from_region<
 // of various hash functions
 const SHA3_256_DIGEST_SIZE: usize =
     <Sha3_256Core as OutputSizeUser>::OutputSize::USIZE;
+const SHA256_DIGEST_SIZE: usize =
+    <sha2::Sha256 as OutputSizeUser>::OutputSize::USIZE;
+const SHA384_DIGEST_SIZE: usize =
+    <sha2::Sha384 as OutputSizeUser>::OutputSize::USIZE;
+
+const fn digest_size(algorithm: HashAlgorithm) -> usize {
+    match algorithm {
+        HashAlgorithm::Sha3_256 => SHA3_256_DIGEST_SIZE,
+        HashAlgorithm::Sha256 => SHA256_DIGEST_SIZE,
+        HashAlgorithm::Sha384 => SHA384_DIGEST_SIZE,
+    }
+}
 
 // the number of Measurements we can record
 const CAPACITY: usize = 16;
 
 // Digest is a fixed length array of bytes
-#[serde_as]
-#[derive(Clone, Copy, Debug, PartialEq, Serialize, SerializedSize)]
-struct Digest<const N: usize>(#[serde_as(as = "[_; N]")] [u8; N]);
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Digest<const N: usize>([u8; N]);
 
 impl<const N: usize> Default for Digest<N> {
     fn default() -> Self {
@@ -98,34 +114,62 @@ impl<const N: usize> Default for Digest<N> {
     }
 }
 
+impl<const N: usize> Digest<N> {
+    fn read(data: &Leased<R, [u8]>) -> Result<Self, RequestError<AttestError>> {
+        if data.len() != N {
+            ringbuf_entry!(Trace::BadLease(data.len()));
+            return Err(AttestError::BadLease.into());
+        }
+
+        let mut digest = Self::default();
+        data.read_range(0..N, &mut digest.0)
+            .map_err(|_| RequestError::went_away())?;
+
+        Ok(digest)
+    }
+}
+
 type Sha3_256Digest = Digest<SHA3_256_DIGEST_SIZE>;
+type Sha256Digest = Digest<SHA256_DIGEST_SIZE>;
+type Sha384Digest = Digest<SHA384_DIGEST_SIZE>;
 
 // Measurement is an enum that can hold any of the supported hash algorithms
-#[derive(Clone, Copy, Debug, PartialEq, Serialize, SerializedSize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 enum Measurement {
     Sha3_256(Sha3_256Digest),
+    Sha256(Sha256Digest),
+    Sha384(Sha384Digest),
 }
 
 impl Measurement {
     fn new(
         algorithm: HashAlgorithm,
-        data: idol_runtime::Leased<idol_runtime::R, [u8]>,
+        data: Leased<R, [u8]>,
     ) -> Result<Self, RequestError<AttestError>> {
         Ok(match algorithm {
             HashAlgorithm::Sha3_256 => {
-                if data.len() != SHA3_256_DIGEST_SIZE {
-                    ringbuf_entry!(Trace::BadLease(data.len()));
-                    return Err(AttestError::BadLease.into());
-                }
-
-                let mut digest = Sha3_256Digest::default();
-                data.read_range(0..digest.0.len(), &mut digest.0)
-                    .map_err(|_| RequestError::went_away())?;
-
-                Measurement::Sha3_256(digest)
+                Measurement::Sha3_256(Digest::read(&data)?)
             }
+            HashAlgorithm::Sha256 => Measurement::Sha256(Digest::read(&data)?),
+            HashAlgorithm::Sha384 => Measurement::Sha384(Digest::read(&data)?),
         })
     }
+
+    fn algorithm(&self) -> HashAlgorithm {
+        match self {
+            Measurement::Sha3_256(_) => HashAlgorithm::Sha3_256,
+            Measurement::Sha256(_) => HashAlgorithm::Sha256,
+            Measurement::Sha384(_) => HashAlgorithm::Sha384,
+        }
+    }
+
+    fn digest(&self) -> &[u8] {
+        match self {
+            Measurement::Sha3_256(d) => &d.0,
+            Measurement::Sha256(d) => &d.0,
+            Measurement::Sha384(d) => &d.0,
+        }
+    }
 }
 
 impl Default for Measurement {
@@ -134,29 +178,69 @@ impl Default for Measurement {
     }
 }
 
-// ArrayVec has everything we need but isn't compatible with hubpack. We only
-// need a small subset of its functionality so this saves us some flash.
-#[serde_as]
-#[derive(Serialize, SerializedSize)]
+// An entry in the log: a measurement, the TCG event type it was recorded as
+// and a short description of what was measured
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Event {
+    event_type: u32,
+    measurement: Measurement,
+    tag_len: u8,
+    tag: [u8; EVENT_TAG_MAX_SIZE],
+}
+
+impl Event {
+    fn tag(&self) -> &[u8] {
+        &self.tag[..self.tag_len as usize]
+    }
+}
+
+// ArrayVec has everything we need but is larger than this. We only need a
+// small subset of its functionality so this saves us some flash.
 struct Log<const N: usize> {
     index: u32,
-    #[serde_as(as = "[_; N]")]
-    measurements: [Measurement; N],
+    events: [Event; N],
 }
 
 impl<const N: usize> Log<N> {
+    // the size of the log when full of the largest events, serialized by
+    // `Log::serialize`
+    const MAX_SIZE: usize = tcg::SPEC_ID_EVENT_SIZE + N * tcg::MAX_EVENT_SIZE;
+
     fn is_full(&self) -> bool {
         self.index as usize == N
     }
 
-    fn push(&mut self, measurement: Measurement) -> bool {
-        if !self.is_full() {
-            self.measurements[self.index as usize] = measurement;
-            self.index += 1;
-            true
-        } else {
-            false
+    // A TCG log needs a digest of every algorithm it lists for each event,
+    // and we only have one digest per event, so every event must use the
+    // algorithm that this image was built to log
+    fn push(&mut self, event: Event) -> Result<(), AttestError> {
+        if self.is_full() {
+            return Err(AttestError::LogFull);
         }
+        if event.measurement.algorithm() != LOG_ALGORITHM {
+            return Err(AttestError::AlgorithmMismatch);
+        }
+
+        self.events[self.index as usize] = event;
+        self.index += 1;
+        Ok(())
+    }
+
+    // Serialize the log into `buf` as a TCG PC Client event log, returning
+    // the number of bytes used
+    fn serialize(&self, buf: &mut [u8]) -> Result<usize, AttestError> {
+        let mut writer = tcg::Writer::new(buf);
+        writer.spec_id_event(LOG_ALGORITHM)?;
+        for event in &self.events[..self.index as usize] {
+            writer.event(
+                event.event_type,
+                event.measurement.algorithm(),
+                event.measurement.digest(),
+                event.tag(),
+            )?;
+        }
+
+        Ok(writer.finish())
     }
 }
 
@@ -164,7 +248,7 @@ impl<const N: usize> Default for Log<N> {
     fn default() -> Self {
         Self {
             index: 0,
-            measurements: [Measurement::default(); N],
+            events: [Event::default(); N],
         }
     }
 }
@@ -305,7 +389,39 @@ impl idl::InOrderAttestImpl for AttestServer {
             return Err(AttestError::LogFull.into());
         }
 
-        self.measurements.push(Measurement::new(algorithm, data)?);
+        self.measurements.push(Event {
+            event_type: EventType::NonhostCode as u32,
+            measurement: Measurement::new(algorithm, data)?,
+            ..Default::default()
+        })?;
+
+        Ok(())
+    }
+
+    fn record_event(
+        &mut self,
+        _: &userlib::RecvMessage,
+        algorithm: HashAlgorithm,
+        event_type: u32,
+        data: Leased<R, [u8]>,
+        tag: LenLimit<Leased<R, [u8]>, EVENT_TAG_MAX_SIZE>,
+    ) -> Result<(), RequestError<AttestError>> {
+        ringbuf_entry!(Trace::RecordEvent(algorithm, event_type));
+
+        if self.measurements.is_full() {
+            return Err(AttestError::LogFull.into());
+        }
+
+        let mut event = Event {
+            event_type,
+            measurement: Measurement::new(algorithm, data)?,
+            tag_len: tag.len() as u8,
+            ..Default::default()
+        };
+        tag.read_range(0..tag.len(), &mut event.tag[..tag.len()])
+            .map_err(|_| RequestError::went_away())?;
+
+        self.measurements.push(event)?;
 
         Ok(())
     }
@@ -319,7 +435,9 @@ impl idl::InOrderAttestImpl for AttestServer {
         ringbuf_entry!(Trace::Log);
 
         let offset = offset as usize;
-        let log_len = hubpack::serialize(self.buf, &self.measurements)
+        let log_len = self
+            .measurements
+            .serialize(self.buf)
             .map_err(|_| AttestError::SerializeLog)?;
 
         if log_len < offset || dest.len() > log_len - offset {
@@ -338,7 +456,9 @@ impl idl::InOrderAttestImpl for AttestServer {
         &mut self,
         _: &userlib::RecvMessage,
     ) -> Result<u32, RequestError<AttestError>> {
-        let len = hubpack::serialize(self.buf, &self.measurements)
+        let len = self
+            .measurements
+            .serialize(self.buf)
             .map_err(|_| AttestError::SerializeLog)?;
         let len = u32::try_from(len).map_err(|_| AttestError::LogTooBig)?;
 
@@ -346,6 +466,52 @@ impl idl::InOrderAttestImpl for AttestServer {
 
         Ok(len)
     }
+
+    fn attest(
+        &mut self,
+        _: &userlib::RecvMessage,
+        nonce: Leased<R, [u8]>,
+        dest: Leased<W, [u8]>,
+    ) -> Result<(), RequestError<AttestError>> {
+        ringbuf_entry!(Trace::Attest);
+
+        let alias_data =
+            self.alias_data.as_ref().ok_or(AttestError::NoCerts)?;
+
+        if nonce.len() != NONCE_SIZE {
+            ringbuf_entry!(Trace::BadLease(nonce.len()));
+            return Err(AttestError::BadLease.into());
+        }
+        if dest.len() != ATTESTATION_SIZE {
+            ringbuf_entry!(Trace::BadLease(dest.len()));
+            return Err(AttestError::BadLease.into());
+        }
+
+        let mut nonce_buf = [0u8; NONCE_SIZE];
+        nonce
+            .read_range(0..NONCE_SIZE, &mut nonce_buf)
+            .map_err(|_| RequestError::went_away())?;
+
+        let log_len = self
+            .measurements
+            .serialize(self.buf)
+            .map_err(|_| AttestError::SerializeLog)?;
+
+        // Binding the nonce to the log lets the caller check that the
+        // signature is fresh, and wasn't replayed from an earlier attestation.
+        let mut hasher = Sha3_256::new();
+        hasher.update(&self.buf[..log_len]);
+        hasher.update(nonce_buf);
+        let digest = hasher.finalize();
+
+        let keypair = salty::Keypair::from(alias_data.alias_seed.as_bytes());
+        let signature = keypair.sign(&digest).to_bytes();
+
+        dest.write_range(0..ATTESTATION_SIZE, &signature[..])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+
+        Ok(())
+    }
 }
 
 #[export_name = "main"]
@@ -353,9 +519,13 @@ fn main() -> ! {
     ringbuf_entry!(Trace::Startup);
 
     let mut buffer = [0; idl::INCOMING_SIZE];
-    let mut attest = AttestServer::default();
+    // The server holds the cert chain and the log, several KiB that would
+    // otherwise sit on the stack beneath Ed25519 signing in `attest`.
+    let [attest] = mutable_statics! {
+        static mut SERVER: [AttestServer; 1] = [Default::default; _];
+    };
     loop {
-        idol_runtime::dispatch(&mut buffer, &mut attest);
+        idol_runtime::dispatch(&mut buffer, attest);
     }
 }
 
@@ -0,0 +1,73 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Serialization of the measurement log in the "crypto agile" event log
+//! format of the TCG PC Client Platform Firmware Profile.
+//!
+//! The format itself is written by the `tcg-event-log` crate; this maps our
+//! algorithms and errors onto it.
+//!
+//! The spec requires every event to carry a digest for each algorithm in the
+//! spec ID event.  We only have the digest each event was recorded with, so
+//! a log holds a single algorithm (see `Log::push`).  We have no PCRs, so
+//! every event is logged against PCR 0.
+
+use crate::{digest_size, SHA384_DIGEST_SIZE};
+use attest_api::{AttestError, HashAlgorithm, EVENT_TAG_MAX_SIZE};
+use tcg_event_log::{TPM_ALG_SHA256, TPM_ALG_SHA384, TPM_ALG_SHA3_256};
+
+pub use tcg_event_log::SPEC_ID_EVENT_SIZE;
+
+/// Maximum size of a `TCG_PCR_EVENT2`
+pub const MAX_EVENT_SIZE: usize =
+    tcg_event_log::event_size(SHA384_DIGEST_SIZE, EVENT_TAG_MAX_SIZE);
+
+const fn algorithm_id(algorithm: HashAlgorithm) -> u16 {
+    match algorithm {
+        HashAlgorithm::Sha3_256 => TPM_ALG_SHA3_256,
+        HashAlgorithm::Sha256 => TPM_ALG_SHA256,
+        HashAlgorithm::Sha384 => TPM_ALG_SHA384,
+    }
+}
+
+/// Appends events to a buffer
+pub struct Writer<'a>(tcg_event_log::Writer<'a>);
+
+impl<'a> Writer<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self(tcg_event_log::Writer::new(buf))
+    }
+
+    /// Returns the number of bytes written
+    pub fn finish(self) -> usize {
+        self.0.finish()
+    }
+
+    /// Writes the `TCG_PCClientPCREvent` that must start a log of events
+    /// hashed with `algorithm`
+    pub fn spec_id_event(
+        &mut self,
+        algorithm: HashAlgorithm,
+    ) -> Result<(), AttestError> {
+        self.0
+            .spec_id_event(
+                algorithm_id(algorithm),
+                digest_size(algorithm) as u16,
+            )
+            .map_err(|_| AttestError::SerializeLog)
+    }
+
+    /// Writes a `TCG_PCR_EVENT2` holding a single digest
+    pub fn event(
+        &mut self,
+        event_type: u32,
+        algorithm: HashAlgorithm,
+        digest: &[u8],
+        tag: &[u8],
+    ) -> Result<(), AttestError> {
+        self.0
+            .event(event_type, algorithm_id(algorithm), digest, tag)
+            .map_err(|_| AttestError::SerializeLog)
+    }
+}
@@ -24,6 +24,7 @@ use task_net_api::{
     Address, LargePayloadBehavior, Net, RecvError, SendError, SocketName,
     UdpMetadata,
 };
+use task_sensor_api::AlarmEvent;
 use userlib::{sys_set_timer, task_slot};
 
 mod inventory;
@@ -66,6 +67,10 @@ enum Log {
     ReadCaboose(u32, usize),
     GotCabooseChunk([u8; 4]),
     ReadRotPage,
+    IgnitionFlap(u64),
+    IgnitionFlapError,
+    SensorAlarm(AlarmEvent),
+    SensorAlarmsMissed(u32),
 }
 
 // This enum does not define the actual MGS protocol - it is only used in the
@@ -157,6 +162,12 @@ ringbuf!(Log, 16, Log::Empty);
 
 const SOCKET: SocketName = SocketName::control_plane_agent;
 
+// Only a sidecar has an Ignition Controller to tell us about flapping links.
+#[cfg(feature = "sidecar")]
+const IGNITION_FLAP_MASK: u32 = notifications::IGNITION_FLAP_MASK;
+#[cfg(not(feature = "sidecar"))]
+const IGNITION_FLAP_MASK: u32 = 0;
+
 #[export_name = "main"]
 fn main() {
     let mut server = ServerImpl::claim_static_resources();
@@ -193,6 +204,8 @@ impl NotificationHandler for ServerImpl {
         notifications::SOCKET_MASK
             | notifications::USART_IRQ_MASK
             | notifications::TIMER_MASK
+            | notifications::SENSOR_ALARM_MASK
+            | IGNITION_FLAP_MASK
     }
 
     fn handle_notification(&mut self, bits: u32) {
@@ -204,6 +217,14 @@ impl NotificationHandler for ServerImpl {
             self.mgs_handler.handle_timer_fired();
         }
 
+        if (bits & notifications::SENSOR_ALARM_MASK) != 0 {
+            self.mgs_handler.handle_sensor_alarm();
+        }
+
+        if (bits & IGNITION_FLAP_MASK) != 0 {
+            self.mgs_handler.handle_ignition_flap();
+        }
+
         if (bits & notifications::SOCKET_MASK) != 0
             || self.net_handler.packet_to_send.is_some()
             || self.mgs_handler.wants_to_send_packet_to_mgs()
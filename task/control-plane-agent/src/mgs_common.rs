@@ -19,7 +19,7 @@ use static_assertions::const_assert;
 use task_control_plane_agent_api::VpdIdentity;
 use task_net_api::MacAddress;
 use task_packrat_api::Packrat;
-use task_sensor_api::{Sensor, SensorId};
+use task_sensor_api::{AlarmError, Sensor, SensorId};
 use userlib::{kipc, sys_get_timer, task_slot};
 
 task_slot!(PACKRAT, packrat);
@@ -36,6 +36,7 @@ pub(crate) struct MgsCommon {
     sprot: SpRot,
     sp_update: Update,
     sensor: Sensor,
+    next_sensor_alarm: u32,
 }
 
 impl MgsCommon {
@@ -48,6 +49,7 @@ impl MgsCommon {
             sprot: SpRot::from(SPROT.get_task_id()),
             sp_update: Update::from(UPDATE_SERVER.get_task_id()),
             sensor: Sensor::from(SENSOR.get_task_id()),
+            next_sensor_alarm: 0,
         }
     }
 
@@ -56,6 +58,34 @@ impl MgsCommon {
         &self.packrat
     }
 
+    /// Called when the sensor task tells us that a sensor's alarm has
+    /// changed.  We collect the events that we haven't yet seen from its
+    /// alarm log; they are only recorded in our ringbuf, as the MGS protocol
+    /// has no message to carry them yet.
+    pub(crate) fn handle_sensor_alarm(&mut self) {
+        let end = self.sensor.get_next_alarm_sequence();
+
+        // If the sensor task has restarted, its sequence numbers have started
+        // over from zero.
+        if end < self.next_sensor_alarm {
+            self.next_sensor_alarm = 0;
+        }
+
+        let mut missed = 0;
+        for sequence in self.next_sensor_alarm..end {
+            match self.sensor.get_alarm_event(sequence) {
+                Ok(event) => ringbuf_entry!(Log::SensorAlarm(event)),
+                // The log has wrapped past this event since we last looked.
+                Err(AlarmError::NoSuchEvent) => missed += 1,
+                Err(_) => break,
+            }
+        }
+        if missed > 0 {
+            ringbuf_entry!(Log::SensorAlarmsMissed(missed));
+        }
+        self.next_sensor_alarm = end;
+    }
+
     pub(crate) fn discover(
         &mut self,
         port: SpPort,
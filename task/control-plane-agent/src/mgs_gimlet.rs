@@ -85,6 +85,11 @@ static_assertions::const_assert!(
 /// is this old, even if our buffer isn't full yet.
 const SERIAL_CONSOLE_FLUSH_TIMEOUT_MILLIS: u64 = 500;
 
+// How long we'll wait for the sequencer to report the power state, which it
+// should do immediately; if it's hung, we'd rather fail the request than stop
+// answering MGS altogether.
+const SEQUENCER_STATE_TIMEOUT_MILLIS: u64 = 1000;
+
 userlib::task_slot!(HOST_FLASH, hf);
 userlib::task_slot!(GIMLET_SEQ, gimlet_seq);
 userlib::task_slot!(USER_LEDS, user_leds);
@@ -225,6 +230,12 @@ impl MgsHandler {
         self.usart.run_until_blocked();
     }
 
+    pub(crate) fn handle_sensor_alarm(&mut self) {
+        self.common.handle_sensor_alarm();
+    }
+
+    pub(crate) fn handle_ignition_flap(&mut self) {}
+
     pub(crate) fn wants_to_send_packet_to_mgs(&mut self) -> bool {
         // If we should be forwarding uart data to MGS but we don't have one
         // attached, discard any buffered data.
@@ -429,10 +440,11 @@ impl MgsHandler {
         //
         // TODO Do we want to expose A1 to the control plane at all? If not,
         // what would we map it to? Maybe easier to leave it exposed.
-        let state = match self
-            .sequencer
-            .get_state()
-            .map_err(|e| SpError::PowerStateError(e as u32))?
+        let deadline = sys_get_timer().now + SEQUENCER_STATE_TIMEOUT_MILLIS;
+        let state = match userlib::with_send_deadline(deadline, || {
+            self.sequencer.get_state()
+        })
+        .map_err(|e| SpError::PowerStateError(e as u32))?
         {
             DrvPowerState::A2 | DrvPowerState::A2PlusFans => PowerState::A2,
             DrvPowerState::A1 => PowerState::A1,
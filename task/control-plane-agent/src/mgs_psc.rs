@@ -84,6 +84,12 @@ impl MgsHandler {
 
     pub(crate) fn drive_usart(&mut self) {}
 
+    pub(crate) fn handle_sensor_alarm(&mut self) {
+        self.common.handle_sensor_alarm();
+    }
+
+    pub(crate) fn handle_ignition_flap(&mut self) {}
+
     pub(crate) fn wants_to_send_packet_to_mgs(&mut self) -> bool {
         false
     }
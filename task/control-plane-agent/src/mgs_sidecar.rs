@@ -104,6 +104,21 @@ impl MgsHandler {
 
     pub(crate) fn drive_usart(&mut self) {}
 
+    /// Called when a sensor's alarm changes; see `MgsCommon`.
+    pub(crate) fn handle_sensor_alarm(&mut self) {
+        self.common.handle_sensor_alarm();
+    }
+
+    /// Called when the ignition server finds one or more ports to be
+    /// flapping. The details are kept in the port history of the ignition
+    /// server; here we only record which ports were flapping at the time.
+    pub(crate) fn handle_ignition_flap(&mut self) {
+        match self.ignition.flapping_ports() {
+            Ok(ports) => ringbuf_entry!(Log::IgnitionFlap(ports)),
+            Err(_) => ringbuf_entry!(Log::IgnitionFlapError),
+        }
+    }
+
     pub(crate) fn wants_to_send_packet_to_mgs(&mut self) -> bool {
         false
     }
@@ -43,6 +43,10 @@ impl IgnitionController {
         Ok(n)
     }
 
+    pub(super) fn flapping_ports(&self) -> Result<u64, IgnitionError> {
+        self.task.flapping_ports()
+    }
+
     pub(super) fn target_state(
         &self,
         target: u8,
@@ -8,6 +8,8 @@
 
 use derive_idol_err::IdolError;
 use dumper_api::DumperError;
+use hubpack::SerializedSize;
+use serde::{Deserialize, Serialize};
 use userlib::*;
 
 pub use humpty::*;
@@ -97,6 +99,36 @@ impl From<DumpAgentError> for humpty::udp::Error {
 
 pub const DUMP_READ_SIZE: usize = 256;
 
+/// Metadata that Jefe keeps about each dump it knows of, so that dumps can be
+/// listed without reading them out.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+pub struct DumpRecord {
+    /// Sequence number of the dump. These count up from zero across every
+    /// dump taken since the dump areas were last initialized, so a gap
+    /// indicates dumps that were evicted.
+    pub sequence: u32,
+    /// Index of the (first) dump area holding the dump.
+    pub area: u8,
+    /// What was dumped.
+    pub contents: DumpContents,
+    /// Index of the dumped task, or `None` for a whole-system dump.
+    pub task_index: Option<u16>,
+    /// Time of the dump, in kernel timer ticks since boot.
+    pub timestamp: u64,
+}
+
+impl dump_policy::Dump for DumpRecord {
+    fn sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    fn task(&self) -> Option<u16> {
+        self.task_index
+    }
+}
+
 //
 // We use the version field to denote how a dump area is being used.
 //
@@ -143,6 +143,13 @@ impl ServerImpl {
         Ok(())
     }
 
+    fn dump_record(
+        &self,
+        index: u8,
+    ) -> Result<Option<DumpRecord>, DumpAgentError> {
+        self.jefe.get_dump_record(index)
+    }
+
     #[cfg(not(feature = "no-rot"))]
     fn take_dump(&mut self) -> Result<(), DumpAgentError> {
         use drv_sprot_api::DumpOrSprotError;
@@ -251,6 +258,14 @@ impl idl::InOrderDumpAgentImpl for ServerImpl {
     ) -> Result<(), RequestError<DumpAgentError>> {
         self.reinitialize_dump_from(index).map_err(|e| e.into())
     }
+
+    fn get_dump_record(
+        &mut self,
+        _msg: &RecvMessage,
+        index: u8,
+    ) -> Result<Option<DumpRecord>, RequestError<DumpAgentError>> {
+        self.dump_record(index).map_err(|e| e.into())
+    }
 }
 
 #[export_name = "main"]
@@ -72,6 +72,8 @@ pub enum Functions {
         (Controller, PortIndex, Mux, Segment, u8, u8, usize, usize),
         ResponseCode,
     ),
+    #[cfg(feature = "i2c")]
+    I2cStats((Controller, PortIndex, Mux, Segment, u8), ResponseCode),
     #[cfg(feature = "gpio")]
     GpioInput(drv_stm32xx_sys_api::Port, u32),
     #[cfg(feature = "gpio")]
@@ -340,6 +342,46 @@ fn i2c_bulk_write(
     }
 }
 
+///
+/// Returns the I2C server's error and recovery counters for a device, its bus
+/// and its segment, as an `I2cStatsReport`.  Takes the normal i2c parameters,
+/// less the register.
+///
+#[cfg(feature = "i2c")]
+fn i2c_stats(
+    stack: &[Option<u32>],
+    _data: &[u8],
+    rval: &mut [u8],
+) -> Result<usize, Failure> {
+    use zerocopy::AsBytes;
+
+    if stack.len() < 5 {
+        return Err(Failure::Fault(Fault::MissingParameters));
+    }
+
+    let fp = stack.len() - 5;
+    let mut args = [None; 6];
+    args[..5].copy_from_slice(&stack[fp..]);
+    let (controller, port, mux, addr, _) = i2c_args(&args)?;
+
+    let task = I2C.get_task_id();
+    let device = I2cDevice::new(task, controller, port, mux, addr);
+
+    match device.stats() {
+        Ok(report) => {
+            let report = report.as_bytes();
+
+            if rval.len() < report.len() {
+                return Err(Failure::Fault(Fault::ReturnValueOverflow));
+            }
+
+            rval[..report.len()].copy_from_slice(report);
+            Ok(report.len())
+        }
+        Err(err) => Err(Failure::FunctionError(err.into())),
+    }
+}
+
 #[cfg(feature = "gpio")]
 fn gpio_args(
     stack: &[Option<u32>],
@@ -532,6 +574,8 @@ pub(crate) static HIFFY_FUNCS: &[Function] = &[
     i2c_write,
     #[cfg(feature = "i2c")]
     i2c_bulk_write,
+    #[cfg(feature = "i2c")]
+    i2c_stats,
     #[cfg(feature = "gpio")]
     gpio_input,
     #[cfg(feature = "gpio")]
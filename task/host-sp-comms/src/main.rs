@@ -10,13 +10,14 @@ use drv_stm32h7_usart as drv_usart;
 
 use drv_gimlet_hf_api::{HfDevSelect, HostFlash};
 use drv_gimlet_seq_api::{PowerState, SeqError, Sequencer};
+use drv_sprot_api::SpRot;
 use drv_stm32xx_sys_api as sys_api;
 use drv_usart::Usart;
 use enum_map::Enum;
 use heapless::Vec;
 use host_sp_messages::{
-    Bsu, DecodeFailureReason, Header, HostToSp, Key, KeyLookupResult,
-    KeySetResult, SpToHost, Status, MAX_MESSAGE_SIZE,
+    Bsu, DecodeFailureReason, Header, HostAlert, HostToSp, Key,
+    KeyLookupResult, KeySetResult, SpToHost, Status, MAX_MESSAGE_SIZE,
     MIN_SP_TO_HOST_FILL_DATA_LEN,
 };
 use hubpack::SerializedSize;
@@ -30,12 +31,13 @@ use task_control_plane_agent_api::{
 };
 use task_host_sp_comms_api::HostSpCommsError;
 use task_net_api::Net;
-use task_packrat_api::Packrat;
+use task_packrat_api::{HostAlertKind, Packrat};
 use userlib::{
     hl, sys_get_timer, sys_irq_control, task_slot, FromPrimitive, UnwrapLite,
 };
 
 mod inventory;
+mod rot;
 use inventory::INVENTORY_API_VERSION;
 
 #[cfg_attr(
@@ -57,9 +59,19 @@ task_slot!(CONTROL_PLANE_AGENT, control_plane_agent);
 task_slot!(GIMLET_SEQ, gimlet_seq);
 task_slot!(HOST_FLASH, hf);
 task_slot!(PACKRAT, packrat);
+task_slot!(SPROT, sprot);
 task_slot!(NET, net);
 task_slot!(SYS, sys);
 
+#[cfg(feature = "gimlet")]
+task_slot!(SENSOR, sensor);
+
+// Only a gimlet has sensors with alarms worth telling the host about.
+#[cfg(feature = "gimlet")]
+const SENSOR_ALARM_MASK: u32 = notifications::SENSOR_ALARM_MASK;
+#[cfg(not(feature = "gimlet"))]
+const SENSOR_ALARM_MASK: u32 = 0;
+
 // TODO: When rebooting the host, we need to wait for the relevant power rails
 // to decay. We ought to do this properly by monitoring the rails, but for now,
 // we'll simply wait a fixed period of time. This time is a WAG - we should
@@ -71,6 +83,17 @@ const A2_REBOOT_DELAY: u64 = 5_000;
 // response to send, and we haven't yet started to receive a request).
 const UART_ZERO_DELAY: u64 = 200;
 
+// How frequently should we check packrat for new alerts for the host? Tasks
+// raising alerts run at a higher priority than us, so they can't tell us
+// directly.
+const ALERT_POLL_INTERVAL: u64 = 100;
+
+// How old can an alert be before we consider it stale and don't bother passing
+// it on to the host? We only expect to see alerts this old if we've restarted
+// (or the host hasn't been asking for them). Alerts about the host losing power
+// are exempt; see `outlives_power_cycle`.
+const MAX_ALERT_AGE: u64 = 10_000;
+
 // How long of a host panic / boot fail message are we willing to keep?
 const MAX_HOST_FAIL_MESSAGE_LEN: usize = 4096;
 
@@ -116,6 +139,20 @@ enum Trace {
         sequence: u64,
         message: SpToHost,
     },
+    AlertDropped {
+        sequence: u32,
+        kind: u8,
+        timestamp: u64,
+    },
+    AlertDelivered {
+        sequence: u32,
+        kind: HostAlertKind,
+    },
+    #[cfg(feature = "gimlet")]
+    SensorCritical {
+        id: task_sensor_api::SensorId,
+        alarm: task_sensor_api::Alarm,
+    },
 }
 
 ringbuf!(Trace, 16, Trace::None);
@@ -140,6 +177,8 @@ enum Timers {
     WaitingInA2ToReboot,
     /// Timer set when we want to send periodic 0x00 bytes on the uart.
     TxPeriodicZeroByte,
+    /// Repeating timer on which we check packrat for host alerts.
+    PollAlerts,
 }
 
 #[export_name = "main"]
@@ -250,8 +289,19 @@ struct ServerImpl {
     net: Net,
     cp_agent: ControlPlaneAgent,
     packrat: Packrat,
+    sprot: SpRot,
     reboot_state: Option<RebootState>,
     host_kv_storage: HostKeyValueStorage,
+    // Sequence number (as assigned by packrat) of the next host alert to
+    // deliver.  Packrat keeps a copy, which we start from, so that a restart
+    // of this task doesn't deliver alerts again.
+    next_alert: u32,
+    #[cfg(feature = "gimlet")]
+    sensor: task_sensor_api::Sensor,
+    // Sequence number (as assigned by the sensor task) of the next sensor
+    // alarm event to look at.
+    #[cfg(feature = "gimlet")]
+    next_sensor_alarm: u32,
 }
 
 impl ServerImpl {
@@ -266,6 +316,19 @@ impl ServerImpl {
             sys_get_timer().now,
             Some(Repeat::AfterWake(UART_ZERO_DELAY)),
         );
+        timers.set_timer(
+            Timers::PollAlerts,
+            sys_get_timer().now,
+            Some(Repeat::AfterDeadline(ALERT_POLL_INTERVAL)),
+        );
+
+        // Packrat only keeps alerts on Gimlet.
+        let packrat = Packrat::from(PACKRAT.get_task_id());
+        let next_alert = if cfg!(feature = "gimlet") {
+            packrat.get_host_alert_cursor()
+        } else {
+            0
+        };
 
         Self {
             uart,
@@ -280,9 +343,15 @@ impl ServerImpl {
             cp_agent: ControlPlaneAgent::from(
                 CONTROL_PLANE_AGENT.get_task_id(),
             ),
-            packrat: Packrat::from(PACKRAT.get_task_id()),
+            packrat,
+            sprot: SpRot::from(SPROT.get_task_id()),
             reboot_state: None,
             host_kv_storage: HostKeyValueStorage::claim_static_resources(),
+            next_alert,
+            #[cfg(feature = "gimlet")]
+            sensor: task_sensor_api::Sensor::from(SENSOR.get_task_id()),
+            #[cfg(feature = "gimlet")]
+            next_sensor_alarm: 0,
         }
     }
 
@@ -635,6 +704,101 @@ impl ServerImpl {
         }
     }
 
+    // Find the next alert to pass on to the host, skipping (and moving our
+    // cursor past) any that are stale or that we don't understand.
+    fn next_alert(&mut self) -> Option<(u32, HostAlert)> {
+        // Packrat only keeps alerts on Gimlet, and will fault us if we ask
+        // for them anywhere else.
+        if !cfg!(feature = "gimlet") {
+            return None;
+        }
+
+        let now = sys_get_timer().now;
+        while let Ok(event) = self.packrat.get_host_alert(self.next_alert) {
+            let age_ms = now.saturating_sub(event.timestamp);
+            match event.kind() {
+                Some(kind)
+                    if age_ms <= MAX_ALERT_AGE
+                        || outlives_power_cycle(kind) =>
+                {
+                    let alert = HostAlert {
+                        kind,
+                        index: event.index,
+                        age_ms,
+                    };
+                    return Some((event.sequence, alert));
+                }
+                _ => {
+                    ringbuf_entry!(Trace::AlertDropped {
+                        sequence: event.sequence,
+                        kind: event.kind,
+                        timestamp: event.timestamp,
+                    });
+                    self.alert_handled(event.sequence);
+                }
+            }
+        }
+        None
+    }
+
+    // Move our cursor past the alert numbered `sequence`, and tell packrat.
+    fn alert_handled(&mut self, sequence: u32) {
+        self.next_alert = sequence.wrapping_add(1);
+        self.packrat.set_host_alert_cursor(self.next_alert);
+    }
+
+    // Raise a host alert for each sensor that has crossed a critical threshold
+    // since we last looked, then update our status straight away rather than
+    // waiting for the next poll.
+    #[cfg(feature = "gimlet")]
+    fn handle_sensor_alarm_notification(&mut self) {
+        use task_sensor_api::Alarm;
+
+        let end = self.sensor.get_next_alarm_sequence();
+
+        // If the sensor task has restarted, its sequence numbers have started
+        // over from zero.
+        if end < self.next_sensor_alarm {
+            self.next_sensor_alarm = 0;
+        }
+
+        for sequence in self.next_sensor_alarm..end {
+            // Events that have already fallen out of the alarm log are lost;
+            // a sensor still past its threshold will be in a later one.
+            let Ok(event) = self.sensor.get_alarm_event(sequence) else {
+                continue;
+            };
+            if matches!(
+                event.alarm,
+                Alarm::LowerCritical | Alarm::UpperCritical
+            ) {
+                ringbuf_entry!(Trace::SensorCritical {
+                    id: event.id,
+                    alarm: event.alarm,
+                });
+                let index = u8::try_from(event.id.0).unwrap_or(u8::MAX);
+                self.packrat.raise_host_alert(
+                    HostAlertKind::SensorCritical as u8,
+                    index,
+                );
+            }
+        }
+        self.next_sensor_alarm = end;
+
+        self.update_alert_status();
+    }
+
+    // Set or clear `ALERTS_AVAILABLE` (interrupting the host if it's newly
+    // set) depending on whether we have an alert to pass on.
+    fn update_alert_status(&mut self) {
+        let status = if self.next_alert().is_some() {
+            self.status.union(Status::ALERTS_AVAILABLE)
+        } else {
+            self.status.difference(Status::ALERTS_AVAILABLE)
+        };
+        self.set_status_impl(status);
+    }
+
     // Process the framed packet sitting in `self.rx_buf`. If it warrants a
     // response, we configure `self.tx_buf` appropriate: either populating it
     // with a response if we can come up with that response immediately, or
@@ -780,16 +944,46 @@ impl ServerImpl {
                 Some(SpToHost::Ack)
             }
             HostToSp::GetAlert => {
-                // TODO define alerts
-                Some(SpToHost::Alert { action: 0 })
+                // Hand over the oldest alert, then update our status to
+                // reflect whether there are any more.
+                action = Some(Action::UpdateAlertStatus);
+                match self.next_alert() {
+                    Some((sequence, alert)) => {
+                        ringbuf_entry!(Trace::AlertDelivered {
+                            sequence,
+                            kind: alert.kind,
+                        });
+                        self.alert_handled(sequence);
+                        self.tx_buf.encode_response(
+                            header.sequence,
+                            &SpToHost::Alert {
+                                action: alert.kind as u8,
+                            },
+                            |buf| hubpack::serialize(buf, &alert).unwrap_lite(),
+                        );
+                        None
+                    }
+                    None => Some(SpToHost::Alert { action: 0 }),
+                }
             }
             HostToSp::RotRequest => {
-                // TODO forward request to RoT
-                Some(SpToHost::RotResponse)
+                // Borrow `sprot` to avoid borrowing `self` in the closure.
+                let sprot = &self.sprot;
+                self.tx_buf.encode_response(
+                    header.sequence,
+                    &SpToHost::RotResponse,
+                    |buf| rot::handle_request(sprot, data, buf),
+                );
+                None
             }
             HostToSp::RotAddHostMeasurements => {
-                // TODO forward request to RoT
-                Some(SpToHost::Ack)
+                let result = rot::add_measurements(&self.sprot, data);
+                self.tx_buf.encode_response(
+                    header.sequence,
+                    &SpToHost::RotResponse,
+                    |buf| rot::serialize_result(result, buf),
+                );
+                None
             }
             HostToSp::GetPhase2Data { hash, offset } => {
                 // We don't have a response to transmit now, but need to avoid
@@ -858,6 +1052,7 @@ impl ServerImpl {
                 Action::ClearStatusBits(to_clear) => {
                     self.set_status_impl(self.status.difference(to_clear))
                 }
+                Action::UpdateAlertStatus => self.update_alert_status(),
             }
         }
 
@@ -1036,6 +1231,7 @@ impl NotificationHandler for ServerImpl {
             | notifications::JEFE_STATE_CHANGE_MASK
             | notifications::MULTITIMER_MASK
             | notifications::CONTROL_PLANE_AGENT_MASK
+            | SENSOR_ALARM_MASK
     }
 
     fn handle_notification(&mut self, bits: u32) {
@@ -1054,6 +1250,11 @@ impl NotificationHandler for ServerImpl {
             self.handle_control_plane_agent_notification();
         }
 
+        #[cfg(feature = "gimlet")]
+        if bits & SENSOR_ALARM_MASK != 0 {
+            self.handle_sensor_alarm_notification();
+        }
+
         // We may want to clear our TX periodic zero byte timer (if the TX FIFO
         // is full), but we can't modify the timers while iterating over them.
         // We'll record whether or not we want to clear the timer in this
@@ -1061,6 +1262,7 @@ impl NotificationHandler for ServerImpl {
         // fired timers.
         self.timers.handle_notification(bits);
         let mut tx_timer_disposition = TimerDisposition::LeaveRunning;
+        let mut poll_alerts = false;
         for t in self.timers.iter_fired() {
             match t {
                 Timers::WaitingInA2ToReboot => {
@@ -1076,9 +1278,14 @@ impl NotificationHandler for ServerImpl {
                         self.rx_buf,
                     );
                 }
+                Timers::PollAlerts => poll_alerts = true,
             }
         }
 
+        if poll_alerts {
+            self.update_alert_status();
+        }
+
         match tx_timer_disposition {
             TimerDisposition::LeaveRunning => (),
             TimerDisposition::Cancel => {
@@ -1088,6 +1295,20 @@ impl NotificationHandler for ServerImpl {
     }
 }
 
+// Alerts about the host losing power are often raised when it's too late for
+// the host to see them while it's up (the sequencer only learns of a THERMTRIP
+// once power is gone, and thermal powers off in the same pass that it raises
+// `ImpendingPowerOff`). We keep these however long the host takes to come back
+// and ask, so that it can find out why it went down; `age_ms` tells it when.
+fn outlives_power_cycle(kind: HostAlertKind) -> bool {
+    matches!(
+        kind,
+        HostAlertKind::ThermalEmergency
+            | HostAlertKind::ImpendingPowerOff
+            | HostAlertKind::PsuLoss
+    )
+}
+
 // This is conceptually a method on `ServerImpl`, but it takes a reference to
 // `rx_buf` instead of `self` to avoid borrow checker issues.
 fn parse_received_message(
@@ -1205,6 +1426,7 @@ enum Action {
     RebootHost,
     PowerOffHost,
     ClearStatusBits(Status),
+    UpdateAlertStatus,
 }
 
 #[cfg(any(feature = "stm32h743", feature = "stm32h753"))]
@@ -0,0 +1,162 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Forwarding of host requests to the RoT
+//!
+//! The host can't talk to the RoT directly, so it sends us
+//! [`HostRotRequest`]s and measurements, which we pass along to the RoT's
+//! `attest` task over sprot.
+
+use attest_api::{
+    AttestError, HashAlgorithm, ATTESTATION_SIZE, EVENT_TAG_MAX_SIZE,
+};
+use drv_sprot_api::{AttestOrSprotError, SpRot};
+use host_sp_messages::{
+    HostHashAlgorithm, HostMeasurement, HostRotError, HostRotRequest,
+    HostRotResponse, MAX_HOST_MEASUREMENT_TAG_LEN, MAX_ROT_RESPONSE_DATA_LEN,
+};
+use hubpack::SerializedSize;
+
+type HostRotResult = Result<HostRotResponse, HostRotError>;
+
+impl From<AttestOrSprotError> for HostRotError {
+    fn from(e: AttestOrSprotError) -> Self {
+        match e {
+            AttestOrSprotError::Sprot(_) => HostRotError::CommsFailed,
+            AttestOrSprotError::Attest(e) => match e {
+                AttestError::NoCerts => HostRotError::NoCerts,
+                AttestError::InvalidCertIndex => HostRotError::InvalidCertIndex,
+                AttestError::OutOfRange => HostRotError::OutOfRange,
+                AttestError::LogFull => HostRotError::LogFull,
+                AttestError::AlgorithmMismatch => {
+                    HostRotError::AlgorithmMismatch
+                }
+                AttestError::TaskRestarted => HostRotError::CommsFailed,
+                _ => HostRotError::AttestFailed,
+            },
+        }
+    }
+}
+
+/// Performs the hubpack-encoded `request`, serializing the result (and any
+/// data that follows it) into `buf`.  Returns the number of bytes used.
+pub(crate) fn handle_request(
+    sprot: &SpRot,
+    request: &[u8],
+    buf: &mut [u8],
+) -> usize {
+    // Cert, log and signature bytes follow the serialized result, whose
+    // length we don't know until we have it; fetch them into the space after
+    // the largest possible result, then move them into place.
+    let start = HostRotResult::MAX_SIZE;
+
+    let (result, len) = match hubpack::deserialize::<HostRotRequest>(request)
+        .map_err(|_| HostRotError::BadRequest)
+        .and_then(|(request, _)| {
+            perform_request(sprot, request, &mut buf[start..])
+        }) {
+        Ok((response, len)) => (Ok(response), len),
+        Err(e) => (Err(e), 0),
+    };
+
+    let n = serialize_result(result, buf);
+    buf.copy_within(start..start + len, n);
+    n + len
+}
+
+/// Performs `request`, returning the response and the number of bytes of
+/// data that it left at the start of `data`.
+fn perform_request(
+    sprot: &SpRot,
+    request: HostRotRequest,
+    data: &mut [u8],
+) -> Result<(HostRotResponse, usize), HostRotError> {
+    Ok(match request {
+        HostRotRequest::CertChainLen => {
+            (HostRotResponse::CertChainLen(sprot.cert_chain_len()?), 0)
+        }
+        HostRotRequest::CertLen { index } => {
+            (HostRotResponse::CertLen(sprot.cert_len(index)?), 0)
+        }
+        HostRotRequest::Cert {
+            index,
+            offset,
+            size,
+        } => {
+            let n = clamp_len(size, data.len());
+            sprot.cert(index, offset, &mut data[..n])?;
+            (HostRotResponse::Cert(n as u32), n)
+        }
+        HostRotRequest::LogLen => {
+            (HostRotResponse::LogLen(sprot.log_len()?), 0)
+        }
+        HostRotRequest::Log { offset, size } => {
+            let n = clamp_len(size, data.len());
+            sprot.log(offset, &mut data[..n])?;
+            (HostRotResponse::Log(n as u32), n)
+        }
+        HostRotRequest::Attest { nonce } => {
+            sprot.attest(&nonce, &mut data[..ATTESTATION_SIZE])?;
+            (
+                HostRotResponse::Attest(ATTESTATION_SIZE as u32),
+                ATTESTATION_SIZE,
+            )
+        }
+    })
+}
+
+fn clamp_len(size: u32, available: usize) -> usize {
+    usize::min(
+        usize::min(size as usize, MAX_ROT_RESPONSE_DATA_LEN),
+        available,
+    )
+}
+
+// The host's limit must fit in the attestation log's
+static_assertions::const_assert!(
+    MAX_HOST_MEASUREMENT_TAG_LEN <= EVENT_TAG_MAX_SIZE
+);
+
+/// Records each of the measurements in `blob` (a sequence of hubpack-encoded
+/// [`HostMeasurement`]s, each followed by a digest and a tag) in the RoT's
+/// attestation log.
+pub(crate) fn add_measurements(
+    sprot: &SpRot,
+    mut blob: &[u8],
+) -> HostRotResult {
+    let mut count = 0;
+
+    while !blob.is_empty() {
+        let (m, rest) = hubpack::deserialize::<HostMeasurement>(blob)
+            .map_err(|_| HostRotError::BadRequest)?;
+        let digest_len = m.algorithm.digest_len();
+        let tag_len = m.tag_len as usize;
+        if tag_len > MAX_HOST_MEASUREMENT_TAG_LEN
+            || rest.len() < digest_len + tag_len
+        {
+            return Err(HostRotError::BadRequest);
+        }
+        let (digest, rest) = rest.split_at(digest_len);
+        let (tag, rest) = rest.split_at(tag_len);
+
+        let algorithm = match m.algorithm {
+            HostHashAlgorithm::Sha3_256 => HashAlgorithm::Sha3_256,
+            HostHashAlgorithm::Sha256 => HashAlgorithm::Sha256,
+            HostHashAlgorithm::Sha384 => HashAlgorithm::Sha384,
+        };
+        sprot.record_event(algorithm, m.event_type, digest, tag)?;
+
+        count += 1;
+        blob = rest;
+    }
+
+    Ok(HostRotResponse::MeasurementsRecorded(count))
+}
+
+/// Serializes `result` into `buf`, returning the number of bytes used.
+pub(crate) fn serialize_result(result: HostRotResult, buf: &mut [u8]) -> usize {
+    // We're always given a buffer far larger than any result, so this can't
+    // fail.
+    hubpack::serialize(buf, &result).unwrap_or(0)
+}
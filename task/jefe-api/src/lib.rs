@@ -7,8 +7,10 @@
 #![no_std]
 
 use derive_idol_err::IdolError;
-pub use dump_agent_api::DumpAgentError;
+pub use dump_agent_api::{DumpAgentError, DumpRecord};
+use hubpack::SerializedSize;
 use serde::{Deserialize, Serialize};
+use serde_big_array::BigArray;
 use userlib::*;
 
 /// Platform-agnostic (but heavily influenced) reset status bits.
@@ -34,6 +36,151 @@ pub enum DumpAreaError {
     AlreadyInUse,
 }
 
+/// Errors from Jefe operations that are not related to dumping.
+#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
+#[repr(C)]
+pub enum JefeError {
+    /// The task index is out of range for this image.
+    BadTaskIndex = 1,
+    /// The requested fault record has not been written yet, or has already
+    /// been overwritten by newer ones.
+    NoSuchFaultRecord,
+}
+
+/// Fault and restart bookkeeping that Jefe maintains for each task.
+///
+/// Timestamps are in units of the kernel timer (milliseconds since boot); a
+/// timestamp of zero indicates that the event has not occurred.
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    Eq,
+    PartialEq,
+    Serialize,
+    Deserialize,
+    SerializedSize,
+)]
+pub struct TaskRestartStats {
+    /// Total number of faults observed since boot.
+    pub faults: u32,
+    /// Total number of times Jefe has restarted the task since boot.
+    pub restarts: u32,
+    /// Number of restarts within the current restart policy window.
+    pub window_restarts: u32,
+    /// Time of the most recent fault.
+    pub last_fault: u64,
+    /// Time of the most recent restart.
+    pub last_restart: u64,
+    /// If a restart is pending (i.e., the task is backing off), the time at
+    /// which it will be restarted.
+    pub next_restart: Option<u64>,
+    /// Whether the task is being held at a fault.
+    pub held: bool,
+}
+
+/// CPU accounting and scheduling counters that the kernel maintains for each
+/// task; see `abi::TaskStats`.
+///
+/// These are cumulative since boot, and are not reset when the task restarts.
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    Eq,
+    PartialEq,
+    Serialize,
+    Deserialize,
+    SerializedSize,
+)]
+pub struct TaskCpuStats {
+    /// Number of kernel timer ticks that arrived while the task was running.
+    pub ticks: u64,
+    /// Number of times the scheduler switched to the task.
+    pub scheduled: u32,
+    /// Number of times the task was preempted by an interrupt.
+    pub preemptions: u32,
+    /// Number of syscalls made by the task, indexed by `Sysnum`.
+    pub syscalls: [u32; Sysnum::COUNT],
+}
+
+impl From<TaskStats> for TaskCpuStats {
+    fn from(s: TaskStats) -> Self {
+        Self {
+            ticks: s.ticks,
+            scheduled: s.scheduled,
+            preemptions: s.preemptions,
+            syscalls: s.syscalls,
+        }
+    }
+}
+
+/// Number of bytes of a panic message kept in a [`FaultRecord`]; longer
+/// messages are truncated.
+pub const FAULT_EPITAPH_LEN: usize = 64;
+
+/// An entry in Jefe's fault history, describing a single task fault.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+pub struct FaultRecord {
+    /// Sequence number of the record. These count up from zero across every
+    /// fault recorded since the history was created, including faults from
+    /// before a warm reset.
+    pub sequence: u32,
+    /// The boot during which the fault occurred; see
+    /// [`FaultHistoryInfo::boot`].
+    pub boot: u32,
+    /// Index of the task that faulted.
+    pub task_index: u16,
+    /// Generation of the task when it faulted.
+    pub generation: u8,
+    /// Time of the fault, in kernel timer ticks since the start of `boot`.
+    pub timestamp: u64,
+    /// The fault reported by the kernel.
+    pub fault: FaultInfo,
+    /// Number of valid bytes in `epitaph`.
+    pub epitaph_len: u8,
+    /// For `FaultInfo::Panic`, the start of the task's panic message.
+    #[serde(with = "BigArray")]
+    pub epitaph: [u8; FAULT_EPITAPH_LEN],
+}
+
+impl FaultRecord {
+    /// Returns the panic message recorded with this fault, if any.
+    pub fn epitaph(&self) -> &[u8] {
+        &self.epitaph[..usize::from(self.epitaph_len).min(FAULT_EPITAPH_LEN)]
+    }
+}
+
+/// Summary of Jefe's fault history.
+///
+/// The history holds the most recent `held` records, whose sequence numbers
+/// run up to (but not including) `next_sequence`.
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    Eq,
+    PartialEq,
+    Serialize,
+    Deserialize,
+    SerializedSize,
+)]
+pub struct FaultHistoryInfo {
+    /// Number of boots since the history was created, counting from zero.
+    /// This goes up on every warm reset; the history is lost (and this starts
+    /// over) when power is removed.
+    pub boot: u32,
+    /// Sequence number that will be given to the next fault.
+    pub next_sequence: u32,
+    /// Number of records that can still be read.
+    pub held: u32,
+}
+
 impl Jefe {
     /// Asks the supervisor to restart the current task without recording a
     /// fault.
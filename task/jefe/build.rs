@@ -57,13 +57,53 @@ fn main() -> Result<()> {
         writeln!(out, "];")?;
     }
 
+    {
+        let count = cfg.restart_policy.len();
+        writeln!(
+            out,
+            "pub(crate) const RESTART_POLICIES: \
+                [({task}, crate::RestartPolicy); {count}] = [",
+        )?;
+        for (name, policy) in &cfg.restart_policy {
+            policy
+                .validate()
+                .with_context(|| format!("restart policy for {name}"))?;
+
+            let max_restarts = match policy.max_restarts {
+                Some(n) => format!("Some({n})"),
+                None => "None".to_string(),
+            };
+            let escalation = match policy.escalate {
+                Escalation::Hold => "Hold",
+                Escalation::Reset => "Reset",
+            };
+            writeln!(
+                out,
+                "    ({task}::{name}, crate::RestartPolicy {{
+        initial_backoff_ms: {},
+        max_backoff_ms: {},
+        max_restarts: {max_restarts},
+        window_ms: {},
+        escalation: crate::Escalation::{escalation},
+    }}),",
+                policy.initial_backoff_ms,
+                policy.max_backoff_ms,
+                policy.window_ms,
+            )?;
+        }
+        writeln!(out, "];")?;
+    }
+
     #[cfg(feature = "dump")]
-    output_dump_areas(&mut out)?;
+    {
+        output_dump_areas(&mut out)?;
+        output_dump_policy(&mut out, &cfg)?;
+    }
     Ok(())
 }
 
 /// Jefe task-level configuration.
-#[derive(Deserialize, Default)]
+#[derive(Deserialize)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 struct Config {
     /// Task requests to be notified on state change, as a map from task name to
@@ -77,6 +117,109 @@ struct Config {
     /// failure, unless overridden at runtime through Humility.
     #[serde(default)]
     tasks_to_hold: BTreeSet<String>,
+    /// Map of task names to the policy that governs how they are restarted on
+    /// failure.  Tasks without a policy are restarted immediately, every time.
+    #[serde(default)]
+    restart_policy: BTreeMap<String, RestartPolicy>,
+    /// What to do with a new task dump once `max_dumps` dumps are held or the
+    /// dump areas are full.
+    #[serde(default)]
+    #[cfg_attr(not(feature = "dump"), allow(dead_code))]
+    dump_policy: DumpPolicy,
+    /// Maximum number of dumps held at once.
+    #[serde(default = "default_max_dumps")]
+    #[cfg_attr(not(feature = "dump"), allow(dead_code))]
+    max_dumps: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            on_state_change: BTreeMap::new(),
+            allowed_callers: BTreeMap::new(),
+            tasks_to_hold: BTreeSet::new(),
+            restart_policy: BTreeMap::new(),
+            dump_policy: DumpPolicy::default(),
+            max_dumps: default_max_dumps(),
+        }
+    }
+}
+
+fn default_max_dumps() -> usize {
+    8
+}
+
+/// Policy for reusing dump areas once they are full.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+#[cfg_attr(not(feature = "dump"), allow(dead_code))]
+enum DumpPolicy {
+    /// Keep the first dumps taken, discarding any new ones.
+    #[default]
+    KeepFirst,
+    /// Evict the oldest dump to make room for a new one.
+    KeepRecent,
+    /// Keep at most this many dumps of any one task, evicting the oldest
+    /// dump of that task to make room for a new one.  If there's still no
+    /// room, the oldest dump of the task holding the most dumps is evicted.
+    TaskQuota(u8),
+}
+
+/// Per-task restart policy.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct RestartPolicy {
+    /// Delay before the first restart in a window; each subsequent restart in
+    /// the same window doubles the delay, up to `max_backoff_ms`.  This must
+    /// be non-zero, or the delay would never grow.
+    initial_backoff_ms: u32,
+    /// Upper bound on the restart delay.
+    #[serde(default)]
+    max_backoff_ms: u32,
+    /// Number of restarts permitted within `window_ms` before we escalate.
+    /// If absent, the task is restarted indefinitely.
+    #[serde(default)]
+    max_restarts: Option<u32>,
+    /// Length of the window over which restarts are counted, measured from
+    /// the fault that opened it.  The first fault after the window has
+    /// elapsed opens a new one, resetting the backoff and restart count.
+    window_ms: u32,
+    /// What to do once `max_restarts` has been exceeded.
+    #[serde(default)]
+    escalate: Escalation,
+}
+
+impl RestartPolicy {
+    fn validate(&self) -> Result<()> {
+        if self.window_ms == 0 {
+            anyhow::bail!("window-ms must be non-zero");
+        }
+        if self.initial_backoff_ms == 0 {
+            anyhow::bail!("initial-backoff-ms must be non-zero");
+        }
+        if self.max_backoff_ms < self.initial_backoff_ms {
+            anyhow::bail!(
+                "max-backoff-ms ({}) is less than initial-backoff-ms ({})",
+                self.max_backoff_ms,
+                self.initial_backoff_ms
+            );
+        }
+        if self.max_restarts == Some(0) {
+            anyhow::bail!("max-restarts must be non-zero (use tasks-to-hold)");
+        }
+        Ok(())
+    }
+}
+
+/// Action taken when a task exceeds its restart budget.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+enum Escalation {
+    /// Stop restarting the task, leaving it held at its fault.
+    #[default]
+    Hold,
+    /// Reset the entire system.
+    Reset,
 }
 
 #[cfg(feature = "dump")]
@@ -162,3 +305,30 @@ pub(crate) const DUMP_ADDRESS_MAX: u32 = {max:#x};"##
 
     Ok(())
 }
+
+#[cfg(feature = "dump")]
+fn output_dump_policy(out: &mut std::fs::File, cfg: &Config) -> Result<()> {
+    if cfg.max_dumps == 0 {
+        anyhow::bail!("max-dumps must be non-zero");
+    }
+
+    let policy = match cfg.dump_policy {
+        DumpPolicy::KeepFirst => "KeepFirst".to_string(),
+        DumpPolicy::KeepRecent => "KeepRecent".to_string(),
+        DumpPolicy::TaskQuota(0) => {
+            anyhow::bail!("dump-policy task-quota must be non-zero")
+        }
+        DumpPolicy::TaskQuota(n) => format!("TaskQuota({n})"),
+    };
+
+    writeln!(
+        out,
+        r##"
+pub(crate) const DUMP_POLICY: crate::dump::DumpPolicy =
+    crate::dump::DumpPolicy::{policy};
+pub(crate) const MAX_DUMPS: usize = {};"##,
+        cfg.max_dumps,
+    )?;
+
+    Ok(())
+}
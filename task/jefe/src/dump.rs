@@ -4,10 +4,12 @@
 
 //! Dump support for Jefe
 
-use crate::generated::{DUMP_ADDRESS_MAX, DUMP_ADDRESS_MIN, DUMP_AREAS};
+use crate::generated::{
+    DUMP_ADDRESS_MAX, DUMP_ADDRESS_MIN, DUMP_AREAS, DUMP_POLICY, MAX_DUMPS,
+};
 use humpty::{DumpArea, DumpContents};
 use ringbuf::*;
-use task_jefe_api::DumpAgentError;
+use task_jefe_api::{DumpAgentError, DumpRecord};
 use userlib::*;
 
 #[cfg(all(
@@ -55,10 +57,168 @@ enum Trace {
     },
     DumpRead(usize),
     DumpDone(Result<(), humpty::DumpError<()>>),
+    Evicting {
+        sequence: u32,
+        area: u8,
+    },
+    ReleaseFailed(humpty::DumpError<()>),
+    Dropped(usize),
 }
 
 ringbuf!(Trace, 8, Trace::None);
 
+pub use dump_policy::DumpPolicy;
+
+/// The dumps that we know of, which is all of them: the dump areas are
+/// initialized when we start, and every claim of a dump area comes through
+/// us.
+pub struct Dumps {
+    base: u32,
+    records: [Option<DumpRecord>; MAX_DUMPS],
+    next_sequence: u32,
+}
+
+impl Dumps {
+    pub fn new() -> Self {
+        Self {
+            base: initialize_dump_areas(),
+            records: [None; MAX_DUMPS],
+            next_sequence: 0,
+        }
+    }
+
+    pub fn base(&self) -> u32 {
+        self.base
+    }
+
+    pub fn reinitialize(&mut self) {
+        *self = Self::new();
+    }
+
+    pub fn reinitialize_from(
+        &mut self,
+        index: u8,
+    ) -> Result<(), DumpAgentError> {
+        reinitialize_dump_from(self.base, index)?;
+
+        for r in &mut self.records {
+            if matches!(r, Some(r) if r.area >= index) {
+                *r = None;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the `index`'th oldest dump.
+    pub fn record(&self, index: u8) -> Option<DumpRecord> {
+        let index = usize::from(index);
+
+        self.records.iter().flatten().copied().find(|r| {
+            self.records
+                .iter()
+                .flatten()
+                .filter(|o| o.sequence < r.sequence)
+                .count()
+                == index
+        })
+    }
+
+    /// Claims a dump area for a whole-system dump.  This doesn't make room:
+    /// a whole-system dump needs every area, so we'd have to evict every
+    /// task dump to take one.
+    pub fn claim_dump_area(&mut self) -> Result<DumpArea, DumpAgentError> {
+        let area = claim_dump_area(self.base)?;
+        self.insert(&area, None, sys_get_timer().now);
+        Ok(area)
+    }
+
+    fn insert(&mut self, area: &DumpArea, task: Option<usize>, now: u64) {
+        let record = DumpRecord {
+            sequence: self.next_sequence,
+            area: area.index,
+            contents: area.contents,
+            task_index: task.map(|t| t as u16),
+            timestamp: now,
+        };
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+
+        // We make room before claiming for a task dump, and whole-system
+        // dumps can only be claimed with no other dumps present, so there
+        // should always be a free slot; if somehow there's not, forget the
+        // oldest record rather than the newest.
+        let slot = self
+            .records
+            .iter()
+            .position(Option::is_none)
+            .or_else(|| dump_policy::oldest(&self.records, |_| true))
+            .unwrap_lite();
+        self.records[slot] = Some(record);
+    }
+
+    /// Picks a dump to evict to make room for a dump of `task`, according to
+    /// the policy.
+    fn victim(&self, task: u16) -> Option<usize> {
+        DUMP_POLICY.victim(&self.records, task)
+    }
+
+    /// Releases the dump area of the dump in `slot`.
+    fn evict(&mut self, slot: usize) -> Result<(), DumpAgentError> {
+        let Some(record) = self.records[slot].take() else {
+            return Ok(());
+        };
+        ringbuf_entry!(Trace::Evicting {
+            sequence: record.sequence,
+            area: record.area,
+        });
+
+        release_dump_area(self.base, record.area)
+    }
+
+    /// Claims a dump area for a dump of `task`, evicting other dumps as
+    /// required by the policy.  Once it's claimed, we have committed to
+    /// dumping into it:  any failure will result in a partial or otherwise
+    /// corrupted dump.
+    fn claim_for_task(
+        &mut self,
+        task: usize,
+        contents: DumpTaskContents,
+        now: u64,
+    ) -> Result<DumpArea, DumpAgentError> {
+        let t = task as u16;
+        let over_quota = DUMP_POLICY.over_quota(&self.records, t);
+        let full = self.records.iter().all(Option::is_some);
+
+        if over_quota || full {
+            let Some(victim) = self.victim(t) else {
+                ringbuf_entry!(Trace::Dropped(task));
+                return Err(DumpAgentError::DumpAreaInUse);
+            };
+            self.evict(victim)?;
+        }
+
+        // Dumps vary in size, so evicting one may not have freed enough
+        // space; keep going until the claim succeeds or there's nothing left
+        // that we may evict.
+        loop {
+            match dump_task_setup(self.base, contents) {
+                Ok(area) => {
+                    self.insert(&area, Some(task), now);
+                    return Ok(area);
+                }
+                Err(DumpAgentError::DumpAreaInUse) => {
+                    let Some(victim) = self.victim(t) else {
+                        ringbuf_entry!(Trace::Dropped(task));
+                        return Err(DumpAgentError::DumpAreaInUse);
+                    };
+                    self.evict(victim)?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
 pub fn initialize_dump_areas() -> u32 {
     let areas = humpty::initialize_dump_areas(
         &crate::generated::DUMP_AREAS,
@@ -133,6 +293,7 @@ pub fn claim_dump_area(base: u32) -> Result<DumpArea, DumpAgentError> {
 }
 
 /// Marker for whether we're dumping an entire task or a sub-region
+#[derive(Copy, Clone)]
 enum DumpTaskContents {
     SingleTask,
     TaskRegion,
@@ -152,11 +313,6 @@ fn dump_task_setup(
     base: u32,
     contents: DumpTaskContents,
 ) -> Result<DumpArea, DumpAgentError> {
-    //
-    // We need to claim a dump area.  Once it's claimed, we have committed
-    // to dumping into it:  any failure will result in a partial or otherwise
-    // corrupted dump.
-    //
     // SAFETY: we have set up the memory correctly, and we're trusting
     // Humpty to do the right thing here, but ideally we could do this without
     // `unsafe` (given sufficient changes to `humpty`)
@@ -176,7 +332,11 @@ fn dump_task_setup(
 }
 
 /// Once a task dump is set up, this function executes it
-fn dump_task_run(base: u32, task: usize) -> Result<(), DumpAgentError> {
+fn dump_task_run(
+    base: u32,
+    task: usize,
+    now: u64,
+) -> Result<(), DumpAgentError> {
     ringbuf_entry!(Trace::DumpStart { base });
 
     //
@@ -184,7 +344,7 @@ fn dump_task_run(base: u32, task: usize) -> Result<(), DumpAgentError> {
     //
     let r = humpty::dump::<(), 512, { humpty::DUMPER_JEFE }>(
         base,
-        Some(humpty::DumpTask::new(task as u16, sys_get_timer().now)),
+        Some(humpty::DumpTask::new(task as u16, now)),
         || Ok(None),
         |addr, buf, meta| {
             ringbuf_entry!(Trace::DumpReading {
@@ -229,10 +389,12 @@ fn dump_task_run(base: u32, task: usize) -> Result<(), DumpAgentError> {
     Ok(())
 }
 
-pub fn dump_task(base: u32, task: usize) -> Result<u8, DumpAgentError> {
+pub fn dump_task(dumps: &mut Dumps, task: usize) -> Result<u8, DumpAgentError> {
+    let base = dumps.base();
     ringbuf_entry!(Trace::Dumping { task, base });
 
-    let area = dump_task_setup(base, DumpTaskContents::SingleTask)?;
+    let now = sys_get_timer().now;
+    let area = dumps.claim_for_task(task, DumpTaskContents::SingleTask, now)?;
 
     for ndx in 0.. {
         //
@@ -272,17 +434,18 @@ pub fn dump_task(base: u32, task: usize) -> Result<u8, DumpAgentError> {
         }
     }
 
-    dump_task_run(area.region.address, task)?;
+    dump_task_run(area.region.address, task, now)?;
     Ok(area.index)
 }
 
 /// Dumps a specific region from the given task
 pub fn dump_task_region(
-    base: u32,
+    dumps: &mut Dumps,
     task: usize,
     start: u32,
     length: u32,
 ) -> Result<u8, DumpAgentError> {
+    let base = dumps.base();
     ringbuf_entry!(Trace::DumpingTaskRegion {
         task,
         base,
@@ -298,8 +461,6 @@ pub fn dump_task_region(
         return Err(DumpAgentError::UnalignedSegmentLength);
     }
 
-    let area = dump_task_setup(base, DumpTaskContents::TaskRegion)?;
-
     // We don't trust the caller; it may request to dump a region that isn't
     // owned by this particular task!  To check this, we iterate over all of the
     // valid dump regions and confirm that our desired region is within one of
@@ -327,6 +488,9 @@ pub fn dump_task_region(
         return Err(DumpAgentError::BadSegmentAdd);
     }
 
+    let now = sys_get_timer().now;
+    let area = dumps.claim_for_task(task, DumpTaskContents::TaskRegion, now)?;
+
     // SAFETY: we have configured memory so that humpty should only read
     // headers which are properly initialized and readable by this task, and
     // should only write memory which is writeable by this task (i.e. the
@@ -342,14 +506,42 @@ pub fn dump_task_region(
         return Err(DumpAgentError::BadSegmentAdd);
     }
 
-    dump_task_run(area.region.address, task)?;
+    dump_task_run(area.region.address, task, now)?;
     Ok(area.index)
 }
 
-pub fn reinitialize_dump_from(
-    base: u32,
-    index: u8,
-) -> Result<(), DumpAgentError> {
+/// Releases the single dump area at `index`, leaving the others alone.
+///
+/// Humpty can only release every dump area from a given one onwards, so we
+/// confine it to this area by discarding its writes to any other area's
+/// header (see `dump_policy::in_area`).  This should become a call into
+/// humpty once it has a single-area release of its own.
+fn release_dump_area(base: u32, index: u8) -> Result<(), DumpAgentError> {
+    let area = get_dump_area(base, index)?;
+    let start = area.region.address;
+    let length = area.region.length;
+
+    // SAFETY: humpty should walk through the linked list of dump areas owned by
+    // this task, reading and writing to initialized header data; we only let
+    // it write to the area being released.
+    humpty::release_dump_areas_from(
+        start,
+        |addr, buf, _| unsafe { humpty::from_mem(addr, buf) },
+        |addr, buf| {
+            if dump_policy::in_area(start, length, addr, buf.len()) {
+                unsafe { humpty::to_mem(addr, buf) }
+            } else {
+                Ok(())
+            }
+        },
+    )
+    .map_err(|e| {
+        ringbuf_entry!(Trace::ReleaseFailed(e));
+        DumpAgentError::DumpFailed
+    })
+}
+
+fn reinitialize_dump_from(base: u32, index: u8) -> Result<(), DumpAgentError> {
     let area = get_dump_area(base, index)?;
 
     // SAFETY: humpty should walk through the linked list of dump areas owned by
@@ -45,6 +45,7 @@
 
 use crate::{Disposition, TaskStatus};
 use core::sync::atomic::{AtomicU32, Ordering};
+use restart_policy::RestartWindow;
 
 #[cfg(armv6m)]
 use armv6m_atomic_hack::AtomicU32Ext;
@@ -161,6 +162,7 @@ fn check_inner(states: &mut [TaskStatus]) -> Result<bool, Error> {
             // must issue Release, below. This means it's useful for starting
             // the task but still catching it on the _next_ fault.
             kipc::restart_task(ndx, true);
+            state.restart_at = None;
         }
 
         Request::Release => {
@@ -168,6 +170,9 @@ fn check_inner(states: &mut [TaskStatus]) -> Result<bool, Error> {
             // not only the disposition change, but may also have to restart the
             // task to clear a held fault.
             state.disposition = Disposition::Restart;
+            // Give the task a fresh restart budget, lest a task that had
+            // exhausted it be held again on its next fault.
+            state.window = RestartWindow::default();
             if state.holding_fault {
                 state.holding_fault = false;
                 kipc::restart_task(ndx, true);
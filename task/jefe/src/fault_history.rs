@@ -0,0 +1,163 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Fault history for Jefe
+//!
+//! We keep a record of the most recent task faults in RAM that isn't
+//! initialized at startup, so that it survives a warm reset -- including the
+//! one we perform when a crash-looping task escalates to `Escalation::Reset`.
+//! After a cold boot that RAM holds garbage, so we check it before trusting
+//! it, and start a fresh history if it doesn't look like ours.
+
+use armv6m_atomic_hack::AtomicBoolExt;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, Ordering};
+use hubpack::SerializedSize;
+use task_jefe_api::{
+    FaultHistoryInfo, FaultRecord, JefeError, FAULT_EPITAPH_LEN,
+};
+use userlib::*;
+
+/// Number of records we keep; older ones are overwritten.
+const DEPTH: usize = 16;
+
+/// Identifies RAM holding a fault history in the layout below.
+const MAGIC: u32 = 0x4a46_4831; // "JFH1"
+
+/// The persistent part of the history.
+///
+/// Everything in here is a plain integer or byte, so whatever we find in RAM
+/// after a reset is at least a valid value of this type. Records are kept in
+/// their hubpack encoding, so that a damaged record fails to deserialize
+/// rather than producing a nonsense `FaultRecord`.
+#[repr(C)]
+struct Persistent {
+    magic: u32,
+    /// Complement of `magic ^ boot ^ next_sequence`, which guards the header
+    /// against garbage that happens to start with `MAGIC`.
+    check: u32,
+    boot: u32,
+    next_sequence: u32,
+    records: [[u8; FaultRecord::MAX_SIZE]; DEPTH],
+}
+
+impl Persistent {
+    fn checksum(&self) -> u32 {
+        !(self.magic ^ self.boot ^ self.next_sequence)
+    }
+
+    fn is_valid(&self) -> bool {
+        self.magic == MAGIC && self.check == self.checksum()
+    }
+
+    fn seal(&mut self) {
+        self.check = self.checksum();
+    }
+}
+
+pub struct FaultHistory {
+    mem: &'static mut Persistent,
+}
+
+impl FaultHistory {
+    /// Takes ownership of the persistent history. If it survived from a
+    /// previous boot we count a new boot and carry on; otherwise we start an
+    /// empty history.
+    ///
+    /// # Panics
+    ///
+    /// If called more than once.
+    pub fn claim() -> Self {
+        static TAKEN: AtomicBool = AtomicBool::new(false);
+        if TAKEN.swap(true, Ordering::Relaxed) {
+            panic!()
+        }
+
+        #[link_section = ".uninit"]
+        static mut HISTORY: MaybeUninit<Persistent> = MaybeUninit::uninit();
+
+        // Safety: unsafe because of reference to mutable static; safe because
+        // the swap of `TAKEN` above means we only do this once, and `HISTORY`
+        // is not visible anywhere else. Treating its contents as initialized
+        // is okay because every bit pattern is a valid `Persistent` -- the
+        // memory holds either our data from before a reset or leftovers from
+        // power-on, and we check which before using it.
+        let mem = unsafe { &mut *HISTORY.as_mut_ptr() };
+
+        if mem.is_valid() {
+            mem.boot = mem.boot.wrapping_add(1);
+        } else {
+            mem.magic = MAGIC;
+            mem.boot = 0;
+            mem.next_sequence = 0;
+        }
+        mem.seal();
+
+        Self { mem }
+    }
+
+    pub fn info(&self) -> FaultHistoryInfo {
+        FaultHistoryInfo {
+            boot: self.mem.boot,
+            next_sequence: self.mem.next_sequence,
+            held: self.mem.next_sequence.min(DEPTH as u32),
+        }
+    }
+
+    /// Returns the record with the given sequence number, if we still have
+    /// it.
+    pub fn get(&self, sequence: u32) -> Result<FaultRecord, JefeError> {
+        let age = self.mem.next_sequence.wrapping_sub(sequence);
+        if age == 0 || age > DEPTH as u32 {
+            return Err(JefeError::NoSuchFaultRecord);
+        }
+
+        let slot = &self.mem.records[sequence as usize % DEPTH];
+        match hubpack::deserialize::<FaultRecord>(slot) {
+            Ok((record, _)) if record.sequence == sequence => Ok(record),
+            _ => Err(JefeError::NoSuchFaultRecord),
+        }
+    }
+
+    /// Records the fault that task `index` is sitting in, as of time `now`.
+    /// This must be called before the task is restarted, as that discards
+    /// the fault (and, for a panic, the message).
+    pub fn record(&mut self, index: usize, now: u64) {
+        let TaskState::Faulted { fault, .. } = kipc::read_task_status(index)
+        else {
+            return;
+        };
+
+        let id = sys_refresh_task_id(TaskId::for_index_and_gen(
+            index,
+            Generation::ZERO,
+        ));
+
+        let mut epitaph = [0; FAULT_EPITAPH_LEN];
+        let epitaph_len = if fault == FaultInfo::Panic {
+            kipc::read_panic_message(index, &mut epitaph)
+        } else {
+            0
+        };
+
+        let sequence = self.mem.next_sequence;
+        let record = FaultRecord {
+            sequence,
+            boot: self.mem.boot,
+            task_index: index as u16,
+            generation: id.generation().into(),
+            timestamp: now,
+            fault,
+            epitaph_len: epitaph_len as u8,
+            epitaph,
+        };
+
+        // This can't fail, as slots are sized to fit any record.
+        let slot = &mut self.mem.records[sequence as usize % DEPTH];
+        hubpack::serialize(slot, &record).unwrap_lite();
+
+        self.mem.next_sequence = sequence.wrapping_add(1);
+        self.mem.seal();
+    }
+}
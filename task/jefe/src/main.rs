@@ -31,14 +31,40 @@ mod dump;
 
 mod external;
 
+#[cfg(feature = "fault-history")]
+mod fault_history;
+
 use core::convert::Infallible;
 
 use hubris_num_tasks::NUM_TASKS;
 use humpty::DumpArea;
 use idol_runtime::RequestError;
-use task_jefe_api::{DumpAgentError, ResetReason};
+use restart_policy::{RestartAction, RestartWindow};
+use ringbuf::*;
+use task_jefe_api::{
+    DumpAgentError, DumpRecord, FaultHistoryInfo, FaultRecord, JefeError,
+    ResetReason, TaskCpuStats, TaskRestartStats,
+};
 use userlib::*;
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Trace {
+    None,
+    /// The kernel has observed `client` blocking on the less important
+    /// `server`; `count` is the client's total number of inversions, which may
+    /// have gone up by more than one since we last checked.
+    PriorityInversion {
+        client: usize,
+        server: u16,
+        ticks: u64,
+        count: u32,
+    },
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
+pub use restart_policy::{Escalation, RestartPolicy};
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Default)]
 pub enum Disposition {
     #[default]
@@ -59,6 +85,9 @@ fn main() -> ! {
     for held_task in generated::HELD_TASKS {
         task_states[held_task as usize].disposition = Disposition::Hold;
     }
+    for (task, policy) in generated::RESTART_POLICIES {
+        task_states[task as usize].policy = policy;
+    }
 
     let deadline = sys_get_timer().now + TIMER_INTERVAL;
 
@@ -71,8 +100,10 @@ fn main() -> ! {
         deadline,
         task_states: &mut task_states,
         reset_reason: ResetReason::Unknown,
+        #[cfg(feature = "fault-history")]
+        fault_history: fault_history::FaultHistory::claim(),
         #[cfg(feature = "dump")]
-        dump_areas: dump::initialize_dump_areas(),
+        dumps: dump::Dumps::new(),
     };
     let mut buf = [0u8; idl::INCOMING_SIZE];
 
@@ -86,8 +117,10 @@ struct ServerImpl<'s> {
     task_states: &'s mut [TaskStatus; NUM_TASKS],
     deadline: u64,
     reset_reason: ResetReason,
+    #[cfg(feature = "fault-history")]
+    fault_history: fault_history::FaultHistory,
     #[cfg(feature = "dump")]
-    dump_areas: u32,
+    dumps: dump::Dumps,
 }
 
 impl idl::InOrderJefeImpl for ServerImpl<'_> {
@@ -154,6 +187,74 @@ impl idl::InOrderJefeImpl for ServerImpl<'_> {
         Ok(())
     }
 
+    fn get_restart_stats(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        task_index: u32,
+    ) -> Result<TaskRestartStats, RequestError<JefeError>> {
+        let status = self
+            .task_states
+            .get(task_index as usize)
+            .ok_or(JefeError::BadTaskIndex)?;
+
+        Ok(TaskRestartStats {
+            faults: status.faults,
+            restarts: status.restarts,
+            window_restarts: status.window.restarts,
+            last_fault: status.last_fault,
+            last_restart: status.last_restart,
+            next_restart: status.restart_at,
+            held: status.holding_fault,
+        })
+    }
+
+    fn get_task_cpu_stats(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        task_index: u32,
+    ) -> Result<TaskCpuStats, RequestError<JefeError>> {
+        let task_index = task_index as usize;
+        if task_index >= NUM_TASKS {
+            return Err(JefeError::BadTaskIndex.into());
+        }
+        Ok(kipc::read_task_stats(task_index).into())
+    }
+
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "fault-history")] {
+            fn get_fault_history_info(
+                &mut self,
+                _msg: &userlib::RecvMessage,
+            ) -> Result<FaultHistoryInfo, RequestError<Infallible>> {
+                Ok(self.fault_history.info())
+            }
+
+            fn get_fault_record(
+                &mut self,
+                _msg: &userlib::RecvMessage,
+                sequence: u32,
+            ) -> Result<FaultRecord, RequestError<JefeError>> {
+                self.fault_history.get(sequence).map_err(|e| e.into())
+            }
+        } else {
+            // Without the history, we report it as empty.
+            fn get_fault_history_info(
+                &mut self,
+                _msg: &userlib::RecvMessage,
+            ) -> Result<FaultHistoryInfo, RequestError<Infallible>> {
+                Ok(FaultHistoryInfo::default())
+            }
+
+            fn get_fault_record(
+                &mut self,
+                _msg: &userlib::RecvMessage,
+                _sequence: u32,
+            ) -> Result<FaultRecord, RequestError<JefeError>> {
+                Err(JefeError::NoSuchFaultRecord.into())
+            }
+        }
+    }
+
     cfg_if::cfg_if! {
         if #[cfg(feature = "dump")] {
             fn get_dump_area(
@@ -161,7 +262,7 @@ impl idl::InOrderJefeImpl for ServerImpl<'_> {
                 _msg: &userlib::RecvMessage,
                 index: u8,
             ) -> Result<DumpArea, RequestError<DumpAgentError>> {
-                dump::get_dump_area(self.dump_areas, index)
+                dump::get_dump_area(self.dumps.base(), index)
                     .map_err(|e| e.into())
             }
 
@@ -169,14 +270,14 @@ impl idl::InOrderJefeImpl for ServerImpl<'_> {
                 &mut self,
                 _msg: &userlib::RecvMessage,
             ) -> Result<DumpArea, RequestError<DumpAgentError>> {
-                dump::claim_dump_area(self.dump_areas).map_err(|e| e.into())
+                self.dumps.claim_dump_area().map_err(|e| e.into())
             }
 
             fn reinitialize_dump_areas(
                 &mut self,
                 _msg: &userlib::RecvMessage,
             ) -> Result<(), RequestError<DumpAgentError>> {
-                self.dump_areas = dump::initialize_dump_areas();
+                self.dumps.reinitialize();
                 Ok(())
             }
 
@@ -194,7 +295,7 @@ impl idl::InOrderJefeImpl for ServerImpl<'_> {
                     // Can't dump a non-existent task
                     return Err(DumpAgentError::BadOffset.into());
                 }
-                dump::dump_task(self.dump_areas, task_index as usize)
+                dump::dump_task(&mut self.dumps, task_index as usize)
                     .map_err(|e| e.into())
             }
 
@@ -211,7 +312,7 @@ impl idl::InOrderJefeImpl for ServerImpl<'_> {
                     return Err(DumpAgentError::BadOffset.into());
                 }
                 dump::dump_task_region(
-                    self.dump_areas, task_index as usize, address, length
+                    &mut self.dumps, task_index as usize, address, length
                 ).map_err(|e| e.into())
             }
 
@@ -220,9 +321,17 @@ impl idl::InOrderJefeImpl for ServerImpl<'_> {
                 _msg: &userlib::RecvMessage,
                 index: u8,
             ) -> Result<(), RequestError<DumpAgentError>> {
-                dump::reinitialize_dump_from(self.dump_areas, index)
+                self.dumps.reinitialize_from(index)
                     .map_err(|e| e.into())
             }
+
+            fn get_dump_record(
+                &mut self,
+                _msg: &userlib::RecvMessage,
+                index: u8,
+            ) -> Result<Option<DumpRecord>, RequestError<DumpAgentError>> {
+                Ok(self.dumps.record(index))
+            }
         } else {
             fn get_dump_area(
                 &mut self,
@@ -271,6 +380,14 @@ impl idl::InOrderJefeImpl for ServerImpl<'_> {
             ) -> Result<(), RequestError<DumpAgentError>> {
                 Err(DumpAgentError::DumpAgentUnsupported.into())
             }
+
+            fn get_dump_record(
+                &mut self,
+                _msg: &userlib::RecvMessage,
+                _index: u8,
+            ) -> Result<Option<DumpRecord>, RequestError<DumpAgentError>> {
+                Err(DumpAgentError::DumpAgentUnsupported.into())
+            }
         }
     }
 }
@@ -281,6 +398,41 @@ impl idl::InOrderJefeImpl for ServerImpl<'_> {
 struct TaskStatus {
     disposition: Disposition,
     holding_fault: bool,
+    policy: RestartPolicy,
+
+    faults: u32,
+    restarts: u32,
+    last_fault: u64,
+    last_restart: u64,
+
+    /// Restarts we've performed within the current restart window.
+    window: RestartWindow,
+
+    /// If the task is faulted and backing off, the time at which we intend
+    /// to restart it.
+    restart_at: Option<u64>,
+
+    /// Number of priority inversions the kernel had reported for this task
+    /// when we last checked.
+    inversions_seen: u32,
+}
+
+impl TaskStatus {
+    /// Records a fault at time `now`.  Whether the fault counts against the
+    /// task's restart budget is up to the caller.
+    fn record_fault(&mut self, now: u64) {
+        self.faults = self.faults.wrapping_add(1);
+        self.last_fault = now;
+    }
+
+    /// Restarts the task at index `i`, recording it as having happened at
+    /// time `now`.
+    fn restart(&mut self, i: usize, now: u64) {
+        kipc::restart_task(i, true);
+        self.restarts = self.restarts.wrapping_add(1);
+        self.last_restart = now;
+        self.restart_at = None;
+    }
 }
 
 impl idol_runtime::NotificationHandler for ServerImpl<'_> {
@@ -292,11 +444,14 @@ impl idol_runtime::NotificationHandler for ServerImpl<'_> {
         // Handle any external (debugger) requests.
         external::check(self.task_states);
 
+        let now = sys_get_timer().now;
+
         if bits & notifications::TIMER_MASK != 0 {
-            // If our timer went off, we need to reestablish it
-            if sys_get_timer().now >= self.deadline {
+            // If our periodic timer went off, advance it; we reestablish the
+            // timer below, once we know whether any restarts are pending.
+            if now >= self.deadline {
                 self.deadline += TIMER_INTERVAL;
-                sys_set_timer(Some(self.deadline), notifications::TIMER_MASK);
+                check_inversions(self.task_states);
             }
         }
 
@@ -306,39 +461,111 @@ impl idol_runtime::NotificationHandler for ServerImpl<'_> {
             // unlikely since a fault causes us to immediately preempt. In any
             // case, let's assume we might have to handle multiple tasks.
             //
-            // TODO: it would be fantastic to have a way of finding this out in
-            // one syscall.
-            for (i, status) in self.task_states.iter_mut().enumerate() {
-                // If we're aware that this task is in a fault state, don't
-                // bother making a syscall to enquire.
-                if status.holding_fault {
-                    continue;
-                }
+            // The kernel reports faulted tasks as a bitmap, one page of 32
+            // tasks per syscall.
+            for base in (0..NUM_TASKS).step_by(u32::BITS as usize) {
+                let mut faulted = kipc::find_faulted_tasks(base);
+
+                while faulted != 0 {
+                    let i = base + faulted.trailing_zeros() as usize;
+                    faulted &= faulted - 1;
+
+                    let status = &mut self.task_states[i];
+
+                    // If we're already aware that this task is in a fault
+                    // state, there's nothing new to do.
+                    if status.holding_fault || status.restart_at.is_some() {
+                        continue;
+                    }
+
+                    // Take down the details before a restart discards them.
+                    #[cfg(feature = "fault-history")]
+                    self.fault_history.record(i, now);
 
-                if let abi::TaskState::Faulted { .. } =
-                    kipc::read_task_status(i)
-                {
                     #[cfg(feature = "dump")]
                     {
-                        // We'll ignore the result of dumping; it could fail
-                        // if we're out of space, but we don't have a way of
-                        // dealing with that right now.
-                        //
-                        // TODO: some kind of circular buffer?
-                        _ = dump::dump_task(self.dump_areas, i);
+                        // We'll ignore the result of dumping; if there's no
+                        // room, the dump policy has already decided which dump
+                        // to lose, and it's been recorded in the ringbuf.
+                        _ = dump::dump_task(&mut self.dumps, i);
                     }
 
-                    if status.disposition == Disposition::Restart {
-                        // Stand it back up
-                        kipc::restart_task(i, true);
-                    } else {
+                    status.record_fault(now);
+
+                    if status.disposition == Disposition::Hold {
                         // Mark this one off so we don't revisit it until
-                        // requested.
+                        // requested.  A held task isn't being restarted, so
+                        // this fault doesn't count against its restart
+                        // budget.
                         status.holding_fault = true;
+                        continue;
+                    }
+
+                    match status.window.admit(&status.policy, now) {
+                        RestartAction::Now => {
+                            // Stand it back up
+                            status.restart(i, now);
+                        }
+                        RestartAction::At(when) => {
+                            // Leave it faulted until its backoff elapses.
+                            status.restart_at = Some(when);
+                        }
+                        RestartAction::Escalate(Escalation::Hold) => {
+                            // This task is crash-looping; leave it be until
+                            // someone intervenes.
+                            status.disposition = Disposition::Hold;
+                            status.holding_fault = true;
+                        }
+                        RestartAction::Escalate(Escalation::Reset) => {
+                            kipc::system_restart();
+                        }
                     }
                 }
             }
         }
+
+        // Restart any tasks whose backoff has elapsed, and work out when we
+        // next need to wake up.
+        let mut wake = self.deadline;
+
+        for (i, status) in self.task_states.iter_mut().enumerate() {
+            let Some(when) = status.restart_at else {
+                continue;
+            };
+
+            if status.disposition == Disposition::Hold {
+                // We were asked to hold this task while it was backing off.
+                status.restart_at = None;
+                status.holding_fault = true;
+            } else if now >= when {
+                status.restart(i, now);
+            } else {
+                wake = wake.min(when);
+            }
+        }
+
+        sys_set_timer(Some(wake), notifications::TIMER_MASK);
+    }
+}
+
+/// Records any priority inversions that the kernel has observed since we last
+/// looked in our ringbuf.
+fn check_inversions(task_states: &mut [TaskStatus; NUM_TASKS]) {
+    for (i, status) in task_states.iter_mut().enumerate() {
+        let inversions = kipc::read_priority_inversions(i);
+        if inversions.count == status.inversions_seen {
+            continue;
+        }
+        status.inversions_seen = inversions.count;
+
+        if let Some(last) = inversions.last {
+            ringbuf_entry!(Trace::PriorityInversion {
+                client: i,
+                server: last.server,
+                ticks: last.ticks,
+                count: inversions.count,
+            });
+        }
     }
 }
 
@@ -351,6 +578,9 @@ include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
 
 // And the Idol bits
 mod idl {
-    use task_jefe_api::{DumpAgentError, ResetReason};
+    use task_jefe_api::{
+        DumpAgentError, DumpRecord, FaultHistoryInfo, FaultRecord, JefeError,
+        ResetReason, TaskCpuStats, TaskRestartStats,
+    };
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
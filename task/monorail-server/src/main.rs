@@ -9,6 +9,7 @@
 #[cfg_attr(target_board = "sidecar-c", path = "bsp/sidecar_bc.rs")]
 mod bsp;
 mod server;
+mod stats;
 
 use crate::{bsp::Bsp, server::ServerImpl};
 use drv_spi_api::SpiServer;
@@ -5,10 +5,12 @@
 use crate::{
     bsp::{self, Bsp},
     notifications,
+    stats::{self, PortStats},
 };
 use drv_monorail_api::{
-    LinkStatus, MacTableEntry, MonorailError, PacketCount, PhyStatus, PhyType,
-    PortCounters, PortDev, PortStatus, VscError,
+    AlarmThresholds, LinkStatus, MacTableEntry, MonorailError, PacketCount,
+    PhyStatus, PhyType, PortCounters, PortDev, PortRates, PortStatus,
+    RateWindow, VscError,
 };
 use idol_runtime::{NotificationHandler, RequestError};
 use userlib::{sys_get_timer, sys_set_timer};
@@ -30,6 +32,11 @@ pub struct ServerImpl<'a, R> {
     /// However, the PHY registers typically use self-clearing bits.  We cache
     /// the bit here, so that it can be explicitly cleared.
     phy_link_down_sticky: [bool; PORT_COUNT],
+
+    /// Periodically sampled counters, for rates and error-rate alarms
+    stats: &'static mut [PortStats; PORT_COUNT],
+    stats_target_time: u64,
+    alarm_thresholds: AlarmThresholds,
 }
 
 pub const INCOMING_SIZE: usize = idl::INCOMING_SIZE;
@@ -44,6 +51,7 @@ impl<'a, R: Vsc7448Rw> ServerImpl<'a, R> {
         // logging.  We schedule a wake-up before entering the idol_runtime dispatch
         // loop, to make sure that this gets called periodically.
         let wake_target_time = sys_get_timer().now;
+        let stats_target_time = wake_target_time;
 
         // Trigger a wake IRQ right away
         sys_set_timer(Some(0), notifications::WAKE_TIMER_MASK);
@@ -53,23 +61,37 @@ impl<'a, R: Vsc7448Rw> ServerImpl<'a, R> {
             map,
             vsc7448,
             phy_link_down_sticky: [false; PORT_COUNT],
+            stats: stats::claim_stats(),
+            stats_target_time,
+            alarm_thresholds: stats::DEFAULT_THRESHOLDS,
         }
     }
 
     pub fn wake(&mut self) -> Result<(), VscError> {
         let now = sys_get_timer().now;
+        let mut out = Ok(());
+
+        if now >= self.stats_target_time {
+            stats::sample_all(
+                self.vsc7448,
+                self.map,
+                self.stats,
+                &self.alarm_thresholds,
+            );
+            self.stats_target_time = now + stats::SAMPLE_INTERVAL_MS;
+        }
+        let mut deadline = self.stats_target_time;
+
         if let Some(wake_interval) = bsp::WAKE_INTERVAL {
             if now >= self.wake_target_time {
-                let out = self.bsp.wake();
+                out = self.bsp.wake();
                 self.wake_target_time = now + wake_interval;
-                sys_set_timer(
-                    Some(self.wake_target_time),
-                    notifications::WAKE_TIMER_MASK,
-                );
-                return out;
             }
+            deadline = deadline.min(self.wake_target_time);
         }
-        Ok(())
+
+        sys_set_timer(Some(deadline), notifications::WAKE_TIMER_MASK);
+        out
     }
 
     /// Helper function to return an error if a user-specified port is invalid
@@ -350,6 +372,7 @@ impl<'a, R: Vsc7448Rw> idl::InOrderMonorailImpl for ServerImpl<'a, R> {
                 self.vsc7448
                     .write(stats.TX_MC_CNT(), 0.into())
                     .map_err(MonorailError::from)?;
+                self.stats[port as usize].packet_counters_reset();
 
                 let dev = match cfg.dev.0 {
                     PortDev::Dev1g => DevGeneric::new_1g(cfg.dev.1),
@@ -401,6 +424,7 @@ impl<'a, R: Vsc7448Rw> idl::InOrderMonorailImpl for ServerImpl<'a, R> {
                 self.vsc7448
                     .write(stats.TX_MC_CNT(), 0.into())
                     .map_err(MonorailError::from)?;
+                self.stats[port as usize].packet_counters_reset();
 
                 self.vsc7448
                     .write_with(
@@ -413,6 +437,46 @@ impl<'a, R: Vsc7448Rw> idl::InOrderMonorailImpl for ServerImpl<'a, R> {
         Ok(())
     }
 
+    fn get_port_rates(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        port: u8,
+        window: RateWindow,
+    ) -> Result<PortRates, RequestError<MonorailError>> {
+        self.check_port(port)?;
+        Ok(self.stats[port as usize].rates(window))
+    }
+
+    fn get_alarmed_ports(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+    ) -> Result<u64, RequestError<MonorailError>> {
+        Ok(self
+            .stats
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.alarms.any())
+            .fold(0, |mask, (port, _)| mask | (1 << port)))
+    }
+
+    fn get_alarm_thresholds(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+    ) -> Result<AlarmThresholds, RequestError<MonorailError>> {
+        Ok(self.alarm_thresholds)
+    }
+
+    fn set_alarm_thresholds(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        thresholds: AlarmThresholds,
+    ) -> Result<(), RequestError<MonorailError>> {
+        // Alarms are re-evaluated against the new thresholds on the next
+        // sample, which is at most a second away.
+        self.alarm_thresholds = thresholds;
+        Ok(())
+    }
+
     fn read_phy_reg(
         &mut self,
         _msg: &userlib::RecvMessage,
@@ -0,0 +1,303 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Periodic sampling of port counters, for rates and error-rate alarms
+//!
+//! The VSC7448's counters are free-running 32-bit values which anyone may
+//! reset through `reset_port_counters`, so we can't use them directly.
+//! Instead, we sample them once a second and accumulate the (wrapping)
+//! differences into totals of our own.  Totals are kept in two rings: one of
+//! 1 second samples covering [RateWindow::Short], and one of 10 second
+//! samples covering [RateWindow::Long].  A rate is then just the difference
+//! between the newest and oldest totals in a ring, over the time between
+//! them.
+//!
+//! Only the counters of each port's MAC are sampled.  Frames dropped by the
+//! switch core are counted elsewhere (in `SYS:STAT`, behind a per-port view),
+//! and aren't reported here.
+
+use drv_monorail_api::{
+    AlarmThresholds, PortAlarms, PortDev, PortRates, RateWindow,
+};
+use ringbuf::*;
+use userlib::sys_get_timer;
+use vsc7448::{config::PortConfig, Vsc7448, Vsc7448Rw, VscError, PORT_COUNT};
+use vsc7448_pac::*;
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    SampleError(u8, VscError),
+    Alarm(u8, PortAlarms),
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Interval between samples of the port counters
+pub const SAMPLE_INTERVAL_MS: u64 = 1000;
+
+/// Number of samples per [RateWindow::Long] sample
+const LONG_SAMPLE_RATIO: u32 = 10;
+
+/// Ring depths; each ring holds one more sample than the number of intervals
+/// that it covers.
+const SHORT_DEPTH: usize = 11;
+const LONG_DEPTH: usize = 7;
+
+pub const DEFAULT_THRESHOLDS: AlarmThresholds = AlarmThresholds {
+    rx_crc_errors: 1.0,
+    rx_malformed: 10.0,
+};
+
+/// Counter values, either raw from the VSC7448 or as accumulated totals
+#[derive(Copy, Clone, Default)]
+struct Counts {
+    rx_packets: u32,
+    tx_packets: u32,
+    rx_crc_errors: u32,
+    rx_malformed: u32,
+}
+
+impl Counts {
+    const ZERO: Self = Self {
+        rx_packets: 0,
+        tx_packets: 0,
+        rx_crc_errors: 0,
+        rx_malformed: 0,
+    };
+
+    fn wrapping_sub(&self, other: &Self) -> Self {
+        Self {
+            rx_packets: self.rx_packets.wrapping_sub(other.rx_packets),
+            tx_packets: self.tx_packets.wrapping_sub(other.tx_packets),
+            rx_crc_errors: self.rx_crc_errors.wrapping_sub(other.rx_crc_errors),
+            rx_malformed: self.rx_malformed.wrapping_sub(other.rx_malformed),
+        }
+    }
+
+    fn wrapping_add(&self, other: &Self) -> Self {
+        Self {
+            rx_packets: self.rx_packets.wrapping_add(other.rx_packets),
+            tx_packets: self.tx_packets.wrapping_add(other.tx_packets),
+            rx_crc_errors: self.rx_crc_errors.wrapping_add(other.rx_crc_errors),
+            rx_malformed: self.rx_malformed.wrapping_add(other.rx_malformed),
+        }
+    }
+}
+
+/// Accumulated totals, and the time at which they were sampled
+#[derive(Copy, Clone)]
+struct Sample {
+    time: u64,
+    total: Counts,
+}
+
+/// A ring of accumulated totals, sampled at a (nominally) fixed interval
+#[derive(Copy, Clone)]
+struct Ring<const N: usize> {
+    samples: [Sample; N],
+    next: usize,
+    len: usize,
+}
+
+impl<const N: usize> Ring<N> {
+    const EMPTY: Self = Self {
+        samples: [Sample {
+            time: 0,
+            total: Counts::ZERO,
+        }; N],
+        next: 0,
+        len: 0,
+    };
+
+    fn push(&mut self, sample: Sample) {
+        self.samples[self.next] = sample;
+        self.next = (self.next + 1) % N;
+        self.len = (self.len + 1).min(N);
+    }
+
+    /// Returns the per-second rates over the ring.  These are computed from
+    /// the times at which the oldest and newest samples were actually taken,
+    /// since sampling slips behind its schedule by however long each round
+    /// of sampling takes.
+    fn rates(&self) -> PortRates {
+        if self.len < 2 {
+            return PortRates::default();
+        }
+        let newest = self.samples[(self.next + N - 1) % N];
+        let oldest = self.samples[(self.next + N - self.len) % N];
+        let window_ms = newest.time.saturating_sub(oldest.time);
+        if window_ms == 0 {
+            return PortRates::default();
+        }
+        let delta = newest.total.wrapping_sub(&oldest.total);
+
+        let per_sec = |n: u32| n as f32 * 1000.0 / window_ms as f32;
+        PortRates {
+            window_ms: window_ms as u32,
+            rx_packets: per_sec(delta.rx_packets),
+            tx_packets: per_sec(delta.tx_packets),
+            rx_crc_errors: per_sec(delta.rx_crc_errors),
+            rx_malformed: per_sec(delta.rx_malformed),
+            alarms: PortAlarms::default(),
+        }
+    }
+}
+
+pub struct PortStats {
+    /// Raw counter values at the last sample, or `None` if the port has never
+    /// been sampled
+    raw: Option<Counts>,
+    total: Counts,
+    samples: u32,
+    short: Ring<SHORT_DEPTH>,
+    long: Ring<LONG_DEPTH>,
+    pub alarms: PortAlarms,
+}
+
+impl PortStats {
+    pub const EMPTY: Self = Self {
+        raw: None,
+        total: Counts::ZERO,
+        samples: 0,
+        short: Ring::EMPTY,
+        long: Ring::EMPTY,
+        alarms: PortAlarms {
+            rx_crc_errors: false,
+            rx_malformed: false,
+        },
+    };
+
+    fn sample(&mut self, raw: Counts, now: u64) {
+        if let Some(prev) = self.raw {
+            self.total = self.total.wrapping_add(&raw.wrapping_sub(&prev));
+        }
+        self.raw = Some(raw);
+
+        let sample = Sample {
+            time: now,
+            total: self.total,
+        };
+        self.short.push(sample);
+        if self.samples % LONG_SAMPLE_RATIO == 0 {
+            self.long.push(sample);
+        }
+        self.samples = self.samples.wrapping_add(1);
+    }
+
+    /// Called when the packet counters have been reset behind our back, so
+    /// that the reset isn't mistaken for the counters wrapping.
+    pub fn packet_counters_reset(&mut self) {
+        if let Some(raw) = self.raw.as_mut() {
+            raw.rx_packets = 0;
+            raw.tx_packets = 0;
+        }
+    }
+
+    pub fn rates(&self, window: RateWindow) -> PortRates {
+        let rates = match window {
+            RateWindow::Short => self.short.rates(),
+            RateWindow::Long => self.long.rates(),
+        };
+        PortRates {
+            alarms: self.alarms,
+            ..rates
+        }
+    }
+
+    fn check_alarms(&mut self, port: u8, thresholds: &AlarmThresholds) {
+        let rates = self.short.rates();
+        let alarms = if rates.window_ms == 0 {
+            PortAlarms::default()
+        } else {
+            PortAlarms {
+                rx_crc_errors: rates.rx_crc_errors > thresholds.rx_crc_errors,
+                rx_malformed: rates.rx_malformed > thresholds.rx_malformed,
+            }
+        };
+        if alarms != self.alarms {
+            ringbuf_entry!(Trace::Alarm(port, alarms));
+            self.alarms = alarms;
+        }
+    }
+}
+
+/// Samples the counters of every configured port, updating their rates and
+/// alarms.  Errors are logged and the port skipped until the next sample.
+pub fn sample_all<R: Vsc7448Rw>(
+    vsc7448: &Vsc7448<'_, R>,
+    map: &vsc7448::config::PortMap,
+    stats: &mut [PortStats; PORT_COUNT],
+    thresholds: &AlarmThresholds,
+) {
+    for (port, s) in stats.iter_mut().enumerate().take(map.len()) {
+        let port = port as u8;
+        let Some(cfg) = map.port_config(port) else {
+            continue;
+        };
+        match read_counts(vsc7448, port, cfg) {
+            Ok(raw) => {
+                s.sample(raw, sys_get_timer().now);
+                s.check_alarms(port, thresholds);
+            }
+            Err(e) => ringbuf_entry!(Trace::SampleError(port, e)),
+        }
+    }
+}
+
+fn read_counts<R: Vsc7448Rw>(
+    vsc7448: &Vsc7448<'_, R>,
+    port: u8,
+    cfg: PortConfig,
+) -> Result<Counts, VscError> {
+    // The 1G/2G5 and 10G statistics blocks use the same register names, but
+    // are different types; a macro saves us from writing this out twice.
+    macro_rules! read_counts {
+        ($stats:expr) => {{
+            let stats = $stats;
+            let rx_packets = u32::from(vsc7448.read(stats.RX_UC_CNT())?)
+                .wrapping_add(vsc7448.read(stats.RX_MC_CNT())?.into())
+                .wrapping_add(vsc7448.read(stats.RX_BC_CNT())?.into());
+            let tx_packets = u32::from(vsc7448.read(stats.TX_UC_CNT())?)
+                .wrapping_add(vsc7448.read(stats.TX_MC_CNT())?.into())
+                .wrapping_add(vsc7448.read(stats.TX_BC_CNT())?.into());
+            let rx_crc_errors = vsc7448.read(stats.RX_CRC_ERR_CNT())?.into();
+            let undersize = u32::from(vsc7448.read(stats.RX_UNDERSIZE_CNT())?);
+            let fragments = u32::from(vsc7448.read(stats.RX_FRAGMENTS_CNT())?);
+            let jabbers = u32::from(vsc7448.read(stats.RX_JABBERS_CNT())?);
+            let oversize = u32::from(vsc7448.read(stats.RX_OVERSIZE_CNT())?);
+            let rx_malformed = undersize
+                .wrapping_add(fragments)
+                .wrapping_add(jabbers)
+                .wrapping_add(oversize);
+            Counts {
+                rx_packets,
+                tx_packets,
+                rx_crc_errors,
+                rx_malformed,
+            }
+        }};
+    }
+
+    Ok(match cfg.dev.0 {
+        PortDev::Dev1g | PortDev::Dev2g5 => {
+            read_counts!(ASM().DEV_STATISTICS(port))
+        }
+        PortDev::Dev10g => {
+            read_counts!(DEV10G(cfg.dev.1).DEV_STATISTICS_32BIT())
+        }
+    })
+}
+
+/// Grabs a reference to the static per-port statistics.  Can only be called
+/// once.
+pub fn claim_stats() -> &'static mut [PortStats; PORT_COUNT] {
+    mutable_statics::mutable_statics! {
+        static mut STATS: [PortStats; PORT_COUNT] = [|| PortStats::EMPTY; _];
+    }
+}
@@ -10,7 +10,7 @@ use derive_idol_err::IdolError;
 use userlib::*;
 use zerocopy::{AsBytes, FromBytes, LittleEndian, U16};
 
-pub use host_sp_messages::HostStartupOptions;
+pub use host_sp_messages::{HostAlertKind, HostStartupOptions};
 pub use oxide_barcode::VpdIdentity;
 
 /// Represents a range of allocated MAC addresses, per RFD 320
@@ -25,6 +25,45 @@ pub struct MacAddressBlock {
     pub stride: u8,
 }
 
+/// Number of host alerts that packrat holds; once full, the oldest is
+/// discarded to make room for a new one.
+pub const HOST_ALERT_QUEUE_DEPTH: usize = 8;
+
+/// An alert for the host, raised by another task and held by packrat until
+/// `host-sp-comms` passes it along.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, FromBytes, AsBytes, Default)]
+#[repr(C)]
+pub struct HostAlertEvent {
+    /// Time at which the alert was raised, in milliseconds since the SP
+    /// booted
+    pub timestamp: u64,
+    /// Sequence number assigned by packrat. These count up from zero across
+    /// every alert raised since packrat started, so a gap indicates alerts
+    /// discarded from a full queue; they start over if packrat restarts.
+    pub sequence: u32,
+    /// [`HostAlertKind`], as a raw byte
+    pub kind: u8,
+    /// Instance of the component that raised the alert
+    pub index: u8,
+    _reserved: [u8; 2],
+}
+
+impl HostAlertEvent {
+    pub fn new(timestamp: u64, sequence: u32, kind: u8, index: u8) -> Self {
+        Self {
+            timestamp,
+            sequence,
+            kind,
+            index,
+            _reserved: [0; 2],
+        }
+    }
+
+    pub fn kind(&self) -> Option<HostAlertKind> {
+        HostAlertKind::from_u8(self.kind)
+    }
+}
+
 #[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
 pub enum CacheGetError {
     ValueNotSet = 1,
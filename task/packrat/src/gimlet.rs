@@ -10,7 +10,10 @@ use drv_gimlet_seq_api::NUM_SPD_BANKS;
 use idol_runtime::{ClientError, Leased, LenLimit, RequestError};
 use mutable_statics::mutable_statics;
 use ringbuf::ringbuf_entry_root as ringbuf_entry;
-use task_packrat_api::HostStartupOptions;
+use task_packrat_api::{
+    CacheGetError, HostAlertEvent, HostStartupOptions, HOST_ALERT_QUEUE_DEPTH,
+};
+use userlib::sys_get_timer;
 
 const SPD_DATA_LEN: usize =
     NUM_SPD_BANKS * spd::MAX_SIZE * spd::MAX_DEVICES as usize;
@@ -20,6 +23,35 @@ pub(crate) struct GimletData {
     host_startup_options: &'static mut HostStartupOptions,
     spd_present: &'static mut [bool; NUM_SPD_BANKS * spd::MAX_DEVICES as usize],
     spd_data: &'static mut [u8; SPD_DATA_LEN],
+    host_alerts: &'static mut HostAlertQueue,
+}
+
+/// Alerts for the host, raised by tasks that can't send to `host-sp-comms`
+/// directly (because it runs at a lower priority than they do), and kept here
+/// until `host-sp-comms` collects them.
+pub(crate) struct HostAlertQueue {
+    events: [HostAlertEvent; HOST_ALERT_QUEUE_DEPTH],
+    /// Slot to be written next
+    next: usize,
+    /// Number of slots in use
+    len: usize,
+    next_sequence: u32,
+    /// Sequence number of the first alert not yet delivered to the host;
+    /// kept here, rather than in `host-sp-comms`, so that it survives a
+    /// restart of that task.
+    cursor: u32,
+}
+
+impl HostAlertQueue {
+    fn new() -> Self {
+        Self {
+            events: [HostAlertEvent::default(); HOST_ALERT_QUEUE_DEPTH],
+            next: 0,
+            len: 0,
+            next_sequence: 0,
+            cursor: 0,
+        }
+    }
 }
 
 fn default_host_startup_options() -> HostStartupOptions {
@@ -35,7 +67,7 @@ fn default_host_startup_options() -> HostStartupOptions {
 impl GimletData {
     // Panics if called more than once.
     pub(crate) fn claim_static_resources() -> Self {
-        let (spd_present, spd_data, host_startup_options) = mutable_statics! {
+        let (spd_present, spd_data, host_startup_options, host_alerts) = mutable_statics! {
             static mut SPD_PRESENT:
                 [bool; NUM_SPD_BANKS * spd::MAX_DEVICES as usize]
                     = [|| false; _];
@@ -44,12 +76,16 @@ impl GimletData {
 
             static mut HOST_STARTUP_OPTIONS: [HostStartupOptions; 1] =
                 [default_host_startup_options; _];
+
+            static mut HOST_ALERTS: [HostAlertQueue; 1] =
+                [HostAlertQueue::new; _];
         };
 
         Self {
             host_startup_options: &mut host_startup_options[0],
             spd_present,
             spd_data,
+            host_alerts: &mut host_alerts[0],
         }
     }
 
@@ -146,4 +182,49 @@ impl GimletData {
             ))
         }
     }
+
+    pub(crate) fn raise_host_alert(
+        &mut self,
+        kind: u8,
+        index: u8,
+    ) -> Result<u32, RequestError<Infallible>> {
+        let q = &mut *self.host_alerts;
+
+        let sequence = q.next_sequence;
+        ringbuf_entry!(Trace::HostAlert {
+            sequence,
+            kind,
+            index,
+        });
+
+        q.events[q.next] =
+            HostAlertEvent::new(sys_get_timer().now, sequence, kind, index);
+        q.next = (q.next + 1) % HOST_ALERT_QUEUE_DEPTH;
+        q.len = usize::min(q.len + 1, HOST_ALERT_QUEUE_DEPTH);
+        q.next_sequence = sequence.wrapping_add(1);
+
+        Ok(sequence)
+    }
+
+    pub(crate) fn get_host_alert(
+        &self,
+        sequence: u32,
+    ) -> Result<HostAlertEvent, RequestError<CacheGetError>> {
+        let q = &*self.host_alerts;
+
+        // Walk from the oldest queued alert to the newest.
+        let oldest = q.next + HOST_ALERT_QUEUE_DEPTH - q.len;
+        (oldest..oldest + q.len)
+            .map(|i| q.events[i % HOST_ALERT_QUEUE_DEPTH])
+            .find(|e| e.sequence >= sequence)
+            .ok_or(CacheGetError::ValueNotSet.into())
+    }
+
+    pub(crate) fn set_host_alert_cursor(&mut self, sequence: u32) {
+        self.host_alerts.cursor = sequence;
+    }
+
+    pub(crate) fn host_alert_cursor(&self) -> u32 {
+        self.host_alerts.cursor
+    }
 }
@@ -34,8 +34,8 @@ use idol_runtime::{Leased, LenLimit, RequestError};
 use mutable_statics::mutable_statics;
 use ringbuf::{ringbuf, ringbuf_entry};
 use task_packrat_api::{
-    CacheGetError, CacheSetError, HostStartupOptions, MacAddressBlock,
-    VpdIdentity,
+    CacheGetError, CacheSetError, HostAlertEvent, HostStartupOptions,
+    MacAddressBlock, VpdIdentity,
 };
 use userlib::RecvMessage;
 
@@ -55,6 +55,11 @@ enum Trace {
         offset: u8,
         len: u8,
     },
+    HostAlert {
+        sequence: u32,
+        kind: u8,
+        index: u8,
+    },
 }
 
 impl From<TraceSet<MacAddressBlock>> for Trace {
@@ -310,12 +315,93 @@ impl idl::InOrderPackratImpl for ServerImpl {
             idol_runtime::ClientError::BadMessageContents,
         ))
     }
+
+    #[cfg(feature = "gimlet")]
+    fn raise_host_alert(
+        &mut self,
+        _: &RecvMessage,
+        kind: u8,
+        index: u8,
+    ) -> Result<u32, RequestError<Infallible>> {
+        self.gimlet_data.raise_host_alert(kind, index)
+    }
+
+    #[cfg(not(feature = "gimlet"))]
+    fn raise_host_alert(
+        &mut self,
+        _: &RecvMessage,
+        _kind: u8,
+        _index: u8,
+    ) -> Result<u32, RequestError<Infallible>> {
+        Err(RequestError::Fail(
+            idol_runtime::ClientError::BadMessageContents,
+        ))
+    }
+
+    #[cfg(feature = "gimlet")]
+    fn get_host_alert(
+        &mut self,
+        _: &RecvMessage,
+        sequence: u32,
+    ) -> Result<HostAlertEvent, RequestError<CacheGetError>> {
+        self.gimlet_data.get_host_alert(sequence)
+    }
+
+    #[cfg(not(feature = "gimlet"))]
+    fn get_host_alert(
+        &mut self,
+        _: &RecvMessage,
+        _sequence: u32,
+    ) -> Result<HostAlertEvent, RequestError<CacheGetError>> {
+        Err(RequestError::Fail(
+            idol_runtime::ClientError::BadMessageContents,
+        ))
+    }
+
+    #[cfg(feature = "gimlet")]
+    fn set_host_alert_cursor(
+        &mut self,
+        _: &RecvMessage,
+        sequence: u32,
+    ) -> Result<(), RequestError<Infallible>> {
+        self.gimlet_data.set_host_alert_cursor(sequence);
+        Ok(())
+    }
+
+    #[cfg(not(feature = "gimlet"))]
+    fn set_host_alert_cursor(
+        &mut self,
+        _: &RecvMessage,
+        _sequence: u32,
+    ) -> Result<(), RequestError<Infallible>> {
+        Err(RequestError::Fail(
+            idol_runtime::ClientError::BadMessageContents,
+        ))
+    }
+
+    #[cfg(feature = "gimlet")]
+    fn get_host_alert_cursor(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<u32, RequestError<Infallible>> {
+        Ok(self.gimlet_data.host_alert_cursor())
+    }
+
+    #[cfg(not(feature = "gimlet"))]
+    fn get_host_alert_cursor(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<u32, RequestError<Infallible>> {
+        Err(RequestError::Fail(
+            idol_runtime::ClientError::BadMessageContents,
+        ))
+    }
 }
 
 mod idl {
     use super::{
-        CacheGetError, CacheSetError, HostStartupOptions, MacAddressBlock,
-        VpdIdentity,
+        CacheGetError, CacheSetError, HostAlertEvent, HostStartupOptions,
+        MacAddressBlock, VpdIdentity,
     };
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
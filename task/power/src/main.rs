@@ -38,6 +38,8 @@ use drv_i2c_devices::{
 enum Trace {
     GotVersion(u32),
     GotAddr(u32),
+    InputPowerLost(f32),
+    InputPowerRestored(f32),
     None,
 }
 
@@ -53,9 +55,17 @@ enum PowerState {
 
 const TIMER_INTERVAL: u64 = 1000;
 
+// Below this voltage at the output of the hot swap controller, we consider the
+// 54V supplied by the rack to have sagged enough (presumably because the power
+// shelf has lost supplies) that the host should be told about it.
+const MIN_INPUT_VOLTAGE: f32 = 48.0;
+
 task_slot!(I2C, i2c_driver);
 task_slot!(SENSOR, sensor);
 
+#[cfg(feature = "gimlet")]
+task_slot!(PACKRAT, packrat);
+
 include!(concat!(env!("OUT_DIR"), "/i2c_config.rs"));
 
 #[allow(dead_code, clippy::upper_case_acronyms)]
@@ -412,6 +422,7 @@ fn main() -> ! {
         i2c_task,
         sensor: sensor_api::Sensor::from(SENSOR.get_task_id()),
         devices: claim_devices(i2c_task),
+        input_power: InputPowerMonitor { lost: false },
     };
     let mut buffer = [0; idl::INCOMING_SIZE];
 
@@ -428,6 +439,43 @@ struct ServerImpl {
     i2c_task: TaskId,
     sensor: sensor_api::Sensor,
     devices: &'static mut [Device; bsp::CONTROLLER_CONFIG_LEN],
+    input_power: InputPowerMonitor,
+}
+
+/// Tracks whether our input power has sagged, so that we alert the host once
+/// when it does (rather than every time we sample it).
+struct InputPowerMonitor {
+    lost: bool,
+}
+
+impl InputPowerMonitor {
+    fn update(&mut self, c: &PowerControllerConfig, vout: Volts) {
+        if !matches!(c.device, DeviceType::HotSwap(_)) {
+            return;
+        }
+
+        let lost = vout.0 < MIN_INPUT_VOLTAGE;
+        if lost && !self.lost {
+            ringbuf_entry!(Trace::InputPowerLost(vout.0));
+            raise_host_alert_psu_loss();
+        } else if !lost && self.lost {
+            ringbuf_entry!(Trace::InputPowerRestored(vout.0));
+        }
+        self.lost = lost;
+    }
+}
+
+#[cfg(feature = "gimlet")]
+fn raise_host_alert_psu_loss() {
+    use task_packrat_api::{HostAlertKind, Packrat};
+
+    let packrat = Packrat::from(PACKRAT.get_task_id());
+    packrat.raise_host_alert(HostAlertKind::PsuLoss as u8, 0);
+}
+
+#[cfg(not(feature = "gimlet"))]
+fn raise_host_alert_psu_loss() {
+    // Only Gimlet has a host to alert.
 }
 
 impl ServerImpl {
@@ -473,6 +521,7 @@ impl ServerImpl {
             match dev.read_vout() {
                 Ok(reading) => {
                     sensor.post_now(c.voltage, reading.0).unwrap();
+                    self.input_power.update(c, reading);
                 }
                 Err(_) => {
                     sensor.nodata_now(c.voltage, NoData::DeviceError).unwrap();
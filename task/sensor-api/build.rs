@@ -18,7 +18,13 @@ struct GlobalConfig {
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 struct SensorConfig {
+    #[serde(default)]
     devices: Vec<Sensor>,
+
+    /// Number of sensors set aside to be allocated at runtime, for devices
+    /// (like pluggable modules) whose sensors aren't known at build time
+    #[serde(default)]
+    dynamic: usize,
 }
 
 #[derive(Debug, Deserialize)]
@@ -38,6 +44,8 @@ fn main() -> Result<()> {
 
     let config: GlobalConfig = build_util::config()?;
 
+    let dynamic = config.sensor.as_ref().map(|s| s.dynamic).unwrap_or(0);
+
     let (count, text) = if let Some(config_sensor) = &config.sensor {
         let sensor_count: usize =
             config_sensor.devices.iter().map(|d| d.sensors.len()).sum();
@@ -130,8 +138,14 @@ fn main() -> Result<()> {
     pub use i2c_sensors::NUM_SENSORS as NUM_I2C_SENSORS;
     pub use other_sensors::NUM_SENSORS as NUM_OTHER_SENSORS;
 
+    // Dynamic sensors follow all others
+    pub const NUM_DYNAMIC_SENSORS: usize = {dynamic};
+    pub const FIRST_DYNAMIC_SENSOR: SensorId =
+        SensorId((NUM_I2C_SENSORS + NUM_OTHER_SENSORS) as u32);
+
     // Here's what we actually care about:
-    pub const NUM_SENSORS: usize = NUM_I2C_SENSORS + NUM_OTHER_SENSORS;
+    pub const NUM_SENSORS: usize =
+        NUM_I2C_SENSORS + NUM_OTHER_SENSORS + NUM_DYNAMIC_SENSORS;
 }}"#
     )
     .unwrap();
@@ -3,7 +3,7 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 //! Client types for the Sensor API
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 use derive_idol_err::IdolError;
 use drv_i2c_types::ResponseCode;
@@ -50,6 +50,64 @@ impl Reading {
     }
 }
 
+/// Summary statistics over a sensor's data readings
+#[derive(
+    Copy, Clone, Debug, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+pub struct SensorStats {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+    pub count: u32,
+}
+
+impl SensorStats {
+    pub const EMPTY: Self = Self {
+        min: f32::NAN,
+        max: f32::NAN,
+        mean: f32::NAN,
+        count: 0,
+    };
+
+    /// Folds a new data reading into the statistics
+    ///
+    /// NaN readings are ignored, as they would poison every statistic.
+    pub fn add(&mut self, value: f32) {
+        if value.is_nan() {
+            return;
+        }
+        if self.count == 0 {
+            *self = Self {
+                min: value,
+                max: value,
+                mean: value,
+                count: 1,
+            };
+        } else {
+            // Once the count saturates, the mean becomes a (very slow)
+            // moving average, which is fine.
+            self.count = self.count.saturating_add(1);
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+            self.mean += (value - self.mean) / self.count as f32;
+        }
+    }
+}
+
+/// Summary statistics over a window of time
+#[derive(
+    Copy, Clone, Debug, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+pub struct WindowStats {
+    pub stats: SensorStats,
+
+    /// Timestamp at which the window opened
+    pub start: u64,
+
+    /// Timestamp at which the window closed
+    pub end: u64,
+}
+
 //
 // Note that [`counter_encoding`] relies on [`NoData`] being numbered from 0 and
 // being numbered sequentially.
@@ -156,3 +214,395 @@ impl From<SensorApiError> for SensorError {
         }
     }
 }
+
+/// Alarm thresholds for a sensor
+///
+/// Each threshold is optional; a sensor with no thresholds never alarms.
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    PartialEq,
+    Serialize,
+    Deserialize,
+    SerializedSize,
+)]
+pub struct Thresholds {
+    pub lower_critical: Option<f32>,
+    pub lower_warning: Option<f32>,
+    pub upper_warning: Option<f32>,
+    pub upper_critical: Option<f32>,
+
+    /// Once a threshold has been crossed, the reading must move back past it
+    /// by this much before the alarm clears, to avoid flapping.
+    pub hysteresis: f32,
+}
+
+impl Thresholds {
+    /// Returns `true` if no threshold is set
+    pub fn is_empty(&self) -> bool {
+        self.lower_critical.is_none()
+            && self.lower_warning.is_none()
+            && self.upper_warning.is_none()
+            && self.upper_critical.is_none()
+    }
+
+    /// Checks that the thresholds are ordered from lower-critical up to
+    /// upper-critical, and that the hysteresis is not negative.
+    pub fn is_valid(&self) -> bool {
+        let mut prev = f32::NEG_INFINITY;
+        for t in [
+            self.lower_critical,
+            self.lower_warning,
+            self.upper_warning,
+            self.upper_critical,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if t.is_nan() || t < prev {
+                return false;
+            }
+            prev = t;
+        }
+        self.hysteresis >= 0.0
+    }
+
+    /// Returns the alarm for a new reading, given the previous alarm.
+    ///
+    /// A threshold is crossed when the reading reaches it; a threshold that
+    /// was crossed (per `prev`) stays crossed until the reading is
+    /// `hysteresis` back on the safe side of it.  Critical thresholds take
+    /// priority over warnings.
+    pub fn evaluate(&self, prev: Alarm, value: f32) -> Alarm {
+        let h = self.hysteresis;
+        let above = |t: Option<f32>, crossed: bool| {
+            t.map_or(
+                false,
+                |t| if crossed { value > t - h } else { value >= t },
+            )
+        };
+        let below = |t: Option<f32>, crossed: bool| {
+            t.map_or(
+                false,
+                |t| if crossed { value < t + h } else { value <= t },
+            )
+        };
+
+        if above(self.upper_critical, prev == Alarm::UpperCritical) {
+            Alarm::UpperCritical
+        } else if below(self.lower_critical, prev == Alarm::LowerCritical) {
+            Alarm::LowerCritical
+        } else if above(
+            self.upper_warning,
+            matches!(prev, Alarm::UpperWarning | Alarm::UpperCritical),
+        ) {
+            Alarm::UpperWarning
+        } else if below(
+            self.lower_warning,
+            matches!(prev, Alarm::LowerWarning | Alarm::LowerCritical),
+        ) {
+            Alarm::LowerWarning
+        } else {
+            Alarm::Normal
+        }
+    }
+}
+
+/// The most severe threshold that a sensor is currently past
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    FromPrimitive,
+    Eq,
+    PartialEq,
+    Serialize,
+    Deserialize,
+    SerializedSize,
+)]
+pub enum Alarm {
+    #[default]
+    Normal = 0,
+    LowerWarning = 1,
+    LowerCritical = 2,
+    UpperWarning = 3,
+    UpperCritical = 4,
+}
+
+/// A change in a sensor's alarm, as recorded in the sensor task's alarm log
+#[derive(
+    Copy, Clone, Debug, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+pub struct AlarmEvent {
+    pub sequence: u32,
+    pub id: SensorId,
+
+    /// The sensor's new alarm
+    pub alarm: Alarm,
+
+    /// The reading that changed the alarm
+    pub value: f32,
+    pub timestamp: u64,
+}
+
+/// Errors from the sensor alarm API
+#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
+pub enum AlarmError {
+    InvalidSensor = 1,
+    InvalidThresholds = 2,
+    /// Every slot for thresholds is in use
+    NoFreeSlot = 3,
+    /// The requested event is not (or no longer) in the alarm log
+    NoSuchEvent = 4,
+}
+
+/// Errors from the dynamic sensor API
+#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
+pub enum DynamicSensorError {
+    /// Every dynamic sensor is in use
+    NoFreeSensor = 1,
+    /// The sensor is not a dynamic sensor
+    InvalidSensor = 2,
+    /// The sensor is allocated to a different task
+    NotOwner = 3,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(values: &[f32]) -> SensorStats {
+        let mut s = SensorStats::EMPTY;
+        for &v in values {
+            s.add(v);
+        }
+        s
+    }
+
+    #[test]
+    fn stats_first_sample() {
+        let s = stats(&[]);
+        assert_eq!(s.count, 0);
+        assert!(s.min.is_nan() && s.max.is_nan() && s.mean.is_nan());
+
+        assert_eq!(
+            stats(&[-3.5]),
+            SensorStats {
+                min: -3.5,
+                max: -3.5,
+                mean: -3.5,
+                count: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn stats_min_max_count() {
+        assert_eq!(
+            stats(&[20.0, 25.0, 15.0, 20.0]),
+            SensorStats {
+                min: 15.0,
+                max: 25.0,
+                mean: 20.0,
+                count: 4,
+            }
+        );
+        assert_eq!(
+            stats(&[-1.0, -4.0, 2.0]),
+            SensorStats {
+                min: -4.0,
+                max: 2.0,
+                mean: -1.0,
+                count: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn stats_error_readings() {
+        // Read errors are reported with `nodata` and never reach `add`, but a
+        // NaN posted as a reading mustn't count or poison the statistics,
+        // whether or not it's the first.
+        let s = stats(&[f32::NAN]);
+        assert_eq!(s.count, 0);
+        assert!(s.min.is_nan() && s.max.is_nan() && s.mean.is_nan());
+
+        assert_eq!(
+            stats(&[f32::NAN, 10.0, f32::NAN, 30.0, f32::NAN]),
+            SensorStats {
+                min: 10.0,
+                max: 30.0,
+                mean: 20.0,
+                count: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn stats_count_saturates() {
+        let mut s = SensorStats {
+            min: 0.0,
+            max: 10.0,
+            mean: 5.0,
+            count: u32::MAX,
+        };
+        s.add(11.0);
+        assert_eq!(s.count, u32::MAX);
+        assert_eq!(s.max, 11.0);
+        // The new reading has (next to) no weight in the mean
+        assert!((s.mean - 5.0).abs() < 0.001);
+    }
+
+    const THRESHOLDS: Thresholds = Thresholds {
+        lower_critical: Some(0.0),
+        lower_warning: Some(10.0),
+        upper_warning: Some(80.0),
+        upper_critical: Some(90.0),
+        hysteresis: 2.0,
+    };
+
+    /// Feeds `values` through `evaluate` in turn, returning each alarm.
+    fn alarms<const N: usize>(t: &Thresholds, values: [f32; N]) -> [Alarm; N] {
+        let mut alarm = Alarm::Normal;
+        values.map(|v| {
+            alarm = t.evaluate(alarm, v);
+            alarm
+        })
+    }
+
+    #[test]
+    fn upper_warning() {
+        assert_eq!(
+            alarms(&THRESHOLDS, [50.0, 79.9, 80.0, 85.0, 79.0, 78.1, 78.0]),
+            [
+                Alarm::Normal,
+                Alarm::Normal,
+                // Reaching the threshold crosses it...
+                Alarm::UpperWarning,
+                Alarm::UpperWarning,
+                // ...and it stays crossed inside the hysteresis band...
+                Alarm::UpperWarning,
+                Alarm::UpperWarning,
+                // ...until the reading is back by the hysteresis.
+                Alarm::Normal,
+            ]
+        );
+    }
+
+    #[test]
+    fn lower_warning() {
+        assert_eq!(
+            alarms(&THRESHOLDS, [20.0, 10.0, 11.9, 12.0, 11.0]),
+            [
+                Alarm::Normal,
+                Alarm::LowerWarning,
+                Alarm::LowerWarning,
+                Alarm::Normal,
+                // Back inside the band, but not past the threshold
+                Alarm::Normal,
+            ]
+        );
+    }
+
+    #[test]
+    fn critical() {
+        assert_eq!(
+            alarms(&THRESHOLDS, [95.0, 89.0, 88.0, 79.0, 78.0]),
+            [
+                Alarm::UpperCritical,
+                Alarm::UpperCritical,
+                // Clearing a critical alarm can leave a warning
+                Alarm::UpperWarning,
+                Alarm::UpperWarning,
+                Alarm::Normal,
+            ]
+        );
+        assert_eq!(
+            alarms(&THRESHOLDS, [-5.0, 1.0, 2.0, 11.0, 12.0]),
+            [
+                Alarm::LowerCritical,
+                Alarm::LowerCritical,
+                Alarm::LowerWarning,
+                Alarm::LowerWarning,
+                Alarm::Normal,
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_thresholds() {
+        let t = Thresholds {
+            upper_critical: Some(90.0),
+            hysteresis: 2.0,
+            ..Default::default()
+        };
+        assert_eq!(
+            alarms(&t, [-1000.0, 85.0, 90.0, 88.5, 87.0]),
+            [
+                Alarm::Normal,
+                Alarm::Normal,
+                Alarm::UpperCritical,
+                Alarm::UpperCritical,
+                Alarm::Normal,
+            ]
+        );
+
+        let t = Thresholds::default();
+        assert!(t.is_empty());
+        assert!(t.is_valid());
+        assert_eq!(alarms(&t, [f32::MIN, f32::MAX]), [Alarm::Normal; 2]);
+    }
+
+    #[test]
+    fn validity() {
+        assert!(THRESHOLDS.is_valid());
+
+        // Thresholds may be equal, and unset ones are skipped
+        assert!(Thresholds {
+            lower_warning: Some(0.0),
+            upper_warning: Some(90.0),
+            ..THRESHOLDS
+        }
+        .is_valid());
+        assert!(Thresholds {
+            lower_warning: None,
+            upper_warning: None,
+            ..THRESHOLDS
+        }
+        .is_valid());
+
+        // Out of order
+        assert!(!Thresholds {
+            lower_warning: Some(-1.0),
+            ..THRESHOLDS
+        }
+        .is_valid());
+        assert!(!Thresholds {
+            upper_warning: Some(95.0),
+            ..THRESHOLDS
+        }
+        .is_valid());
+        assert!(!Thresholds {
+            lower_critical: Some(100.0),
+            lower_warning: None,
+            upper_warning: None,
+            ..THRESHOLDS
+        }
+        .is_valid());
+
+        // NaN thresholds and negative hysteresis
+        assert!(!Thresholds {
+            upper_warning: Some(f32::NAN),
+            ..THRESHOLDS
+        }
+        .is_valid());
+        assert!(!Thresholds {
+            hysteresis: -1.0,
+            ..THRESHOLDS
+        }
+        .is_valid());
+    }
+}
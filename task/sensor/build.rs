@@ -2,13 +2,156 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Config {
+    /// Number of threshold slots beyond those declared in the I2C config,
+    /// available for thresholds set at runtime
+    #[serde(default)]
+    spare_thresholds: usize,
+
+    /// Number of alarm events to keep in the alarm log
+    #[serde(default)]
+    alarm_log_depth: Option<usize>,
+
+    /// Tasks (and the notification that they want) to notify when a
+    /// sensor's alarm changes
+    #[serde(default)]
+    on_alarm: BTreeMap<String, String>,
+
+    /// Length of the window over which statistics are kept for the sensors
+    /// that ask for them (with `windowed` in the I2C config), at a cost of
+    /// 32 bytes of RAM per sensor
+    #[serde(default)]
+    stats_window_ms: Option<u64>,
+}
+
+/// Default depth of the alarm log, if any thresholds are possible
+const DEFAULT_ALARM_LOG_DEPTH: usize = 8;
+
+fn f32_option(v: Option<f32>) -> String {
+    match v {
+        Some(v) => format!("Some({v:?})"),
+        None => "None".to_string(),
+    }
+}
+
+fn main() -> Result<()> {
     build_util::expose_target_board();
     build_util::build_notifications()?;
     idol::server::build_server_support(
         "../../idl/sensor.idol",
         "server_stub.rs",
         idol::server::ServerStyle::InOrder,
+    )
+    .map_err(|e| anyhow::anyhow!("idol error: {e}"))?;
+
+    let cfg = build_util::task_maybe_config::<Config>()?.unwrap_or_default();
+
+    let mut thresholds = vec![];
+    let mut windowed = vec![];
+    for d in build_i2c::device_descriptions() {
+        let what = d.refdes.as_ref().or(d.name.as_ref()).unwrap_or(&d.device);
+        for kind in &d.windowed {
+            let before = windowed.len();
+            windowed.extend(
+                d.sensors.iter().filter(|s| s.kind == *kind).map(|s| s.id),
+            );
+            if windowed.len() == before {
+                bail!("{what} has {kind:?} windowed but no such sensor");
+            }
+        }
+        for (kind, t) in &d.thresholds {
+            t.validate().with_context(|| {
+                format!("bad {kind:?} thresholds on {what}")
+            })?;
+            let mut found = false;
+            for s in d.sensors.iter().filter(|s| s.kind == *kind) {
+                thresholds.push((s.id, t.clone()));
+                found = true;
+            }
+            if !found {
+                bail!("{what} has {kind:?} thresholds but no such sensor");
+            }
+        }
+    }
+
+    let slots = thresholds.len() + cfg.spare_thresholds;
+    let depth = match cfg.alarm_log_depth {
+        Some(depth) => depth,
+        None if slots > 0 => DEFAULT_ALARM_LOG_DEPTH,
+        None => 0,
+    };
+
+    let out_dir = build_util::out_dir();
+    let dest_path = out_dir.join("sensor_config.rs");
+    let mut out = std::fs::File::create(dest_path)
+        .context("creating sensor_config.rs")?;
+
+    writeln!(
+        out,
+        "pub(crate) const NUM_THRESHOLD_SLOTS: usize = {slots};"
+    )?;
+    writeln!(out, "pub(crate) const ALARM_LOG_DEPTH: usize = {depth};")?;
+
+    let window = match cfg.stats_window_ms {
+        Some(0) => bail!("stats-window-ms must be non-zero"),
+        Some(window) => window,
+        None if windowed.is_empty() => 0,
+        None => bail!("sensors are windowed, but stats-window-ms is not set"),
+    };
+    writeln!(out, "pub(crate) const STATS_WINDOW_MS: u64 = {window};")?;
+
+    let count = windowed.len();
+    writeln!(out, "pub(crate) const WINDOWED: [SensorId; {count}] = [")?;
+    for id in windowed {
+        writeln!(out, "    SensorId({id}),")?;
+    }
+    writeln!(out, "];")?;
+
+    let count = thresholds.len();
+    writeln!(
+        out,
+        "pub(crate) const THRESHOLDS: [(SensorId, Thresholds); {count}] = [",
     )?;
+    for (id, t) in thresholds {
+        writeln!(
+            out,
+            "    (SensorId({id}), Thresholds {{
+        lower_critical: {},
+        lower_warning: {},
+        upper_warning: {},
+        upper_critical: {},
+        hysteresis: {:?},
+    }}),",
+            f32_option(t.lower_critical),
+            f32_option(t.lower_warning),
+            f32_option(t.upper_warning),
+            f32_option(t.upper_critical),
+            t.hysteresis,
+        )?;
+    }
+    writeln!(out, "];")?;
+
+    let task = "hubris_num_tasks::Task";
+    let count = cfg.on_alarm.len();
+    writeln!(
+        out,
+        "pub(crate) const ALARM_SUBSCRIBERS: [({task}, u32); {count}] = [",
+    )?;
+    for (name, rec) in cfg.on_alarm {
+        writeln!(
+            out,
+            "    ({task}::{name}, crate::notifications::{name}::{}_MASK),",
+            rec.to_ascii_uppercase().replace('-', "_"),
+        )?;
+    }
+    writeln!(out, "];")?;
+
     Ok(())
 }
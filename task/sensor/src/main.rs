@@ -8,10 +8,21 @@
 #![no_main]
 
 use idol_runtime::{NotificationHandler, RequestError};
-use task_sensor_api::{NoData, Reading, SensorApiError, SensorError, SensorId};
+use task_sensor_api::{
+    Alarm, AlarmError, AlarmEvent, DynamicSensorError, NoData, Reading,
+    SensorApiError, SensorError, SensorId, SensorStats, Thresholds,
+    WindowStats,
+};
 use userlib::*;
 
-use task_sensor_api::config::NUM_SENSORS;
+use task_sensor_api::config::{
+    FIRST_DYNAMIC_SENSOR, NUM_DYNAMIC_SENSORS, NUM_SENSORS,
+};
+
+use config::{ALARM_LOG_DEPTH, NUM_THRESHOLD_SLOTS, STATS_WINDOW_MS};
+
+/// Windowed statistics are only kept for the sensors that ask for them
+const NUM_WINDOW_STATS: usize = config::WINDOWED.len();
 
 #[derive(Copy, Clone)]
 enum LastReading {
@@ -47,6 +58,149 @@ impl<T: 'static> SensorArray<T> {
     }
 }
 
+/// Alarm thresholds for one sensor, along with its current alarm
+///
+/// Few sensors have thresholds, so we keep these in a small table of slots
+/// rather than paying for them on every sensor.
+#[derive(Copy, Clone)]
+struct ThresholdSlot {
+    id: Option<SensorId>,
+    thresholds: Thresholds,
+    alarm: Alarm,
+}
+
+impl ThresholdSlot {
+    const EMPTY: Self = Self {
+        id: None,
+        thresholds: Thresholds {
+            lower_critical: None,
+            lower_warning: None,
+            upper_warning: None,
+            upper_critical: None,
+            hysteresis: 0.0,
+        },
+        alarm: Alarm::Normal,
+    };
+}
+
+/// The task that allocated a dynamic sensor, and the tag it allocated it with
+#[derive(Copy, Clone, PartialEq, Eq)]
+struct DynamicOwner {
+    task: u16,
+    tag: u32,
+}
+
+/// Statistics over consecutive windows of `STATS_WINDOW_MS`, for the sensors
+/// in `config::WINDOWED` (in that order)
+///
+/// Window boundaries fall on our timer tick, so are only as precise as
+/// `TIMER_INTERVAL`.
+struct StatsWindow {
+    /// Statistics for the window that is still open
+    current: &'static mut [SensorStats; NUM_WINDOW_STATS],
+
+    /// Statistics for the most recently completed window
+    last: &'static mut [SensorStats; NUM_WINDOW_STATS],
+
+    /// When the current window opened
+    start: u64,
+
+    /// When the last window opened and closed, if there has been one
+    last_span: Option<(u64, u64)>,
+}
+
+impl StatsWindow {
+    fn index(id: SensorId) -> Option<usize> {
+        config::WINDOWED.iter().position(|&w| w == id)
+    }
+
+    fn add(&mut self, id: SensorId, value: f32) {
+        if let Some(i) = Self::index(id) {
+            self.current[i].add(value);
+        }
+    }
+
+    fn clear(&mut self, id: SensorId) {
+        if let Some(i) = Self::index(id) {
+            self.current[i] = SensorStats::EMPTY;
+            self.last[i] = SensorStats::EMPTY;
+        }
+    }
+
+    /// Closes the current window if it has run its length, opening another
+    fn tick(&mut self, now: u64) {
+        if STATS_WINDOW_MS == 0 || now < self.start + STATS_WINDOW_MS {
+            return;
+        }
+        for (last, current) in self.last.iter_mut().zip(self.current.iter_mut())
+        {
+            *last = core::mem::replace(current, SensorStats::EMPTY);
+        }
+        self.last_span = Some((self.start, now));
+        self.start = now;
+    }
+
+    fn get(&self, id: SensorId) -> Option<WindowStats> {
+        let (start, end) = self.last_span?;
+        Some(WindowStats {
+            stats: self.last[Self::index(id)?],
+            start,
+            end,
+        })
+    }
+}
+
+/// Circular log of alarm changes, indexed by sequence number
+struct AlarmLog {
+    events: &'static mut [AlarmEvent; ALARM_LOG_DEPTH],
+    next: u32,
+
+    /// Number of events in `events`, saturating at `ALARM_LOG_DEPTH`
+    len: usize,
+}
+
+impl AlarmLog {
+    fn record(
+        &mut self,
+        id: SensorId,
+        alarm: Alarm,
+        value: f32,
+        timestamp: u64,
+    ) {
+        let sequence = self.next;
+        self.next = self.next.wrapping_add(1);
+
+        if let Some(i) = (sequence as usize).checked_rem(ALARM_LOG_DEPTH) {
+            self.len = (self.len + 1).min(ALARM_LOG_DEPTH);
+            self.events[i] = AlarmEvent {
+                sequence,
+                id,
+                alarm,
+                value,
+                timestamp,
+            };
+        }
+
+        for (task, mask) in config::ALARM_SUBSCRIBERS {
+            let taskid =
+                TaskId::for_index_and_gen(task as usize, Generation::ZERO);
+            let taskid = sys_refresh_task_id(taskid);
+            sys_post(taskid, mask);
+        }
+    }
+
+    fn get(&self, sequence: u32) -> Option<AlarmEvent> {
+        // Only the most recent `len` events are present; anything older has
+        // been overwritten, and anything newer hasn't happened yet.
+        let age = self.next.wrapping_sub(sequence) as usize;
+        if age == 0 || age > self.len {
+            return None;
+        }
+        let i = (sequence as usize).checked_rem(ALARM_LOG_DEPTH)?;
+        Some(self.events[i])
+    }
+}
+
 struct ServerImpl {
     // We're using structure-of-arrays packing here because otherwise padding
     // eats up a considerable amount of RAM; for example, Sidecar goes from 2868
@@ -63,6 +217,16 @@ struct ServerImpl {
     err_time: SensorArray<u64>,
 
     nerrors: SensorArray<u32>,
+
+    stats: SensorArray<SensorStats>,
+    window: StatsWindow,
+
+    thresholds: &'static mut [ThresholdSlot; NUM_THRESHOLD_SLOTS],
+    alarms: AlarmLog,
+
+    /// Owners of dynamic sensors, indexed from `FIRST_DYNAMIC_SENSOR`
+    dynamic: &'static mut [Option<DynamicOwner>; NUM_DYNAMIC_SENSORS],
+
     deadline: u64,
 }
 
@@ -153,6 +317,18 @@ impl idl::InOrderSensorImpl for ServerImpl {
         self.last_reading[id] = Some(r);
         self.data_value[id] = value;
         self.data_time[id] = timestamp;
+        self.stats[id].add(value);
+        self.window.add(id, value);
+
+        if let Some(slot) =
+            self.thresholds.iter_mut().find(|s| s.id == Some(id))
+        {
+            let alarm = slot.thresholds.evaluate(slot.alarm, value);
+            if alarm != slot.alarm {
+                slot.alarm = alarm;
+                self.alarms.record(id, alarm, value, timestamp);
+            }
+        }
         Ok(())
     }
 
@@ -203,6 +379,174 @@ impl idl::InOrderSensorImpl for ServerImpl {
             .cloned()
             .ok_or_else(|| SensorApiError::InvalidSensor.into())
     }
+
+    fn get_stats(
+        &mut self,
+        _: &RecvMessage,
+        id: SensorId,
+    ) -> Result<SensorStats, RequestError<SensorApiError>> {
+        match self.stats.get(id) {
+            Some(stats) if stats.count > 0 => Ok(*stats),
+            Some(_) => Err(SensorApiError::NoReading.into()),
+            None => Err(SensorApiError::InvalidSensor.into()),
+        }
+    }
+
+    fn get_window_stats(
+        &mut self,
+        _: &RecvMessage,
+        id: SensorId,
+    ) -> Result<WindowStats, RequestError<SensorApiError>> {
+        if self.stats.get(id).is_none() {
+            return Err(SensorApiError::InvalidSensor.into());
+        }
+        match self.window.get(id) {
+            Some(w) if w.stats.count > 0 => Ok(w),
+            _ => Err(SensorApiError::NoReading.into()),
+        }
+    }
+
+    fn get_thresholds(
+        &mut self,
+        _: &RecvMessage,
+        id: SensorId,
+    ) -> Result<Thresholds, RequestError<AlarmError>> {
+        Ok(self
+            .threshold_slot(id)?
+            .map(|s| s.thresholds)
+            .unwrap_or_default())
+    }
+
+    fn set_thresholds(
+        &mut self,
+        _: &RecvMessage,
+        id: SensorId,
+        thresholds: Thresholds,
+    ) -> Result<(), RequestError<AlarmError>> {
+        if !thresholds.is_valid() {
+            return Err(AlarmError::InvalidThresholds.into());
+        }
+
+        if self.last_reading.get(id).is_none() {
+            return Err(AlarmError::InvalidSensor.into());
+        }
+        let index = match self
+            .thresholds
+            .iter()
+            .position(|s| s.id == Some(id))
+            .or_else(|| self.thresholds.iter().position(|s| s.id.is_none()))
+        {
+            Some(index) => index,
+            None => return Err(AlarmError::NoFreeSlot.into()),
+        };
+        let slot = &mut self.thresholds[index];
+        if slot.id.is_none() && thresholds.is_empty() {
+            return Ok(());
+        }
+
+        // Work out the alarm under the new thresholds from the most recent
+        // data, if that is the most recent reading.
+        let value = self.data_value[id];
+        let alarm = match self.last_reading[id] {
+            _ if thresholds.is_empty() => Alarm::Normal,
+            Some(LastReading::Data | LastReading::DataOnly) => {
+                thresholds.evaluate(slot.alarm, value)
+            }
+            _ => slot.alarm,
+        };
+        let changed = alarm != slot.alarm;
+
+        *slot = ThresholdSlot {
+            id: (!thresholds.is_empty()).then_some(id),
+            thresholds,
+            alarm,
+        };
+        if changed {
+            self.alarms.record(id, alarm, value, sys_get_timer().now);
+        }
+        Ok(())
+    }
+
+    fn get_alarm(
+        &mut self,
+        _: &RecvMessage,
+        id: SensorId,
+    ) -> Result<Alarm, RequestError<AlarmError>> {
+        Ok(self
+            .threshold_slot(id)?
+            .map(|s| s.alarm)
+            .unwrap_or_default())
+    }
+
+    fn get_next_alarm_sequence(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<u32, RequestError<core::convert::Infallible>> {
+        Ok(self.alarms.next)
+    }
+
+    fn get_alarm_event(
+        &mut self,
+        _: &RecvMessage,
+        sequence: u32,
+    ) -> Result<AlarmEvent, RequestError<AlarmError>> {
+        self.alarms
+            .get(sequence)
+            .ok_or_else(|| AlarmError::NoSuchEvent.into())
+    }
+
+    fn allocate(
+        &mut self,
+        msg: &RecvMessage,
+        tag: u32,
+    ) -> Result<SensorId, RequestError<DynamicSensorError>> {
+        let owner = DynamicOwner {
+            task: msg.sender.index() as u16,
+            tag,
+        };
+
+        // A task that restarts will allocate its sensors again; give it
+        // back the ones it had rather than leaking them.
+        if let Some(i) = self.dynamic.iter().position(|o| *o == Some(owner)) {
+            return Ok(dynamic_sensor(i));
+        }
+
+        let i = match self.dynamic.iter().position(Option::is_none) {
+            Some(i) => i,
+            None => return Err(DynamicSensorError::NoFreeSensor.into()),
+        };
+        self.dynamic[i] = Some(owner);
+
+        let id = dynamic_sensor(i);
+        self.clear(id);
+        Ok(id)
+    }
+
+    fn release(
+        &mut self,
+        msg: &RecvMessage,
+        id: SensorId,
+    ) -> Result<(), RequestError<DynamicSensorError>> {
+        let i =
+            id.0.checked_sub(FIRST_DYNAMIC_SENSOR.0)
+                .map(|i| i as usize)
+                .filter(|&i| i < NUM_DYNAMIC_SENSORS)
+                .ok_or(DynamicSensorError::InvalidSensor)?;
+
+        match self.dynamic[i] {
+            None => Ok(()),
+            Some(o) if o.task == msg.sender.index() as u16 => {
+                self.dynamic[i] = None;
+                self.clear(id);
+                Ok(())
+            }
+            Some(_) => Err(DynamicSensorError::NotOwner.into()),
+        }
+    }
+}
+
+fn dynamic_sensor(index: usize) -> SensorId {
+    SensorId(FIRST_DYNAMIC_SENSOR.0 + index as u32)
 }
 
 impl ServerImpl {
@@ -216,6 +560,35 @@ impl ServerImpl {
             .cloned()
             .ok_or(SensorApiError::InvalidSensor)
     }
+
+    /// Forgets everything about a sensor, as if it had just been allocated
+    fn clear(&mut self, id: SensorId) {
+        self.last_reading[id] = None;
+        self.data_value[id] = f32::NAN;
+        self.data_time[id] = 0;
+        self.err_value[id] = NoData::DeviceUnavailable;
+        self.err_time[id] = 0;
+        self.nerrors[id] = 0;
+        self.stats[id] = SensorStats::EMPTY;
+        self.window.clear(id);
+
+        if let Some(slot) =
+            self.thresholds.iter_mut().find(|s| s.id == Some(id))
+        {
+            *slot = ThresholdSlot::EMPTY;
+        }
+    }
+
+    /// Returns the threshold slot for the given sensor, if it has one
+    fn threshold_slot(
+        &self,
+        id: SensorId,
+    ) -> Result<Option<&ThresholdSlot>, AlarmError> {
+        if self.last_reading.get(id).is_none() {
+            return Err(AlarmError::InvalidSensor);
+        }
+        Ok(self.thresholds.iter().find(|s| s.id == Some(id)))
+    }
 }
 
 impl NotificationHandler for ServerImpl {
@@ -224,6 +597,7 @@ impl NotificationHandler for ServerImpl {
     }
 
     fn handle_notification(&mut self, _bits: u32) {
+        self.window.tick(sys_get_timer().now);
         self.deadline += TIMER_INTERVAL;
         sys_set_timer(Some(self.deadline), notifications::TIMER_MASK);
     }
@@ -247,6 +621,38 @@ fn main() -> ! {
         static mut NERRORS: [u32; NUM_SENSORS] = [|| 0; _];
     };
 
+    let (stats, window_current, window_last) = mutable_statics::mutable_statics! {
+        static mut STATS: [SensorStats; NUM_SENSORS] = [|| SensorStats::EMPTY; _];
+        static mut WINDOW_CURRENT: [SensorStats; NUM_WINDOW_STATS] =
+            [|| SensorStats::EMPTY; _];
+        static mut WINDOW_LAST: [SensorStats; NUM_WINDOW_STATS] =
+            [|| SensorStats::EMPTY; _];
+    };
+
+    let (thresholds, alarm_events) = mutable_statics::mutable_statics! {
+        static mut THRESHOLDS: [ThresholdSlot; NUM_THRESHOLD_SLOTS] =
+            [|| ThresholdSlot::EMPTY; _];
+        static mut ALARM_EVENTS: [AlarmEvent; ALARM_LOG_DEPTH] = [|| AlarmEvent {
+            sequence: 0,
+            id: SensorId(0),
+            alarm: Alarm::Normal,
+            value: f32::NAN,
+            timestamp: 0,
+        }; _];
+    };
+
+    let dynamic = mutable_statics::mutable_statics! {
+        static mut DYNAMIC: [Option<DynamicOwner>; NUM_DYNAMIC_SENSORS] =
+            [|| None; _];
+    };
+
+    for (slot, &(id, thresholds)) in
+        thresholds.iter_mut().zip(&config::THRESHOLDS)
+    {
+        slot.id = Some(id);
+        slot.thresholds = thresholds;
+    }
+
     let mut server = ServerImpl {
         last_reading: SensorArray(last_reading),
         data_value: SensorArray(data_value),
@@ -254,6 +660,20 @@ fn main() -> ! {
         err_value: SensorArray(err_value),
         err_time: SensorArray(err_time),
         nerrors: SensorArray(nerrors),
+        stats: SensorArray(stats),
+        window: StatsWindow {
+            current: window_current,
+            last: window_last,
+            start: deadline,
+            last_span: None,
+        },
+        thresholds,
+        alarms: AlarmLog {
+            events: alarm_events,
+            next: 0,
+            len: 0,
+        },
+        dynamic,
         deadline,
     };
 
@@ -265,9 +685,19 @@ fn main() -> ! {
 }
 
 mod idl {
-    use super::{NoData, Reading, SensorApiError, SensorError, SensorId};
+    use super::{
+        Alarm, AlarmError, AlarmEvent, DynamicSensorError, NoData, Reading,
+        SensorApiError, SensorError, SensorId, SensorStats, Thresholds,
+        WindowStats,
+    };
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
 
+mod config {
+    use task_sensor_api::{SensorId, Thresholds};
+
+    include!(concat!(env!("OUT_DIR"), "/sensor_config.rs"));
+}
+
 include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
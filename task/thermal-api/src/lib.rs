@@ -74,6 +74,61 @@ pub enum ThermalAutoState {
     Uncontrollable,
 }
 
+/// State of a single zone when running in automatic mode
+///
+/// Each zone runs its own control loop, driving its fans based on its own
+/// inputs.  The `Boot` and `Uncontrollable` states apply to the system as a
+/// whole, so every zone reports them together.
+#[derive(
+    Copy, Clone, Debug, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+pub struct ThermalZoneState {
+    pub state: ThermalAutoState,
+
+    /// Most recent PWM duty cycle for this zone's fans, as a percentage
+    pub pwm: u8,
+
+    /// Smallest margin among this zone's inputs, in degrees Celsius; this is
+    /// `f32::MAX` if none of its inputs are active.
+    pub worst_margin: f32,
+}
+
+/// Health of a fan, judged by comparing its speed to its PWM duty cycle
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    FromPrimitive,
+    Eq,
+    PartialEq,
+    Serialize,
+    Deserialize,
+    SerializedSize,
+)]
+pub enum FanHealth {
+    /// The fan is spinning about as fast as expected, or we haven't been able
+    /// to tell otherwise
+    Ok,
+    /// The fan isn't spinning, despite being driven
+    Stalled,
+    /// The fan is spinning well below the expected speed
+    Degraded,
+}
+
+/// State of a single fan, as returned by `get_fan_status`
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+pub struct FanStatus {
+    /// Whether the fan is present; absent fans are not driven
+    pub present: bool,
+    pub health: FanHealth,
+    /// Most recent PWM duty cycle for the fan, as a percentage
+    pub pwm: u8,
+    /// Most recent speed reading, if the last attempt to read it succeeded
+    pub rpm: Option<u16>,
+}
+
 /// Properties for a particular part in the system
 #[derive(Clone, Copy, AsBytes, FromBytes)]
 #[repr(C)]
@@ -2,7 +2,13 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+use anyhow::{anyhow, bail, Context, Result};
+use build_i2c::{I2cDeviceDescription, Sensor};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+fn main() -> Result<()> {
     build_util::expose_target_board();
     build_util::build_notifications()?;
     build_i2c::codegen(build_i2c::Disposition::Sensors)?;
@@ -11,6 +17,545 @@ fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         "../../idl/thermal.idol",
         "server_stub.rs",
         idol::server::ServerStyle::InOrder,
+    )
+    .map_err(|e| anyhow!("{e}"))?;
+
+    let cfg = build_util::config::<GlobalConfig>()?.thermal;
+    let devices = build_i2c::device_descriptions().collect::<Vec<_>>();
+
+    let out_dir = build_util::out_dir();
+    let dest_path = out_dir.join("thermal_config.rs");
+    let mut out = std::fs::File::create(dest_path)
+        .context("creating thermal_config.rs")?;
+
+    write_config(&mut out, &cfg, &devices).context("in config.thermal")?;
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct GlobalConfig {
+    thermal: ThermalConfig,
+}
+
+/// Board-level description of the thermal control loop, from the
+/// `[config.thermal]` section of app.toml.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct ThermalConfig {
+    /// Default tuning for the PID controller
+    pid: PidConfig,
+    /// Thermal models, by name; inputs refer to these
+    models: BTreeMap<String, ThermalModel>,
+    /// Temperature sensors that drive the control loop, in the order in which
+    /// they are read
+    inputs: Vec<Input>,
+    /// Temperature sensors that are read and logged, but not used for control
+    #[serde(default)]
+    misc_sensors: Vec<SensorRef>,
+    /// Fan controllers, and the fans on each; fans are numbered in the order
+    /// in which they appear here
+    fan_controllers: Vec<FanController>,
+    /// Airflow zones, each of which is controlled independently.  If this is
+    /// empty, every input and fan is in a single zone.
+    #[serde(default)]
+    zones: Vec<Zone>,
+    /// Thresholds for detecting failed fans
+    #[serde(default)]
+    fan_health: FanHealthConfig,
+}
+
+/// How to decide whether a fan has failed, and what to do about it
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct FanHealthConfig {
+    /// Below this PWM duty cycle (as a percentage), fans may legitimately
+    /// stop, so we don't judge them
+    #[serde(default = "FanHealthConfig::default_min_pwm")]
+    min_pwm: u8,
+    /// Speed of the fans at 100% PWM; if given, a fan that is well below the
+    /// speed expected for its PWM is considered degraded
+    max_rpm: Option<u16>,
+    /// A fan spinning slower than this percentage of its expected speed is
+    /// degraded
+    #[serde(default = "FanHealthConfig::default_degraded_percent")]
+    degraded_percent: u8,
+    /// PWM percentage added to the other fans in a zone with a failed fan
+    #[serde(default = "FanHealthConfig::default_boost")]
+    boost: u8,
+}
+
+impl FanHealthConfig {
+    fn default_min_pwm() -> u8 {
+        20
+    }
+    fn default_degraded_percent() -> u8 {
+        50
+    }
+    fn default_boost() -> u8 {
+        25
+    }
+}
+
+impl Default for FanHealthConfig {
+    fn default() -> Self {
+        Self {
+            min_pwm: Self::default_min_pwm(),
+            max_rpm: None,
+            degraded_percent: Self::default_degraded_percent(),
+            boost: Self::default_boost(),
+        }
+    }
+}
+
+/// A set of fans which cools a set of inputs
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Zone {
+    /// Name of the zone; inputs refer to this
+    name: String,
+    /// Fans in this zone, by fan number
+    fans: Vec<usize>,
+    /// Whether the BSP's dynamic inputs are in this zone
+    #[serde(default)]
+    dynamic_inputs: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct PidConfig {
+    zero: f32,
+    gain_p: f32,
+    gain_i: f32,
+    gain_d: f32,
+}
+
+/// Thermal properties of a part, in degrees Celsius; see
+/// `task_thermal_api::ThermalProperties`.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct ThermalModel {
+    target: f32,
+    critical: f32,
+    power_down: f32,
+    /// Maximum rate of temperature change, in degrees per second
+    slew: f32,
+}
+
+impl ThermalModel {
+    fn validate(&self) -> Result<()> {
+        if self.target > self.critical || self.critical > self.power_down {
+            bail!(
+                "temperatures must satisfy target ({}) <= critical ({}) <= \
+                 power-down ({})",
+                self.target,
+                self.critical,
+                self.power_down
+            );
+        }
+        if self.slew.is_nan() || self.slew <= 0.0 {
+            bail!("slew must be positive");
+        }
+        Ok(())
+    }
+}
+
+/// A reference to an I2C device in `config.i2c.devices`, by reference
+/// designator or (for devices without one) by name.  `device` may be omitted
+/// if the refdes or name is unambiguous.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct SensorRef {
+    device: Option<String>,
+    refdes: Option<String>,
+    name: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Input {
+    device: Option<String>,
+    refdes: Option<String>,
+    name: Option<String>,
+    /// Name of the thermal model in `models`
+    model: String,
+    /// Name of the BSP's `PowerBitmask` constant for the power states in
+    /// which this sensor is read
+    power: String,
+    /// How to treat a missing sensor; defaults to `removable` for devices
+    /// marked as removable, and `must-be-present` otherwise
+    channel: Option<ChannelType>,
+    /// Names of the zones cooling this sensor, which must be given if any
+    /// zones are configured
+    #[serde(default)]
+    zones: Vec<String>,
+}
+
+impl Input {
+    fn sensor(&self) -> SensorRef {
+        SensorRef {
+            device: self.device.clone(),
+            refdes: self.refdes.clone(),
+            name: self.name.clone(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum ChannelType {
+    MustBePresent,
+    Removable,
+    RemovableAndErrorProne,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct FanController {
+    device: String,
+    refdes: Option<String>,
+    name: Option<String>,
+    /// Controller channel driving each of our fans, in fan order
+    fans: Vec<u8>,
+}
+
+impl SensorRef {
+    fn describe(&self) -> String {
+        let what = match (&self.refdes, &self.name) {
+            (Some(refdes), _) => format!("refdes {refdes}"),
+            (None, Some(name)) => format!("name {name}"),
+            (None, None) => "no refdes or name".to_string(),
+        };
+        match &self.device {
+            Some(device) => format!("{device} with {what}"),
+            None => format!("device with {what}"),
+        }
+    }
+
+    /// Finds the one device in `devices` that this refers to.
+    fn find<'a>(
+        &self,
+        devices: &'a [I2cDeviceDescription],
+    ) -> Result<&'a I2cDeviceDescription> {
+        if self.refdes.is_none() && self.name.is_none() {
+            bail!("sensor must have a refdes or a name");
+        }
+        let mut found = devices.iter().filter(|d| {
+            self.device.as_ref().map_or(true, |dev| *dev == d.device)
+                && match (&self.refdes, &self.name) {
+                    (Some(refdes), _) => d.refdes.as_ref() == Some(refdes),
+                    (None, name) => d.name.as_ref() == name.as_ref(),
+                }
+        });
+        let Some(d) = found.next() else {
+            bail!("no I2C {} in config.i2c.devices", self.describe());
+        };
+        if found.next().is_some() {
+            bail!(
+                "more than one I2C {} in config.i2c.devices; specify `device`",
+                self.describe()
+            );
+        }
+        Ok(d)
+    }
+
+    /// Returns the name of the function in `i2c_config::devices` that builds
+    /// `d`, which must have been found with `find`.
+    fn builder(&self, d: &I2cDeviceDescription) -> String {
+        // Devices are named by refdes in preference to name, and `find` makes
+        // sure that we have one of the two.
+        let suffix = self.refdes.as_ref().or(self.name.as_ref()).unwrap();
+        format!("{}_{}", d.device, suffix.to_lowercase())
+    }
+}
+
+/// Returns the IDs of the sensors of kind `kind` on device `d`, in order.
+fn sensor_ids(d: &I2cDeviceDescription, kind: Sensor) -> Vec<usize> {
+    d.sensors
+        .iter()
+        .filter(|s| s.kind == kind)
+        .map(|s| s.id)
+        .collect()
+}
+
+/// Returns the mask of zones named in `names`, for an input.
+fn input_zones(cfg: &ThermalConfig, names: &[String]) -> Result<u32> {
+    if cfg.zones.is_empty() {
+        if !names.is_empty() {
+            bail!("zones are given, but none are configured");
+        }
+        return Ok(1);
+    }
+    if names.is_empty() {
+        bail!("must be in at least one zone");
+    }
+    let mut mask = 0;
+    for name in names {
+        let Some(i) = cfg.zones.iter().position(|z| z.name == *name) else {
+            bail!("unknown zone {name}");
+        };
+        mask |= 1 << i;
+    }
+    Ok(mask)
+}
+
+/// Returns the index of the zone containing each fan, checking that every
+/// fan is in exactly one zone.
+fn fan_zones(cfg: &ThermalConfig, num_fans: usize) -> Result<Vec<usize>> {
+    if cfg.zones.is_empty() {
+        return Ok(vec![0; num_fans]);
+    }
+    let mut out = vec![None; num_fans];
+    for (i, z) in cfg.zones.iter().enumerate() {
+        if z.fans.is_empty() {
+            bail!("zone {} has no fans", z.name);
+        }
+        for &fan in &z.fans {
+            match out.get_mut(fan) {
+                None => bail!("zone {} has unknown fan {fan}", z.name),
+                Some(Some(prev)) => bail!(
+                    "fan {fan} is in zones {} and {}",
+                    cfg.zones[*prev].name,
+                    z.name
+                ),
+                Some(f) => *f = Some(i),
+            }
+        }
+    }
+    out.into_iter()
+        .enumerate()
+        .map(|(fan, z)| z.ok_or_else(|| anyhow!("fan {fan} is not in a zone")))
+        .collect()
+}
+
+/// Returns the `control::Device` variant used to read temperatures from an
+/// I2C device of the given type.
+fn device_kind(device: &str) -> Result<&'static str> {
+    Ok(match device {
+        "tmp117" => "Tmp117",
+        "tmp451" => "Tmp451(drv_i2c_devices::tmp451::Target::Remote)",
+        "sbtsi" => "Sbtsi",
+        "tse2004av" => "Tse2004Av",
+        "nvme_bmc" | "m2_hp_only" => "NvmeBmc",
+        _ => bail!("thermal can't read temperatures from a {device}"),
+    })
+}
+
+/// Emits the `TemperatureSensor` for `sensor`, validating it against the I2C
+/// configuration.  Returns it, along with the device it refers to.
+fn temperature_sensor<'a>(
+    sensor: &SensorRef,
+    devices: &'a [I2cDeviceDescription],
+) -> Result<(String, &'a I2cDeviceDescription)> {
+    let d = sensor.find(devices)?;
+    let kind = device_kind(&d.device)?;
+    let id = match sensor_ids(d, Sensor::Temperature)[..] {
+        [id] => id,
+        [] => bail!("{} has no temperature sensor", sensor.describe()),
+        _ => bail!("{} has multiple temperature sensors", sensor.describe()),
+    };
+    let s = format!(
+        "TemperatureSensor::new(
+        Device::{kind},
+        devices::{},
+        SensorId({id}),
+    )",
+        sensor.builder(d)
+    );
+    Ok((s, d))
+}
+
+fn write_config(
+    out: &mut std::fs::File,
+    cfg: &ThermalConfig,
+    devices: &[I2cDeviceDescription],
+) -> Result<()> {
+    writeln!(
+        out,
+        "use crate::{{
+    bsp::PowerBitmask,
+    control::{{
+        ChannelType, Device, FanHealthConfig, InputChannel, PidConfig,
+        TemperatureSensor, ZoneMask,
+    }},
+    i2c_config::devices,
+}};
+use drv_i2c_api::I2cDevice;
+use task_sensor_api::SensorId;
+use task_thermal_api::ThermalProperties;
+use userlib::{{units::Celsius, TaskId}};
+"
+    )?;
+
+    let pid = &cfg.pid;
+    writeln!(
+        out,
+        "pub const PID_CONFIG: PidConfig = PidConfig {{
+    zero: {:?},
+    gain_p: {:?},
+    gain_i: {:?},
+    gain_d: {:?},
+}};",
+        pid.zero, pid.gain_p, pid.gain_i, pid.gain_d,
+    )?;
+
+    for (name, model) in &cfg.models {
+        model
+            .validate()
+            .with_context(|| format!("thermal model {name}"))?;
+    }
+
+    // Without any zones configured, everything is in one implicit zone.
+    let num_zones = cfg.zones.len().max(1);
+    if num_zones > 32 {
+        bail!("at most 32 zones are supported");
+    }
+    writeln!(out, "pub const NUM_ZONES: usize = {num_zones};")?;
+
+    let n = cfg.inputs.len();
+    writeln!(out, "pub const NUM_TEMPERATURE_INPUTS: usize = {n};")?;
+    writeln!(out, "pub const INPUTS: [InputChannel; {n}] = [")?;
+    for input in &cfg.inputs {
+        let sensor = input.sensor();
+        let (s, d) = temperature_sensor(&sensor, devices)
+            .with_context(|| format!("input {}", sensor.describe()))?;
+        let model = cfg.models.get(&input.model).ok_or_else(|| {
+            anyhow!(
+                "input {} uses unknown model {}",
+                sensor.describe(),
+                input.model
+            )
+        })?;
+        let channel = match input.channel {
+            Some(c) => c,
+            None if d.removable => ChannelType::Removable,
+            None => ChannelType::MustBePresent,
+        };
+        let channel = match channel {
+            ChannelType::MustBePresent => "MustBePresent",
+            ChannelType::Removable => "Removable",
+            ChannelType::RemovableAndErrorProne => "RemovableAndErrorProne",
+        };
+        let zones = input_zones(cfg, &input.zones)
+            .with_context(|| format!("input {}", sensor.describe()))?;
+        writeln!(
+            out,
+            "    // {}
+    InputChannel::new(
+        {s},
+        ThermalProperties {{
+            target_temperature: Celsius({:?}),
+            critical_temperature: Celsius({:?}),
+            power_down_temperature: Celsius({:?}),
+            temperature_slew_deg_per_sec: {:?},
+        }},
+        PowerBitmask::{},
+        ChannelType::{channel},
+        ZoneMask({zones:#b}),
+    ),",
+            d.description,
+            model.target,
+            model.critical,
+            model.power_down,
+            model.slew,
+            input.power,
+        )?;
+    }
+    writeln!(out, "];")?;
+
+    let n = cfg.misc_sensors.len();
+    writeln!(out, "pub const MISC_SENSORS: [TemperatureSensor; {n}] = [")?;
+    for sensor in &cfg.misc_sensors {
+        let (s, d) = temperature_sensor(sensor, devices)
+            .with_context(|| format!("misc sensor {}", sensor.describe()))?;
+        writeln!(out, "    // {}\n    {s},", d.description)?;
+    }
+    writeln!(out, "];")?;
+
+    let mut builders = vec![];
+    let mut fans = vec![];
+    for (i, c) in cfg.fan_controllers.iter().enumerate() {
+        let sensor = SensorRef {
+            device: Some(c.device.clone()),
+            refdes: c.refdes.clone(),
+            name: c.name.clone(),
+        };
+        let d = sensor.find(devices).context("fan controller")?;
+        if d.device != "max31790" {
+            bail!("unsupported fan controller {}", d.device);
+        }
+        let speeds = sensor_ids(d, Sensor::Speed);
+        for &channel in &c.fans {
+            let Some(id) = speeds.get(usize::from(channel)) else {
+                bail!(
+                    "{} has no speed sensor for channel {channel}",
+                    sensor.describe()
+                );
+            };
+            fans.push((i, channel, *id));
+        }
+        builders.push(sensor.builder(d));
+    }
+
+    writeln!(
+        out,
+        "pub const NUM_FAN_CONTROLLERS: usize = {};
+pub const FAN_CONTROLLERS: [fn(TaskId) -> I2cDevice; NUM_FAN_CONTROLLERS] = [",
+        builders.len()
+    )?;
+    for b in &builders {
+        writeln!(out, "    devices::{b},")?;
+    }
+    writeln!(out, "];")?;
+
+    writeln!(
+        out,
+        "pub const NUM_FANS: usize = {};
+
+/// For each fan, the index of its controller in `FAN_CONTROLLERS`, the
+/// controller channel that drives it, and its speed sensor.
+pub const FANS: [(usize, u8, SensorId); NUM_FANS] = [",
+        fans.len()
+    )?;
+    for (controller, channel, id) in &fans {
+        writeln!(out, "    ({controller}, {channel}, SensorId({id})),")?;
+    }
+    writeln!(out, "];")?;
+
+    let health = &cfg.fan_health;
+    if health.min_pwm > 100 || health.degraded_percent > 100 {
+        bail!("fan-health percentages must be at most 100");
+    }
+    writeln!(
+        out,
+        "pub const FAN_HEALTH: FanHealthConfig = FanHealthConfig {{
+    min_pwm: {},
+    max_rpm: {:?},
+    degraded_percent: {},
+    boost: {},
+}};",
+        health.min_pwm, health.max_rpm, health.degraded_percent, health.boost,
+    )?;
+
+    let fan_zones = fan_zones(cfg, fans.len())?;
+    writeln!(
+        out,
+        "/// Index of the zone containing each fan
+pub const FAN_ZONES: [usize; NUM_FANS] = {fan_zones:?};"
+    )?;
+
+    let dynamic_zones = if cfg.zones.is_empty() {
+        1
+    } else {
+        cfg.zones
+            .iter()
+            .enumerate()
+            .filter(|(_, z)| z.dynamic_inputs)
+            .fold(0u32, |mask, (i, _)| mask | 1 << i)
+    };
+    writeln!(
+        out,
+        "pub const DYNAMIC_INPUT_ZONES: ZoneMask = ZoneMask({dynamic_zones:#b});"
     )?;
 
     Ok(())
@@ -4,71 +4,33 @@
 
 //! BSP for the Gimlet rev B hardware
 
-use crate::{
-    control::{
-        ChannelType, Device, FanControl, Fans, InputChannel, PidConfig,
-        TemperatureSensor,
-    },
-    i2c_config::{devices, sensors},
-};
-use core::convert::TryInto;
+use crate::{config, control::Fans, i2c_config::devices};
 pub use drv_gimlet_seq_api::SeqError;
 use drv_gimlet_seq_api::{PowerState, Sequencer};
-use drv_i2c_devices::max31790::Max31790;
+use task_packrat_api::{HostAlertKind, Packrat};
 use task_sensor_api::SensorId;
-use task_thermal_api::ThermalProperties;
-use userlib::{task_slot, units::Celsius, TaskId};
+use userlib::{task_slot, TaskId};
 
 task_slot!(SEQ, gimlet_seq);
-
-// We monitor the TMP117 air temperature sensors, but don't use them as part of
-// the control loop.
-const NUM_TEMPERATURE_SENSORS: usize = sensors::NUM_TMP117_TEMPERATURE_SENSORS;
-
-// BMC sensors vary depending on hardware revision
-#[cfg(any(target_board = "gimlet-b", target_board = "gimlet-c"))]
-const NUM_NVME_BMC_TEMPERATURE_SENSORS: usize =
-    sensors::NUM_NVME_BMC_TEMPERATURE_SENSORS
-        + sensors::NUM_M2_HP_ONLY_TEMPERATURE_SENSORS;
-
-#[cfg(any(target_board = "gimlet-d", target_board = "gimlet-e"))]
-const NUM_NVME_BMC_TEMPERATURE_SENSORS: usize =
-    sensors::NUM_NVME_BMC_TEMPERATURE_SENSORS;
-
-// The control loop is driven by CPU, NIC, and DIMM temperatures
-pub const NUM_TEMPERATURE_INPUTS: usize = sensors::NUM_SBTSI_TEMPERATURE_SENSORS
-    + sensors::NUM_TMP451_TEMPERATURE_SENSORS
-    + sensors::NUM_TSE2004AV_TEMPERATURE_SENSORS
-    + NUM_NVME_BMC_TEMPERATURE_SENSORS;
+task_slot!(PACKRAT, packrat);
 
 // Every temperature sensor on Gimlet is owned by this task
 pub const NUM_DYNAMIC_TEMPERATURE_INPUTS: usize = 0;
 
-// We've got 6 fans, driven from a single MAX31790 IC
-pub const NUM_FANS: usize = drv_i2c_devices::max31790::MAX_FANS as usize;
-
 /// This controller is tuned and ready to go
 pub const USE_CONTROLLER: bool = true;
 
 pub(crate) struct Bsp {
-    /// Controlled sensors
-    pub inputs: &'static [InputChannel],
     pub dynamic_inputs: &'static [SensorId],
 
-    /// Monitored sensors
-    pub misc_sensors: &'static [TemperatureSensor],
-
-    /// Fan control IC
-    fctrl: Max31790,
-
     /// Handle to the sequencer task, to query power state
     seq: Sequencer,
 
+    /// Handle to packrat, to queue alerts for the host
+    packrat: Packrat,
+
     /// Id of the I2C task, to query MAX5970 status
     i2c_task: TaskId,
-
-    /// Tuning for the PID controller
-    pub pid_config: PidConfig,
 }
 
 bitflags::bitflags! {
@@ -89,18 +51,15 @@ bitflags::bitflags! {
 }
 
 impl Bsp {
-    pub fn fan_control(&self, fan: crate::Fan) -> FanControl<'_> {
-        FanControl::Max31790(&self.fctrl, fan.0.try_into().unwrap())
-    }
-
-    pub fn for_each_fctrl(&self, mut fctrl: impl FnMut(FanControl<'_>)) {
-        fctrl(self.fan_control(0.into()))
-    }
-
     pub fn power_down(&self) -> Result<(), SeqError> {
         self.seq.set_state(PowerState::A2)
     }
 
+    /// Queues an alert for the host, which `host-sp-comms` will pass on
+    pub fn raise_host_alert(&self, kind: HostAlertKind, index: u8) {
+        self.packrat.raise_host_alert(kind as u8, index);
+    }
+
     pub fn power_mode(&self) -> PowerBitmask {
         let state = match self.seq.get_state() {
             Ok(p) => p,
@@ -152,455 +111,27 @@ impl Bsp {
     }
 
     // We assume Gimlet fan presence cannot change
-    pub fn get_fan_presence(&self) -> Result<Fans<{ NUM_FANS }>, SeqError> {
+    pub fn get_fan_presence(
+        &self,
+    ) -> Result<Fans<{ config::NUM_FANS }>, SeqError> {
         // Awkwardly build the fan array, because there's not a great way to
         // build a fixed-size array from a function
         let mut fans = Fans::new();
         for i in 0..fans.len() {
-            fans[i] = Some(sensors::MAX31790_SPEED_SENSORS[i]);
+            fans[i] = Some(config::FANS[i].2);
         }
         Ok(fans)
     }
 
     pub fn new(i2c_task: TaskId) -> Self {
-        // Initializes and build a handle to the fan controller IC
-        let fctrl = Max31790::new(&devices::max31790(i2c_task)[0]);
-        fctrl.initialize().unwrap();
-
         // Handle for the sequencer task, which we check for power state
         let seq = Sequencer::from(SEQ.get_task_id());
 
         Self {
             seq,
+            packrat: Packrat::from(PACKRAT.get_task_id()),
             i2c_task,
-            fctrl,
-
-            // Based on experimental tuning!
-            pid_config: PidConfig {
-                zero: 35.0,
-                gain_p: 1.75,
-                gain_i: 0.0135,
-                gain_d: 0.4,
-            },
-
-            inputs: &INPUTS,
             dynamic_inputs: &[],
-
-            // We monitor and log all of the air temperatures
-            misc_sensors: &MISC_SENSORS,
         }
     }
 }
-
-// In general, see RFD 276 Detailed Thermal Loop Design for references.
-// TODO: temperature_slew_deg_per_sec is made up.
-
-// JEDEC specification requires Tcasemax <= 85°C for normal temperature
-// range.  We're using RAM with industrial temperature ranges, listed on
-// the datasheet as 0°C <= T_oper <= 95°C.
-const DIMM_THERMALS: ThermalProperties = ThermalProperties {
-    target_temperature: Celsius(80f32),
-    critical_temperature: Celsius(90f32),
-    power_down_temperature: Celsius(95f32),
-    temperature_slew_deg_per_sec: 0.5,
-};
-
-// Thermal throttling begins at 78° for WD-SN840 (primary source) and
-// 75° for Micron-9300 (secondary source).
-//
-// For the WD part, thermal shutdown is at 84°C, which also voids the
-// warranty. The Micron drive doesn't specify a thermal shutdown
-// temperature, but the "critical" temperature is 80°C.
-//
-// All temperature are "composite" temperatures.
-const U2_THERMALS: ThermalProperties = ThermalProperties {
-    target_temperature: Celsius(65f32),
-    critical_temperature: Celsius(70f32),
-    power_down_temperature: Celsius(75f32),
-    temperature_slew_deg_per_sec: 0.5,
-};
-
-// The Micron-7300 (primary source) begins throttling at 72°, and its "critical
-// composite temperature" is 76°.  The WD-SN640 (secondary source) begins
-// throttling at 77°C.
-const M2_THERMALS: ThermalProperties = ThermalProperties {
-    target_temperature: Celsius(65f32),
-    critical_temperature: Celsius(70f32),
-    power_down_temperature: Celsius(75f32),
-    temperature_slew_deg_per_sec: 0.5,
-};
-
-// The CPU doesn't actually report true temperature; it reports a
-// unitless "temperature control value".  Throttling starts at 95, and
-// becomes more aggressive at 100.  Let's aim for 80, to stay well below
-// the throttling range.
-const CPU_THERMALS: ThermalProperties = ThermalProperties {
-    target_temperature: Celsius(80f32),
-    critical_temperature: Celsius(90f32),
-    power_down_temperature: Celsius(100f32),
-    temperature_slew_deg_per_sec: 0.5,
-};
-
-// The T6's specifications aren't clearly detailed anywhere.
-const T6_THERMALS: ThermalProperties = ThermalProperties {
-    target_temperature: Celsius(70f32),
-    critical_temperature: Celsius(80f32),
-    power_down_temperature: Celsius(85f32),
-    temperature_slew_deg_per_sec: 0.5,
-};
-
-const INPUTS: [InputChannel; NUM_TEMPERATURE_INPUTS] = [
-    // The M.2 devices are polled first deliberately: they're only polled if
-    // powered, and we want to minimize the TOCTOU window between asking the
-    // MAX5970 "is it powered?" and actually reading data.
-    //
-    // See hardware-gimlet#1804 for details; this is fixed in later revisions.
-    InputChannel::new(
-        #[cfg(any(target_board = "gimlet-b", target_board = "gimlet-c"))]
-        TemperatureSensor::new(
-            Device::M2,
-            devices::m2_hp_only_m2_a,
-            sensors::M2_HP_ONLY_M2_A_TEMPERATURE_SENSOR,
-        ),
-        #[cfg(any(target_board = "gimlet-d", target_board = "gimlet-e"))]
-        TemperatureSensor::new(
-            Device::M2,
-            devices::nvme_bmc_m2_a,
-            sensors::NVME_BMC_M2_A_TEMPERATURE_SENSOR,
-        ),
-        M2_THERMALS,
-        PowerBitmask::M2A,
-        ChannelType::Removable,
-    ),
-    InputChannel::new(
-        #[cfg(any(target_board = "gimlet-b", target_board = "gimlet-c"))]
-        TemperatureSensor::new(
-            Device::M2,
-            devices::m2_hp_only_m2_b,
-            sensors::M2_HP_ONLY_M2_B_TEMPERATURE_SENSOR,
-        ),
-        #[cfg(any(target_board = "gimlet-d", target_board = "gimlet-e"))]
-        TemperatureSensor::new(
-            Device::M2,
-            devices::nvme_bmc_m2_b,
-            sensors::NVME_BMC_M2_B_TEMPERATURE_SENSOR,
-        ),
-        M2_THERMALS,
-        PowerBitmask::M2B,
-        ChannelType::Removable,
-    ),
-    InputChannel::new(
-        TemperatureSensor::new(
-            Device::CPU,
-            devices::sbtsi_cpu,
-            sensors::SBTSI_CPU_TEMPERATURE_SENSOR,
-        ),
-        CPU_THERMALS,
-        PowerBitmask::A0,
-        ChannelType::MustBePresent,
-    ),
-    InputChannel::new(
-        TemperatureSensor::new(
-            Device::Tmp451(drv_i2c_devices::tmp451::Target::Remote),
-            devices::tmp451_t6,
-            sensors::TMP451_T6_TEMPERATURE_SENSOR,
-        ),
-        T6_THERMALS,
-        PowerBitmask::A0,
-        ChannelType::MustBePresent,
-    ),
-    InputChannel::new(
-        TemperatureSensor::new(
-            Device::Dimm,
-            devices::tse2004av_dimm_a0,
-            sensors::TSE2004AV_DIMM_A0_TEMPERATURE_SENSOR,
-        ),
-        DIMM_THERMALS,
-        PowerBitmask::A0_OR_A2,
-        ChannelType::Removable,
-    ),
-    InputChannel::new(
-        TemperatureSensor::new(
-            Device::Dimm,
-            devices::tse2004av_dimm_a1,
-            sensors::TSE2004AV_DIMM_A1_TEMPERATURE_SENSOR,
-        ),
-        DIMM_THERMALS,
-        PowerBitmask::A0_OR_A2,
-        ChannelType::Removable,
-    ),
-    InputChannel::new(
-        TemperatureSensor::new(
-            Device::Dimm,
-            devices::tse2004av_dimm_b0,
-            sensors::TSE2004AV_DIMM_B0_TEMPERATURE_SENSOR,
-        ),
-        DIMM_THERMALS,
-        PowerBitmask::A0_OR_A2,
-        ChannelType::Removable,
-    ),
-    InputChannel::new(
-        TemperatureSensor::new(
-            Device::Dimm,
-            devices::tse2004av_dimm_b1,
-            sensors::TSE2004AV_DIMM_B1_TEMPERATURE_SENSOR,
-        ),
-        DIMM_THERMALS,
-        PowerBitmask::A0_OR_A2,
-        ChannelType::Removable,
-    ),
-    InputChannel::new(
-        TemperatureSensor::new(
-            Device::Dimm,
-            devices::tse2004av_dimm_c0,
-            sensors::TSE2004AV_DIMM_C0_TEMPERATURE_SENSOR,
-        ),
-        DIMM_THERMALS,
-        PowerBitmask::A0_OR_A2,
-        ChannelType::Removable,
-    ),
-    InputChannel::new(
-        TemperatureSensor::new(
-            Device::Dimm,
-            devices::tse2004av_dimm_c1,
-            sensors::TSE2004AV_DIMM_C1_TEMPERATURE_SENSOR,
-        ),
-        DIMM_THERMALS,
-        PowerBitmask::A0_OR_A2,
-        ChannelType::Removable,
-    ),
-    InputChannel::new(
-        TemperatureSensor::new(
-            Device::Dimm,
-            devices::tse2004av_dimm_d0,
-            sensors::TSE2004AV_DIMM_D0_TEMPERATURE_SENSOR,
-        ),
-        DIMM_THERMALS,
-        PowerBitmask::A0_OR_A2,
-        ChannelType::Removable,
-    ),
-    InputChannel::new(
-        TemperatureSensor::new(
-            Device::Dimm,
-            devices::tse2004av_dimm_d1,
-            sensors::TSE2004AV_DIMM_D1_TEMPERATURE_SENSOR,
-        ),
-        DIMM_THERMALS,
-        PowerBitmask::A0_OR_A2,
-        ChannelType::Removable,
-    ),
-    InputChannel::new(
-        TemperatureSensor::new(
-            Device::Dimm,
-            devices::tse2004av_dimm_e0,
-            sensors::TSE2004AV_DIMM_E0_TEMPERATURE_SENSOR,
-        ),
-        DIMM_THERMALS,
-        PowerBitmask::A0_OR_A2,
-        ChannelType::Removable,
-    ),
-    InputChannel::new(
-        TemperatureSensor::new(
-            Device::Dimm,
-            devices::tse2004av_dimm_e1,
-            sensors::TSE2004AV_DIMM_E1_TEMPERATURE_SENSOR,
-        ),
-        DIMM_THERMALS,
-        PowerBitmask::A0_OR_A2,
-        ChannelType::Removable,
-    ),
-    InputChannel::new(
-        TemperatureSensor::new(
-            Device::Dimm,
-            devices::tse2004av_dimm_f0,
-            sensors::TSE2004AV_DIMM_F0_TEMPERATURE_SENSOR,
-        ),
-        DIMM_THERMALS,
-        PowerBitmask::A0_OR_A2,
-        ChannelType::Removable,
-    ),
-    InputChannel::new(
-        TemperatureSensor::new(
-            Device::Dimm,
-            devices::tse2004av_dimm_f1,
-            sensors::TSE2004AV_DIMM_F1_TEMPERATURE_SENSOR,
-        ),
-        DIMM_THERMALS,
-        PowerBitmask::A0_OR_A2,
-        ChannelType::Removable,
-    ),
-    InputChannel::new(
-        TemperatureSensor::new(
-            Device::Dimm,
-            devices::tse2004av_dimm_g0,
-            sensors::TSE2004AV_DIMM_G0_TEMPERATURE_SENSOR,
-        ),
-        DIMM_THERMALS,
-        PowerBitmask::A0_OR_A2,
-        ChannelType::Removable,
-    ),
-    InputChannel::new(
-        TemperatureSensor::new(
-            Device::Dimm,
-            devices::tse2004av_dimm_g1,
-            sensors::TSE2004AV_DIMM_G1_TEMPERATURE_SENSOR,
-        ),
-        DIMM_THERMALS,
-        PowerBitmask::A0_OR_A2,
-        ChannelType::Removable,
-    ),
-    InputChannel::new(
-        TemperatureSensor::new(
-            Device::Dimm,
-            devices::tse2004av_dimm_h0,
-            sensors::TSE2004AV_DIMM_H0_TEMPERATURE_SENSOR,
-        ),
-        DIMM_THERMALS,
-        PowerBitmask::A0_OR_A2,
-        ChannelType::Removable,
-    ),
-    InputChannel::new(
-        TemperatureSensor::new(
-            Device::Dimm,
-            devices::tse2004av_dimm_h1,
-            sensors::TSE2004AV_DIMM_H1_TEMPERATURE_SENSOR,
-        ),
-        DIMM_THERMALS,
-        PowerBitmask::A0_OR_A2,
-        ChannelType::Removable,
-    ),
-    // U.2 drives
-    InputChannel::new(
-        TemperatureSensor::new(
-            Device::U2,
-            devices::nvme_bmc_u2_n0,
-            sensors::NVME_BMC_U2_N0_TEMPERATURE_SENSOR,
-        ),
-        U2_THERMALS,
-        PowerBitmask::A0,
-        ChannelType::RemovableAndErrorProne,
-    ),
-    InputChannel::new(
-        TemperatureSensor::new(
-            Device::U2,
-            devices::nvme_bmc_u2_n1,
-            sensors::NVME_BMC_U2_N1_TEMPERATURE_SENSOR,
-        ),
-        U2_THERMALS,
-        PowerBitmask::A0,
-        ChannelType::RemovableAndErrorProne,
-    ),
-    InputChannel::new(
-        TemperatureSensor::new(
-            Device::U2,
-            devices::nvme_bmc_u2_n2,
-            sensors::NVME_BMC_U2_N2_TEMPERATURE_SENSOR,
-        ),
-        U2_THERMALS,
-        PowerBitmask::A0,
-        ChannelType::RemovableAndErrorProne,
-    ),
-    InputChannel::new(
-        TemperatureSensor::new(
-            Device::U2,
-            devices::nvme_bmc_u2_n3,
-            sensors::NVME_BMC_U2_N3_TEMPERATURE_SENSOR,
-        ),
-        U2_THERMALS,
-        PowerBitmask::A0,
-        ChannelType::RemovableAndErrorProne,
-    ),
-    InputChannel::new(
-        TemperatureSensor::new(
-            Device::U2,
-            devices::nvme_bmc_u2_n4,
-            sensors::NVME_BMC_U2_N4_TEMPERATURE_SENSOR,
-        ),
-        U2_THERMALS,
-        PowerBitmask::A0,
-        ChannelType::RemovableAndErrorProne,
-    ),
-    InputChannel::new(
-        TemperatureSensor::new(
-            Device::U2,
-            devices::nvme_bmc_u2_n5,
-            sensors::NVME_BMC_U2_N5_TEMPERATURE_SENSOR,
-        ),
-        U2_THERMALS,
-        PowerBitmask::A0,
-        ChannelType::RemovableAndErrorProne,
-    ),
-    InputChannel::new(
-        TemperatureSensor::new(
-            Device::U2,
-            devices::nvme_bmc_u2_n6,
-            sensors::NVME_BMC_U2_N6_TEMPERATURE_SENSOR,
-        ),
-        U2_THERMALS,
-        PowerBitmask::A0,
-        ChannelType::RemovableAndErrorProne,
-    ),
-    InputChannel::new(
-        TemperatureSensor::new(
-            Device::U2,
-            devices::nvme_bmc_u2_n7,
-            sensors::NVME_BMC_U2_N7_TEMPERATURE_SENSOR,
-        ),
-        U2_THERMALS,
-        PowerBitmask::A0,
-        ChannelType::RemovableAndErrorProne,
-    ),
-    InputChannel::new(
-        TemperatureSensor::new(
-            Device::U2,
-            devices::nvme_bmc_u2_n8,
-            sensors::NVME_BMC_U2_N8_TEMPERATURE_SENSOR,
-        ),
-        U2_THERMALS,
-        PowerBitmask::A0,
-        ChannelType::RemovableAndErrorProne,
-    ),
-    InputChannel::new(
-        TemperatureSensor::new(
-            Device::U2,
-            devices::nvme_bmc_u2_n9,
-            sensors::NVME_BMC_U2_N9_TEMPERATURE_SENSOR,
-        ),
-        U2_THERMALS,
-        PowerBitmask::A0,
-        ChannelType::RemovableAndErrorProne,
-    ),
-];
-
-const MISC_SENSORS: [TemperatureSensor; NUM_TEMPERATURE_SENSORS] = [
-    TemperatureSensor::new(
-        Device::Tmp117,
-        devices::tmp117_southwest,
-        sensors::TMP117_SOUTHWEST_TEMPERATURE_SENSOR,
-    ),
-    TemperatureSensor::new(
-        Device::Tmp117,
-        devices::tmp117_southeast,
-        sensors::TMP117_SOUTHEAST_TEMPERATURE_SENSOR,
-    ),
-    TemperatureSensor::new(
-        Device::Tmp117,
-        devices::tmp117_northwest,
-        sensors::TMP117_NORTHWEST_TEMPERATURE_SENSOR,
-    ),
-    TemperatureSensor::new(
-        Device::Tmp117,
-        devices::tmp117_northeast,
-        sensors::TMP117_NORTHEAST_TEMPERATURE_SENSOR,
-    ),
-    TemperatureSensor::new(
-        Device::Tmp117,
-        devices::tmp117_north,
-        sensors::TMP117_NORTH_TEMPERATURE_SENSOR,
-    ),
-    TemperatureSensor::new(
-        Device::Tmp117,
-        devices::tmp117_south,
-        sensors::TMP117_SOUTH_TEMPERATURE_SENSOR,
-    ),
-];
@@ -4,7 +4,7 @@
 
 use crate::{
     bsp::{self, Bsp, PowerBitmask},
-    Fan, ThermalError, Trace,
+    config, Fan, ThermalError, Trace,
 };
 use drv_i2c_api::ResponseCode;
 use drv_i2c_devices::{
@@ -18,8 +18,12 @@ use drv_i2c_devices::{
 };
 
 use ringbuf::ringbuf_entry_root as ringbuf_entry;
+use task_packrat_api::HostAlertKind;
 use task_sensor_api::{Reading, Sensor as SensorApi, SensorError, SensorId};
-use task_thermal_api::{SensorReadError, ThermalAutoState, ThermalProperties};
+use task_thermal_api::{
+    FanHealth, FanStatus, SensorReadError, ThermalAutoState, ThermalProperties,
+    ThermalZoneState,
+};
 use userlib::{
     sys_get_timer,
     units::{Celsius, PWMDuty, Rpm},
@@ -31,14 +35,16 @@ use userlib::{
 /// Type containing all of our temperature sensor types, so we can store them
 /// generically in an array.  These are all `I2cDevice`s, so functions on
 /// this `enum` return an `drv_i2c_api::ResponseCode`.
-#[allow(dead_code, clippy::upper_case_acronyms)]
+///
+/// The build script picks the variant for each sensor in `config.thermal`
+/// based on its I2C device type.
+#[allow(dead_code)]
 pub enum Device {
     Tmp117,
     Tmp451(drv_i2c_devices::tmp451::Target),
-    CPU,
-    Dimm,
-    U2,
-    M2,
+    Sbtsi,
+    Tse2004Av,
+    NvmeBmc,
 }
 
 /// Represents a sensor in the system.
@@ -68,10 +74,10 @@ impl TemperatureSensor {
         let dev = (self.builder)(i2c_task);
         let t = match &self.device {
             Device::Tmp117 => Tmp117::new(&dev).read_temperature()?,
-            Device::CPU => Sbtsi::new(&dev).read_temperature()?,
+            Device::Sbtsi => Sbtsi::new(&dev).read_temperature()?,
             Device::Tmp451(t) => Tmp451::new(&dev, *t).read_temperature()?,
-            Device::Dimm => Tse2004Av::new(&dev).read_temperature()?,
-            Device::U2 | Device::M2 => NvmeBmc::new(&dev).read_temperature()?,
+            Device::Tse2004Av => Tse2004Av::new(&dev).read_temperature()?,
+            Device::NvmeBmc => NvmeBmc::new(&dev).read_temperature()?,
         };
         Ok(t)
     }
@@ -86,7 +92,7 @@ impl TemperatureSensor {
 #[derive(Copy, Clone)]
 pub struct Fans<const N: usize>([Option<SensorId>; N]);
 
-impl core::ops::Index<usize> for Fans<{ bsp::NUM_FANS }> {
+impl core::ops::Index<usize> for Fans<{ config::NUM_FANS }> {
     type Output = Option<SensorId>;
 
     fn index(&self, index: usize) -> &Option<SensorId> {
@@ -94,15 +100,15 @@ impl core::ops::Index<usize> for Fans<{ bsp::NUM_FANS }> {
     }
 }
 
-impl core::ops::IndexMut<usize> for Fans<{ bsp::NUM_FANS }> {
+impl core::ops::IndexMut<usize> for Fans<{ config::NUM_FANS }> {
     fn index_mut(&mut self, index: usize) -> &mut Option<SensorId> {
         &mut self.0[index]
     }
 }
 
-impl Fans<{ bsp::NUM_FANS }> {
+impl Fans<{ config::NUM_FANS }> {
     pub fn new() -> Self {
-        Self([None; bsp::NUM_FANS])
+        Self([None; config::NUM_FANS])
     }
     pub fn len(&self) -> usize {
         self.0.len()
@@ -122,6 +128,101 @@ impl Fans<{ bsp::NUM_FANS }> {
 
 ////////////////////////////////////////////////////////////////////////////////
 
+/// Thresholds for judging fan health, from `config.thermal`
+pub(crate) struct FanHealthConfig {
+    /// Below this PWM duty cycle, fans may legitimately stop spinning, so we
+    /// can't judge them
+    pub min_pwm: u8,
+
+    /// Fan speed at 100% PWM, if known
+    pub max_rpm: Option<u16>,
+
+    /// A fan spinning slower than this percentage of its expected speed is
+    /// considered degraded
+    pub degraded_percent: u8,
+
+    /// PWM percentage added to the rest of a zone's fans when one has failed
+    pub boost: u8,
+}
+
+impl FanHealthConfig {
+    /// Judges a single speed reading, taken while the fan was driven at
+    /// `pwm`.  Returns `None` if the reading doesn't tell us anything.
+    fn judge(&self, pwm: PWMDuty, rpm: Rpm) -> Option<FanHealth> {
+        if pwm.0 < self.min_pwm {
+            return None;
+        }
+        if rpm.0 == 0 {
+            return Some(FanHealth::Stalled);
+        }
+        let Some(max_rpm) = self.max_rpm else {
+            return Some(FanHealth::Ok);
+        };
+        // We model fan speed as proportional to PWM, which is pessimistic at
+        // low duty cycles; `degraded_percent` should leave plenty of slack.
+        let expected = u32::from(max_rpm) * u32::from(pwm.0) / 100;
+        if u32::from(rpm.0) * 100 < expected * u32::from(self.degraded_percent)
+        {
+            Some(FanHealth::Degraded)
+        } else {
+            Some(FanHealth::Ok)
+        }
+    }
+}
+
+/// Number of consecutive readings needed to change a fan's health, which
+/// gives fans time to spin up or down after a change in PWM
+const FAN_HEALTH_SAMPLES: u8 = 3;
+
+/// Tracks the health of a single fan
+#[derive(Copy, Clone)]
+struct FanMonitor {
+    health: FanHealth,
+
+    /// Number of consecutive readings that disagree with `health`
+    strikes: u8,
+
+    /// PWM duty cycle most recently sent to the fan
+    pwm: PWMDuty,
+
+    /// Most recent speed reading, or `None` if it couldn't be read
+    rpm: Option<Rpm>,
+}
+
+impl FanMonitor {
+    const fn new() -> Self {
+        Self {
+            health: FanHealth::Ok,
+            strikes: 0,
+            pwm: PWMDuty(0),
+            rpm: None,
+        }
+    }
+
+    /// Records a speed reading, returning the fan's new health if it changed
+    fn update(&mut self, rpm: Rpm, cfg: &FanHealthConfig) -> Option<FanHealth> {
+        self.rpm = Some(rpm);
+        match cfg.judge(self.pwm, rpm) {
+            Some(h) if h != self.health => {
+                self.strikes += 1;
+                if self.strikes >= FAN_HEALTH_SAMPLES {
+                    self.health = h;
+                    self.strikes = 0;
+                    return Some(h);
+                }
+            }
+            _ => self.strikes = 0,
+        }
+        None
+    }
+
+    fn is_failed(&self) -> bool {
+        self.health != FanHealth::Ok
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
 /// Enum representing any of our fan controller types, bound to one of their
 /// fans.  This lets us handle heterogeneous fan controller ICs generically
 /// (although there's only one at the moment)
@@ -149,6 +250,42 @@ impl<'a> FanControl<'a> {
     }
 }
 
+/// The fan controller ICs in the system, as listed in `config.thermal`,
+/// which also maps each of our fans to a controller channel.
+pub(crate) struct FanControllers {
+    controllers: [Max31790; config::NUM_FAN_CONTROLLERS],
+}
+
+impl FanControllers {
+    /// Builds handles to every fan controller, and initializes them.
+    pub fn new(i2c_task: TaskId) -> Self {
+        let controllers =
+            config::FAN_CONTROLLERS.map(|dev| Max31790::new(&dev(i2c_task)));
+        for c in &controllers {
+            c.initialize().unwrap();
+        }
+        Self { controllers }
+    }
+
+    pub fn fan_control(&self, fan: Fan) -> FanControl<'_> {
+        let (controller, channel, _sensor) = config::FANS[fan.0 as usize];
+        FanControl::Max31790(
+            &self.controllers[controller],
+            channel.try_into().unwrap(),
+        )
+    }
+
+    pub fn set_watchdog(&self, wd: I2cWatchdog) -> Result<(), ResponseCode> {
+        let mut result = Ok(());
+        for c in &self.controllers {
+            if let Err(e) = c.set_watchdog(wd) {
+                result = Err(e);
+            }
+        }
+        result
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 /// An `InputChannel` represents a temperature sensor associated with a
@@ -165,6 +302,9 @@ pub(crate) struct InputChannel {
 
     /// Channel type
     ty: ChannelType,
+
+    /// Zones whose fans cool this component
+    zones: ZoneMask,
 }
 
 #[derive(Copy, Clone, Eq, PartialEq)]
@@ -212,16 +352,32 @@ impl InputChannel {
         model: ThermalProperties,
         power_mode_mask: PowerBitmask,
         ty: ChannelType,
+        zones: ZoneMask,
     ) -> Self {
         Self {
             sensor,
             model,
             power_mode_mask,
             ty,
+            zones,
         }
     }
 }
 
+/// A set of zones, as a bitmask: bit `i` is set if zone `i` is a member.
+///
+/// A zone is a group of fans and the inputs that they cool, which is
+/// controlled independently of other zones; they're defined in
+/// `config.thermal`.
+#[derive(Copy, Clone)]
+pub(crate) struct ZoneMask(pub u32);
+
+impl ZoneMask {
+    fn contains(&self, zone: usize) -> bool {
+        self.0 & (1 << zone) != 0
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 /// A `DynamicInputChannel` represents a temperature input channel with thermal
@@ -290,6 +446,9 @@ pub(crate) struct ThermalControl<'a> {
     /// Reference to board-specific parameters
     bsp: &'a Bsp,
 
+    /// Fan controller ICs
+    fctrl: FanControllers,
+
     /// I2C task
     i2c_task: TaskId,
 
@@ -306,6 +465,10 @@ pub(crate) struct ThermalControl<'a> {
     /// How long to wait in the `Overheated` state before powering down
     overheat_timeout_ms: u64,
 
+    /// How long before `overheat_timeout_ms` expires to warn the host that
+    /// we're about to power it off
+    power_off_warning_ms: u64,
+
     /// Once we're in `Overheated`, how much does the temperature have to drop
     /// by before we return to `Normal`
     overheat_hysteresis: Celsius,
@@ -313,7 +476,7 @@ pub(crate) struct ThermalControl<'a> {
     /// Most recent power mode mask
     power_mode: PowerBitmask,
 
-    /// PID parameters, pulled from the BSP by default but user-modifiable
+    /// PID parameters, from `config.thermal` by default but user-modifiable
     pid_config: PidConfig,
 
     /// Dynamic inputs are fixed in number but configured at runtime.
@@ -333,10 +496,26 @@ pub(crate) struct ThermalControl<'a> {
     prev_err_blackbox: &'static mut ThermalSensorErrors,
 
     /// Fans for the system
-    fans: Fans<{ bsp::NUM_FANS }>,
+    fans: Fans<{ config::NUM_FANS }>,
+
+    /// Health of each fan
+    fan_monitors: [FanMonitor; config::NUM_FANS],
 
     /// Last group PWM control value
     last_pwm: PWMDuty,
+
+    /// Most recent control output for each zone
+    zone_outputs: [ZoneOutput; config::NUM_ZONES],
+}
+
+/// The most recent control output for a zone, for reporting
+#[derive(Copy, Clone)]
+struct ZoneOutput {
+    pwm: PWMDuty,
+    worst_margin: f32,
+
+    /// Whether the PWM is boosted to compensate for a failed fan
+    boosted: bool,
 }
 
 /// Represents the state of a temperature sensor, which either has a valid
@@ -390,6 +569,7 @@ pub struct PidConfig {
 
 /// Represents a PID controller that can only push in one direction (i.e. the
 /// output must always be positive).
+#[derive(Copy, Clone)]
 struct OneSidedPidState {
     /// Previous (time, input) tuple, for derivative term
     prev_error: Option<f32>,
@@ -446,9 +626,10 @@ impl Default for OneSidedPidState {
 }
 
 const TEMPERATURE_ARRAY_SIZE: usize =
-    bsp::NUM_TEMPERATURE_INPUTS + bsp::NUM_DYNAMIC_TEMPERATURE_INPUTS;
+    config::NUM_TEMPERATURE_INPUTS + bsp::NUM_DYNAMIC_TEMPERATURE_INPUTS;
 
-/// This corresponds to states shown in RFD 276
+/// This corresponds to states shown in RFD 276, with the `Running` and
+/// `Overheated` states tracked separately for each zone (see `ZoneState`).
 ///
 /// All of our temperature arrays contain, in order
 /// - I2C temperature inputs (read by this task)
@@ -465,19 +646,10 @@ enum ThermalControlState {
         values: [Option<TemperatureReading>; TEMPERATURE_ARRAY_SIZE],
     },
 
-    /// Normal happy control loop
+    /// The control loop is running, with each zone in its own state
     Running {
         values: [TemperatureReading; TEMPERATURE_ARRAY_SIZE],
-        pid: OneSidedPidState,
-    },
-
-    /// In the overheated state, one or more components has entered their
-    /// critical temperature ranges.  We turn on fans at high power and record
-    /// the time at which we entered this state; at a certain point, we will
-    /// timeout and drop into `Uncontrolled` if components do not recover.
-    Overheated {
-        values: [TemperatureReading; TEMPERATURE_ARRAY_SIZE],
-        start_time: u64,
+        zones: [ZoneState; config::NUM_ZONES],
     },
 
     /// The system cannot control the temperature; power down and wait for
@@ -485,8 +657,71 @@ enum ThermalControlState {
     Uncontrollable,
 }
 
+/// State of a single zone while the control loop is running
+#[derive(Copy, Clone)]
+enum ZoneState {
+    /// Normal happy control loop
+    Running { pid: OneSidedPidState },
+
+    /// In the overheated state, one or more of the zone's components has
+    /// entered their critical temperature ranges.  We turn on the zone's fans
+    /// at high power and record the time at which we entered this state; at a
+    /// certain point, we will timeout and drop into `Uncontrollable` if
+    /// components do not recover.  `warned` records whether we've told the
+    /// host that this timeout is approaching.
+    Overheated { start_time: u64, warned: bool },
+}
+
+impl ZoneState {
+    fn auto_state(&self) -> ThermalAutoState {
+        match self {
+            ZoneState::Running { .. } => ThermalAutoState::Running,
+            ZoneState::Overheated { .. } => ThermalAutoState::Overheated,
+        }
+    }
+}
+
+/// Summary of the worst-case temperatures of a zone's inputs
+#[derive(Copy, Clone)]
+struct ZoneTemperatures {
+    any_power_down: bool,
+    any_critical: bool,
+    all_subcritical: bool,
+    worst_margin: f32,
+}
+
+impl Default for ZoneTemperatures {
+    fn default() -> Self {
+        Self {
+            any_power_down: false,
+            any_critical: false,
+            all_subcritical: true,
+            worst_margin: f32::MAX,
+        }
+    }
+}
+
+impl ZoneTemperatures {
+    fn update(
+        &mut self,
+        model: &ThermalProperties,
+        temperature: Celsius,
+        hysteresis: Celsius,
+    ) {
+        self.any_power_down |= model.should_power_down(temperature);
+        self.any_critical |= model.is_critical(temperature);
+        self.all_subcritical &= model.is_sub_critical(temperature, hysteresis);
+
+        // Remember, positive margin means that all parts are happily below
+        // their max temperature; negative means someone is overheating.  We
+        // want to pick the _smallest_ margin, since that's the part which is
+        // most overheated.
+        self.worst_margin = self.worst_margin.min(model.margin(temperature).0);
+    }
+}
+
 enum ControlResult {
-    Pwm(PWMDuty),
+    Pwm([PWMDuty; config::NUM_ZONES]),
     PowerDown,
 }
 
@@ -500,8 +735,7 @@ impl ThermalControlState {
             ThermalControlState::Boot { values } => {
                 values[index] = Some(r);
             }
-            ThermalControlState::Running { values, .. }
-            | ThermalControlState::Overheated { values, .. } => {
+            ThermalControlState::Running { values, .. } => {
                 values[index] = r;
             }
             ThermalControlState::Uncontrollable => (),
@@ -513,8 +747,7 @@ impl ThermalControlState {
             ThermalControlState::Boot { values } => {
                 values[index] = Some(TemperatureReading::Inactive)
             }
-            ThermalControlState::Running { values, .. }
-            | ThermalControlState::Overheated { values, .. } => {
+            ThermalControlState::Running { values, .. } => {
                 values[index] = TemperatureReading::Inactive;
             }
             ThermalControlState::Uncontrollable => (),
@@ -537,23 +770,31 @@ impl<'a> ThermalControl<'a> {
         };
         Self {
             bsp,
+            fctrl: FanControllers::new(i2c_task),
             i2c_task,
             sensor_api,
             target_margin: Celsius(0.0f32),
             state: ThermalControlState::Boot {
                 values: [None; TEMPERATURE_ARRAY_SIZE],
             },
-            pid_config: bsp.pid_config,
+            pid_config: config::PID_CONFIG,
 
             overheat_hysteresis: Celsius(1.0),
             overheat_timeout_ms: 60_000,
+            power_off_warning_ms: 10_000,
 
             power_mode: PowerBitmask::empty(), // no sensors active
 
             dynamic_inputs: [None; bsp::NUM_DYNAMIC_TEMPERATURE_INPUTS],
 
             fans: Fans::new(),
+            fan_monitors: [FanMonitor::new(); config::NUM_FANS],
             last_pwm: PWMDuty(0),
+            zone_outputs: [ZoneOutput {
+                pwm: PWMDuty(0),
+                worst_margin: f32::MAX,
+                boosted: false,
+            }; config::NUM_ZONES],
 
             err_blackbox,
             prev_err_blackbox,
@@ -580,9 +821,13 @@ impl<'a> ThermalControl<'a> {
         // If the incoming integral gain is zero, then it will never be able
         // to wind down the integral accumulator (which is pre-multiplied),
         // so clear it here.
-        if let ThermalControlState::Running { pid, .. } = &mut self.state {
-            if i == 0.0 {
-                pid.integral = 0.0;
+        if let ThermalControlState::Running { zones, .. } = &mut self.state {
+            for z in zones.iter_mut() {
+                if let ZoneState::Running { pid } = z {
+                    if i == 0.0 {
+                        pid.integral = 0.0;
+                    }
+                }
             }
         }
 
@@ -610,8 +855,8 @@ impl<'a> ThermalControl<'a> {
     pub fn reset(&mut self) {
         self.reset_state();
 
-        // Reset the PID configuration from the BSP
-        self.pid_config = self.bsp.pid_config;
+        // Reset the PID configuration to its default
+        self.pid_config = config::PID_CONFIG;
 
         // Set the target_margin to 0, indicating no overcooling
         self.target_margin = Celsius(0.0f32);
@@ -630,11 +875,14 @@ impl<'a> ThermalControl<'a> {
         match self.bsp.get_fan_presence() {
             Ok(next) => {
                 for fan in next.as_fans() {
+                    let monitor = &mut self.fan_monitors[fan.0 as usize];
                     if !self.fans.is_present(fan) && next.is_present(fan) {
                         ringbuf_entry!(Trace::FanAdded(fan));
+                        *monitor = FanMonitor::new();
                     } else if self.fans.is_present(fan) && !next.is_present(fan)
                     {
                         ringbuf_entry!(Trace::FanRemoved(fan));
+                        *monitor = FanMonitor::new();
                     }
                 }
                 self.fans = next;
@@ -653,13 +901,41 @@ impl<'a> ThermalControl<'a> {
         // Read fan data and log it to the sensors task
         for (index, sensor_id) in self.fans.enumerate() {
             if let Some(sensor_id) = sensor_id {
+                let monitor = &mut self.fan_monitors[index];
                 let post_result =
-                    match self.bsp.fan_control(Fan::from(index)).fan_rpm() {
-                        Ok(reading) => self
-                            .sensor_api
-                            .post_now(*sensor_id, reading.0.into()),
+                    match self.fctrl.fan_control(Fan::from(index)).fan_rpm() {
+                        Ok(reading) => {
+                            let was_failed = monitor.is_failed();
+                            if let Some(h) =
+                                monitor.update(reading, &config::FAN_HEALTH)
+                            {
+                                ringbuf_entry!(Trace::FanHealth(
+                                    Fan::from(index),
+                                    h
+                                ));
+                                if !was_failed && monitor.is_failed() {
+                                    self.bsp.raise_host_alert(
+                                        HostAlertKind::FanFailure,
+                                        index as u8,
+                                    );
+                                }
+                            }
+                            if monitor.is_failed() {
+                                // The speed of a failed fan isn't meaningful
+                                // as a sensor reading; its health (and speed)
+                                // are available from `get_fan_status`.
+                                self.sensor_api.nodata_now(
+                                    *sensor_id,
+                                    task_sensor_api::NoData::DeviceError,
+                                )
+                            } else {
+                                self.sensor_api
+                                    .post_now(*sensor_id, reading.0.into())
+                            }
+                        }
                         Err(e) => {
                             ringbuf_entry!(Trace::FanReadFailed(*sensor_id, e));
+                            monitor.rpm = None;
                             self.err_blackbox
                                 .push(*sensor_id, SensorReadError::I2cError(e));
                             self.sensor_api.nodata_now(*sensor_id, e.into())
@@ -673,7 +949,7 @@ impl<'a> ThermalControl<'a> {
         }
 
         // Read miscellaneous temperature data and log it to the sensors task
-        for s in self.bsp.misc_sensors.iter() {
+        for s in config::MISC_SENSORS.iter() {
             let post_result = match s.read_temp(self.i2c_task) {
                 Ok(v) => self.sensor_api.post_now(s.sensor_id, v.0),
                 Err(e) => {
@@ -691,7 +967,7 @@ impl<'a> ThermalControl<'a> {
         // potential TOCTOU issues; some sensors cannot be read if they are not
         // powered.
         let power_mode = self.bsp.power_mode();
-        for s in self.bsp.inputs.iter() {
+        for s in config::INPUTS.iter() {
             let post_result = if power_mode.intersects(s.power_mode_mask) {
                 match s.sensor.read_temp(self.i2c_task) {
                     Ok(v) => self.sensor_api.post_now(s.sensor.sensor_id, v.0),
@@ -734,7 +1010,7 @@ impl<'a> ThermalControl<'a> {
         // they are, so someone else has to do that.
     }
 
-    /// Returns an iterator over tuples of `(value, thermal model)`
+    /// Returns an iterator over tuples of `(value, thermal model, zones)`
     ///
     /// The `values` array must contain `static_inputs.len()` +
     /// `dynamic_inputs.len()` values, in that order; this function will panic
@@ -748,17 +1024,41 @@ impl<'a> ThermalControl<'a> {
             &'b [InputChannel],
             &'b [Option<DynamicInputChannel>],
         ),
-    ) -> impl Iterator<Item = (&'b T, ThermalProperties)> {
+    ) -> impl Iterator<Item = (&'b T, ThermalProperties, ZoneMask)> {
         assert_eq!(values.len(), static_inputs.len() + dynamic_inputs.len());
         values
             .iter()
             .zip(
                 static_inputs
                     .iter()
-                    .map(|i| Some(i.model))
-                    .chain(dynamic_inputs.iter().map(|i| i.map(|i| i.model))),
+                    .map(|i| (Some(i.model), i.zones))
+                    .chain(dynamic_inputs.iter().map(|i| {
+                        (i.map(|i| i.model), config::DYNAMIC_INPUT_ZONES)
+                    })),
             )
-            .filter_map(|(v, model)| model.map(|t| (v, t)))
+            .filter_map(|(v, (model, zones))| model.map(|t| (v, t, zones)))
+    }
+
+    /// Summarizes the worst-case temperatures of each zone's inputs.
+    fn zone_temperatures<'b>(
+        readings: impl Iterator<
+            Item = (&'b TemperatureReading, ThermalProperties, ZoneMask),
+        >,
+        now_ms: u64,
+        hysteresis: Celsius,
+    ) -> [ZoneTemperatures; config::NUM_ZONES] {
+        let mut out = [ZoneTemperatures::default(); config::NUM_ZONES];
+        for (v, model, zones) in readings {
+            if let TemperatureReading::Valid(v) = v {
+                let temperature = v.worst_case(now_ms, &model);
+                for (i, t) in out.iter_mut().enumerate() {
+                    if zones.contains(i) {
+                        t.update(&model, temperature, hysteresis);
+                    }
+                }
+            }
+        }
+        out
     }
 
     /// An extremely simple thermal control loop.
@@ -785,7 +1085,7 @@ impl<'a> ThermalControl<'a> {
         // in `self.state`.  When we're in the `Boot` state, this will leave the
         // value as `None`; when we're `Running`, it will maintain the previous
         // state, estimating a new temperature with the thermal model.
-        for (i, s) in self.bsp.inputs.iter().enumerate() {
+        for (i, s) in config::INPUTS.iter().enumerate() {
             if self.power_mode.intersects(s.power_mode_mask) {
                 let sensor_id = s.sensor.sensor_id;
                 let r = self.sensor_api.get_reading(sensor_id);
@@ -816,7 +1116,7 @@ impl<'a> ThermalControl<'a> {
         // this model is set by external callers using
         // `update_dynamic_input` and `remove_dynamic_input`.
         for (i, sensor_id) in self.bsp.dynamic_inputs.iter().enumerate() {
-            let index = i + self.bsp.inputs.len();
+            let index = i + config::INPUTS.len();
             match self.dynamic_inputs[i] {
                 Some(..) => {
                     if let Ok(r) = self.sensor_api.get_reading(*sensor_id) {
@@ -830,165 +1130,184 @@ impl<'a> ThermalControl<'a> {
         // A bit awkward, but we have to borrow these explicitly to work around
         // the lifetime checker, which won't let us call a &self function when
         // self.state is mutably borrowed.
-        let inputs = (self.bsp.inputs, self.dynamic_inputs.as_slice());
+        let inputs = (&config::INPUTS[..], self.dynamic_inputs.as_slice());
 
         let control_result = match &mut self.state {
             ThermalControlState::Boot { values } => {
-                let mut all_some = true;
-                let mut any_power_down = false;
-                let mut worst_margin = f32::MAX;
-                for (v, model) in Self::zip_temperatures(values, inputs) {
-                    match v {
-                        Some(TemperatureReading::Valid(v)) => {
-                            let temperature = v.worst_case(now_ms, &model);
-                            any_power_down |=
-                                model.should_power_down(temperature);
-                            worst_margin =
-                                worst_margin.min(model.margin(temperature).0);
-                        }
-                        Some(TemperatureReading::Inactive) => {
-                            // Inactive sensors are ignored, but do not gate us
-                            // from transitioning to `Running`
-                        }
-
-                        None => all_some = false,
-                    }
+                // Inactive sensors are ignored, but do not gate us from
+                // transitioning to `Running`
+                let all_some = Self::zip_temperatures(values, inputs)
+                    .all(|(v, ..)| v.is_some());
+                let temps = Self::zone_temperatures(
+                    Self::zip_temperatures(values, inputs)
+                        .filter_map(|(v, m, z)| v.as_ref().map(|v| (v, m, z))),
+                    now_ms,
+                    self.overheat_hysteresis,
+                );
+                for (out, t) in self.zone_outputs.iter_mut().zip(&temps) {
+                    out.worst_margin = t.worst_margin;
                 }
 
-                if any_power_down {
+                if temps.iter().any(|t| t.any_power_down) {
+                    // Past the power-down temperature there's no time to
+                    // warn the host: we power off in this same pass, before
+                    // it can poll for this alert.  It's raised anyway so that
+                    // the host can find out why once it's back up.
+                    self.bsp
+                        .raise_host_alert(HostAlertKind::ImpendingPowerOff, 0);
                     self.state = ThermalControlState::Uncontrollable;
                     ringbuf_entry!(Trace::AutoState(self.get_state()));
 
                     ControlResult::PowerDown
                 } else if all_some {
                     // Transition to the Running state and run a single
-                    // iteration of the PID control loop.
-                    let mut pid = OneSidedPidState::default();
-                    let pwm = pid.run(
-                        &self.pid_config,
-                        self.target_margin.0 - worst_margin,
-                        100.0,
-                    );
+                    // iteration of each zone's PID control loop.
+                    let mut pids =
+                        [OneSidedPidState::default(); config::NUM_ZONES];
+                    let mut pwm = [PWMDuty(0); config::NUM_ZONES];
+                    for (i, (pid, t)) in pids.iter_mut().zip(&temps).enumerate()
+                    {
+                        let out = pid.run(
+                            &self.pid_config,
+                            self.target_margin.0 - t.worst_margin,
+                            100.0,
+                        );
+                        pwm[i] = PWMDuty(out as u8);
+                    }
                     self.state = ThermalControlState::Running {
                         values: values.map(Option::unwrap),
-                        pid,
+                        zones: pids.map(|pid| ZoneState::Running { pid }),
                     };
                     ringbuf_entry!(Trace::AutoState(self.get_state()));
 
-                    ControlResult::Pwm(PWMDuty(pwm as u8))
+                    ControlResult::Pwm(pwm)
                 } else {
-                    ControlResult::Pwm(PWMDuty(100))
+                    ControlResult::Pwm([PWMDuty(100); config::NUM_ZONES])
                 }
             }
-            ThermalControlState::Running { values, pid } => {
-                let mut any_power_down = false;
-                let mut any_critical = false;
-                let mut worst_margin = f32::MAX;
-
-                // Remember, positive margin means that all parts are happily
-                // below their max temperature; negative means someone is
-                // overheating.  We want to pick the _smallest_ margin, since
-                // that's the part which is most overheated.
-                for (v, model) in Self::zip_temperatures(values, inputs) {
-                    if let TemperatureReading::Valid(v) = v {
-                        let temperature = v.worst_case(now_ms, &model);
-                        any_power_down |= model.should_power_down(temperature);
-                        any_critical |= model.is_critical(temperature);
-
-                        worst_margin =
-                            worst_margin.min(model.margin(temperature).0);
-                    }
+            ThermalControlState::Running { values, zones } => {
+                let temps = Self::zone_temperatures(
+                    Self::zip_temperatures(values, inputs),
+                    now_ms,
+                    self.overheat_hysteresis,
+                );
+                for (out, t) in self.zone_outputs.iter_mut().zip(&temps) {
+                    out.worst_margin = t.worst_margin;
                 }
 
-                if any_power_down {
-                    self.state = ThermalControlState::Uncontrollable;
-                    ringbuf_entry!(Trace::AutoState(self.get_state()));
-
-                    ControlResult::PowerDown
-                } else if any_critical {
-                    self.state = ThermalControlState::Overheated {
-                        values: *values,
-                        start_time: now_ms,
-                    };
-                    ringbuf_entry!(Trace::AutoState(self.get_state()));
-
-                    ControlResult::Pwm(PWMDuty(100))
-                } else {
-                    // We adjust the worst component margin by our target
-                    // margin, which must be > 0.  This effectively tells the
-                    // control loop to overcool the system.
-                    //
-                    // `PidControl::run` expects the sign of the input and
-                    // output to match, so we negate things here: if the worst
-                    // margin is negative (i.e. the system is overheating), then
-                    // the input to `run` is positive, because we want a
-                    // positive fan speed.
-                    let pwm = pid.run(
-                        &self.pid_config,
-                        self.target_margin.0 - worst_margin,
-                        100.0,
-                    );
-                    ControlResult::Pwm(PWMDuty(pwm as u8))
-                }
-            }
-            ThermalControlState::Overheated { values, start_time } => {
-                let mut all_subcritical = true;
-                let mut any_power_down = false;
-                let mut worst_margin = f32::MAX;
-
-                for (v, model) in Self::zip_temperatures(values, inputs) {
-                    if let TemperatureReading::Valid(v) = v {
-                        let temperature = v.worst_case(now_ms, &model);
-                        all_subcritical &= model.is_sub_critical(
-                            temperature,
-                            self.overheat_hysteresis,
-                        );
-                        any_power_down |= model.should_power_down(temperature);
-                        worst_margin =
-                            worst_margin.min(model.margin(temperature).0);
+                // The host only needs to hear about an impending power-off
+                // once, however many zones are overheated.
+                let mut warn = false;
+                let mut warned = zones.iter().any(|z| {
+                    matches!(z, ZoneState::Overheated { warned: true, .. })
+                });
+                let mut timed_out = false;
+                let mut pwm = [PWMDuty(100); config::NUM_ZONES];
+                for (i, (z, t)) in zones.iter_mut().zip(&temps).enumerate() {
+                    match z {
+                        ZoneState::Running { .. } if t.any_critical => {
+                            *z = ZoneState::Overheated {
+                                start_time: now_ms,
+                                warned: false,
+                            };
+                            ringbuf_entry!(Trace::ZoneState(i, z.auto_state()));
+                            self.bsp.raise_host_alert(
+                                HostAlertKind::ThermalEmergency,
+                                i as u8,
+                            );
+                        }
+                        ZoneState::Running { pid } => {
+                            // We adjust the worst component margin by our
+                            // target margin, which must be > 0.  This
+                            // effectively tells the control loop to overcool
+                            // the system.
+                            //
+                            // `PidControl::run` expects the sign of the input
+                            // and output to match, so we negate things here:
+                            // if the worst margin is negative (i.e. the zone
+                            // is overheating), then the input to `run` is
+                            // positive, because we want a positive fan speed.
+                            let out = pid.run(
+                                &self.pid_config,
+                                self.target_margin.0 - t.worst_margin,
+                                100.0,
+                            );
+                            pwm[i] = PWMDuty(out as u8);
+                        }
+                        ZoneState::Overheated { .. } if t.all_subcritical => {
+                            // Return to the Running state and run a single
+                            // iteration of the PID control loop.
+                            let mut pid = OneSidedPidState::default();
+                            let out = pid.run(
+                                &self.pid_config,
+                                self.target_margin.0 - t.worst_margin,
+                                100.0,
+                            );
+                            pwm[i] = PWMDuty(out as u8);
+                            *z = ZoneState::Running { pid };
+                            ringbuf_entry!(Trace::ZoneState(i, z.auto_state()));
+                        }
+                        ZoneState::Overheated {
+                            start_time,
+                            warned: zone_warned,
+                        } => {
+                            // If blasting the fans hasn't cooled us down in
+                            // this amount of time, then something is terribly
+                            // wrong - abort!  Warn the host shortly before
+                            // that happens, so that it has time to react.
+                            let timeout =
+                                *start_time + self.overheat_timeout_ms;
+                            if !*zone_warned
+                                && now_ms + self.power_off_warning_ms > timeout
+                            {
+                                *zone_warned = true;
+                                warn |= !warned;
+                                warned = true;
+                            }
+                            timed_out |= now_ms > timeout;
+                        }
                     }
                 }
 
-                if any_power_down {
+                if warn {
+                    self.bsp
+                        .raise_host_alert(HostAlertKind::ImpendingPowerOff, 0);
+                }
+
+                if timed_out {
+                    // The host was warned `power_off_warning_ms` ago
                     self.state = ThermalControlState::Uncontrollable;
                     ringbuf_entry!(Trace::AutoState(self.get_state()));
 
                     ControlResult::PowerDown
-                } else if all_subcritical {
-                    // Transition to the Running state and run a single
-                    // iteration of the PID control loop.
-                    let mut pid = OneSidedPidState::default();
-                    let pwm = pid.run(
-                        &self.pid_config,
-                        self.target_margin.0 - worst_margin,
-                        100.0,
-                    );
-                    self.state = ThermalControlState::Running {
-                        values: *values,
-                        pid,
-                    };
-                    ringbuf_entry!(Trace::AutoState(self.get_state()));
-
-                    ControlResult::Pwm(PWMDuty(pwm as u8))
-                } else if now_ms > *start_time + self.overheat_timeout_ms {
-                    // If blasting the fans hasn't cooled us down in this amount
-                    // of time, then something is terribly wrong - abort!
+                } else if temps.iter().any(|t| t.any_power_down) {
+                    // As above, the host only sees this once it's back up.
+                    if !warned {
+                        self.bsp.raise_host_alert(
+                            HostAlertKind::ImpendingPowerOff,
+                            0,
+                        );
+                    }
                     self.state = ThermalControlState::Uncontrollable;
                     ringbuf_entry!(Trace::AutoState(self.get_state()));
 
                     ControlResult::PowerDown
                 } else {
-                    ControlResult::Pwm(PWMDuty(100))
+                    ControlResult::Pwm(pwm)
                 }
             }
             ThermalControlState::Uncontrollable => ControlResult::PowerDown,
         };
 
         match control_result {
-            ControlResult::Pwm(target_pwm) => {
-                // Send the new RPM to all of our fans
-                ringbuf_entry!(Trace::ControlPwm(target_pwm.0));
-                self.set_pwm(target_pwm)?;
+            ControlResult::Pwm(mut target_pwm) => {
+                self.compensate_failed_fans(&mut target_pwm);
+
+                // Send the new RPM to each zone's fans
+                for (i, pwm) in target_pwm.iter().enumerate() {
+                    ringbuf_entry!(Trace::ControlPwm(i, pwm.0));
+                }
+                self.set_zone_pwm(target_pwm)?;
             }
             ControlResult::PowerDown => {
                 ringbuf_entry!(Trace::PowerDownAt(sys_get_timer().now));
@@ -1015,14 +1334,30 @@ impl<'a> ThermalControl<'a> {
             return Err(ThermalError::InvalidPWM);
         }
         self.last_pwm = pwm;
+        self.set_zone_pwm([pwm; config::NUM_ZONES])
+    }
+
+    /// Attempts to set the PWM duty cycle of every fan in each zone.
+    ///
+    /// Behaves like `set_pwm`, except that fans are set to the PWM for their
+    /// zone.
+    fn set_zone_pwm(
+        &mut self,
+        pwm: [PWMDuty; config::NUM_ZONES],
+    ) -> Result<(), ThermalError> {
+        for (out, pwm) in self.zone_outputs.iter_mut().zip(pwm) {
+            out.pwm = pwm;
+        }
         let mut last_err = Ok(());
         for (index, sensor_id) in self.fans.enumerate() {
             // If a fan is missing, keep its PWM signal low
             let pwm = match sensor_id {
-                Some(_) => pwm,
+                Some(_) => pwm[config::FAN_ZONES[index]],
                 None => PWMDuty(0),
             };
-            if let Err(e) = self.bsp.fan_control(Fan::from(index)).set_pwm(pwm)
+            self.fan_monitors[index].pwm = pwm;
+            if let Err(e) =
+                self.fctrl.fan_control(Fan::from(index)).set_pwm(pwm)
             {
                 last_err = Err(e);
             }
@@ -1030,11 +1365,35 @@ impl<'a> ThermalControl<'a> {
         last_err.map_err(|_| ThermalError::DeviceError)
     }
 
+    /// Boosts the PWM of each zone with a failed fan, so that the zone's
+    /// remaining fans make up for the lost airflow.
+    fn compensate_failed_fans(
+        &mut self,
+        pwm: &mut [PWMDuty; config::NUM_ZONES],
+    ) {
+        let mut failed = [false; config::NUM_ZONES];
+        for (index, sensor_id) in self.fans.enumerate() {
+            if sensor_id.is_some() && self.fan_monitors[index].is_failed() {
+                failed[config::FAN_ZONES[index]] = true;
+            }
+        }
+        for (i, (pwm, failed)) in pwm.iter_mut().zip(failed).enumerate() {
+            if failed {
+                let boosted = pwm.0.saturating_add(config::FAN_HEALTH.boost);
+                *pwm = PWMDuty(boosted.min(100));
+            }
+            if failed != self.zone_outputs[i].boosted {
+                ringbuf_entry!(Trace::ZoneBoost(i, failed));
+                self.zone_outputs[i].boosted = failed;
+            }
+        }
+    }
+
     /// Sets the PWM for a single fan
     ///
     /// If the fan is present, set to `pwm`. if it is not present, set to zero.
     pub fn set_fan_pwm(
-        &self,
+        &mut self,
         fan: Fan,
         pwm: PWMDuty,
     ) -> Result<(), ResponseCode> {
@@ -1042,7 +1401,19 @@ impl<'a> ThermalControl<'a> {
             true => pwm,
             false => PWMDuty(0),
         };
-        self.bsp.fan_control(fan).set_pwm(pwm)
+        self.fan_monitors[fan.0 as usize].pwm = pwm;
+        self.fctrl.fan_control(fan).set_pwm(pwm)
+    }
+
+    /// Returns the most recent speed and health of a fan
+    pub fn get_fan_status(&self, fan: Fan) -> FanStatus {
+        let m = &self.fan_monitors[fan.0 as usize];
+        FanStatus {
+            present: self.fans.is_present(fan),
+            health: m.health,
+            pwm: m.pwm.0,
+            rpm: m.rpm.map(|r| r.0),
+        }
     }
 
     /// Attempts to set the PWM of every fan to whatever the previous value was.
@@ -1064,23 +1435,23 @@ impl<'a> ThermalControl<'a> {
     }
 
     pub fn set_watchdog(&self, wd: I2cWatchdog) -> Result<(), ResponseCode> {
-        let mut result = Ok(());
-
-        self.bsp.for_each_fctrl(|fctrl| {
-            if let Err(e) = fctrl.set_watchdog(wd) {
-                result = Err(e);
-            }
-        });
-
-        result
+        self.fctrl.set_watchdog(wd)
     }
 
+    /// Returns the state of the system as a whole, which is `Overheated` if
+    /// any zone is overheated.
     pub fn get_state(&self) -> ThermalAutoState {
-        match self.state {
+        match &self.state {
             ThermalControlState::Boot { .. } => ThermalAutoState::Boot,
-            ThermalControlState::Running { .. } => ThermalAutoState::Running,
-            ThermalControlState::Overheated { .. } => {
-                ThermalAutoState::Overheated
+            ThermalControlState::Running { zones, .. } => {
+                if zones
+                    .iter()
+                    .any(|z| matches!(z, ZoneState::Overheated { .. }))
+                {
+                    ThermalAutoState::Overheated
+                } else {
+                    ThermalAutoState::Running
+                }
             }
             ThermalControlState::Uncontrollable => {
                 ThermalAutoState::Uncontrollable
@@ -1088,6 +1459,25 @@ impl<'a> ThermalControl<'a> {
         }
     }
 
+    /// Returns the state of a single zone, or `None` if there's no such zone
+    pub fn get_zone_state(&self, zone: usize) -> Option<ThermalZoneState> {
+        let out = self.zone_outputs.get(zone)?;
+        let state = match &self.state {
+            ThermalControlState::Boot { .. } => ThermalAutoState::Boot,
+            ThermalControlState::Running { zones, .. } => {
+                zones[zone].auto_state()
+            }
+            ThermalControlState::Uncontrollable => {
+                ThermalAutoState::Uncontrollable
+            }
+        };
+        Some(ThermalZoneState {
+            state,
+            pwm: out.pwm.0,
+            worst_margin: out.worst_margin,
+        })
+    }
+
     pub fn update_dynamic_input(
         &mut self,
         index: usize,
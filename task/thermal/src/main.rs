@@ -28,6 +28,10 @@
 mod bsp;
 mod control;
 
+mod config {
+    include!(concat!(env!("OUT_DIR"), "/thermal_config.rs"));
+}
+
 use crate::{
     bsp::{Bsp, PowerBitmask, SeqError},
     control::ThermalControl,
@@ -39,8 +43,8 @@ use idol_runtime::{NotificationHandler, RequestError};
 use ringbuf::*;
 use task_sensor_api::{Sensor as SensorApi, SensorApiError, SensorId};
 use task_thermal_api::{
-    SensorReadError, ThermalAutoState, ThermalError, ThermalMode,
-    ThermalProperties,
+    FanHealth, FanStatus, SensorReadError, ThermalAutoState, ThermalError,
+    ThermalMode, ThermalProperties, ThermalZoneState,
 };
 use userlib::units::PWMDuty;
 use userlib::*;
@@ -69,13 +73,16 @@ enum Trace {
     MiscReadFailed(SensorId, SensorReadError),
     SensorReadFailed(SensorId, SensorReadError),
     PostFailed(SensorId, SensorApiError),
-    ControlPwm(u8),
+    ControlPwm(usize, u8),
+    ZoneState(usize, ThermalAutoState),
     PowerModeChanged(PowerBitmask),
     PowerDownFailed(SeqError),
     ControlError(ThermalError),
     FanPresenceUpdateFailed(SeqError),
     FanAdded(Fan),
     FanRemoved(Fan),
+    FanHealth(Fan, FanHealth),
+    ZoneBoost(usize, bool),
     PowerDownAt(u64),
     AddedDynamicInput(usize),
     RemovedDynamicInput(usize),
@@ -152,6 +159,19 @@ impl<'a> idl::InOrderThermalImpl for ServerImpl<'a> {
         Ok(self.control.get_state())
     }
 
+    fn get_zone_auto_state(
+        &mut self,
+        _: &RecvMessage,
+        zone: u8,
+    ) -> Result<ThermalZoneState, RequestError<ThermalError>> {
+        if self.mode != ThermalMode::Auto {
+            return Err(ThermalError::NotInAutoMode.into());
+        }
+        self.control
+            .get_zone_state(zone as usize)
+            .ok_or_else(|| ThermalError::InvalidIndex.into())
+    }
+
     fn set_fan_pwm(
         &mut self,
         _: &RecvMessage,
@@ -173,6 +193,17 @@ impl<'a> idl::InOrderThermalImpl for ServerImpl<'a> {
         }
     }
 
+    fn get_fan_status(
+        &mut self,
+        _: &RecvMessage,
+        index: u8,
+    ) -> Result<FanStatus, RequestError<ThermalError>> {
+        match self.control.fan(index) {
+            Some(fan) => Ok(self.control.get_fan_status(fan)),
+            None => Err(ThermalError::InvalidFan.into()),
+        }
+    }
+
     fn set_mode_manual(
         &mut self,
         _: &RecvMessage,
@@ -36,6 +36,7 @@ pub enum AssistOp {
     RefreshTaskIdOffByOne = 21,
     RefreshTaskIdOffByMany = 22,
     ReadNotifications = 23,
+    NoReply = 24,
 }
 
 /// Operations that are performed by the test-suite
@@ -277,6 +277,10 @@ fn main() -> ! {
                     AssistOp::ReadNotifications => {
                         caller.reply(core::mem::replace(posted_bits, 0));
                     }
+                    AssistOp::NoReply => {
+                        // Leave the caller hanging.
+                        drop(caller);
+                    }
                     _ => {
                         // Anything else should be fatal
                         for (which, func) in &fatalops {
@@ -112,6 +112,10 @@ test_cases! {
     test_task_config,
     test_task_status,
     test_task_fault_injection,
+    test_find_faulted_tasks,
+    test_read_task_stats,
+    test_send_timeout,
+    test_send_timeout_expired,
     test_refresh_task_id_basic,
     test_refresh_task_id_off_by_one,
     test_refresh_task_id_off_by_many,
@@ -1338,6 +1342,125 @@ fn test_task_fault_injection() {
     }
 }
 
+/// Tests that the kernel's bitmap of faulted tasks tracks the assistant.
+fn test_find_faulted_tasks() {
+    let index: usize = ASSIST.get_task_index().into();
+    let base = index - index % u32::BITS as usize;
+    let bit = 1 << (index - base);
+
+    // Assistant should be fine
+    assert_eq!(kipc::find_faulted_tasks(base) & bit, 0);
+
+    // Inject a fault into it, and make sure it shows up
+    kipc::fault_task(index);
+    assert_ne!(kipc::find_faulted_tasks(base) & bit, 0);
+
+    // We're running, so we had better not be faulted
+    let me: usize = SUITE.get_task_index().into();
+    let mine = kipc::find_faulted_tasks(me - me % u32::BITS as usize);
+    assert_eq!(mine & (1 << (me % u32::BITS as usize)), 0);
+
+    // And restarting the assistant should clear it
+    restart_assistant();
+    assert_eq!(kipc::find_faulted_tasks(base) & bit, 0);
+}
+
+/// Tests that the kernel's per-task counters track our syscalls and the
+/// assistant's scheduling.
+fn test_read_task_stats() {
+    let me: usize = SUITE.get_task_index().into();
+    let assist: usize = ASSIST.get_task_index().into();
+
+    let before = kipc::read_task_stats(me);
+    let assist_before = kipc::read_task_stats(assist);
+    sys_get_timer();
+    test_send();
+    let after = kipc::read_task_stats(me);
+    let assist_after = kipc::read_task_stats(assist);
+
+    // Each read is itself a SEND to the kernel, counted before the stats are
+    // copied out, so the second read counts itself but not the first.
+    let get_timer = Sysnum::GetTimer as usize;
+    assert_eq!(after.syscalls[get_timer], before.syscalls[get_timer] + 1);
+    let send = Sysnum::Send as usize;
+    assert!(after.syscalls[send] >= before.syscalls[send] + 3);
+
+    // Sending to the assistant should have switched to it at least once.
+    assert!(assist_after.scheduled > assist_before.scheduled);
+    assert!(after.ticks >= before.ticks);
+}
+
+/// Tests that a send that never gets a reply times out.
+///
+/// Having abandoned a send, we can't reach the assistant again until it has
+/// replied to it or restarted, and the `NoReply` operation never replies, so we
+/// restart the assistant after each one.
+fn test_send_timeout() {
+    let assist = assist_task_id();
+    let deadline = sys_get_timer().now + 10;
+    let mut response = 0_u32;
+    let (rc, len) = sys_send_timeout(
+        assist,
+        AssistOp::NoReply as u16,
+        &0_u32.to_le_bytes(),
+        response.as_bytes_mut(),
+        &[],
+        deadline,
+    );
+    assert_eq!(rc, TIMED_OUT);
+    assert_eq!(len, 0);
+    assert!(sys_get_timer().now >= deadline);
+    restart_assistant();
+
+    // The same, via the deadline that applies to plain sends.
+    let assist = assist_task_id();
+    let deadline = sys_get_timer().now + 10;
+    let (rc, _) = with_send_deadline(deadline, || {
+        sys_send(
+            assist,
+            AssistOp::NoReply as u16,
+            &0_u32.to_le_bytes(),
+            response.as_bytes_mut(),
+            &[],
+        )
+    });
+    assert_eq!(rc, TIMED_OUT);
+    restart_assistant();
+
+    // Once restarted, the assistant should be answering us again.
+    test_send();
+}
+
+/// Tests that a send with a deadline in the past fails without being
+/// delivered.
+fn test_send_timeout_expired() {
+    let assist = assist_task_id();
+    let deadline = sys_get_timer().now;
+    let challenge = 0xDEADBEEF_u32;
+    let mut response = 0_u32;
+    let (rc, _) = sys_send_timeout(
+        assist,
+        AssistOp::Store as u16,
+        &challenge.to_le_bytes(),
+        response.as_bytes_mut(),
+        &[],
+        deadline,
+    );
+    assert_eq!(rc, TIMED_OUT);
+
+    // If the message had been delivered, the assistant would have stored the
+    // challenge.
+    let (rc, _) = sys_send(
+        assist,
+        AssistOp::Store as u16,
+        &0_u32.to_le_bytes(),
+        response.as_bytes_mut(),
+        &[],
+    );
+    assert_eq!(rc, 0);
+    assert_ne!(response, challenge);
+}
+
 /// Tests that we can get current task IDs for the assistant. In practice, this
 /// is already tested because the test runner relies on it -- but this may
 /// provide a more specific failure if we break it, and is meant to complement